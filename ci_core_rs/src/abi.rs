@@ -0,0 +1,131 @@
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::config::AbiCheckConfig;
+use crate::utils::find_local_file;
+
+/// The GKI symbol list in Android common kernels.
+const DEFAULT_SYMBOL_LIST: &str = "android/abi_gki_aarch64";
+
+/// Symbols listed in the log; the rest are in the report file.
+const MAX_LISTED_SYMBOLS: usize = 30;
+
+/// Symbol to CRC from a `Module.symvers`
+/// (`crc<TAB>symbol<TAB>module<TAB>export type[<TAB>namespace]`).
+fn read_symvers(path: &Path) -> Result<HashMap<String, String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let crc = fields.next()?;
+            let symbol = fields.next()?;
+            Some((symbol.to_string(), crc.to_string()))
+        })
+        .collect())
+}
+
+/// Symbols named in a symbol list: one per line under `[abi_symbol_list]`
+/// style headers, with `#` comments.
+fn read_symbol_list(path: &Path) -> Result<Vec<String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('['))
+        .map(String::from)
+        .collect())
+}
+
+fn list_symbols(what: &str, symbols: &[String]) {
+    info!("{} {}:", symbols.len(), what);
+    for symbol in symbols.iter().take(MAX_LISTED_SYMBOLS) {
+        info!("  {}", symbol);
+    }
+    if symbols.len() > MAX_LISTED_SYMBOLS {
+        info!("  ... and {} more", symbols.len() - MAX_LISTED_SYMBOLS);
+    }
+}
+
+/// Checks the symbols the build exports (`Module.symvers`) against the
+/// configured symbol lists: every listed symbol must be exported, and with
+/// `reference_symvers` it must keep the CRC the stock kernel had, or
+/// vendor modules built against that kernel won't load. The full report
+/// goes to `<out>/abi-check.txt`.
+pub fn check(cfg: &AbiCheckConfig, kernel_source: &Path, out_path: &Path) -> Result<()> {
+    let symvers = out_path.join("Module.symvers");
+    if !symvers.exists() {
+        return Err(anyhow!(
+            "{} not found; abi_check needs CONFIG_MODULES",
+            symvers.display()
+        ));
+    }
+    let exported = read_symvers(&symvers)?;
+
+    let lists = if cfg.symbol_lists.is_empty() {
+        vec![DEFAULT_SYMBOL_LIST.to_string()]
+    } else {
+        cfg.symbol_lists.clone()
+    };
+    let mut listed = BTreeSet::new();
+    for list in &lists {
+        let path = find_local_file(list, kernel_source)?;
+        listed.extend(read_symbol_list(&path)?);
+    }
+    info!(
+        "Checking {} listed symbol(s) against {} exported...",
+        listed.len(),
+        exported.len()
+    );
+
+    let missing: Vec<String> = listed
+        .iter()
+        .filter(|s| !exported.contains_key(*s))
+        .cloned()
+        .collect();
+    let mut changed = Vec::new();
+    if let Some(reference) = &cfg.reference_symvers {
+        let reference = read_symvers(&find_local_file(reference, kernel_source)?)?;
+        for symbol in &listed {
+            if let (Some(old), Some(new)) = (reference.get(symbol), exported.get(symbol))
+                && old != new
+            {
+                changed.push(format!("{} ({} -> {})", symbol, old, new));
+            }
+        }
+    }
+
+    let mut report = String::new();
+    for symbol in &missing {
+        report.push_str(&format!("missing {}\n", symbol));
+    }
+    for symbol in &changed {
+        report.push_str(&format!("changed {}\n", symbol));
+    }
+    fs::write(out_path.join("abi-check.txt"), report)?;
+
+    if missing.is_empty() && changed.is_empty() {
+        info!("ABI check passed.");
+        return Ok(());
+    }
+    if !missing.is_empty() {
+        list_symbols("listed symbol(s) not exported", &missing);
+    }
+    if !changed.is_empty() {
+        list_symbols("symbol CRC(s) changed from the reference", &changed);
+    }
+    if cfg.allow_changes {
+        warn!("ABI check found differences; allow_changes is set, continuing.");
+        return Ok(());
+    }
+    Err(anyhow!(
+        "ABI check failed: {} missing, {} changed symbol(s) (see abi-check.txt)",
+        missing.len(),
+        changed.len()
+    ))
+}
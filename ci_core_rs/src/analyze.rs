@@ -0,0 +1,224 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::AnalyzeConfig;
+use crate::output;
+use crate::utils::{get_cache_dir, run_cmd, run_cmd_with_env, save_json};
+use crate::warnings::{Warning, new_since};
+
+/// Values `analyze.tool` accepts.
+pub const TOOLS: &[&str] = &["sparse", "smatch", "clang-analyzer"];
+
+/// Findings listed after the run; the rest are only counted.
+const MAX_LISTED_FINDINGS: usize = 30;
+
+/// C files and kbuild directories that differ from the kernel source's
+/// HEAD, i.e. what integration and patches touched. Build output is left
+/// out.
+fn changed_sources(kernel_source: &Path) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for args in [
+        &["diff", "--name-only", "HEAD"][..],
+        &["ls-files", "--others", "--exclude-standard"][..],
+    ] {
+        let mut cmd = vec!["git"];
+        cmd.extend_from_slice(args);
+        let listed = run_cmd(&cmd, Some(kernel_source), true)?.unwrap_or_default();
+        paths.extend(listed.lines().map(|l| l.trim_end_matches('/').to_string()));
+    }
+    let mut sources: Vec<String> = paths
+        .into_iter()
+        .filter(|p| {
+            let top = p.split('/').next().unwrap_or_default();
+            top != "out" && !top.starts_with("out-")
+        })
+        .filter(|p| {
+            let full = kernel_source.join(p);
+            // A symlinked driver dir such as drivers/kernelsu shows up as
+            // one entry.
+            p.ends_with(".c")
+                || (full.is_dir()
+                    && (full.join("Makefile").exists() || full.join("Kbuild").exists()))
+        })
+        .collect();
+    sources.sort();
+    sources.dedup();
+    Ok(sources)
+}
+
+/// Where findings are shown relative to: `../fs/x.c` in O= builds, or an
+/// absolute path from clang-tidy.
+fn relative(file: &str, kernel_source: &Path) -> String {
+    let source = fs::canonicalize(kernel_source).unwrap_or_else(|_| kernel_source.to_path_buf());
+    let prefix = format!("{}/", source.display());
+    let mut file = file.strip_prefix(&prefix).unwrap_or(file);
+    while let Some(rest) = file.strip_prefix("../") {
+        file = rest;
+    }
+    file.to_string()
+}
+
+fn baseline_path(project_key: &str, branch: &str) -> PathBuf {
+    get_cache_dir()
+        .join("analyze")
+        .join(format!("{}-{}.json", project_key, branch))
+}
+
+/// Runs the configured checker over the files integration changed (or the
+/// whole tree) and lists its findings, highlighting those the last analyzed
+/// build of the branch didn't have. Fails on more new findings than
+/// `max_new_findings`.
+pub fn run(
+    cfg: &AnalyzeConfig,
+    project_key: &str,
+    branch: &str,
+    kernel_source: &Path,
+    out_path: &Path,
+    make_args: &[&str],
+    build_env: &HashMap<String, String>,
+) -> Result<()> {
+    let tool = cfg.tool.as_deref().unwrap_or("sparse");
+    let targets = if cfg.whole_tree {
+        Vec::new()
+    } else {
+        let sources = changed_sources(kernel_source)?;
+        if sources.is_empty() {
+            info!("No changed C sources to analyze.");
+            return Ok(());
+        }
+        info!(
+            "Analyzing {} changed path(s) with {}...",
+            sources.len(),
+            tool
+        );
+        sources
+    };
+
+    let before = output::warnings().len();
+    match tool {
+        "sparse" | "smatch" => {
+            let check = if tool == "smatch" {
+                "CHECK=smatch -p=kernel"
+            } else {
+                "CHECK=sparse"
+            };
+            let object_targets: Vec<String> = targets
+                .iter()
+                .map(|t| match t.strip_suffix(".c") {
+                    Some(stem) => format!("{}.o", stem),
+                    None => format!("{}/", t),
+                })
+                .collect();
+            let mut cmd = vec!["make"];
+            cmd.extend_from_slice(make_args);
+            cmd.extend(["C=2", check]);
+            cmd.extend(object_targets.iter().map(|t| t.as_str()));
+            run_cmd_with_env(&cmd, Some(kernel_source), build_env)?;
+        }
+        "clang-analyzer" => {
+            let mut cmd = vec!["make"];
+            cmd.extend_from_slice(make_args);
+            cmd.push("compile_commands.json");
+            run_cmd_with_env(&cmd, Some(kernel_source), build_env)?;
+            let database = compile_commands(kernel_source, out_path, &targets)?;
+            let database = database.to_string_lossy();
+            run_cmd_with_env(
+                &[
+                    "python3",
+                    "scripts/clang-tools/run-clang-tools.py",
+                    "clang-analyzer",
+                    &database,
+                ],
+                Some(kernel_source),
+                build_env,
+            )?;
+        }
+        other => {
+            return Err(anyhow!(
+                "Unknown analyze.tool '{}' (expected one of: {})",
+                other,
+                TOOLS.join(", ")
+            ));
+        }
+    }
+
+    // Findings aren't compiler warnings; keep them out of the build's counts.
+    let findings: Vec<Warning> = output::take_warnings_from(before)
+        .into_iter()
+        .map(|mut w| {
+            w.file = relative(&w.file, kernel_source);
+            w
+        })
+        .collect();
+    info!("{}: {} finding(s)", tool, findings.len());
+
+    let baseline = baseline_path(project_key, branch);
+    let previous: Option<Vec<Warning>> = fs::read_to_string(&baseline)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    let listed: Vec<&Warning> = match &previous {
+        Some(previous) => new_since(&findings, previous),
+        None => findings.iter().collect(),
+    };
+    if !listed.is_empty() {
+        info!(
+            "{} {}:",
+            listed.len(),
+            if previous.is_some() {
+                "new finding(s) since the last analyzed build"
+            } else {
+                "finding(s)"
+            }
+        );
+        for finding in listed.iter().take(MAX_LISTED_FINDINGS) {
+            info!("  {}", finding);
+        }
+        if listed.len() > MAX_LISTED_FINDINGS {
+            info!("  ... and {} more", listed.len() - MAX_LISTED_FINDINGS);
+        }
+    }
+    if let Some(max) = cfg.max_new_findings
+        && previous.is_some()
+        && listed.len() > max as usize
+    {
+        return Err(anyhow!(
+            "{} new {} finding(s) (max_new_findings is {})",
+            listed.len(),
+            tool,
+            max
+        ));
+    }
+
+    fs::create_dir_all(baseline.parent().unwrap())?;
+    if let Err(e) = save_json(&baseline, &findings) {
+        warn!("Couldn't save the findings for the next build: {:#}", e);
+    }
+    Ok(())
+}
+
+/// The build's compile_commands.json, cut down to `targets` unless that's
+/// the whole tree.
+fn compile_commands(kernel_source: &Path, out_path: &Path, targets: &[String]) -> Result<PathBuf> {
+    let full = out_path.join("compile_commands.json");
+    if targets.is_empty() {
+        return Ok(full);
+    }
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&full)?)?;
+    let source = fs::canonicalize(kernel_source)?;
+    let wanted: Vec<PathBuf> = targets.iter().map(|t| source.join(t)).collect();
+    let entries: Vec<serde_json::Value> = entries
+        .into_iter()
+        .filter(|entry| {
+            let file = Path::new(entry["file"].as_str().unwrap_or_default());
+            wanted.iter().any(|w| file.starts_with(w))
+        })
+        .collect();
+    let dir = out_path.join("analyze");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("compile_commands.json");
+    save_json(&path, &entries)?;
+    Ok(path)
+}
@@ -0,0 +1,73 @@
+use anyhow::{Result, anyhow};
+
+/// Per-architecture build settings selected by a project's `arch`.
+pub struct Arch {
+    /// `ARCH=` for make, also the directory under `arch/`.
+    pub kernel_arch: &'static str,
+    pub cross_compile: &'static str,
+    /// 32-bit triple for the compat vDSO, where there is one.
+    pub cross_compile_compat: Option<&'static str>,
+    /// Boot image path under `arch/<kernel_arch>/boot/`.
+    pub image: &'static str,
+}
+
+pub const ARCHES: &[(&str, Arch)] = &[
+    (
+        "arm64",
+        Arch {
+            kernel_arch: "arm64",
+            cross_compile: "aarch64-linux-gnu-",
+            cross_compile_compat: Some("arm-linux-gnueabi-"),
+            image: "Image",
+        },
+    ),
+    (
+        "arm",
+        Arch {
+            kernel_arch: "arm",
+            cross_compile: "arm-linux-gnueabi-",
+            cross_compile_compat: None,
+            image: "zImage",
+        },
+    ),
+    (
+        "x86_64",
+        Arch {
+            kernel_arch: "x86",
+            cross_compile: "x86_64-linux-gnu-",
+            cross_compile_compat: None,
+            image: "bzImage",
+        },
+    ),
+    (
+        "riscv64",
+        Arch {
+            kernel_arch: "riscv",
+            cross_compile: "riscv64-linux-gnu-",
+            cross_compile_compat: None,
+            image: "Image",
+        },
+    ),
+];
+
+pub fn resolve_arch(name: Option<&str>) -> Result<&'static Arch> {
+    let name = name.unwrap_or("arm64");
+    ARCHES
+        .iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, arch)| arch)
+        .ok_or_else(|| {
+            let known: Vec<&str> = ARCHES.iter().map(|(key, _)| *key).collect();
+            anyhow!("Unknown arch '{}' (known: {})", name, known.join(", "))
+        })
+}
+
+impl Arch {
+    pub fn boot_dir(&self) -> String {
+        format!("arch/{}/boot", self.kernel_arch)
+    }
+
+    pub fn defconfig_path(&self, defconfig: &str) -> String {
+        format!("arch/{}/configs/{}", self.kernel_arch, defconfig)
+    }
+}
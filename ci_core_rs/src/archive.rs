@@ -0,0 +1,234 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::read::MultiGzDecoder;
+use indicatif::ProgressBar;
+use log::info;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::interrupt;
+use crate::progress;
+
+/// One logical archive: either a single file or the ordered parts of a
+/// split archive that must be concatenated before decoding.
+struct ArchiveGroup {
+    name: String,
+    parts: Vec<PathBuf>,
+}
+
+/// Extracts every archive found in `src_dir` into `dest`.
+///
+/// Split archives are recognised in both layouts used by our toolchain
+/// releases: `name.tar.gz.00` / `name.tar.gz.aa` and `name_part_aa.tar.gz`.
+/// gzip, xz, zstd and bzip2 tarballs and zips are supported; the format is
+/// taken from the extension, or sniffed from the file header if that fails.
+pub fn extract_all(src_dir: &Path, dest: &Path) -> Result<()> {
+    let groups = group_archives(src_dir)?;
+    if groups.is_empty() {
+        return Err(anyhow!("No archives found in {}", src_dir.display()));
+    }
+
+    for group in groups {
+        if group.parts.len() > 1 {
+            info!("Extracting {} ({} parts)...", group.name, group.parts.len());
+        } else {
+            info!("Extracting {}...", group.name);
+        }
+        extract_group(&group, dest).with_context(|| format!("Failed to extract {}", group.name))?;
+    }
+    Ok(())
+}
+
+const ARCHIVE_EXTS: &str = r"tar\.gz|tgz|tar\.xz|txz|tar\.zst|tzst|tar\.bz2|tbz2?|tar|zip";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+    TarBz2,
+    Zip,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        [
+            (".tar.gz", Format::TarGz),
+            (".tgz", Format::TarGz),
+            (".tar.xz", Format::TarXz),
+            (".txz", Format::TarXz),
+            (".tar.zst", Format::TarZst),
+            (".tzst", Format::TarZst),
+            (".tar.bz2", Format::TarBz2),
+            (".tbz2", Format::TarBz2),
+            (".tbz", Format::TarBz2),
+            (".tar", Format::Tar),
+            (".zip", Format::Zip),
+        ]
+        .into_iter()
+        .find(|(ext, _)| name.ends_with(ext))
+        .map(|(_, format)| format)
+    }
+
+    fn sniff(path: &Path) -> Result<Option<Self>> {
+        let mut header = [0u8; 512];
+        let mut file = File::open(path)?;
+        let mut len = 0;
+        while len < header.len() {
+            match file.read(&mut header[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        let header = &header[..len];
+
+        let format = if header.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::TarGz)
+        } else if header.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Format::TarXz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::TarZst)
+        } else if header.starts_with(b"BZh") {
+            Some(Format::TarBz2)
+        } else if header.starts_with(b"PK\x03\x04") {
+            Some(Format::Zip)
+        } else if header.len() >= 262 && &header[257..262] == b"ustar" {
+            Some(Format::Tar)
+        } else {
+            None
+        };
+        Ok(format)
+    }
+}
+
+fn group_archives(src_dir: &Path) -> Result<Vec<ArchiveGroup>> {
+    let suffix_split =
+        Regex::new(&format!(r"^(.+\.(?:{}))\.(\d+|[a-z]{{2}})$", ARCHIVE_EXTS)).unwrap();
+    let plain_archive = Regex::new(&format!(r"\.(?:{})$", ARCHIVE_EXTS)).unwrap();
+    let infix_split = Regex::new(&format!(
+        r"^(.+?)_(?:part_)?([a-z]{{2}})(\.(?:{}))$",
+        ARCHIVE_EXTS
+    ))
+    .unwrap();
+
+    let mut names: Vec<String> = fs::read_dir(src_dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for name in names {
+        // Infix first: `x_part_aa.tar.gz` would otherwise read as `x_part_aa.tar` part `gz`.
+        let key = if let Some(caps) = infix_split.captures(&name) {
+            format!("{}{}", &caps[1], &caps[3])
+        } else if let Some(caps) = suffix_split.captures(&name)
+            && !plain_archive.is_match(&name)
+        {
+            caps[1].to_string()
+        } else {
+            name.clone()
+        };
+        groups.entry(key).or_default().push(src_dir.join(&name));
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(name, parts)| ArchiveGroup { name, parts })
+        .collect())
+}
+
+fn extract_group(group: &ArchiveGroup, dest: &Path) -> Result<()> {
+    let format = match Format::from_name(&group.name) {
+        Some(format) => format,
+        None => {
+            Format::sniff(&group.parts[0])?.ok_or_else(|| anyhow!("Unrecognised archive format"))?
+        }
+    };
+
+    let mut len = 0;
+    for part in &group.parts {
+        len += fs::metadata(part)?.len();
+    }
+    let pb = progress::extract_bar(&group.name, len);
+    let reader = || open_parts(&group.parts, &pb);
+    let result = match format {
+        Format::Zip => extract_zip(group, dest, &pb),
+        Format::Tar => unpack_tar(reader()?, dest, &pb),
+        Format::TarGz => unpack_tar(MultiGzDecoder::new(reader()?), dest, &pb),
+        Format::TarXz => unpack_tar(
+            xz2::read::XzDecoder::new_multi_decoder(reader()?),
+            dest,
+            &pb,
+        ),
+        Format::TarZst => unpack_tar(zstd::stream::read::Decoder::new(reader()?)?, dest, &pb),
+        Format::TarBz2 => unpack_tar(bzip2::read::MultiBzDecoder::new(reader()?), dest, &pb),
+    };
+    pb.finish_and_clear();
+    result
+}
+
+fn extract_zip(group: &ArchiveGroup, dest: &Path, pb: &ProgressBar) -> Result<()> {
+    let zip_path = if group.parts.len() == 1 {
+        group.parts[0].clone()
+    } else {
+        // ZipArchive needs Seek, so join the parts on disk first.
+        let joined = dest.join(format!(".{}.joined", group.name));
+        io::copy(
+            &mut open_parts(&group.parts, pb)?,
+            &mut File::create(&joined)?,
+        )?;
+        pb.reset();
+        joined
+    };
+    let mut archive =
+        zip::ZipArchive::new(interrupt::Checked(pb.wrap_read(File::open(&zip_path)?)))?;
+    pb.set_prefix(format!("{} files", archive.len()));
+    archive.extract(dest)?;
+    if zip_path != group.parts[0] {
+        fs::remove_file(zip_path)?;
+    }
+    Ok(())
+}
+
+/// The parts chained into one stream, counted on `pb` as they are read.
+fn open_parts(parts: &[PathBuf], pb: &ProgressBar) -> Result<Box<dyn Read>> {
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    for part in parts {
+        let file =
+            File::open(part).with_context(|| format!("Failed to open {}", part.display()))?;
+        reader = Box::new(reader.chain(file));
+    }
+    Ok(Box::new(interrupt::Checked(pb.wrap_read(reader))))
+}
+
+/// Unpacks entry by entry to count files on `pb`. Directories go last, as
+/// in `Archive::unpack`, so read-only ones don't block their contents.
+fn unpack_tar<R: Read>(reader: R, dest: &Path, pb: &ProgressBar) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_overwrite(true);
+    fs::create_dir_all(dest)?;
+    let mut dirs = Vec::new();
+    let mut files = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_dir() {
+            dirs.push(entry);
+            continue;
+        }
+        entry.unpack_in(dest)?;
+        files += 1;
+        pb.set_prefix(format!("{} files", files));
+    }
+    dirs.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut dir in dirs {
+        dir.unpack_in(dest)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, Timelike};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::arch::Arch;
+use crate::config::{DtbConfig, ModulesConfig};
+use crate::utils::{run_cmd, run_cmd_with_env};
+
+/// Copies what the make targets produced into the AnyKernel3 dir. Returns
+/// artifacts that don't belong in the zip (header tarballs), which are
+/// written to `artifacts_dir` as `<stem>-<name>`.
+pub fn collect_targets(
+    targets: &[String],
+    arch: &Arch,
+    out_path: &Path,
+    ak3_dir: &Path,
+    artifacts_dir: &Path,
+    stem: &str,
+) -> Result<Vec<PathBuf>> {
+    let boot_dir = out_path.join(arch.boot_dir());
+    let mut extra = Vec::new();
+
+    for target in targets {
+        match target.as_str() {
+            "dtbs" => {
+                let dtbs = find_files(&boot_dir.join("dts"), "dtb")?;
+                copy_flat(&dtbs, &ak3_dir.join("dtbs"))?;
+                info!("Collected {} dtb(s)", dtbs.len());
+            }
+            "modules" => {
+                let modules = find_files(out_path, "ko")?;
+                copy_flat(&modules, &ak3_dir.join("modules"))?;
+                info!("Collected {} module(s)", modules.len());
+            }
+            "headers" | "headers_install" => {
+                let include = out_path.join("usr/include");
+                if !include.exists() {
+                    return Err(anyhow!("Headers not found at {:?}", include));
+                }
+                let tarball = artifacts_dir.join(format!("{}-headers.tar.gz", stem));
+                run_cmd(
+                    &[
+                        "tar",
+                        "-czf",
+                        &tarball.to_string_lossy(),
+                        "-C",
+                        &out_path.join("usr").to_string_lossy(),
+                        "include",
+                    ],
+                    None,
+                    false,
+                )?;
+                extra.push(tarball);
+            }
+            image => {
+                let path = boot_dir.join(image);
+                if !path.is_file() {
+                    return Err(anyhow!("{} not found at {:?}", image, path));
+                }
+                fs::copy(&path, ak3_dir.join(image))?;
+            }
+        }
+    }
+    Ok(extra)
+}
+
+/// Packages modules installed under `staging` (an INSTALL_MOD_PATH). Returns
+/// the archive when they go into a separate one instead of the zip.
+pub fn package_modules(
+    cfg: &ModulesConfig,
+    staging: &Path,
+    ak3_dir: &Path,
+    artifacts_dir: &Path,
+    stem: &str,
+) -> Result<Option<PathBuf>> {
+    let modules = find_files(staging, "ko")?;
+    if modules.is_empty() {
+        warn!("no kernel modules were installed.");
+        return Ok(None);
+    }
+
+    match cfg.package.as_deref().unwrap_or("zip") {
+        "zip" => {
+            let dest = ak3_dir.join("vendor_dlkm/lib/modules");
+            copy_flat(&modules, &dest)?;
+            let load: Vec<String> = modules
+                .iter()
+                .filter_map(|m| m.file_name())
+                .map(|n| n.to_string_lossy().to_string())
+                .collect();
+            fs::write(dest.join("modules.load"), load.join("\n") + "\n")?;
+            info!("Packaged {} module(s) into vendor_dlkm", modules.len());
+            Ok(None)
+        }
+        "archive" => {
+            let tarball = artifacts_dir.join(format!("{}-modules.tar.gz", stem));
+            run_cmd(
+                &[
+                    "tar",
+                    "-czf",
+                    &tarball.to_string_lossy(),
+                    "-C",
+                    &staging.to_string_lossy(),
+                    "lib",
+                ],
+                None,
+                false,
+            )?;
+            info!("Packaged {} module(s) into {:?}", modules.len(), tarball);
+            Ok(Some(tarball))
+        }
+        other => Err(anyhow!(
+            "Unknown modules.package '{}' (expected zip or archive)",
+            other
+        )),
+    }
+}
+
+/// Builds dtb.img/dtbo.img from the compiled device trees in `dts_dir`
+/// into `work_dir`, returning `(name in AnyKernel3, path)` pairs.
+pub fn build_dt_images(
+    cfg: &DtbConfig,
+    dts_dir: &Path,
+    work_dir: &Path,
+    build_env: &HashMap<String, String>,
+) -> Result<Vec<(&'static str, PathBuf)>> {
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir)?;
+    }
+    fs::create_dir_all(work_dir)?;
+    let tool = cfg.tool.as_deref().unwrap_or("mkdtimg");
+    let page_size = format!("--page_size={}", cfg.page_size.unwrap_or(2048));
+
+    let mkdtimg = |out: &Path, inputs: &[PathBuf]| -> Result<()> {
+        let out = out.to_string_lossy();
+        let inputs: Vec<String> = inputs
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        let mut cmd = vec![tool, "create", &out, &page_size];
+        cmd.extend(inputs.iter().map(|s| s.as_str()));
+        run_cmd_with_env(&cmd, None, build_env)
+    };
+
+    let mut images = Vec::new();
+    if !cfg.dtbs.is_empty() {
+        let inputs = match_dts(dts_dir, &cfg.dtbs)?;
+        let out = work_dir.join("dtb.img");
+        match cfg.dtb_format.as_deref().unwrap_or("mkdtimg") {
+            "mkdtimg" => mkdtimg(&out, &inputs)?,
+            "concat" => {
+                let mut content = Vec::new();
+                for input in &inputs {
+                    content.extend(fs::read(input)?);
+                }
+                fs::write(&out, content)?;
+            }
+            other => {
+                return Err(anyhow!(
+                    "Unknown dtb_format '{}' (expected mkdtimg or concat)",
+                    other
+                ));
+            }
+        }
+        info!("Built dtb.img from {} dtb(s)", inputs.len());
+        images.push(("dtb", out));
+    }
+    if !cfg.dtbos.is_empty() {
+        let inputs = match_dts(dts_dir, &cfg.dtbos)?;
+        let out = work_dir.join("dtbo.img");
+        mkdtimg(&out, &inputs)?;
+        info!("Built dtbo.img from {} overlay(s)", inputs.len());
+        images.push(("dtbo.img", out));
+    }
+    Ok(images)
+}
+
+/// Resolves dts patterns in order; each must match at least one file.
+fn match_dts(dts_dir: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut all = find_files(dts_dir, "dtb")?;
+    all.extend(find_files(dts_dir, "dtbo")?);
+
+    let mut matched = Vec::new();
+    for pattern in patterns {
+        let re = regex::Regex::new(&format!(
+            "^{}$",
+            regex::escape(pattern).replace(r"\*", "[^/]*")
+        ))?;
+        let before = matched.len();
+        for path in &all {
+            let rel = path.strip_prefix(dts_dir)?.to_string_lossy();
+            if re.is_match(&rel) && !matched.contains(path) {
+                matched.push(path.clone());
+            }
+        }
+        if matched.len() == before {
+            return Err(anyhow!(
+                "No device tree matches '{}' in {:?}",
+                pattern,
+                dts_dir
+            ));
+        }
+    }
+    Ok(matched)
+}
+
+/// All files under `dir` with the given extension, sorted.
+pub fn find_files(dir: &Path, ext: &str) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    if !dir.exists() {
+        return Ok(found);
+    }
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == ext) {
+                found.push(path);
+            }
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// Left out of the AnyKernel3 zip, as `zip -x` patterns.
+pub const AK3_EXCLUDES: &[&str] = &[
+    ".git*",
+    ".github*",
+    "README.md",
+    "LICENSE",
+    "*.gitignore",
+    "patch_linux",
+    "tools/boot.img.lz4",
+    "tools/libmagiskboot.so",
+];
+
+/// Zips the contents of `dir` into `dest` as `zip -r9 -X` would, but with
+/// the entries in sorted order and every timestamp set to `epoch`, so the
+/// same files always make the same bytes.
+pub fn deterministic_zip(dir: &Path, dest: &Path, excludes: &[&str], epoch: i64) -> Result<()> {
+    // DOS timestamps start in 1980.
+    let time = DateTime::from_timestamp(epoch, 0)
+        .and_then(|t| {
+            zip::DateTime::from_date_and_time(
+                t.year().clamp(1980, 2107) as u16,
+                t.month() as u8,
+                t.day() as u8,
+                t.hour() as u8,
+                t.minute() as u8,
+                t.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default();
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .compression_level(Some(9))
+        .last_modified_time(time);
+
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(dest)?));
+    let mut pending = vec![dir.to_path_buf()];
+    let mut entries = Vec::new();
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            let name = path.strip_prefix(dir)?.to_string_lossy().to_string();
+            if path.is_dir() {
+                if !excludes.iter().any(|p| glob(p, &format!("{}/", name))) {
+                    pending.push(path.clone());
+                    entries.push((format!("{}/", name), path));
+                }
+            } else if !excludes.iter().any(|p| glob(p, &name)) {
+                entries.push((name, path));
+            }
+        }
+    }
+    entries.sort();
+    for (name, path) in entries {
+        let mode = fs::metadata(&path)?.permissions().mode() & 0o777;
+        if name.ends_with('/') {
+            zip.add_directory(name, options.unix_permissions(mode))?;
+        } else {
+            zip.start_file(name, options.unix_permissions(mode))?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Matches `name` against a `zip -x` pattern, where `*` also matches `/`.
+fn glob(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((head, rest)) => name.strip_prefix(head).is_some_and(|tail| {
+            (0..=tail.len())
+                .filter(|&i| tail.is_char_boundary(i))
+                .any(|i| glob(rest, &tail[i..]))
+        }),
+    }
+}
+
+fn copy_flat(files: &[PathBuf], dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for file in files {
+        if let Some(name) = file.file_name() {
+            fs::copy(file, dest.join(name))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::arch::Arch;
+use crate::history::{self, BuildMetrics};
+use crate::utils::get_cache_dir;
+
+const BLOAT_O_METER: &str = "scripts/bloat-o-meter";
+
+/// Symbols listed from the bloat-o-meter diff; the full diff is written
+/// next to the build output.
+const MAX_LISTED_SYMBOLS: usize = 15;
+
+/// Sections shown in the summary; the rest are in the history for
+/// `compare`.
+const MAIN_SECTIONS: &[&str] = &[".text", ".rodata", ".data", ".bss", ".init.text"];
+
+fn mib(bytes: u64) -> String {
+    format!("{:.2} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+/// " (+1234 since #41)" against the previous build's id and value.
+fn since(previous: Option<(i64, u64)>, new: u64) -> String {
+    match previous {
+        Some((id, old)) if old != new => {
+            format!(" ({:+} since #{})", new as i128 - old as i128, id)
+        }
+        Some((id, _)) => format!(" (unchanged since #{})", id),
+        None => String::new(),
+    }
+}
+
+/// The previous build's vmlinux, kept without debug info for bloat-o-meter.
+fn previous_vmlinux(project_key: &str, branch: &str) -> PathBuf {
+    get_cache_dir()
+        .join("bloat")
+        .join(format!("{}-{}.vmlinux", project_key, branch))
+}
+
+/// Reports the boot images' sizes, vmlinux sections and a bloat-o-meter
+/// diff against the branch's previous build, then fails if the packaged
+/// image is over `max_image_size`.
+#[allow(clippy::too_many_arguments)]
+pub fn report(
+    project_key: &str,
+    branch: &str,
+    arch: &Arch,
+    use_gcc: bool,
+    make_targets: Option<&[String]>,
+    max_image_size: Option<u64>,
+    metrics: &BuildMetrics,
+    kernel_source: &Path,
+    out_path: &Path,
+    build_env: &HashMap<String, String>,
+) -> Result<()> {
+    let previous = match history::last_successful(project_key, branch) {
+        Ok(Some(id)) => history::load_metrics(id).ok().map(|m| (id, m.metrics)),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Can't compare sizes with the last build: {:#}", e);
+            None
+        }
+    };
+
+    let boot_dir = out_path.join(arch.boot_dir());
+    let mut images: Vec<(String, u64)> = fs::read_dir(&boot_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            let meta = e.metadata().ok().filter(|m| m.is_file())?;
+            name.starts_with(arch.image).then_some((name, meta.len()))
+        })
+        .collect();
+    images.sort();
+    info!("Kernel image size:");
+    for (name, size) in &images {
+        let old = previous
+            .as_ref()
+            .filter(|_| name == arch.image)
+            .and_then(|(id, m)| Some((*id, m.image_size?)));
+        let change = since(old, *size);
+        info!("  {:<16} {:>12} ({}){}", name, size, mib(*size), change);
+    }
+    if !metrics.sections.is_empty() {
+        info!("vmlinux sections:");
+        for section in MAIN_SECTIONS {
+            let Some(size) = metrics.sections.get(*section) else {
+                continue;
+            };
+            let old = previous
+                .as_ref()
+                .and_then(|(id, m)| Some((*id, *m.sections.get(*section)?)));
+            let change = since(old, *size);
+            info!("  {:<16} {:>12}{}", section, size, change);
+        }
+    }
+
+    bloat_o_meter(
+        project_key,
+        branch,
+        arch,
+        use_gcc,
+        kernel_source,
+        out_path,
+        build_env,
+    );
+
+    let Some(max) = max_image_size else {
+        return Ok(());
+    };
+    let packaged = make_targets
+        .into_iter()
+        .flatten()
+        .map(|t| t.as_str())
+        .chain([arch.image])
+        .find_map(|t| images.iter().find(|(name, _)| name == t));
+    match packaged {
+        Some((name, size)) if *size > max => Err(anyhow!(
+            "{} is {} bytes ({}), over max_image_size {} ({}) by {} bytes",
+            name,
+            size,
+            mib(*size),
+            max,
+            mib(max),
+            size - max
+        )),
+        Some((name, size)) => {
+            info!(
+                "{} fits max_image_size with {} bytes to spare.",
+                name,
+                max - size
+            );
+            Ok(())
+        }
+        None => Err(anyhow!(
+            "max_image_size is set but no kernel image was found in {}",
+            boot_dir.display()
+        )),
+    }
+}
+
+/// Runs the kernel's bloat-o-meter against the vmlinux kept from the last
+/// build, writes `<out>/bloat-o-meter.txt` and keeps this vmlinux for the
+/// next one. Best effort: problems are warnings.
+fn bloat_o_meter(
+    project_key: &str,
+    branch: &str,
+    arch: &Arch,
+    use_gcc: bool,
+    kernel_source: &Path,
+    out_path: &Path,
+    build_env: &HashMap<String, String>,
+) {
+    let vmlinux = out_path.join("vmlinux");
+    if !vmlinux.exists() {
+        return;
+    }
+    let (nm, objcopy) = if use_gcc {
+        (
+            format!("{}nm", arch.cross_compile),
+            format!("{}objcopy", arch.cross_compile),
+        )
+    } else {
+        ("llvm-nm".to_string(), "llvm-objcopy".to_string())
+    };
+    let kept = previous_vmlinux(project_key, branch);
+
+    if kept.exists() && kernel_source.join(BLOAT_O_METER).exists() {
+        let output = Command::new("python3")
+            .arg(BLOAT_O_METER)
+            .arg(&kept)
+            .arg(fs::canonicalize(&vmlinux).unwrap_or_else(|_| vmlinux.clone()))
+            .current_dir(kernel_source)
+            .envs(build_env)
+            .env("NM", &nm)
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                let diff = String::from_utf8_lossy(&output.stdout);
+                let _ = fs::write(out_path.join("bloat-o-meter.txt"), diff.as_bytes());
+                let lines: Vec<&str> = diff.lines().collect();
+                info!("bloat-o-meter against the last build:");
+                for line in lines.iter().take(MAX_LISTED_SYMBOLS + 2) {
+                    info!("  {}", line);
+                }
+                if lines.len() > MAX_LISTED_SYMBOLS + 3 {
+                    info!("  ... (bloat-o-meter.txt has the rest)");
+                    info!("  {}", lines[lines.len() - 1]);
+                }
+            }
+            Ok(output) => warn!(
+                "bloat-o-meter failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => warn!("Couldn't run bloat-o-meter: {}", e),
+        }
+    }
+
+    if let Some(dir) = kept.parent()
+        && let Err(e) = fs::create_dir_all(dir)
+    {
+        warn!("Couldn't keep vmlinux for the next size report: {}", e);
+        return;
+    }
+    // Only the symbol table is needed; debug info is most of vmlinux.
+    let stripped = Command::new(&objcopy)
+        .arg("--strip-debug")
+        .arg(&vmlinux)
+        .arg(&kept)
+        .envs(build_env)
+        .output()
+        .is_ok_and(|o| o.status.success());
+    if !stripped && let Err(e) = fs::copy(&vmlinux, &kept) {
+        warn!("Couldn't keep vmlinux for the next size report: {}", e);
+    }
+}
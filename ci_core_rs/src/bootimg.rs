@@ -0,0 +1,113 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{AvbConfig, BootImageConfig};
+use crate::retry;
+use crate::utils::{find_local_file, run_cmd};
+
+/// Repacks the stock boot.img with the freshly built kernel using
+/// magiskboot. Returns the boot.img (and boot.img.lz4) written to
+/// `artifacts_dir` as `<stem>-boot.img`, signed when `avb` is set.
+pub fn repack_boot_image(
+    cfg: &BootImageConfig,
+    avb: Option<&AvbConfig>,
+    kernel_image: &Path,
+    kernel_source: &Path,
+    work_dir: &Path,
+    artifacts_dir: &Path,
+    stem: &str,
+) -> Result<Vec<PathBuf>> {
+    if work_dir.exists() {
+        fs::remove_dir_all(work_dir)?;
+    }
+    fs::create_dir_all(work_dir)?;
+
+    let stock = work_dir.join("boot.img");
+    if cfg.stock.starts_with("http://") || cfg.stock.starts_with("https://") {
+        info!("Downloading stock boot image...");
+        retry::run("stock boot image download", || {
+            run_cmd(
+                &["curl", "-fL", "-o", &stock.to_string_lossy(), &cfg.stock],
+                None,
+                false,
+            )
+        })?;
+    } else {
+        fs::copy(find_local_file(&cfg.stock, kernel_source)?, &stock)?;
+    }
+
+    let magiskboot = cfg.magiskboot.as_deref().unwrap_or("magiskboot");
+    info!("Unpacking stock boot image...");
+    run_cmd(&[magiskboot, "unpack", "boot.img"], Some(work_dir), false)?;
+    if !work_dir.join("kernel").exists() {
+        return Err(anyhow!("magiskboot found no kernel in {}", cfg.stock));
+    }
+    fs::copy(kernel_image, work_dir.join("kernel"))?;
+
+    info!("Repacking boot image...");
+    run_cmd(
+        &[magiskboot, "repack", "boot.img", "new-boot.img"],
+        Some(work_dir),
+        false,
+    )?;
+
+    let boot_img = artifacts_dir.join(format!("{}-boot.img", stem));
+    fs::copy(work_dir.join("new-boot.img"), &boot_img)?;
+    if let Some(avb) = avb {
+        sign_image(avb, &boot_img, kernel_source)?;
+    }
+    let mut outputs = vec![boot_img.clone()];
+
+    if cfg.lz4 {
+        let lz4 = artifacts_dir.join(format!("{}-boot.img.lz4", stem));
+        run_cmd(
+            &[
+                "lz4",
+                "-B6",
+                "--content-size",
+                "-f",
+                &boot_img.to_string_lossy(),
+                &lz4.to_string_lossy(),
+            ],
+            None,
+            false,
+        )?;
+        outputs.push(lz4);
+    }
+
+    info!("Created {}", boot_img.display());
+    Ok(outputs)
+}
+
+fn sign_image(avb: &AvbConfig, image: &Path, kernel_source: &Path) -> Result<()> {
+    let key = find_local_file(&avb.key, kernel_source)?;
+    let algorithm = avb.algorithm.as_deref().unwrap_or("SHA256_RSA4096");
+    let partition_name = avb.partition_name.as_deref().unwrap_or("boot");
+    info!(
+        "Signing {} with {} ({})",
+        image.display(),
+        algorithm,
+        partition_name
+    );
+    run_cmd(
+        &[
+            avb.avbtool.as_deref().unwrap_or("avbtool"),
+            "add_hash_footer",
+            "--image",
+            &image.to_string_lossy(),
+            "--partition_name",
+            partition_name,
+            "--partition_size",
+            &avb.partition_size.to_string(),
+            "--key",
+            &key.to_string_lossy(),
+            "--algorithm",
+            algorithm,
+        ],
+        None,
+        false,
+    )?;
+    Ok(())
+}
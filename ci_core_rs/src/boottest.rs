@@ -0,0 +1,234 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::arch::Arch;
+use crate::config::BootTestConfig;
+use crate::error::CommandFailed;
+use crate::interrupt;
+use crate::utils::find_local_file;
+
+/// Printed by the kernel right before it runs init, so the boot got through
+/// every initcall.
+const DEFAULT_MARKER: &str = "Freeing unused kernel";
+
+/// Console lines that mean the boot failed, even if QEMU keeps running.
+const PANIC_MARKERS: &[&str] = &[
+    "Kernel panic - not syncing",
+    "Internal error: Oops",
+    "Unable to handle kernel",
+];
+
+/// Console lines kept for the failure report.
+const TAIL_LINES: usize = 40;
+
+/// QEMU binary, machine arguments and console device for an arch.
+fn machine(arch: &Arch) -> Result<(&'static str, &'static [&'static str], &'static str)> {
+    match arch.kernel_arch {
+        "arm64" => Ok((
+            "qemu-system-aarch64",
+            &["-M", "virt", "-cpu", "max"],
+            "ttyAMA0",
+        )),
+        "arm" => Ok(("qemu-system-arm", &["-M", "virt"], "ttyAMA0")),
+        "x86" => Ok(("qemu-system-x86_64", &["-M", "q35"], "ttyS0")),
+        "riscv" => Ok(("qemu-system-riscv64", &["-M", "virt"], "ttyS0")),
+        other => Err(anyhow!(
+            "boot_test doesn't know how to boot {} kernels",
+            other
+        )),
+    }
+}
+
+/// Boots `image` in QEMU and waits for the console to show the boot marker.
+/// Fails if the kernel panics, QEMU exits first or the marker doesn't show
+/// up in time. The console goes to `<work_dir>/boot-test.log`.
+pub fn run(
+    cfg: &BootTestConfig,
+    arch: &Arch,
+    image: &Path,
+    kernel_source: &Path,
+    work_dir: &Path,
+) -> Result<()> {
+    fs::create_dir_all(work_dir)?;
+    let initramfs = match &cfg.initramfs {
+        Some(path) => find_local_file(path, kernel_source)?,
+        None => write_initramfs(work_dir)?,
+    };
+    let (default_qemu, machine_args, console) = machine(arch)?;
+    let qemu = cfg.qemu.as_deref().unwrap_or(default_qemu);
+    let marker = cfg.marker.as_deref().unwrap_or(DEFAULT_MARKER);
+    let timeout = Duration::from_secs(cfg.timeout_seconds.unwrap_or(120));
+
+    let mut append = format!("console={} panic=-1 oops=panic", console);
+    if let Some(extra) = &cfg.cmdline {
+        append.push(' ');
+        append.push_str(extra);
+    }
+    let memory = cfg.memory_mib.unwrap_or(1024).to_string();
+    let mut args: Vec<String> = machine_args.iter().map(|a| a.to_string()).collect();
+    args.extend(
+        [
+            "-m",
+            &memory,
+            "-nographic",
+            "-no-reboot",
+            "-kernel",
+            &image.to_string_lossy(),
+            "-initrd",
+            &initramfs.to_string_lossy(),
+            "-append",
+            &append,
+        ]
+        .map(String::from),
+    );
+    args.extend(cfg.qemu_args.iter().flatten().cloned());
+    let command: Vec<&str> = std::iter::once(qemu)
+        .chain(args.iter().map(|a| a.as_str()))
+        .collect();
+
+    info!(
+        "Booting {} in {} (waiting up to {}s for \"{}\")",
+        image.display(),
+        qemu,
+        timeout.as_secs(),
+        marker
+    );
+    let mut child = Command::new(qemu)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start {}: {}", qemu, e))?;
+    let _tracked = interrupt::track(child.id() as i32);
+
+    let (tx, rx) = mpsc::channel();
+    for stream in [
+        child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn std::io::Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn std::io::Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    let mut log = File::create(work_dir.join("boot-test.log"))?;
+    let mut tail = VecDeque::new();
+    let deadline = Instant::now() + timeout;
+    let outcome = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let line = match rx.recv_timeout(left) {
+            Ok(line) => line,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                break Err(format!(
+                    "The kernel didn't reach \"{}\" within {}s",
+                    marker,
+                    timeout.as_secs()
+                ));
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                break Err(format!("QEMU exited before \"{}\"", marker));
+            }
+        };
+        writeln!(log, "{}", line)?;
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(line.clone());
+        if let Some(panic) = PANIC_MARKERS.iter().find(|p| line.contains(*p)) {
+            break Err(format!("The kernel failed to boot in QEMU ({})", panic));
+        }
+        if line.contains(marker) {
+            break Ok(());
+        }
+    };
+    // SAFETY: signals the process group of the QEMU we spawned.
+    unsafe { libc::kill(-(child.id() as i32), libc::SIGKILL) };
+    let _ = child.wait();
+    interrupt::check()?;
+
+    match outcome {
+        Ok(()) => {
+            info!("Boot test passed.");
+            Ok(())
+        }
+        Err(message) => Err(anyhow::Error::new(CommandFailed::new(
+            &command,
+            tail.into_iter().collect(),
+            None,
+        ))
+        .context(message)),
+    }
+}
+
+/// Writes an initramfs whose `/init` exists but can't run: enough for the
+/// kernel to skip mounting a root fs and go on to start init.
+fn write_initramfs(dir: &Path) -> Result<PathBuf> {
+    let mut cpio = Vec::new();
+    cpio_entry(&mut cpio, 1, "dev", 0o040755, (0, 0), b"");
+    cpio_entry(&mut cpio, 2, "dev/console", 0o020600, (5, 1), b"");
+    cpio_entry(&mut cpio, 3, "init", 0o100755, (0, 0), b"#!/bin/sh\n");
+    cpio_entry(&mut cpio, 0, "TRAILER!!!", 0, (0, 0), b"");
+    let path = dir.join("boot-test-initramfs.cpio");
+    fs::write(&path, cpio)?;
+    Ok(path)
+}
+
+/// Appends one entry in the `newc` format the kernel unpacks.
+fn cpio_entry(out: &mut Vec<u8>, ino: u32, name: &str, mode: u32, rdev: (u32, u32), data: &[u8]) {
+    let fields = [
+        ino,
+        mode,
+        0,
+        0,
+        1,
+        0,
+        data.len() as u32,
+        0,
+        0,
+        rdev.0,
+        rdev.1,
+        name.len() as u32 + 1,
+        0,
+    ];
+    out.extend_from_slice(b"070701");
+    for field in fields {
+        out.extend_from_slice(format!("{:08x}", field).as_bytes());
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    pad(out);
+    out.extend_from_slice(data);
+    pad(out);
+}
+
+fn pad(out: &mut Vec<u8>) {
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+}
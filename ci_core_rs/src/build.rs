@@ -1,65 +1,566 @@
-use anyhow::{Result, anyhow};
-use chrono::Local;
-use std::collections::HashMap;
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, Utc};
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs;
+use std::io::{self, IsTerminal};
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
 
-use crate::config::ProjectConfig;
-use crate::utils::{handle_notify, load_projects, run_cmd, run_cmd_with_env};
+use crate::abi;
+use crate::analyze;
+use crate::arch::{Arch, resolve_arch};
+use crate::artifacts::{
+    AK3_EXCLUDES, build_dt_images, collect_targets, deterministic_zip, package_modules,
+};
+use crate::bloat;
+use crate::bootimg::repack_boot_image;
+use crate::boottest;
+use crate::changelog::release_notes;
+use crate::checkpoint::{Checkpoint, ResumeStage};
+use crate::compiler_cache::{CacheStats, CompilerCache, configure_distributed};
+use crate::config::{KsuVariant, ProjectConfig, ProjectsMap};
+use crate::doctor::{MIN_DISK_GIB, check_free_space};
+use crate::error::BuildError;
+use crate::events::{self, Event, StageTime};
+use crate::fingerprint;
+use crate::gha;
+use crate::history::{self, Artifact, BuildMetrics, BuildRecord};
+use crate::interrupt;
+use crate::kconfig::{
+    ConfigEntry, config_args, diff_configs, dropped_entries, merge_into, parse_fragment,
+    parse_spec, render_entry,
+};
+use crate::ksu::{
+    canonical_variant_name, config_fragment, integrate, integration_patches, load_ksu_variants,
+    resolve_variant, variant_label, verify_config, verify_sources,
+};
+use crate::kunit::{self, KunitResults};
+use crate::manifest::sync_manifest;
+use crate::metrics::{self, BuildSample};
+use crate::modsign;
+use crate::notify::{FailureEvent, ReleaseEvent, notify_failure, notify_release};
+use crate::output;
+use crate::patch::{apply, apply_patches, check_patches, checkpatch, directory_patches};
+use crate::plan::print_plan;
+use crate::progress::MakeProgress;
+use crate::provenance;
+use crate::release::{Release, ReleaseArgs, publish};
+use crate::retry;
+use crate::secrets;
+use crate::sign::sign_files;
+use crate::snapshot::Snapshot;
+use crate::timeout::{self, Timeouts};
+use crate::toolchain::{
+    ResolvedToolchain, cached_toolchain, check_lock, setup_toolchain, sha256_file,
+};
+use crate::trace;
+use crate::upload::upload_artifacts;
+use crate::utils::{
+    apply_branch_override, expand_entry, find_local_file, format_duration, get_cache_dir,
+    get_workspace_dir, git_reference_args, load_globals, load_projects, run_cmd, run_cmd_with_env,
+};
+use crate::warnings::{self, Warning, new_since};
 
-pub fn handle_build(project_key: String, branch: String, do_release: bool) -> Result<()> {
+/// `--jobs`/`--load-average`/`--nice`; each overrides the project's setting.
+#[derive(clap::Args, Clone, Default)]
+pub struct ResourceArgs {
+    /// Parallel make jobs (default: nproc).
+    #[arg(long)]
+    pub jobs: Option<u32>,
+    /// Don't start new make jobs while the load average is above this.
+    #[arg(long)]
+    pub load_average: Option<f64>,
+    /// Run the build at this niceness.
+    #[arg(long, allow_negative_numbers = true)]
+    pub nice: Option<i32>,
+}
+
+/// The `localversion` file a build wrote; the file's previous contents are
+/// put back when this drops, whether the build finished or not.
+struct LocalversionFile {
+    path: PathBuf,
+    previous: Option<Vec<u8>>,
+}
+
+impl LocalversionFile {
+    fn write(path: PathBuf, contents: &str) -> Result<Self> {
+        let previous = fs::read(&path).ok();
+        fs::write(&path, contents)?;
+        Ok(LocalversionFile { path, previous })
+    }
+}
+
+impl Drop for LocalversionFile {
+    fn drop(&mut self) {
+        let restored = match &self.previous {
+            Some(contents) => fs::write(&self.path, contents),
+            None => fs::remove_file(&self.path),
+        };
+        if let Err(e) = restored {
+            warn!("Failed to restore {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Files the package stage is writing. Unless `keep` is called they are
+/// removed on drop, so a failed or interrupted stage leaves no half-written
+/// zip behind.
+struct PartialArtifacts {
+    paths: Vec<PathBuf>,
+    armed: bool,
+}
+
+impl PartialArtifacts {
+    fn keep(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for PartialArtifacts {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        for path in &self.paths {
+            if path.exists() {
+                info!("Removing partial {}", path.display());
+                if let Err(e) = fs::remove_file(path) {
+                    warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+/// What a finished build reports back to the summaries.
+#[derive(Default)]
+struct BuildReport {
+    cache: Option<(CompilerCache, CacheStats)>,
+    kernel_version: Option<String>,
+    artifacts: Vec<Artifact>,
+    metrics: BuildMetrics,
+    kunit: Option<KunitResults>,
+    /// The build was skipped because nothing changed since this one.
+    up_to_date: Option<i64>,
+    /// The tag the build was released under.
+    release_tag: Option<String>,
+    /// The whole kernel was compiled, so its warnings are all there are:
+    /// not an `--incremental` build or one resumed after the compile.
+    full_compile: bool,
+}
+
+impl BuildReport {
+    fn summary_suffix(&self) -> String {
+        if let Some(id) = self.up_to_date {
+            return format!("  up to date (#{})", id);
+        }
+        let mut suffix = match &self.cache {
+            Some((cache, stats)) => format!("  {} {:.1}%", cache.name(), stats.hit_rate()),
+            None => String::new(),
+        };
+        if let Some(kunit) = &self.kunit {
+            suffix.push_str(&format!(
+                "  kunit {}/{}",
+                kunit.passed,
+                kunit.passed + kunit.failed
+            ));
+        }
+        suffix
+    }
+
+    fn cache_line(&self) -> Option<String> {
+        self.cache
+            .map(|(cache, stats)| format!("{}: {}", cache.name(), stats))
+    }
+}
+
+pub struct BuildOptions {
+    pub do_release: bool,
+    pub refresh_toolchain: bool,
+    pub locked: bool,
+    pub check_patches: bool,
+    pub keep_source: bool,
+    pub out_dir: Option<PathBuf>,
+    pub incremental: bool,
+    pub dry_run: bool,
+    pub resume_from: Option<ResumeStage>,
+    /// Build even when nothing changed since the last successful build.
+    pub force: bool,
+    pub resources: ResourceArgs,
+    pub release: ReleaseArgs,
+}
+
+pub fn handle_build(project_key: String, branch: String, opts: BuildOptions) -> Result<()> {
     let projects = load_projects()?;
-    let proj_val = projects
-        .get(&project_key)
-        .ok_or_else(|| anyhow!("Project not found"))?;
-    let proj: ProjectConfig = serde_json::from_value(proj_val.clone())?;
+    let proj = load_branch_config(&projects, &project_key, &branch)?;
+    let kernel_source_path = kernel_source_dir(&proj)?;
 
-    let kernel_source_path = PathBuf::from("kernel_source");
-    if !kernel_source_path.exists() {
-        return Err(anyhow!("Kernel source not found at ./kernel_source"));
+    if opts.check_patches {
+        return check_only(&projects, &project_key, &proj, &branch, &kernel_source_path);
+    }
+    if opts.dry_run {
+        return print_plan(
+            &projects,
+            &project_key,
+            &branch,
+            &proj,
+            &kernel_source_path,
+            &opts,
+            "out",
+        );
     }
 
-    // 1. Toolchain Setup
-    if let Some(urls) = &proj.toolchain_urls {
-        let tc_download_dir = PathBuf::from("toolchain_download");
+    build_branch(
+        &projects,
+        &project_key,
+        &branch,
+        &kernel_source_path,
+        &opts,
+        "out",
+        false,
+    )
+    .map(|_| ())
+}
+
+/// Builds several variants of one project in a row. Each variant gets its
+/// own `out-<variant>` dir and zip, and kernel_source is rolled back to its
+/// snapshot between variants so their integrations don't mix.
+pub fn handle_build_matrix(
+    project_key: String,
+    branches: Vec<String>,
+    opts: BuildOptions,
+) -> Result<()> {
+    if opts.keep_source {
+        return Err(anyhow!(
+            "--keep-source can't be used with --branches, variants would share one patched tree"
+        ));
+    }
+    let projects = load_projects()?;
+    let first = branches.first().map_or("", |b| b.as_str());
+    let kernel_source_path =
+        kernel_source_dir(&load_branch_config(&projects, &project_key, first)?)?;
+    let ksu_variants = load_ksu_variants(&projects)?;
 
-        if tc_download_dir.exists() {
-            fs::remove_dir_all(&tc_download_dir)?;
+    let mut results = Vec::new();
+    for (i, branch) in branches.iter().enumerate() {
+        info!(
+            "\n=== [{}/{}] {} / {} ===",
+            i + 1,
+            branches.len(),
+            project_key,
+            branch
+        );
+        let out_dir = format!("out-{}", canonical_variant_name(&ksu_variants, branch));
+        let result = if opts.check_patches {
+            load_branch_config(&projects, &project_key, branch)
+                .and_then(|proj| {
+                    check_only(&projects, &project_key, &proj, branch, &kernel_source_path)
+                })
+                .map(|_| BuildReport::default())
+        } else if opts.dry_run {
+            load_branch_config(&projects, &project_key, branch)
+                .and_then(|proj| {
+                    print_plan(
+                        &projects,
+                        &project_key,
+                        branch,
+                        &proj,
+                        &kernel_source_path,
+                        &opts,
+                        &out_dir,
+                    )
+                })
+                .map(|_| BuildReport::default())
+        } else {
+            build_branch(
+                &projects,
+                &project_key,
+                branch,
+                &kernel_source_path,
+                &opts,
+                &out_dir,
+                true,
+            )
+        };
+        if let Err(e) = &result {
+            info!("❌ {} failed: {:#}", branch, e);
+        }
+        results.push((branch, result));
+        if interrupt::interrupted() {
+            break;
+        }
+    }
+
+    info!("\n=== Build summary for {} ===", project_key);
+    for (branch, result) in &results {
+        match result {
+            Ok(report) => info!("✅ {}{}", branch, report.summary_suffix()),
+            Err(_) => info!("❌ {}", branch),
         }
-        fs::create_dir_all(&tc_download_dir)?;
+    }
+    let errors: Vec<&anyhow::Error> = results
+        .iter()
+        .filter_map(|(_, r)| r.as_ref().err())
+        .collect();
+    if !errors.is_empty() {
+        let message = format!("{} of {} variant(s) failed", errors.len(), results.len());
+        return Err(BuildError::summary(errors, message));
+    }
+    Ok(())
+}
 
-        for url in urls {
-            println!("Downloading toolchain: {}", url);
-            run_cmd(&["wget", "-q", url], Some(&tc_download_dir), false)?;
+/// Builds one branch of several projects in a row. Each project's kernel
+/// source lives in the workspace (see `fetch_source` for how `source_ref`
+/// picks what is built) and is rolled back after its build, and
+/// projects with the same `toolchain_urls` share one toolchain setup.
+pub fn handle_build_all(
+    project_keys: Vec<String>,
+    branch: String,
+    source_ref: Option<String>,
+    opts: BuildOptions,
+) -> Result<()> {
+    let projects = load_projects()?;
+    let mut keys = project_keys;
+    if keys.is_empty() {
+        keys = projects
+            .keys()
+            .filter(|k| !k.starts_with('_'))
+            .cloned()
+            .collect();
+        keys.sort();
+    }
+
+    // Set each distinct toolchain up once; the builds then hit the cache.
+    let mut seen_toolchains: Vec<Option<Vec<String>>> = Vec::new();
+    for key in &keys {
+        let proj = load_branch_config(&projects, key, &branch)?;
+        if seen_toolchains.contains(&proj.toolchain_urls) {
+            continue;
+        }
+        let sharing: Vec<&str> = keys
+            .iter()
+            .filter(|k| {
+                load_branch_config(&projects, k, &branch)
+                    .is_ok_and(|p| p.toolchain_urls == proj.toolchain_urls)
+            })
+            .map(|k| k.as_str())
+            .collect();
+        info!("Toolchain for {}", sharing.join(", "));
+        let workspace = opts.out_dir.clone().unwrap_or_else(get_workspace_dir);
+        check_disk_space(&proj, &[&workspace, &get_cache_dir()])?;
+        setup_toolchain(&proj, opts.refresh_toolchain)?;
+        seen_toolchains.push(proj.toolchain_urls);
+    }
+
+    let workspace = get_workspace_dir();
+    let mut results = Vec::new();
+    for (i, key) in keys.iter().enumerate() {
+        info!("\n=== [{}/{}] {} / {} ===", i + 1, keys.len(), key, branch);
+        let started = Instant::now();
+        let checkout = workspace.join(key);
+        let build_opts = BuildOptions {
+            do_release: opts.do_release,
+            refresh_toolchain: false,
+            locked: opts.locked,
+            check_patches: false,
+            keep_source: false,
+            out_dir: opts.out_dir.as_ref().map(|d| d.join(key)),
+            incremental: false,
+            dry_run: false,
+            resume_from: None,
+            force: opts.force,
+            resources: opts.resources.clone(),
+            release: opts.release.clone(),
+        };
+        let result = load_branch_config(&projects, key, &branch)
+            .and_then(|proj| match &proj.source_manifest {
+                Some(manifest) => {
+                    sync_manifest(manifest, proj.git_reference_dir.as_deref(), &checkout)
+                }
+                None => fetch_source(&proj, &checkout, source_ref.as_deref())
+                    .map(|_| checkout.clone()),
+            })
+            .and_then(|kernel_source_path| {
+                // Rolled back so the next update finds a clean checkout.
+                build_branch(
+                    &projects,
+                    key,
+                    &branch,
+                    &kernel_source_path,
+                    &build_opts,
+                    "out",
+                    true,
+                )
+            });
+        if let Err(e) = &result {
+            info!("❌ {} failed: {:#}", key, e);
+        }
+        results.push((key, started.elapsed(), result));
+        if interrupt::interrupted() {
+            break;
+        }
+    }
+
+    info!("\n=== Build summary ({}) ===", branch);
+    let width = keys.iter().map(|k| k.len()).max().unwrap_or(0);
+    for (key, elapsed, result) in &results {
+        let secs = elapsed.as_secs();
+        let time = format!("{:>3}m{:02}s", secs / 60, secs % 60);
+        match result {
+            Ok(report) => info!("✅ {:<width$}  {}{}", key, time, report.summary_suffix()),
+            Err(e) => info!(
+                "❌ {:<width$}  {}  {}",
+                key,
+                time,
+                e.to_string().lines().next().unwrap_or_default()
+            ),
         }
+    }
+    let errors: Vec<&anyhow::Error> = results
+        .iter()
+        .filter_map(|(_, _, r)| r.as_ref().err())
+        .collect();
+    if !errors.is_empty() {
+        let message = format!("{} of {} project(s) failed", errors.len(), results.len());
+        return Err(BuildError::summary(errors, message));
+    }
+    Ok(())
+}
 
-        println!("Extracting toolchain...");
-        let extract_script = r#"
-            set -e
-            if ls *.tar.gz.[0-9]* 1> /dev/null 2>&1; then
-                cat *.tar.gz.* | tar -zxf - --warning=no-unknown-keyword -C ..
-            elif ls *part_aa* 1> /dev/null 2>&1 || ls *_aa.tar.gz 1> /dev/null 2>&1 || ls *.tar.gz.aa 1> /dev/null 2>&1; then
-                cat *.tar.gz | tar -zxf - --warning=no-unknown-keyword -C ..
-            elif ls *.tar.gz 1> /dev/null 2>&1; then
-                for tarball in *.tar.gz; do
-                    tar -zxf "$tarball" --warning=no-unknown-keyword -C ..
-                done
-            fi
-        "#;
+/// Clones the kernel source into `dest` at `source_branch`, else
+/// `source_ref`, else main, if it is missing. With `source_repo` or
+/// `source_ref` set, an existing clean checkout is updated to the latest
+/// commit of that ref instead of being used as-is.
+fn fetch_source(proj: &ProjectConfig, dest: &Path, source_ref: Option<&str>) -> Result<()> {
+    let repo = proj.source_repo.as_deref().unwrap_or(&proj.repo);
+    let git_ref = proj
+        .source_branch
+        .as_deref()
+        .or(source_ref)
+        .unwrap_or("main");
+    let depth = proj.source_depth.unwrap_or(1);
+    let depth_arg = format!("--depth={}", depth);
 
+    if dest.join(".git").exists() {
+        if proj.source_repo.is_none() && source_ref.is_none() {
+            info!("Using existing kernel source at {:?}", dest);
+            return Ok(());
+        }
+        let status = run_cmd(
+            &["git", "status", "--porcelain", "--untracked-files=no"],
+            Some(dest),
+            true,
+        )?;
+        if !status.unwrap_or_default().is_empty() {
+            warn!(
+                "{:?} has local changes, building it without updating.",
+                dest
+            );
+            return Ok(());
+        }
+        info!("Updating {:?} to {}@{}", dest, repo, git_ref);
+        let mut fetch = vec!["git", "fetch"];
+        if depth > 0 {
+            fetch.push(&depth_arg);
+        }
+        let url = source_url(repo);
+        fetch.extend([url.as_str(), git_ref]);
+        retry::run("git fetch", || run_cmd(&fetch, Some(dest), false))?;
         run_cmd(
-            &["bash", "-c", extract_script],
-            Some(&tc_download_dir),
+            &["git", "checkout", "--detach", "FETCH_HEAD"],
+            Some(dest),
             false,
         )?;
+        retry::run("submodule update", || {
+            run_cmd(
+                &["git", "submodule", "update", "--init", "--recursive"],
+                Some(dest),
+                false,
+            )
+        })?;
+        return Ok(());
+    }
 
-        fs::remove_dir_all(tc_download_dir)?;
+    info!("Cloning {}@{} into {:?}", repo, git_ref, dest);
+    let existed = dest.exists();
+    let url = source_url(repo);
+    let dest_str = dest.to_string_lossy();
+    let reference = git_reference_args(proj.git_reference_dir.as_deref(), repo);
+    let mut clone = vec!["git", "clone", "--recurse-submodules", "-b", git_ref];
+    if depth > 0 {
+        clone.extend([depth_arg.as_str(), "--shallow-submodules"]);
     }
+    clone.extend(reference.iter().map(|a| a.as_str()));
+    clone.extend([url.as_str(), &dest_str]);
+    retry::run("git clone", || {
+        // Start over from an empty directory after a failed attempt.
+        if !existed && dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        run_cmd(&clone, None, false)
+    })?;
+    Ok(())
+}
 
-    // 2. Prepare Environment Variables
+/// URLs and local paths are used as-is; `owner/name` means GitHub, with GH_TOKEN if set.
+pub fn source_url(repo: &str) -> String {
+    if repo.contains("://") || repo.starts_with("git@") || Path::new(repo).exists() {
+        return repo.to_string();
+    }
+    match secrets::find("GH_TOKEN") {
+        Some(token) => format!("https://{}@github.com/{}.git", token, repo),
+        None => format!("https://github.com/{}.git", repo),
+    }
+}
+
+/// `./kernel_source`, fetched first when the project has a `source_repo`.
+/// With `source_manifest` it is a repo checkout and the kernel lives in its
+/// `kernel_dir`.
+pub fn kernel_source_dir(proj: &ProjectConfig) -> Result<PathBuf> {
+    let kernel_source_path = PathBuf::from("kernel_source");
+    if let Some(manifest) = &proj.source_manifest {
+        if proj.source_repo.is_some() {
+            return Err(anyhow!("source_repo and source_manifest can't both be set"));
+        }
+        return sync_manifest(
+            manifest,
+            proj.git_reference_dir.as_deref(),
+            &kernel_source_path,
+        );
+    }
+    if proj.source_repo.is_some() {
+        fetch_source(proj, &kernel_source_path, None)?;
+    }
+    if !kernel_source_path.exists() {
+        return Err(anyhow!(
+            "Kernel source not found at ./kernel_source (set source_repo to fetch it)"
+        ));
+    }
+    Ok(kernel_source_path)
+}
+
+/// Where `kernel_source_dir` leaves the kernel, without fetching it.
+pub fn kernel_source_location(proj: &ProjectConfig) -> PathBuf {
+    let kernel_source_path = PathBuf::from("kernel_source");
+    match proj.source_manifest.as_ref().and_then(|m| m.kernel_dir.as_deref()) {
+        Some(kernel_dir) => kernel_source_path.join(kernel_dir),
+        None => kernel_source_path,
+    }
+}
+
+/// The environment make runs in: PATH with the toolchain's bin dirs first,
+/// ARCH and CROSS_COMPILE, and the host flags `extra_host_env` asks for.
+pub fn toolchain_env(
+    proj: &ProjectConfig,
+    toolchain: &ResolvedToolchain,
+    arch: &Arch,
+    use_gcc: bool,
+) -> HashMap<String, String> {
     let toolchain_prefix = proj.toolchain_path_prefix.as_deref().unwrap_or("");
-    let toolchain_base = env::current_dir()?.join(toolchain_prefix);
+    let toolchain_base = toolchain.root.join(toolchain_prefix);
 
     let mut build_env = HashMap::new();
     let current_path = env::var("PATH").unwrap_or_default();
@@ -76,16 +577,14 @@ pub fn handle_build(project_key: String, branch: String, do_release: bool) -> Re
     }
 
     build_env.insert("PATH".to_string(), new_path);
-    build_env.insert("ARCH".to_string(), "arm64".to_string());
-    build_env.insert("CLANG_TRIPLE".to_string(), "aarch64-linux-gnu-".to_string());
-    build_env.insert(
-        "CROSS_COMPILE".to_string(),
-        "aarch64-linux-gnu-".to_string(),
-    );
-    build_env.insert(
-        "CROSS_COMPILE_COMPAT".to_string(),
-        "arm-linux-gnueabi-".to_string(),
-    );
+    build_env.insert("ARCH".to_string(), arch.kernel_arch.to_string());
+    if !use_gcc {
+        build_env.insert("CLANG_TRIPLE".to_string(), arch.cross_compile.to_string());
+    }
+    build_env.insert("CROSS_COMPILE".to_string(), arch.cross_compile.to_string());
+    if let Some(compat) = arch.cross_compile_compat {
+        build_env.insert("CROSS_COMPILE_COMPAT".to_string(), compat.to_string());
+    }
 
     if let Some(true) = proj.extra_host_env {
         let kbt = toolchain_base.join("kernel-build-tools/linux-x86");
@@ -116,163 +615,1086 @@ pub fn handle_build(project_key: String, branch: String, do_release: bool) -> Re
             format!("{}{}", sysroot_flag, ldflags),
         );
     }
+    build_env
+}
 
-    // ---------------------------------------------------------------------
-    // 3. KernelSU Integration (MODIFIED FOR WILDKSU)
-    // ---------------------------------------------------------------------
-    if branch == "wildksu" {
-        println!("Starting WildKSU + SUSFS + Manual Hook Integration");
+pub fn load_branch_config(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+) -> Result<ProjectConfig> {
+    let proj_val = projects
+        .get(project_key)
+        .ok_or_else(|| anyhow!("Project not found"))?;
+    let proj_val = expand_entry(project_key, &apply_branch_override(proj_val, branch))?;
+    Ok(serde_json::from_value(proj_val)?)
+}
 
-        // A. Install WildKSU
-        // Note: Using 'main' as argument per your script logic (bash -s wild)
-        // Adjust the setup script URL if needed (using WildKernels URL from your snippet)
-        let wild_setup = "curl -LSs 'https://raw.githubusercontent.com/WildKernels/Wild_KSU/wild/kernel/setup.sh' | bash -s wild";
-        run_cmd(&["bash", "-c", wild_setup], Some(&kernel_source_path), false)?;
+/// Runs the whole pipeline for one branch with build output in `out_dir`.
+/// With `restore_source` the kernel source is rolled back even on success.
+/// Failed release and unattended builds (CI, `serve`, cron: no terminal on
+/// stdin) are reported through the project's notifiers, with the failing
+/// stage's log from `logs/` attached where they can.
+fn build_branch(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+    kernel_source_path: &Path,
+    opts: &BuildOptions,
+    out_dir: &str,
+    restore_source: bool,
+) -> Result<BuildReport> {
+    // A resumed build finishes one that was already fingerprinted.
+    let fingerprint = if opts.resume_from.is_none() {
+        fingerprint::compute(
+            projects,
+            project_key,
+            branch,
+            kernel_source_path,
+            opts.do_release,
+        )
+        .inspect_err(|e| {
+            warn!(
+                "Can't tell if anything changed since the last build: {:#}",
+                e
+            )
+        })
+        .ok()
+    } else {
+        None
+    };
+    if !opts.force
+        && let Some(fingerprint) = &fingerprint
+        && let Ok(Some((id, last))) = history::last_successful_fingerprint(project_key, branch)
+        && *fingerprint == last
+        // Nothing to skip to once `clean` or a new out dir lost its output.
+        && history::artifacts(id).is_ok_and(|a| a.iter().all(|a| a.path.exists()))
+    {
+        info!(
+            "✅ {} / {} is up to date: source, patches, config and toolchain are unchanged since build #{} (--force builds anyway).",
+            project_key, branch, id
+        );
+        if gha::active()
+            && let Err(e) = gha::add_summary(&format!(
+                "### ✅ {} / {}\n\nUp to date: nothing changed since build #{}.\n\n",
+                project_key, branch, id
+            ))
+        {
+            warn!("Failed to write the job summary: {:#}", e);
+        }
+        return Ok(BuildReport {
+            up_to_date: Some(id),
+            ..Default::default()
+        });
+    }
 
-        // B. Clone SUSFS (Using shallow clone depth=1)
-        println!("   - Cloning SUSFS...");
-        let susfs_url = "https://gitlab.com/simonpunk/susfs4ksu.git";
-        let susfs_branch = "gki-android13-5.15"; // You can make this dynamic if needed
-        run_cmd(
-            &["git", "clone", "-b", susfs_branch, "--depth=1", susfs_url, "susfs4ksu"],
-            Some(&kernel_source_path),
-            false,
-        )?;
+    output::reset();
+    timeout::reset();
+    output::start_logs(project_key, branch);
+    events::emit(Event::BuildStart {
+        project: project_key,
+        branch,
+    });
+    trace::begin_build(project_key, branch);
+    let started_at = Local::now();
+    let started = Instant::now();
+    let commit = run_cmd(
+        &["git", "rev-parse", "HEAD"],
+        Some(kernel_source_path),
+        true,
+    )
+    .ok()
+    .flatten()
+    .map(|sha| sha.trim().to_string());
+    let mut result = run_pipeline(
+        projects,
+        project_key,
+        branch,
+        kernel_source_path,
+        opts,
+        out_dir,
+        restore_source,
+    )
+    .map_err(|e| BuildError::in_stage(e, timeout::current_stage()));
+    let stage_log = output::close_log();
+    if let Some(dir) = output::log_dir() {
+        info!("Build logs: {}", dir.display());
+    }
+    let total = started.elapsed();
+    let timings = timeout::stage_timings();
+    print_timings(project_key, branch, &timings, total);
+    let error = result
+        .as_ref()
+        .err()
+        .map(|e| secrets::redact(&format!("{:#}", e)).into_owned());
+    events::emit(Event::BuildEnd {
+        project: project_key,
+        branch,
+        ok: result.is_ok(),
+        duration_ms: total.as_millis(),
+        stages: StageTime::list(&timings),
+        error: error.clone(),
+        error_kind: result.as_ref().err().map(|e| e.kind.name()),
+    });
+    trace::end_build(error.as_deref());
+    let mut metrics = result
+        .as_mut()
+        .map(|r| std::mem::take(&mut r.metrics))
+        .unwrap_or_default();
+    let warning_list = output::warnings();
+    metrics.warnings = warnings::counts(&warning_list);
+    let record = BuildRecord {
+        project: project_key.to_string(),
+        branch: branch.to_string(),
+        started_at,
+        duration: total,
+        error: error.clone(),
+        failed_stage: result.as_ref().err().and_then(|e| e.stage.clone()),
+        kernel_version: result.as_ref().ok().and_then(|r| r.kernel_version.clone()),
+        commit,
+        stages: timings,
+        artifacts: result
+            .as_mut()
+            .map(|r| std::mem::take(&mut r.artifacts))
+            .unwrap_or_default(),
+        metrics,
+        warnings: warning_list,
+        full_compile: result.as_ref().is_ok_and(|r| r.full_compile),
+        fingerprint,
+    };
+    let id = match history::record(&record) {
+        Ok(id) => {
+            info!("Recorded as build #{}", id);
+            Some(id)
+        }
+        Err(e) => {
+            warn!("Failed to record build history: {:#}", e);
+            None
+        }
+    };
+    if gha::active() {
+        let release_tag = result.as_ref().ok().and_then(|r| r.release_tag.as_deref());
+        if let Err(e) = gha::report_build(&record, id, release_tag, &output::failed_output()) {
+            warn!("Failed to report the build to GitHub Actions: {:#}", e);
+        }
+    }
+    metrics::push(
+        load_globals(projects).metrics.as_ref(),
+        &BuildSample {
+            project: project_key,
+            branch,
+            ok: result.is_ok(),
+            duration: total,
+            stages: &record.stages,
+            cache_hit_rate: result
+                .as_ref()
+                .ok()
+                .and_then(|r| r.cache.map(|(_, stats)| stats.hit_rate())),
+            artifacts: &record.artifacts,
+            image_size: record.metrics.image_size,
+            warnings: record.warnings.len(),
+            totals: history::result_counts(project_key, branch).ok(),
+        },
+    );
+    if let Err(e) = &result
+        && (opts.do_release || !io::stdin().is_terminal())
+        && !interrupt::interrupted()
+    {
+        report_failure(projects, project_key, branch, e, stage_log.as_deref());
+    }
+    Ok(result?)
+}
 
-        // C. Apply SUSFS Patches
-        println!("   - Applying SUSFS patches...");
-        
-        // Copy patch files
-        let cp_patch_cmd = format!("cp susfs4ksu/kernel_patches/50_add_susfs_in_{}.patch .", susfs_branch);
-        run_cmd(&["bash", "-c", &cp_patch_cmd], Some(&kernel_source_path), false)?;
-        
-        // Copy fs files
-        run_cmd(&["bash", "-c", "cp -rv susfs4ksu/kernel_patches/fs/* fs/"], Some(&kernel_source_path), false)?;
-        
-        // Copy include files
-        run_cmd(&["bash", "-c", "cp -rv susfs4ksu/kernel_patches/include/linux/* include/linux/"], Some(&kernel_source_path), false)?;
-
-        // Apply the main patch
-        let patch_cmd = format!("patch -p1 --fuzz=3 < 50_add_susfs_in_{}.patch", susfs_branch);
-        run_cmd(&["bash", "-c", &patch_cmd], Some(&kernel_source_path), false)?;
-
-        // D. Apply Manual Hook 1.6
-        println!("   - Applying Manual Hook v1.6...");
-        let hook_url = "https://github.com/SukiSU-Ultra/SukiSU_patch/raw/83aa64b7548890bb1f2eff6c990c03a1802df27b/hooks/scope_min_manual_hooks_v1.6.patch";
-        run_cmd(&["curl", "-L", "-o", "manual-hook.patch", hook_url], Some(&kernel_source_path), false)?;
-        run_cmd(&["bash", "-c", "patch -p1 --fuzz=3 < manual-hook.patch"], Some(&kernel_source_path), false)?;
-
-        // E. Fix Compilation Error in fs/namespace.c
-        // PROBLEM: The patch applied to a wrong function (approx line 3808) where variables are missing.
-        // SOLUTION: Remove the bad lines and inject the logic into 'copy_mnt_ns' where 'copy_flags' exists.
-        println!("   - Relocating Manual Hook to correct function...");
-
-        // 1. Delete the misplaced 'if (flags & CLONE_NEWNS)' line
-        run_cmd(
-            &["sed", "-i", "/if (flags & CLONE_NEWNS)/d", "fs/namespace.c"],
-            Some(&kernel_source_path),
-            false,
-        )?;
+fn print_timings(project_key: &str, branch: &str, timings: &[(String, Duration)], total: Duration) {
+    if timings.is_empty() {
+        return;
+    }
+    info!("\n=== Stage timings for {} / {} ===", project_key, branch);
+    let width = timings
+        .iter()
+        .map(|(s, _)| s.len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    for (stage, took) in timings {
+        info!("  {:<width$}  {:>7}", stage, format_duration(*took));
+    }
+    info!("  {:<width$}  {:>7}", "total", format_duration(total));
+}
 
-        // 2. Delete the misplaced 'copy_flags |= ...' line
-        run_cmd(
-            &["sed", "-i", "/copy_flags |= CL_COPY_MNT_NS/d", "fs/namespace.c"],
-            Some(&kernel_source_path),
-            false,
-        )?;
+/// One line for release notifications, e.g. "12m04s (toolchain 3s, build 11m40s, ...)".
+fn timing_line(timings: &[(String, Duration)], total: Duration) -> String {
+    let stages: Vec<String> = timings
+        .iter()
+        .map(|(stage, took)| format!("{} {}", stage, format_duration(*took)))
+        .collect();
+    format!("{} ({})", format_duration(total), stages.join(", "))
+}
 
-        // 3. Inject the logic into the CORRECT place (inside copy_mnt_ns)
-        // We match a unique line known to be in copy_mnt_ns and append our logic using '&' (which means 'matched string').
-        // We use \\& to escape the ampersand for sed.
-        run_cmd(
-            &[
-                "sed", 
-                "-i", 
-                "s/copy_flags = CL_COPY_UNBINDABLE | CL_EXPIRE;/& if (flags \\& CLONE_NEWNS) copy_flags |= CL_COPY_MNT_NS;/", 
-                "fs/namespace.c"
-            ],
-            Some(&kernel_source_path),
-            false,
-        )?;
+/// Longest error text put in a failure report.
+const MAX_ERROR_CHARS: usize = 500;
 
-        // F. Adjust Configs (Disable Kprobes, Disable SUS_SU)
-        // We write to a temporary config fragment or append to defconfig
-        // Since we run 'make defconfig' later, we should append to the arch defconfig OR
-        // handle it in the .config step later. Here we append to defconfig as requested.
-        let defconfig_path = kernel_source_path.join(format!("arch/arm64/configs/{}", proj.defconfig));
-        
-        // Check if defconfig exists before appending
-        if defconfig_path.exists() {
-             let mut file = fs::OpenOptions::new().append(true).open(&defconfig_path)?;
-             use std::io::Write;
-             writeln!(file, "CONFIG_KSU_KPROBES_HOOK=n")?;
-             writeln!(file, "CONFIG_KSU_SUSFS_SUS_SU=n")?;
-        } else {
-            println!("⚠️ Warning: Defconfig not found at {:?}, skipping config append.", defconfig_path);
+fn report_failure(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+    failure: &BuildError,
+    log: Option<&Path>,
+) {
+    let Ok(proj) = load_branch_config(projects, project_key, branch) else {
+        return;
+    };
+    let mut error = secrets::redact(&format!("{:#}", failure.source))
+        .trim()
+        .to_string();
+    if let Some((cut, _)) = error.char_indices().nth(MAX_ERROR_CHARS) {
+        error.truncate(cut);
+        error.push('…');
+    }
+    let run_url = match (
+        env::var("GITHUB_SERVER_URL"),
+        env::var("GITHUB_REPOSITORY"),
+        env::var("GITHUB_RUN_ID"),
+    ) {
+        (Ok(server), Ok(repo), Ok(run)) => {
+            Some(format!("{}/{}/actions/runs/{}", server, repo, run))
         }
+        _ => None,
+    };
+    let command = failure
+        .command
+        .as_deref()
+        .map(|c| secrets::redact(c).into_owned());
+    let event = FailureEvent {
+        repo: &proj.repo,
+        project: project_key,
+        branch,
+        stage: failure.stage.as_deref(),
+        error: &error,
+        command: command.as_deref(),
+        tail: &failure.output,
+        run_url: run_url.as_deref(),
+        log,
+    };
+    if let Err(e) = notify_failure(&proj, &event) {
+        warn!("Failed to send failure notification: {}", e);
+    }
+}
 
+fn run_pipeline(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+    kernel_source_path: &Path,
+    opts: &BuildOptions,
+    out_dir: &str,
+    restore_source: bool,
+) -> Result<BuildReport> {
+    let proj = load_branch_config(projects, project_key, branch)?;
+    let arch = resolve_arch(proj.arch.as_deref())?;
+    let use_gcc = match proj.compiler.as_deref().unwrap_or("clang") {
+        "clang" => false,
+        "gcc" => true,
+        other => {
+            return Err(anyhow!(
+                "Unknown compiler '{}' (expected clang or gcc)",
+                other
+            ));
+        }
+    };
+    // With an output dir everything goes under it; otherwise the build dir
+    // stays inside kernel_source and artifacts land in the current dir.
+    let output_dir = match opts
+        .out_dir
+        .clone()
+        .or_else(|| proj.output_dir.as_ref().map(PathBuf::from))
+    {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            Some(fs::canonicalize(dir)?)
+        }
+        None => None,
+    };
+    let artifacts_dir = output_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let out_path = match &output_dir {
+        Some(dir) => dir.join(out_dir),
+        None => kernel_source_path.join(out_dir),
+    };
+    // make and scripts/config run inside kernel_source.
+    let out_arg = match &output_dir {
+        Some(_) => out_path.display().to_string(),
+        None => out_dir.to_string(),
+    };
+    let config_path = out_path.join(".config");
+    let commit = run_cmd(
+        &["git", "rev-parse", "HEAD"],
+        Some(kernel_source_path),
+        true,
+    )
+    .ok()
+    .flatten()
+    .map(|sha| sha.trim().to_string());
+    let mut checkpoint = match opts.resume_from {
+        Some(stage) => {
+            Checkpoint::resume(&out_path, project_key, branch, commit.as_deref(), stage)?
+        }
+        None => {
+            Checkpoint::clear(&out_path);
+            Checkpoint {
+                project: project_key.to_string(),
+                branch: branch.to_string(),
+                commit,
+                ..Default::default()
+            }
+        }
+    };
+    let build_started = Instant::now();
+    let started_on = Utc::now();
+    let timeouts = Timeouts::start(proj.timeout_minutes.as_ref());
+    retry::configure(proj.retry.as_ref());
+
+    if let Some(nice) = opts.resources.nice.or(proj.nice) {
+        set_niceness(nice);
+    }
+
+    let ksu_variants = load_ksu_variants(projects)?;
+    let variant = resolve_variant(&ksu_variants, branch);
+    let incremental = opts.incremental
+        && can_build_incrementally(
+            &proj,
+            variant.map(|(_, v)| v),
+            kernel_source_path,
+            &config_path,
+        );
+
+    check_disk_space(&proj, &[&out_path, &get_cache_dir()])?;
+    let source_date_epoch = match proj.reproducible {
+        Some(true) => Some(source_date_epoch(kernel_source_path)?),
+        _ => None,
+    };
+
+    // 1. Toolchain Setup
+    timeouts.stage("toolchain");
+    let toolchain = setup_toolchain(&proj, opts.refresh_toolchain)?;
+
+    // 2. Prepare Environment Variables
+    let mut build_env = toolchain_env(&proj, &toolchain, arch, use_gcc);
+    check_lock(
+        project_key,
+        &toolchain,
+        &build_env,
+        use_gcc.then_some(arch.cross_compile),
+        opts.locked,
+    )?;
+
+    let variant_suffix = variant_label(&ksu_variants, branch);
+    let localversion = format!("{}-{}", proj.localversion_base, variant_suffix);
+    let modules_staging = out_path.join("modules_install");
+
+    let (kernel_version, ksu_commit, mut report) = if opts.resume_from.is_some() {
+        info!(
+            "Resuming with the kernel the last build compiled ({})",
+            checkpoint.kernel_version
+        );
+        let report = BuildReport {
+            kernel_version: Some(checkpoint.kernel_version.clone()),
+            metrics: build_metrics(&out_path, arch, use_gcc, &build_env),
+            ..Default::default()
+        };
+        (
+            checkpoint.kernel_version.clone(),
+            checkpoint.ksu_commit.clone(),
+            report,
+        )
     } else {
-        // Standard Logic for other variants
-        let setup_url = match branch.as_str() {
-            "resukisu" => Some((
-                "https://raw.githubusercontent.com/ReSukiSU/ReSukiSU/main/kernel/setup.sh",
-                "builtin",
-            )),
-            "mksu" => Some((
-                "https://raw.githubusercontent.com/5ec1cff/KernelSU/main/kernel/setup.sh",
-                "-",
-            )),
-            "ksu" => Some((
-                "https://raw.githubusercontent.com/tiann/KernelSU/main/kernel/setup.sh",
-                "-",
-            )),
-            _ => None,
+        if let Some(epoch) = source_date_epoch {
+            let timestamp = DateTime::from_timestamp(epoch, 0)
+                .map(|t| t.format("%a %b %e %H:%M:%S UTC %Y").to_string())
+                .unwrap_or_default();
+            info!("Reproducible build, timestamp {}", timestamp);
+            build_env.insert("SOURCE_DATE_EPOCH".to_string(), epoch.to_string());
+            build_env.insert("KBUILD_BUILD_TIMESTAMP".to_string(), timestamp);
+            build_env.insert("KBUILD_BUILD_VERSION".to_string(), "1".to_string());
+            for (name, default) in [
+                ("KBUILD_BUILD_USER", "kokuban"),
+                ("KBUILD_BUILD_HOST", "kokuban-ci"),
+            ] {
+                if env::var(name).is_err() {
+                    build_env.insert(name.to_string(), default.to_string());
+                }
+            }
+        }
+
+        // 3. KernelSU Integration
+        timeouts.stage("integrate");
+        let snapshot = if opts.keep_source || incremental {
+            None
+        } else if kernel_source_path.join(".git").exists() {
+            Some(Snapshot::take(kernel_source_path)?)
+        } else {
+            warn!("kernel_source is not a git checkout, it can't be rolled back.");
+            None
         };
 
-        if let Some((url, arg)) = setup_url {
-            println!("Installing KernelSU for {}", branch);
-            let cmd = format!("curl -LSs '{}' | bash -s {}", url, arg);
-            run_cmd(&["bash", "-c", &cmd], Some(&kernel_source_path), false)?;
+        let mut ksu_commit = None;
+        if let Some((name, variant)) = variant
+            && !incremental
+        {
+            ksu_commit = integrate(name, variant, &proj, kernel_source_path)?;
+            verify_sources(variant, kernel_source_path)?;
+        }
+        if !incremental {
+            let variant_name = variant.map_or(branch, |(name, _)| name);
+            let names = [branch, variant_name];
+            if let Some(patches) = &proj.patches {
+                checkpoint
+                    .patches
+                    .extend(apply_patches(patches, &names, kernel_source_path)?);
+            }
+            let specs = directory_patches(project_key, &names)?;
+            if let Some(cfg) = &proj.checkpatch {
+                info!("Running checkpatch.pl on {} patch(es)...", specs.len());
+                checkpatch(cfg, &specs, kernel_source_path)?;
+            }
+            for spec in specs {
+                checkpoint.patches.push(apply(&spec, kernel_source_path)?);
+            }
         }
-    }
 
-    // 4. Retrieve Kernel Version
-    println!("Extracting kernel version...");
-    let kernel_version = run_cmd(&["make", "kernelversion"], Some(&kernel_source_path), true)?
-        .unwrap_or_else(|| "unknown".to_string())
-        .trim()
-        .to_string();
-    println!("Detected Kernel Version: {}", kernel_version);
+        // 4. Retrieve Kernel Version
+        info!("Extracting kernel version...");
+        let kernel_version = run_cmd(&["make", "kernelversion"], Some(kernel_source_path), true)?
+            .unwrap_or_else(|| "unknown".to_string())
+            .trim()
+            .to_string();
+        info!("Detected Kernel Version: {}", kernel_version);
 
-    // 5. Construct Make Arguments
-    let target_soc = project_key.split('_').nth(1).unwrap_or("unknown");
-    let mut make_args = vec!["O=out", "ARCH=arm64", "LLVM=1", "LLVM_IAS=1"];
+        // 5. Construct Make Arguments
+        timeouts.stage("configure");
+        let target_soc = project_key.split('_').nth(1).unwrap_or("unknown");
+        let o_arg = format!("O={}", out_arg);
+        let arch_arg = format!("ARCH={}", arch.kernel_arch);
+        let mut make_args = vec![o_arg.as_str(), arch_arg.as_str()];
+        let cross_arg = format!("CROSS_COMPILE={}", arch.cross_compile);
+        if use_gcc {
+            make_args.push(&cross_arg);
+        } else {
+            make_args.extend(["LLVM=1", "LLVM_IAS=1"]);
+        }
 
-    let soc_arg = format!("TARGET_SOC={}", target_soc);
-    make_args.push(&soc_arg);
+        let soc_arg = format!("TARGET_SOC={}", target_soc);
+        make_args.push(&soc_arg);
 
-    if run_cmd(&["which", "ccache"], None, false).is_ok() {
-        build_env.insert("CC".to_string(), "ccache clang".to_string());
-        build_env.insert("CXX".to_string(), "ccache clang++".to_string());
-        build_env.insert(
-            "CCACHE_DIR".to_string(),
-            format!("{}/.ccache", env::current_dir()?.display()),
+        let cc = if use_gcc {
+            format!("{}gcc", arch.cross_compile)
+        } else {
+            "clang".to_string()
+        };
+        let cxx = (!use_gcc).then_some("clang++");
+        let compiler_cache = CompilerCache::resolve(proj.compiler_cache.as_deref())?;
+        let distributed = match &proj.distcc {
+            Some(cfg) => configure_distributed(cfg, compiler_cache, &mut build_env)?,
+            None => None,
+        };
+        let cc_arg = match (compiler_cache, distributed) {
+            (Some(cache), _) => {
+                cache.configure(
+                    &mut build_env,
+                    &cc,
+                    cxx,
+                    proj.ccache_max_size.as_deref().unwrap_or("5G"),
+                )?;
+                format!("CC={} {}", cache.name(), cc)
+            }
+            (None, Some(wrapper)) => {
+                build_env.insert("CC".to_string(), format!("{} {}", wrapper, cc));
+                if let Some(cxx) = cxx {
+                    build_env.insert("CXX".to_string(), format!("{} {}", wrapper, cxx));
+                }
+                format!("CC={} {}", wrapper, cc)
+            }
+            (None, None) => format!("CC={}", cc),
+        };
+        make_args.push(&cc_arg);
+
+        let mut flag_args = Vec::new();
+        if let Some(kcflags) = &proj.kcflags {
+            flag_args.push(format!("KCFLAGS={}", kcflags));
+        }
+        if let Some(kldflags) = &proj.kldflags {
+            flag_args.push(format!("LDFLAGS_MODULE={}", kldflags));
+        }
+        make_args.extend(flag_args.iter().map(|a| a.as_str()));
+        make_args.extend(proj.extra_make_args.iter().flatten().map(|a| a.as_str()));
+
+        if incremental {
+            info!("Incremental build: keeping {}", config_path.display());
+        } else {
+            // 6. Make Defconfig
+            let defconfig_path = arch.defconfig_path(&proj.defconfig);
+            if !kernel_source_path.join(&defconfig_path).exists() {
+                warn!("{} not found, make may fail.", defconfig_path);
+            }
+            let mut defconfig_cmd = vec!["make"];
+            defconfig_cmd.extend_from_slice(&make_args);
+            defconfig_cmd.push(&proj.defconfig);
+
+            run_cmd_with_env(&defconfig_cmd, Some(kernel_source_path), &build_env)?;
+            let stock_config = fs::read_to_string(&config_path).unwrap_or_default();
+
+            let mut fragment = Vec::new();
+            if let Some((_, variant)) = variant {
+                fragment.extend(config_fragment(variant));
+            }
+            for path in proj.config_fragments.iter().flatten() {
+                info!("Merging config fragment {}", path);
+                let file = find_local_file(path, kernel_source_path)?;
+                fragment.extend(parse_fragment(&fs::read_to_string(file)?));
+            }
+            if !fragment.is_empty() {
+                merge_into(&config_path, &fragment)?;
+            }
+
+            // 7. Apply Security & Config Patches
+            let config_edits = config_edits(&proj, use_gcc);
+            for edit in &config_edits {
+                let args = config_args(edit);
+                let config_file = format!("{}/.config", out_arg);
+                let mut cmd = vec!["scripts/config", "--file", &config_file];
+                cmd.extend(args.iter().map(|a| a.as_str()));
+                run_cmd(&cmd, Some(kernel_source_path), false)?;
+            }
+
+            // Let Kconfig resolve dependencies, then report what it threw away.
+            let mut olddefconfig_cmd = vec!["make"];
+            olddefconfig_cmd.extend_from_slice(&make_args);
+            olddefconfig_cmd.push("olddefconfig");
+            run_cmd_with_env(&olddefconfig_cmd, Some(kernel_source_path), &build_env)?;
+
+            fragment.extend(config_edits);
+            let dropped = dropped_entries(&fragment, &config_path)?;
+            if !dropped.is_empty() {
+                warn!(
+                    "olddefconfig changed {} requested option(s):",
+                    dropped.len()
+                );
+                for (entry, got) in &dropped {
+                    info!(
+                        "   - {} (now: {})",
+                        render_entry(entry),
+                        render_entry(&(entry.0.clone(), got.clone()))
+                    );
+                }
+                info!("   Check their Kconfig dependencies (`depends on`/`select`).");
+                if proj.strict_config.unwrap_or(false) {
+                    return Err(anyhow!(
+                        "{} requested config option(s) were dropped (strict_config)",
+                        dropped.len()
+                    ));
+                }
+            }
+
+            if let Some((_, variant)) = variant {
+                verify_config(variant, kernel_source_path, &config_path)?;
+            }
+            write_config_diff(
+                &proj.defconfig,
+                &stock_config,
+                &config_path,
+                &out_path.join(CONFIG_DIFF),
+            )?;
+        }
+
+        if let Some(signing) = &proj.module_signing {
+            modsign::install_key(signing, project_key, kernel_source_path, &out_path)?;
+        }
+
+        // 8. Handle Localversion
+        let short_sha = run_cmd(
+            &["git", "rev-parse", "--short", "HEAD"],
+            Some(kernel_source_path),
+            true,
+        )?
+        .unwrap_or_else(|| "unknown".to_string());
+
+        let mut localversion_file = None;
+        if proj.version_method.as_deref().unwrap_or("param") == "file" {
+            localversion_file = Some(LocalversionFile::write(
+                kernel_source_path.join("localversion"),
+                &format!("{}-g{}", localversion, short_sha),
+            )?);
+        } else {
+            make_args.push("LOCALVERSION=");
+            build_env.insert("LOCALVERSION".to_string(), localversion.clone());
+        }
+
+        // 9. Build Kernel
+        timeouts.stage("build");
+        if proj.lto.as_deref() == Some("thin") && !use_gcc {
+            link_thinlto_cache(project_key, kernel_source_path, &out_path)?;
+        }
+        let threads = match opts.resources.jobs.or(proj.jobs) {
+            Some(n) => n.to_string(),
+            None => {
+                let nproc = run_cmd(&["nproc"], None, true)?.unwrap().trim().to_string();
+                match &proj.distcc {
+                    // Most jobs run remotely, so keep more of them in flight.
+                    Some(cfg) => {
+                        let n: u32 = nproc.parse().unwrap_or(1);
+                        (n * cfg.jobs_multiplier.unwrap_or(2)).to_string()
+                    }
+                    None => nproc,
+                }
+            }
+        };
+        let mut parallel = vec![format!("-j{}", threads)];
+        if let Some(load) = opts.resources.load_average.or(proj.load_average) {
+            parallel.push(format!("-l{}", load));
+        }
+        info!("make {}", parallel.join(" "));
+
+        let cache_before = compiler_cache.and_then(|cache| {
+            cache.print_summary("before build", &build_env);
+            cache.read_stats(&build_env)
+        });
+
+        let mut build_cmd = vec!["make"];
+        build_cmd.extend(parallel.iter().map(|a| a.as_str()));
+        build_cmd.extend_from_slice(&make_args);
+        if let Some(targets) = &proj.make_targets {
+            build_cmd.extend(targets.iter().map(|t| t.as_str()));
+        }
+
+        let make_progress = MakeProgress::start(project_key, &out_path);
+        let cache_watch = compiler_cache
+            .zip(cache_before)
+            .filter(|_| events::enabled())
+            .map(|(cache, before)| cache.watch(before, &build_env));
+        run_cmd_with_env(&build_cmd, Some(kernel_source_path), &build_env)?;
+        drop(cache_watch);
+        make_progress.finish();
+
+        if proj.dtb.is_some() {
+            let mut cmd = vec!["make"];
+            cmd.extend(parallel.iter().map(|a| a.as_str()));
+            cmd.extend_from_slice(&make_args);
+            cmd.push("dtbs");
+            run_cmd_with_env(&cmd, Some(kernel_source_path), &build_env)?;
+        }
+
+        if let Some(modules) = &proj.modules {
+            if modules_staging.exists() {
+                fs::remove_dir_all(&modules_staging)?;
+            }
+            let install_path = format!(
+                "INSTALL_MOD_PATH={}",
+                fs::canonicalize(&out_path)?
+                    .join("modules_install")
+                    .display()
+            );
+            let mut module_dirs = vec![None];
+            for dir in &modules.external {
+                let abs = fs::canonicalize(kernel_source_path.join(dir))?;
+                module_dirs.push(Some(format!("M={}", abs.display())));
+            }
+            for m_arg in &module_dirs {
+                let mut cmd = vec!["make"];
+                cmd.extend(parallel.iter().map(|a| a.as_str()));
+                cmd.extend_from_slice(&make_args);
+                cmd.extend(m_arg.as_deref());
+                cmd.push("modules");
+                run_cmd_with_env(&cmd, Some(kernel_source_path), &build_env)?;
+
+                let mut cmd = vec!["make"];
+                cmd.extend_from_slice(&make_args);
+                cmd.extend(m_arg.as_deref());
+                cmd.push(&install_path);
+                if modules.strip {
+                    cmd.push("INSTALL_MOD_STRIP=1");
+                }
+                cmd.push("modules_install");
+                run_cmd_with_env(&cmd, Some(kernel_source_path), &build_env)?;
+            }
+        }
+
+        let build_warnings = output::warnings();
+        warnings::print_summary(&build_warnings);
+        check_new_warnings(project_key, branch, &build_warnings, proj.max_new_warnings)?;
+        let metrics = build_metrics(&out_path, arch, use_gcc, &build_env);
+        bloat::report(
+            project_key,
+            branch,
+            arch,
+            use_gcc,
+            proj.make_targets.as_deref(),
+            proj.max_image_size,
+            &metrics,
+            kernel_source_path,
+            &out_path,
+            &build_env,
+        )?;
+
+        if let Some(analyze) = &proj.analyze {
+            timeouts.stage("analyze");
+            analyze::run(
+                analyze,
+                project_key,
+                branch,
+                kernel_source_path,
+                &out_path,
+                &make_args,
+                &build_env,
+            )?;
+        }
+
+        let mut report = BuildReport {
+            kernel_version: Some(kernel_version.clone()),
+            metrics,
+            full_compile: !incremental,
+            ..Default::default()
+        };
+        if let Some(cache) = compiler_cache {
+            cache.print_summary("after build", &build_env);
+            report.cache = cache_before
+                .zip(cache.read_stats(&build_env))
+                .map(|(before, after)| (cache, after.since(&before)));
+            if let Some(line) = report.cache_line() {
+                info!("{}", line);
+            }
+        }
+
+        drop(localversion_file);
+        if let Some(snapshot) = snapshot
+            && !restore_source
+        {
+            snapshot.release();
+        }
+
+        if let Some(abi_check) = &proj.abi_check {
+            timeouts.stage("abi_check");
+            abi::check(abi_check, kernel_source_path, &out_path)?;
+        }
+        if let Some(boot_test) = &proj.boot_test {
+            timeouts.stage("boot_test");
+            let image = boot_test.kernel_image.as_deref().unwrap_or(arch.image);
+            boottest::run(
+                boot_test,
+                arch,
+                &out_path.join(arch.boot_dir()).join(image),
+                kernel_source_path,
+                &out_path.join("boot_test"),
+            )?;
+        }
+        if let Some(kunit) = &proj.kunit {
+            timeouts.stage("kunit");
+            report.kunit = Some(kunit::run(
+                kunit,
+                arch,
+                use_gcc,
+                kernel_source_path,
+                &out_path,
+                &build_env,
+            )?);
+        }
+
+        checkpoint.kernel_version = kernel_version.clone();
+        checkpoint.ksu_commit = ksu_commit.clone();
+        checkpoint.complete(&["toolchain", "integrate", "configure", "build"], &out_path);
+        (kernel_version, ksu_commit, report)
+    };
+
+    // 10. Package AnyKernel3
+    timeouts.stage("package");
+    let zip_prefix = proj.zip_name_prefix.as_deref().unwrap_or("Kernel");
+    let (date_str, final_zip_path, extra_artifacts) =
+        if opts.resume_from == Some(ResumeStage::Release) {
+            let (Some(date), Some(zip)) = (checkpoint.date.clone(), checkpoint.zip.clone()) else {
+                return Err(anyhow!("The checkpoint doesn't name the packaged zip"));
+            };
+            info!("Resuming with {}", zip.display());
+            record_artifacts(&mut report, &zip, &checkpoint.extra_artifacts)?;
+            (date, zip, checkpoint.extra_artifacts.clone())
+        } else {
+            let ak3_repo = proj
+                .anykernel_repo
+                .as_deref()
+                .unwrap_or("https://github.com/YuzakiKokuban/AnyKernel3.git");
+            let ak3_branch = proj.anykernel_branch.as_deref().unwrap_or("master");
+
+            let ak3_dir = artifacts_dir.join("AnyKernel3");
+            let ak3_dest = ak3_dir.to_string_lossy();
+            let mut ak3_clone = vec!["git", "clone", ak3_repo, "-b", ak3_branch];
+            let reference = git_reference_args(proj.git_reference_dir.as_deref(), ak3_repo);
+            ak3_clone.extend(reference.iter().map(|a| a.as_str()));
+            ak3_clone.push(&ak3_dest);
+            retry::run("AnyKernel3 clone", || {
+                if ak3_dir.exists() {
+                    fs::remove_dir_all(&ak3_dir)?;
+                }
+                run_cmd(&ak3_clone, None, false)
+            })?;
+
+            let date_str = Local::now().format("%Y%m%d-%H%M").to_string();
+
+            let clean_localversion = localversion.trim_start_matches('-');
+            let zip_stem = format!(
+                "{}-{}-{}-{}",
+                zip_prefix, kernel_version, clean_localversion, date_str
+            );
+            let final_zip_name = format!("{}.zip", zip_stem);
+            let final_zip_path = artifacts_dir.join(&final_zip_name);
+            let mut partial = PartialArtifacts {
+                paths: vec![final_zip_path.clone()],
+                armed: true,
+            };
+
+            let default_targets = vec![arch.image.to_string()];
+            let mut extra_artifacts = collect_targets(
+                proj.make_targets.as_ref().unwrap_or(&default_targets),
+                arch,
+                &out_path,
+                &ak3_dir,
+                &artifacts_dir,
+                &zip_stem,
+            )?;
+            if let Some(dtb) = &proj.dtb {
+                let images = build_dt_images(
+                    dtb,
+                    &out_path.join(arch.boot_dir()).join("dts"),
+                    &out_path.join("dt_images"),
+                    &build_env,
+                )?;
+                for (name, path) in images {
+                    match dtb.package.as_deref().unwrap_or("zip") {
+                        "zip" => {
+                            fs::copy(&path, ak3_dir.join(name))?;
+                        }
+                        "separate" => {
+                            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                            let dest = artifacts_dir.join(format!("{}-{}", zip_stem, file_name));
+                            fs::copy(&path, &dest)?;
+                            extra_artifacts.push(dest);
+                        }
+                        other => {
+                            return Err(anyhow!(
+                                "Unknown dtb.package '{}' (expected zip or separate)",
+                                other
+                            ));
+                        }
+                    }
+                }
+            }
+            if proj.avb.is_some() && proj.boot_image.is_none() {
+                warn!("avb is set but there is no boot_image to sign.");
+            }
+            if let Some(boot_image) = &proj.boot_image {
+                let image = boot_image.kernel_image.as_deref().unwrap_or(arch.image);
+                extra_artifacts.extend(repack_boot_image(
+                    boot_image,
+                    proj.avb.as_ref(),
+                    &out_path.join(arch.boot_dir()).join(image),
+                    kernel_source_path,
+                    &out_path.join("boot_repack"),
+                    &artifacts_dir,
+                    &zip_stem,
+                )?);
+            }
+            let config_diff = out_path.join(CONFIG_DIFF);
+            if config_diff.exists() {
+                let dest = artifacts_dir.join(format!("{}-{}", zip_stem, CONFIG_DIFF));
+                fs::copy(&config_diff, &dest)?;
+                extra_artifacts.push(dest);
+            }
+            if let Some(modules) = &proj.modules {
+                extra_artifacts.extend(package_modules(
+                    modules,
+                    &modules_staging,
+                    &ak3_dir,
+                    &artifacts_dir,
+                    &zip_stem,
+                )?);
+            }
+
+            partial.paths.extend(extra_artifacts.iter().cloned());
+            match source_date_epoch {
+                Some(epoch) => deterministic_zip(&ak3_dir, &final_zip_path, AK3_EXCLUDES, epoch)?,
+                None => {
+                    let zip_name = format!("../{}", final_zip_name);
+                    let mut zip_cmd = vec!["zip", "-r9", zip_name.as_str(), "."];
+                    for pattern in AK3_EXCLUDES {
+                        zip_cmd.extend(["-x", pattern]);
+                    }
+                    run_cmd(&zip_cmd, Some(&ak3_dir), false)?;
+                }
+            }
+            info!("Created {}", final_zip_path.display());
+            record_artifacts(&mut report, &final_zip_path, &extra_artifacts)?;
+            partial.keep();
+
+            checkpoint.date = Some(date_str.clone());
+            // Absolute, so a resumed release finds them from any directory.
+            checkpoint.zip = Some(fs::canonicalize(&final_zip_path)?);
+            checkpoint.extra_artifacts = extra_artifacts
+                .iter()
+                .map(fs::canonicalize)
+                .collect::<Result<_, _>>()?;
+            checkpoint.complete(&["package"], &out_path);
+            (date_str, final_zip_path, extra_artifacts)
+        };
+    let checksums = write_checksums(&report.artifacts, &final_zip_path)?;
+    let provenance = provenance::write(
+        &provenance::Inputs {
+            project: project_key,
+            branch,
+            kernel_source: kernel_source_path,
+            commit: checkpoint.commit.as_deref(),
+            ksu: variant
+                .zip(ksu_commit.as_deref())
+                .map(|((name, v), commit)| (name, v.repo.as_deref(), commit)),
+            toolchain: &toolchain,
+            patches: &checkpoint.patches,
+            config: &config_path,
+            started: started_on,
+        },
+        &report.artifacts,
+        &final_zip_path,
+    )?;
+    let signatures = match &proj.gpg {
+        Some(gpg) => sign_files(gpg, &[final_zip_path.clone(), checksums.clone()])?,
+        None => Vec::new(),
+    };
+
+    // 11. Release & Notify
+    timeouts.stage("release");
+    if opts.do_release {
+        let release_tag = format!("{}-{}-{}", zip_prefix, variant_suffix, date_str);
+        let release_title = format!("{} {} Build ({})", zip_prefix, variant_suffix, date_str);
+
+        let mut notes = format!(
+            "Automated build for {}\nKernel Version: {}",
+            branch, kernel_version
         );
-        run_cmd(&["ccache", "-M", "5G"], None, false)?;
-        make_args.push("CC=ccache clang");
-    } else {
-        make_args.push("CC=clang");
+        if let Some(commit) = &ksu_commit {
+            notes.push_str(&format!("\nKernelSU Commit: {}", commit));
+        }
+        let cache_line = report.cache_line();
+        if let Some(line) = &cache_line {
+            notes.push_str(&format!("\n{}", line));
+        }
+        if let Some(kunit) = &report.kunit {
+            notes.push_str(&format!("\nKUnit: {}", kunit));
+        }
+        // The release stage itself is still running; count up to here.
+        let build_time = timing_line(&timeout::stage_timings(), build_started.elapsed());
+        notes.push_str(&format!("\nBuild time: {}", build_time));
+        let zip_sha256 = report.artifacts.first().map(|a| a.sha256.clone());
+        if let Some(sha256) = &zip_sha256 {
+            notes.push_str(&format!("\nSHA-256: `{}`", sha256));
+        }
+        let build_stats = match &cache_line {
+            Some(line) => format!("{}; {}", build_time, line),
+            None => build_time,
+        };
+        let tag_prefix = format!("{}-{}-", zip_prefix, variant_suffix);
+        match release_notes(kernel_source_path, &tag_prefix) {
+            Ok(Some(changelog)) => notes.push_str(&format!("\n\n{}", changelog)),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to generate changelog: {}", e),
+        }
+
+        if final_zip_path.exists() {
+            let mut files = vec![final_zip_path.clone()];
+            files.extend(extra_artifacts.iter().cloned());
+            files.push(checksums.clone());
+            files.push(provenance.clone());
+            files.extend(signatures.iter().cloned());
+            let distribution = proj.distribution.clone().unwrap_or_default();
+            upload_artifacts(&distribution, &release_tag, &files)?;
+            if distribution.github_release.unwrap_or(true) {
+                let info = publish(
+                    &Release {
+                        repo: &proj.repo,
+                        tag: &release_tag,
+                        title: &release_title,
+                        notes: &notes,
+                        files: &files,
+                    },
+                    &opts.release.apply_to(proj.release.as_ref()),
+                )?;
+
+                notify_release(
+                    projects,
+                    &proj,
+                    &ReleaseEvent {
+                        repo: &proj.repo,
+                        tag: &release_tag,
+                        title: &release_title,
+                        author: info
+                            .author
+                            .as_ref()
+                            .map(|a| a.login.as_str())
+                            .unwrap_or("YuzakiKokuban"),
+                        url: &info.html_url,
+                        kernel_version: Some(&kernel_version),
+                        variant: Some(&variant_suffix),
+                        build_stats: Some(&build_stats),
+                        zip_sha256: zip_sha256.as_deref(),
+                        files: &files,
+                    },
+                )?;
+            }
+        } else {
+            return Err(anyhow!("Final zip not found"));
+        }
+        report.release_tag = Some(release_tag);
+        checkpoint.complete(&["release"], &out_path);
     }
 
-    // 6. Make Defconfig
-    let mut defconfig_cmd = vec!["make"];
-    defconfig_cmd.extend_from_slice(&make_args);
-    defconfig_cmd.push(&proj.defconfig);
+    Ok(report)
+}
 
-    run_cmd_with_env(&defconfig_cmd, Some(&kernel_source_path), &build_env)?;
+/// Adds the zip and the other artifacts to the report, with their sizes and
+/// checksums.
+fn record_artifacts(report: &mut BuildReport, zip: &Path, extra: &[PathBuf]) -> Result<()> {
+    for path in std::iter::once(zip).chain(extra.iter().map(|p| p.as_path())) {
+        let artifact = Artifact {
+            path: path.to_path_buf(),
+            size: fs::metadata(path)
+                .with_context(|| format!("{} is missing", path.display()))?
+                .len(),
+            sha256: sha256_file(path)?,
+        };
+        events::emit(Event::Artifact {
+            path,
+            size: artifact.size,
+            sha256: &artifact.sha256,
+        });
+        report.artifacts.push(artifact);
+    }
+    Ok(())
+}
+
+/// Writes `<zip stem>-SHA256SUMS` next to the zip, in `sha256sum -c`
+/// format, for the artifacts the report recorded. Returns its path.
+fn write_checksums(artifacts: &[Artifact], zip: &Path) -> Result<PathBuf> {
+    let stem = zip.file_stem().unwrap_or_default().to_string_lossy();
+    let path = zip.with_file_name(format!("{}-SHA256SUMS", stem));
+    let sums: String = artifacts
+        .iter()
+        .map(|a| {
+            let name = a.path.file_name().unwrap_or_default().to_string_lossy();
+            format!("{}  {}\n", a.sha256, name)
+        })
+        .collect();
+    fs::write(&path, sums).with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote {}", path.display());
+    Ok(path)
+}
 
-    // 7. Apply Security & Config Patches
-    let mut disable_configs = vec![
+/// The `scripts/config` switches applied after defconfig: Samsung security
+/// features off, the LTO mode, then the project's enables and disables.
+pub fn config_edits(proj: &ProjectConfig, use_gcc: bool) -> Vec<ConfigEntry> {
+    let mut config_edits: Vec<ConfigEntry> = [
         "UH",
         "RKP",
         "KDP",
@@ -280,211 +1702,317 @@ pub fn handle_build(project_key: String, branch: String, do_release: bool) -> Re
         "INTEGRITY",
         "FIVE",
         "TRIM_UNUSED_KSYMS",
-    ];
+    ]
+    .iter()
+    .map(|c| (c.to_string(), None))
+    .collect();
     if let Some(disables) = &proj.disable_security {
-        for d in disables {
-            disable_configs.push(d);
-        }
+        config_edits.extend(disables.iter().map(|d| (d.clone(), None)));
     }
 
-    // For WildKSU Manual Hook, ensure we enable Manual Hook config in the final .config
-    if branch == "wildksu" {
-         disable_configs.push("KSU_KPROBES_HOOK"); // Ensure KPROBES is off
-         disable_configs.push("KSU_SUSFS_SUS_SU"); // Ensure SUS_SU is off
-         
-         // We must ENABLE Manual Hook. The loop below disables, so we do enable separately.
-         run_cmd(
-            &["scripts/config", "--file", "out/.config", "-e", "CONFIG_KSU_MANUAL_HOOK"],
-            Some(&kernel_source_path),
-            false,
-        )?;
-        run_cmd(
-            &["scripts/config", "--file", "out/.config", "-e", "CONFIG_SUSFS"],
-            Some(&kernel_source_path),
-            false,
-        )?;
+    let lto_pair = match proj.lto.as_deref() {
+        Some("thin" | "full") if use_gcc => {
+            warn!("LTO needs clang, ignoring lto for this GCC build.");
+            None
+        }
+        Some("thin") => Some(("LTO_CLANG_THIN", "LTO_CLANG_FULL")),
+        Some("full") => Some(("LTO_CLANG_FULL", "LTO_CLANG_THIN")),
+        _ => None,
+    };
+    if let Some((on, off)) = lto_pair {
+        config_edits.push((on.to_string(), Some("y".to_string())));
+        config_edits.push((off.to_string(), None));
     }
 
-    for config in disable_configs {
-        run_cmd(
-            &[
-                "scripts/config",
-                "--file",
-                "out/.config",
-                "--disable",
-                config,
-            ],
-            Some(&kernel_source_path),
-            false,
-        )?;
+    if let Some(signing) = &proj.module_signing {
+        config_edits.extend(modsign::config_entries(signing));
     }
+    config_edits.extend(proj.enable_configs.iter().flatten().map(|c| parse_spec(c)));
+    config_edits.extend(
+        proj.disable_configs
+            .iter()
+            .flatten()
+            .map(|c| (c.trim().trim_start_matches("CONFIG_").to_string(), None)),
+    );
+    config_edits
+}
 
-    if let Some(lto) = &proj.lto {
-        if lto == "thin" {
-            run_cmd(
-                &[
-                    "scripts/config",
-                    "--file",
-                    "out/.config",
-                    "-e",
-                    "LTO_CLANG_THIN",
-                    "-d",
-                    "LTO_CLANG_FULL",
-                ],
-                Some(&kernel_source_path),
-                false,
-            )?;
-        } else if lto == "full" {
-            run_cmd(
-                &[
-                    "scripts/config",
-                    "--file",
-                    "out/.config",
-                    "-e",
-                    "LTO_CLANG_FULL",
-                    "-d",
-                    "LTO_CLANG_THIN",
-                ],
-                Some(&kernel_source_path),
-                false,
-            )?;
-        }
+/// The defconfig-to-final-.config diff, in the build dir and as an artifact.
+const CONFIG_DIFF: &str = "config.diff";
+
+/// Config changes listed in the log; the rest are only in the file.
+const MAX_LISTED_CONFIG_CHANGES: usize = 50;
+
+/// Writes and lists what fragments, config edits and olddefconfig changed
+/// relative to the stock defconfig output.
+fn write_config_diff(defconfig: &str, stock: &str, config_path: &Path, dest: &Path) -> Result<()> {
+    let lines = diff_configs(stock, &fs::read_to_string(config_path)?);
+    let mut content = format!("# {} -> final .config\n", defconfig);
+    for line in &lines {
+        content.push_str(line);
+        content.push('\n');
+    }
+    fs::write(dest, content)?;
+    info!(
+        "{} option(s) changed from {} (see {}):",
+        lines.len(),
+        defconfig,
+        dest.display()
+    );
+    for line in lines.iter().take(MAX_LISTED_CONFIG_CHANGES) {
+        info!("  {}", line);
+    }
+    if lines.len() > MAX_LISTED_CONFIG_CHANGES {
+        info!("  ... and {} more", lines.len() - MAX_LISTED_CONFIG_CHANGES);
     }
+    Ok(())
+}
 
-    // 8. Handle Localversion
-    let short_sha = run_cmd(
-        &["git", "rev-parse", "--short", "HEAD"],
-        Some(&kernel_source_path),
-        true,
-    )?
-    .unwrap_or_else(|| "unknown".to_string());
-
-    let variant_suffix = match branch.as_str() {
-        "main" | "lkm" => "LKM".to_string(),
-        "ksu" => "KSU".to_string(),
-        "mksu" => "MKSU".to_string(),
-        "resukisu" | "sukisuultra" => "ReSuki".to_string(),
-        "wildksu" => "WildKSU".to_string(), // Added label for filename
-        _ => branch.to_uppercase(),
-    };
+/// New warnings listed after a build; the rest are only counted.
+const MAX_LISTED_WARNINGS: usize = 30;
 
-    let localversion = format!("{}-{}", proj.localversion_base, variant_suffix);
+/// Lists the warnings the branch's last successful build didn't have and
+/// fails if there are more than `max_new`.
+fn check_new_warnings(
+    project_key: &str,
+    branch: &str,
+    current: &[Warning],
+    max_new: Option<u32>,
+) -> Result<()> {
+    let (id, previous) = match history::previous_warnings(project_key, branch) {
+        Ok(Some(previous)) => previous,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            warn!("Can't compare warnings with the last build: {:#}", e);
+            return Ok(());
+        }
+    };
+    let new = new_since(current, &previous);
+    if new.is_empty() {
+        info!("No new warnings since build #{}.", id);
+        return Ok(());
+    }
+    info!("{} new warning(s) since build #{}:", new.len(), id);
+    for warning in new.iter().take(MAX_LISTED_WARNINGS) {
+        info!("  {}", warning);
+    }
+    if new.len() > MAX_LISTED_WARNINGS {
+        info!("  ... and {} more", new.len() - MAX_LISTED_WARNINGS);
+    }
+    if let Some(max) = max_new
+        && new.len() > max as usize
+    {
+        return Err(anyhow!(
+            "{} new compiler warning(s) since build #{} (max_new_warnings is {})",
+            new.len(),
+            id,
+            max
+        ));
+    }
+    Ok(())
+}
 
-    if proj.version_method.as_deref().unwrap_or("param") == "file" {
-        fs::write(
-            kernel_source_path.join("localversion"),
-            format!("{}-g{}", localversion, short_sha),
-        )?;
+/// Image size, vmlinux section sizes and the final .config, kept in the
+/// build history for `compare`.
+fn build_metrics(
+    out_path: &Path,
+    arch: &Arch,
+    use_gcc: bool,
+    build_env: &HashMap<String, String>,
+) -> BuildMetrics {
+    let size_tool = if use_gcc {
+        format!("{}size", arch.cross_compile)
     } else {
-        make_args.push("LOCALVERSION=");
-        build_env.insert("LOCALVERSION".to_string(), localversion.clone());
+        "llvm-size".to_string()
+    };
+    let sections = Command::new(&size_tool)
+        .arg("-A")
+        .arg(out_path.join("vmlinux"))
+        .envs(build_env)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| parse_section_sizes(&String::from_utf8_lossy(&o.stdout)))
+        .unwrap_or_default();
+    if sections.is_empty() {
+        warn!("Couldn't read vmlinux section sizes with {}", size_tool);
     }
+    BuildMetrics {
+        config: fs::read_to_string(out_path.join(".config")).ok(),
+        image_size: fs::metadata(out_path.join(arch.boot_dir()).join(arch.image))
+            .ok()
+            .map(|m| m.len()),
+        sections,
+        warnings: BTreeMap::new(),
+    }
+}
 
-    // 9. Build Kernel
-    let threads = run_cmd(&["nproc"], None, true)?.unwrap().trim().to_string();
-    let jobs = format!("-j{}", threads);
-
-    let mut build_cmd = vec!["make", &jobs];
-    build_cmd.extend_from_slice(&make_args);
-
-    run_cmd_with_env(&build_cmd, Some(&kernel_source_path), &build_env)?;
+/// Parses `size -A` output: `.text   19128320   18446743799831724032`.
+fn parse_section_sizes(output: &str) -> BTreeMap<String, u64> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.split_whitespace();
+            let name = cols.next().filter(|n| n.starts_with('.'))?;
+            let size = cols.next()?.parse().ok()?;
+            Some((name.to_string(), size))
+        })
+        .collect()
+}
 
-    if proj.version_method.as_deref().unwrap_or("param") == "file" {
-        fs::write(kernel_source_path.join("localversion"), "")?;
+/// SOURCE_DATE_EPOCH for a reproducible build: the caller's, else the time
+/// of the kernel source's HEAD commit.
+fn source_date_epoch(kernel_source: &Path) -> Result<i64> {
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        return epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid SOURCE_DATE_EPOCH '{}'", epoch));
     }
+    let time = run_cmd(
+        &["git", "log", "-1", "--format=%ct"],
+        Some(kernel_source),
+        true,
+    )
+    .context("reproducible needs SOURCE_DATE_EPOCH or a git checkout of the kernel source")?
+    .unwrap_or_default();
+    Ok(time.trim().parse()?)
+}
 
-    // 10. Package AnyKernel3
-    let ak3_repo = proj
-        .anykernel_repo
-        .as_deref()
-        .unwrap_or("https://github.com/YuzakiKokuban/AnyKernel3.git");
-    let ak3_branch = proj.anykernel_branch.as_deref().unwrap_or("master");
+/// Fails early if `paths` are short of the project's `min_free_space_gib`.
+fn check_disk_space(proj: &ProjectConfig, paths: &[&Path]) -> Result<()> {
+    match proj.min_free_space_gib.unwrap_or(MIN_DISK_GIB) {
+        0 => Ok(()),
+        min => check_free_space(paths, min),
+    }
+}
 
-    if Path::new("AnyKernel3").exists() {
-        fs::remove_dir_all("AnyKernel3")?;
+/// Sets this process's niceness; make and every other child inherits it.
+fn set_niceness(nice: i32) {
+    // SAFETY: setpriority only touches the calling process's scheduling.
+    let ret = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+    if ret == 0 {
+        info!("Running at niceness {}", nice);
+    } else {
+        warn!(
+            "couldn't set niceness {}: {}",
+            nice,
+            std::io::Error::last_os_error()
+        );
     }
+}
 
-    run_cmd(
-        &["git", "clone", ak3_repo, "-b", ak3_branch, "AnyKernel3"],
-        None,
-        false,
-    )?;
+/// Points the build dir's `.thinlto-cache` at a per-project dir in the cache
+/// so ThinLTO relinks survive `clean` and fresh CI checkouts. The kernel
+/// passes `--thinlto-cache-dir` itself; KBUILD_LDFLAGS can't be extended from
+/// the command line because the Makefile resets it.
+fn link_thinlto_cache(project_key: &str, kernel_source: &Path, out_path: &Path) -> Result<()> {
+    let makefile = fs::read_to_string(kernel_source.join("Makefile")).unwrap_or_default();
+    if !makefile.contains("thinlto-cache-dir") {
+        warn!("this kernel doesn't pass --thinlto-cache-dir, ThinLTO cache not used.");
+        return Ok(());
+    }
+    let cache = get_cache_dir().join("thinlto").join(project_key);
+    fs::create_dir_all(&cache)?;
+    fs::create_dir_all(out_path)?;
 
-    let image_path = kernel_source_path.join("out/arch/arm64/boot/Image");
-    if !image_path.exists() {
-        return Err(anyhow!("Image not found at {:?}", image_path));
+    let link = out_path.join(".thinlto-cache");
+    if fs::read_link(&link).is_ok_and(|target| target == cache) {
+        return Ok(());
     }
+    if link.is_symlink() || link.is_file() {
+        fs::remove_file(&link)?;
+    } else if link.is_dir() {
+        fs::remove_dir_all(&link)?;
+    }
+    std::os::unix::fs::symlink(&cache, &link)?;
+    info!("ThinLTO cache: {}", cache.display());
+    Ok(())
+}
 
-    fs::copy(image_path, "AnyKernel3/Image")?;
+/// `--incremental` only skips the setup stages when a previous build left
+/// everything they would produce; otherwise the build runs in full.
+fn can_build_incrementally(
+    proj: &ProjectConfig,
+    variant: Option<&KsuVariant>,
+    kernel_source: &Path,
+    config_path: &Path,
+) -> bool {
+    let mut missing = Vec::new();
+    if !config_path.exists() {
+        missing.push(format!("no {}", config_path.display()));
+    }
+    if let Some(variant) = variant
+        && let Err(e) = verify_sources(variant, kernel_source)
+    {
+        missing.push(e.to_string());
+    }
+    if proj.toolchain_urls.is_some() && cached_toolchain(proj).is_none() {
+        missing.push("no cached toolchain".to_string());
+    }
+    if missing.is_empty() {
+        info!("Incremental build: skipping toolchain, integration and defconfig.");
+        return true;
+    }
+    warn!("--incremental needs a previous build, doing a full one:");
+    for m in &missing {
+        info!("   - {}", m);
+    }
+    false
+}
 
-    let date_str = Local::now().format("%Y%m%d-%H%M").to_string();
-    let zip_prefix = proj.zip_name_prefix.as_deref().unwrap_or("Kernel");
+/// Scratch dir for `--check-patches`, named by pid so concurrent checks
+/// don't remove each other's, and removed when dropped.
+struct CheckDir(PathBuf);
 
-    let clean_localversion = localversion.trim_start_matches('-');
-    let final_zip_name = format!(
-        "{}-{}-{}-{}.zip",
-        zip_prefix, kernel_version, clean_localversion, date_str
-    );
+impl CheckDir {
+    fn create() -> Result<Self> {
+        let dir = env::temp_dir().join(format!("kokuban-patch-check-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        Ok(CheckDir(dir))
+    }
+}
 
-    run_cmd(
-        &[
-            "zip",
-            "-r9",
-            format!("../{}", final_zip_name).as_str(),
-            ".",
-            "-x",
-            ".git*",
-            "-x",
-            ".github*",
-            "-x",
-            "README.md",
-            "-x",
-            "LICENSE",
-            "-x",
-            "*.gitignore",
-            "-x",
-            "patch_linux",
-            "-x",
-            "tools/boot.img.lz4",
-            "-x",
-            "tools/libmagiskboot.so",
-        ],
-        Some(Path::new("AnyKernel3")),
-        false,
-    )?;
+impl Drop for CheckDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
 
-    // 11. Release & Notify
-    if do_release {
-        let release_tag = format!("{}-{}-{}", zip_prefix, variant_suffix, date_str);
-        let release_title = format!("{} {} Build ({})", zip_prefix, variant_suffix, date_str);
+/// `--check-patches`: dry-runs every patch the build would apply and stops.
+fn check_only(
+    projects: &ProjectsMap,
+    project_key: &str,
+    proj: &ProjectConfig,
+    branch: &str,
+    kernel_source: &Path,
+) -> Result<()> {
+    let ksu_variants = load_ksu_variants(projects)?;
+    let variant = resolve_variant(&ksu_variants, branch);
 
-        if Path::new(&final_zip_name).exists() {
-            run_cmd(
-                &[
-                    "gh",
-                    "release",
-                    "create",
-                    &release_tag,
-                    &final_zip_name,
-                    "--repo",
-                    &proj.repo,
-                    "--title",
-                    &release_title,
-                    "--notes",
-                    &format!(
-                        "Automated build for {}\nKernel Version: {}",
-                        branch, kernel_version
-                    ),
-                ],
-                None,
-                false,
-            )?;
+    let work_dir = CheckDir::create()?;
 
-            handle_notify(release_tag)?;
-        } else {
-            return Err(anyhow!("Final zip not found"));
-        }
+    let mut patches = Vec::new();
+    if let Some((_, variant)) = variant {
+        patches = integration_patches(variant, proj, kernel_source, &work_dir.0)?;
     }
+    let variant_name = variant.map_or(branch, |(name, _)| name);
+    patches.extend(
+        proj.patches
+            .iter()
+            .flatten()
+            .filter(|p| p.applies_to(&[branch, variant_name]))
+            .cloned(),
+    );
+    patches.extend(directory_patches(project_key, &[branch, variant_name])?);
 
-    Ok(())
+    info!(
+        "Checking {} patch(es) against {:?}...",
+        patches.len(),
+        kernel_source
+    );
+    check_patches(&patches, kernel_source)
 }
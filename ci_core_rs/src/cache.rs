@@ -0,0 +1,151 @@
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::{Component, Path, PathBuf};
+
+use crate::build::kernel_source_location;
+use crate::config::ProjectConfig;
+use crate::utils::{apply_branch_override, expand_entry, load_projects, run_cmd, set_github_env};
+
+/// Archive prefixes and the directories they are restored to.
+const BUILD_PREFIX: &str = "out";
+const CCACHE_PREFIX: &str = ".ccache";
+
+struct CacheTarget {
+    key: String,
+    build_dir: PathBuf,
+    ccache_dir: PathBuf,
+}
+
+impl CacheTarget {
+    fn resolve(project_key: &str, branch: &str, out_dir: Option<PathBuf>) -> Result<Self> {
+        let projects = load_projects()?;
+        let proj_val = apply_branch_override(
+            projects
+                .get(project_key)
+                .ok_or_else(|| anyhow!("Project not found"))?,
+            branch,
+        );
+        let proj: ProjectConfig = serde_json::from_value(expand_entry(project_key, &proj_val)?)?;
+
+        let kernel_source = kernel_source_location(&proj);
+        let head =
+            run_cmd(&["git", "rev-parse", "HEAD"], Some(&kernel_source), true)?.unwrap_or_default();
+        let mut hasher = Sha256::new();
+        hasher.update(head.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(serde_json::to_string(&proj_val)?.as_bytes());
+        let digest = format!("{:x}", hasher.finalize());
+
+        let build_dir = match out_dir.or_else(|| proj.output_dir.as_ref().map(PathBuf::from)) {
+            Some(dir) => dir.join("out"),
+            None => kernel_source.join("out"),
+        };
+        Ok(CacheTarget {
+            key: format!("{}-{}-{}", project_key, branch, &digest[..16]),
+            build_dir,
+            ccache_dir: PathBuf::from(CCACHE_PREFIX),
+        })
+    }
+
+    fn archive_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.tar.zst", self.key))
+    }
+}
+
+/// Prints the cache key (kernel_source HEAD + merged project config) and
+/// exports it as KOKUBAN_CACHE_KEY for `actions/cache`.
+pub fn handle_cache_key(project_key: &str, branch: &str, out_dir: Option<PathBuf>) -> Result<()> {
+    let target = CacheTarget::resolve(project_key, branch, out_dir)?;
+    println!("{}", target.key);
+    set_github_env("KOKUBAN_CACHE_KEY", &target.key)?;
+    Ok(())
+}
+
+/// Packs the build dir and `.ccache` into `<dir>/<key>.tar.zst`.
+pub fn handle_cache_save(
+    project_key: &str,
+    branch: &str,
+    dir: &Path,
+    out_dir: Option<PathBuf>,
+) -> Result<()> {
+    let target = CacheTarget::resolve(project_key, branch, out_dir)?;
+    if !target.build_dir.exists() {
+        return Err(anyhow!(
+            "Nothing to cache, {:?} doesn't exist",
+            target.build_dir
+        ));
+    }
+    fs::create_dir_all(dir)?;
+    let archive = target.archive_path(dir);
+    let partial = archive.with_extension("zst.partial");
+
+    info!("Saving {:?} to {:?}", target.build_dir, archive);
+    let encoder = zstd::Encoder::new(File::create(&partial)?, 3)?.auto_finish();
+    let mut builder = tar::Builder::new(encoder);
+    // out/source points back at the kernel tree; archive links, not targets.
+    builder.follow_symlinks(false);
+    builder.append_dir_all(BUILD_PREFIX, &target.build_dir)?;
+    if target.ccache_dir.is_dir() {
+        builder.append_dir_all(CCACHE_PREFIX, &target.ccache_dir)?;
+    }
+    builder.into_inner()?;
+    fs::rename(&partial, &archive)?;
+
+    info!(
+        "Saved cache {} ({} MiB)",
+        target.key,
+        fs::metadata(&archive)?.len() / (1024 * 1024)
+    );
+    Ok(())
+}
+
+/// Unpacks `<dir>/<key>.tar.zst` if there is one. Restored files get the
+/// current time as mtime so make sees them as newer than the fresh checkout
+/// and only rebuilds what actually changed. Exports KOKUBAN_CACHE_HIT.
+pub fn handle_cache_restore(
+    project_key: &str,
+    branch: &str,
+    dir: &Path,
+    out_dir: Option<PathBuf>,
+) -> Result<()> {
+    let target = CacheTarget::resolve(project_key, branch, out_dir)?;
+    let archive = target.archive_path(dir);
+    if !archive.exists() {
+        info!("Cache miss for {}", target.key);
+        set_github_env("KOKUBAN_CACHE_HIT", "false")?;
+        return Ok(());
+    }
+
+    info!("Restoring {:?}", archive);
+    let decoder = zstd::Decoder::new(File::open(&archive)?)?;
+    let mut tar = tar::Archive::new(decoder);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow!("Unsafe path in cache archive: {:?}", path));
+        }
+        let dest = if let Ok(rest) = path.strip_prefix(BUILD_PREFIX) {
+            target.build_dir.join(rest)
+        } else if let Ok(rest) = path.strip_prefix(CCACHE_PREFIX) {
+            target.ccache_dir.join(rest)
+        } else {
+            continue;
+        };
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry.set_preserve_mtime(false);
+        entry
+            .unpack(&dest)
+            .with_context(|| format!("Failed to restore {:?}", dest))?;
+    }
+    info!("Restored cache {}", target.key);
+    set_github_env("KOKUBAN_CACHE_HIT", "true")?;
+    Ok(())
+}
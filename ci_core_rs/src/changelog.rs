@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::utils::run_cmd;
+
+/// Commits listed before the rest is summarized as "... and N more".
+const MAX_COMMITS: usize = 100;
+/// Commits shown when no earlier release tag is found.
+const FALLBACK_COMMITS: &str = "20";
+const OTHER_GROUP: &str = "Other";
+
+/// Markdown changelog of `kernel_source` since the newest local tag matching
+/// `<tag_prefix>*`, with subjects grouped by their `subsystem:` prefix.
+/// Without such a tag the latest few commits are listed instead; a shallow
+/// checkout only has what it fetched. `None` when there is nothing to list.
+pub fn release_notes(kernel_source: &Path, tag_prefix: &str) -> Result<Option<String>> {
+    let pattern = format!("{}*", tag_prefix);
+    let previous = run_cmd(
+        &[
+            "git",
+            "tag",
+            "--list",
+            &pattern,
+            "--merged",
+            "HEAD",
+            "--sort=-creatordate",
+        ],
+        Some(kernel_source),
+        true,
+    )?
+    .unwrap_or_default()
+    .lines()
+    .next()
+    .map(str::to_string);
+
+    let range = previous.as_ref().map(|tag| format!("{}..HEAD", tag));
+    let mut cmd = vec!["git", "log", "--no-merges", "--format=%s"];
+    match &range {
+        Some(range) => cmd.push(range),
+        None => cmd.extend(["-n", FALLBACK_COMMITS]),
+    }
+    let log = run_cmd(&cmd, Some(kernel_source), true)?.unwrap_or_default();
+    let subjects: Vec<&str> = log.lines().filter(|l| !l.trim().is_empty()).collect();
+    if subjects.is_empty() {
+        return Ok(None);
+    }
+
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut other = Vec::new();
+    for subject in subjects.iter().take(MAX_COMMITS) {
+        match prefix(subject) {
+            Some(p) => groups.entry(p).or_default().push(subject),
+            None => other.push(*subject),
+        }
+    }
+
+    let mut notes = match &previous {
+        Some(tag) => format!("### Changes since {}\n", tag),
+        None => "### Recent changes\n".to_string(),
+    };
+    for (name, items) in groups.iter().chain([(&OTHER_GROUP, &other)]) {
+        if items.is_empty() {
+            continue;
+        }
+        notes.push_str(&format!("\n**{}**\n", name));
+        for item in items {
+            notes.push_str(&format!("- {}\n", item));
+        }
+    }
+    if subjects.len() > MAX_COMMITS {
+        notes.push_str(&format!(
+            "\n... and {} more\n",
+            subjects.len() - MAX_COMMITS
+        ));
+    }
+    Ok(Some(notes))
+}
+
+/// `arm64` for "arm64: dts: ...". Only a single word counts, so a subject
+/// that merely contains a colon lands in "Other".
+fn prefix(subject: &str) -> Option<&str> {
+    let (head, _) = subject.split_once(':')?;
+    let head = head.trim();
+    (!head.is_empty() && !head.contains(char::is_whitespace)).then_some(head)
+}
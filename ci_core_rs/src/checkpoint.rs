@@ -0,0 +1,136 @@
+use anyhow::{Context, Result, anyhow};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::patch::AppliedPatch;
+
+/// Stages a failed build can be picked up again from.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeStage {
+    /// Package the kernel the last build compiled.
+    Package,
+    /// Publish the zips the last build packaged.
+    Release,
+}
+
+impl ResumeStage {
+    /// The stage that has to have finished before this one.
+    fn after(self) -> &'static str {
+        match self {
+            ResumeStage::Package => "build",
+            ResumeStage::Release => "package",
+        }
+    }
+}
+
+/// What a build has finished so far, kept next to its output in the out
+/// dir. A new build starts by removing it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Checkpoint {
+    pub project: String,
+    pub branch: String,
+    /// kernel_source HEAD the kernel was built from.
+    pub commit: Option<String>,
+    pub completed: Vec<String>,
+    pub kernel_version: String,
+    pub ksu_commit: Option<String>,
+    /// Patches applied before the build, for the provenance.
+    #[serde(default)]
+    pub patches: Vec<AppliedPatch>,
+    /// Set once `package` is done.
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub zip: Option<PathBuf>,
+    #[serde(default)]
+    pub extra_artifacts: Vec<PathBuf>,
+}
+
+fn path(out: &Path) -> PathBuf {
+    out.join("kokuban-checkpoint.json")
+}
+
+impl Checkpoint {
+    /// Forgets the last build's progress in `out`.
+    pub fn clear(out: &Path) {
+        let path = path(out);
+        if path.exists()
+            && let Err(e) = fs::remove_file(&path)
+        {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+
+    /// The checkpoint in `out`, if a build left one there.
+    pub fn load(out: &Path) -> Result<Option<Self>> {
+        let path = path(out);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let checkpoint = serde_json::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Marks `stages` done and saves the checkpoint into `out`. A checkpoint
+    /// that can't be saved only costs the chance to resume.
+    pub fn complete(&mut self, stages: &[&str], out: &Path) {
+        for stage in stages {
+            if !self.completed.iter().any(|s| s == stage) {
+                self.completed.push(stage.to_string());
+            }
+        }
+        let path = path(out);
+        let saved = serde_json::to_string_pretty(self)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&path, json)?));
+        if let Err(e) = saved {
+            warn!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+
+    /// Loads the checkpoint in `out` and checks that the build it records
+    /// can go on at `stage`.
+    pub fn resume(
+        out: &Path,
+        project: &str,
+        branch: &str,
+        commit: Option<&str>,
+        stage: ResumeStage,
+    ) -> Result<Self> {
+        let path = path(out);
+        let Some(checkpoint) = Self::load(out)? else {
+            return Err(anyhow!(
+                "No checkpoint at {}; run the build without --resume-from first",
+                path.display()
+            ));
+        };
+        if checkpoint.project != project || checkpoint.branch != branch {
+            return Err(anyhow!(
+                "{} is from {} / {}, not {} / {}",
+                path.display(),
+                checkpoint.project,
+                checkpoint.branch,
+                project,
+                branch
+            ));
+        }
+        if checkpoint.commit.as_deref() != commit {
+            return Err(anyhow!(
+                "kernel_source is at {} but the checkpoint was built from {}; run a full build",
+                commit.unwrap_or("unknown"),
+                checkpoint.commit.as_deref().unwrap_or("unknown")
+            ));
+        }
+        if !checkpoint.completed.iter().any(|s| s == stage.after()) {
+            return Err(anyhow!(
+                "The last build didn't finish `{}` (done: {}); it can't be resumed there",
+                stage.after(),
+                checkpoint.completed.join(", ")
+            ));
+        }
+        Ok(checkpoint)
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::build::kernel_source_location;
+use crate::config::ProjectConfig;
+use crate::utils::{load_projects, run_cmd};
+
+/// Files in kernel_source that integration and patching leave behind.
+const SOURCE_LEFTOVERS: &[&str] = &["susfs4ksu", "manual-hook.patch", "kokuban-download.patch"];
+
+/// Removes build dirs, AnyKernel3, zips and integration leftovers from
+/// kernel_source (or `project`'s kernel dir in it) and the output dir,
+/// optionally followed by `make mrproper`.
+pub fn handle_clean(
+    project: Option<&str>,
+    out_dir: Option<PathBuf>,
+    mrproper: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let kernel_source = match project {
+        Some(key) => {
+            let projects = load_projects()?;
+            let proj_val = projects
+                .get(key)
+                .ok_or_else(|| anyhow!("Project not found"))?;
+            kernel_source_location(&serde_json::from_value::<ProjectConfig>(proj_val.clone())?)
+        }
+        None => PathBuf::from("kernel_source"),
+    };
+    if project.is_none() && kernel_source.join(".repo").is_dir() {
+        warn!("kernel_source is a repo checkout, pass --project to clean the kernel in it.");
+    }
+    let artifacts_dir = out_dir.unwrap_or_else(|| PathBuf::from("."));
+
+    let mut targets = Vec::new();
+    for dir in [&kernel_source, &artifacts_dir] {
+        targets.extend(entries_matching(dir, |name| {
+            name == "out" || name.starts_with("out-")
+        })?);
+    }
+    targets.extend(
+        ["AnyKernel3", "toolchain_download"]
+            .iter()
+            .map(|name| artifacts_dir.join(name)),
+    );
+    targets.extend(entries_matching(&artifacts_dir, |name| {
+        name.ends_with(".zip")
+    })?);
+    targets.extend(SOURCE_LEFTOVERS.iter().map(|name| kernel_source.join(name)));
+    targets.extend(entries_matching(&kernel_source, |name| {
+        name.starts_with("50_add_susfs_in_") && name.ends_with(".patch")
+    })?);
+    // Only an untracked localversion is ours; some trees commit one.
+    let localversion = kernel_source.join("localversion");
+    if localversion.exists()
+        && run_cmd(
+            &["git", "ls-files", "--error-unmatch", "localversion"],
+            Some(&kernel_source),
+            true,
+        )
+        .is_err()
+    {
+        targets.push(localversion);
+    }
+
+    targets.retain(|p| p.symlink_metadata().is_ok());
+    targets.sort();
+    targets.dedup();
+
+    if targets.is_empty() {
+        info!("Nothing to clean.");
+    }
+    for path in &targets {
+        if dry_run {
+            println!("Would remove {}", path.display());
+            continue;
+        }
+        info!("Removing {}", path.display());
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+
+    if mrproper {
+        if !kernel_source.exists() {
+            warn!("no kernel_source, skipping make mrproper.");
+        } else if dry_run {
+            println!("Would run make mrproper in {}", kernel_source.display());
+        } else {
+            run_cmd(&["make", "mrproper"], Some(&kernel_source), false)?;
+        }
+    }
+    Ok(())
+}
+
+/// Direct children of `dir` whose file name matches.
+fn entries_matching(dir: &Path, matches: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if matches(&entry.file_name().to_string_lossy()) {
+            found.push(entry.path());
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::history::load_metrics;
+use crate::kconfig::parse_fragment;
+
+/// "+1234 (+0.5%)", "-20 (-1.0%)" or "±0".
+fn delta(old: u64, new: u64) -> String {
+    if old == new {
+        return "±0".to_string();
+    }
+    let diff = new as i128 - old as i128;
+    if old == 0 {
+        return format!("{:+}", diff);
+    }
+    format!("{:+} ({:+.1}%)", diff, diff as f64 * 100.0 / old as f64)
+}
+
+/// Rows whose value differs, with 0 for keys only one side has.
+fn changed(old: &BTreeMap<String, u64>, new: &BTreeMap<String, u64>) -> Vec<(String, u64, u64)> {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .map(|k| {
+            let a = old.get(k).copied().unwrap_or(0);
+            let b = new.get(k).copied().unwrap_or(0);
+            (k.clone(), a, b)
+        })
+        .filter(|(_, a, b)| a != b)
+        .collect()
+}
+
+fn print_table(rows: &[(String, u64, u64)]) {
+    let width = rows.iter().map(|(k, _, _)| k.len()).max().unwrap_or(0);
+    for (key, a, b) in rows {
+        println!("  {:<width$}  {:>12}  {:>12}  {}", key, a, b, delta(*a, *b));
+    }
+}
+
+/// Diffs the `.config`, image size, section sizes and warning counts of two
+/// recorded builds.
+pub fn handle_compare(a: i64, b: i64) -> Result<()> {
+    let old = load_metrics(a)?;
+    let new = load_metrics(b)?;
+    println!("Comparing {}", old.label);
+    println!("     with {}", new.label);
+    let (old, new) = (old.metrics, new.metrics);
+
+    println!("\nImage size:");
+    match (old.image_size, new.image_size) {
+        (Some(a), Some(b)) => println!("  {} -> {} bytes, {}", a, b, delta(a, b)),
+        _ => println!("  not recorded for both builds"),
+    }
+
+    println!("\nSections:");
+    if old.sections.is_empty() || new.sections.is_empty() {
+        println!("  not recorded for both builds");
+    } else {
+        let total = |s: &BTreeMap<String, u64>| s.values().sum::<u64>();
+        let rows = changed(&old.sections, &new.sections);
+        if rows.is_empty() {
+            println!("  no changes");
+        } else {
+            print_table(&rows);
+        }
+        let (a, b) = (total(&old.sections), total(&new.sections));
+        println!("  total {} -> {}, {}", a, b, delta(a, b));
+    }
+
+    println!("\nWarnings:");
+    let (wa, wb) = (
+        old.warnings.values().sum::<u64>(),
+        new.warnings.values().sum::<u64>(),
+    );
+    println!("  {} -> {}, {}", wa, wb, delta(wa, wb));
+    print_table(&changed(&old.warnings, &new.warnings));
+
+    println!("\n.config:");
+    let (Some(config_a), Some(config_b)) = (&old.config, &new.config) else {
+        println!("  not recorded for both builds");
+        return Ok(());
+    };
+    let parse =
+        |c: &str| -> BTreeMap<String, Option<String>> { parse_fragment(c).into_iter().collect() };
+    let (config_a, config_b) = (parse(config_a), parse(config_b));
+    let symbols: BTreeSet<&String> = config_a.keys().chain(config_b.keys()).collect();
+    let show = |v: Option<&Option<String>>| match v {
+        None => "(absent)".to_string(),
+        Some(None) => "n".to_string(),
+        Some(Some(v)) => v.clone(),
+    };
+    let diffs: Vec<(String, String, String)> = symbols
+        .into_iter()
+        .filter(|s| config_a.get(*s) != config_b.get(*s))
+        .map(|s| {
+            (
+                format!("CONFIG_{}", s),
+                show(config_a.get(s)),
+                show(config_b.get(s)),
+            )
+        })
+        .collect();
+    if diffs.is_empty() {
+        println!("  no changes");
+    }
+    let width = diffs.iter().map(|(s, _, _)| s.len()).max().unwrap_or(0);
+    for (symbol, a, b) in diffs {
+        println!("  {:<width$}  {} -> {}", symbol, a, b);
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::process::Command;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::config::DistccConfig;
+use crate::events::{self, Event};
+use crate::utils::{run_cmd, run_cmd_with_env};
+
+pub const COMPILER_CACHE_VALUES: &[&str] = &["ccache", "sccache", "none"];
+
+/// Env vars that point sccache at a shared remote backend.
+const SCCACHE_REMOTE_VARS: &[&str] = &[
+    "SCCACHE_BUCKET",
+    "SCCACHE_GCS_BUCKET",
+    "SCCACHE_REDIS",
+    "SCCACHE_REDIS_ENDPOINT",
+    "SCCACHE_MEMCACHED",
+    "SCCACHE_WEBDAV_ENDPOINT",
+    "SCCACHE_GHA_ENABLED",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompilerCache {
+    Ccache,
+    Sccache,
+}
+
+impl CompilerCache {
+    /// `compiler_cache` from the config. The default uses ccache when it is
+    /// installed; asking for sccache explicitly fails if it isn't.
+    pub fn resolve(setting: Option<&str>) -> Result<Option<Self>> {
+        match setting {
+            None => Ok(run_cmd(&["which", "ccache"], None, true)
+                .is_ok()
+                .then_some(CompilerCache::Ccache)),
+            Some("none") => Ok(None),
+            Some(name @ ("ccache" | "sccache")) => {
+                if run_cmd(&["which", name], None, true).is_err() {
+                    return Err(anyhow!("compiler_cache is {} but it isn't on PATH", name));
+                }
+                Ok(Some(if name == "ccache" {
+                    CompilerCache::Ccache
+                } else {
+                    CompilerCache::Sccache
+                }))
+            }
+            Some(other) => Err(anyhow!(
+                "Unknown compiler_cache '{}' (expected one of: {})",
+                other,
+                COMPILER_CACHE_VALUES.join(", ")
+            )),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CompilerCache::Ccache => "ccache",
+            CompilerCache::Sccache => "sccache",
+        }
+    }
+
+    /// Wraps CC/CXX and points the cache at a dir in the current directory
+    /// (sccache only when no remote backend is configured in the env).
+    pub fn configure(
+        self,
+        build_env: &mut HashMap<String, String>,
+        cc: &str,
+        cxx: Option<&str>,
+        max_size: &str,
+    ) -> Result<()> {
+        let tool = self.name();
+        build_env.insert("CC".to_string(), format!("{} {}", tool, cc));
+        if let Some(cxx) = cxx {
+            build_env.insert("CXX".to_string(), format!("{} {}", tool, cxx));
+        }
+        let cwd = env::current_dir()?;
+        match self {
+            CompilerCache::Ccache => {
+                build_env.insert(
+                    "CCACHE_DIR".to_string(),
+                    cwd.join(".ccache").display().to_string(),
+                );
+                run_cmd_with_env(&["ccache", "-M", max_size], None, build_env)?;
+            }
+            CompilerCache::Sccache => {
+                match SCCACHE_REMOTE_VARS.iter().find(|v| env::var(v).is_ok()) {
+                    Some(var) => info!("sccache: remote backend from {}", var),
+                    None => {
+                        build_env.insert(
+                            "SCCACHE_DIR".to_string(),
+                            cwd.join(".sccache").display().to_string(),
+                        );
+                        build_env.insert("SCCACHE_CACHE_SIZE".to_string(), max_size.to_string());
+                    }
+                }
+                // Already running is fine; a broken server shows up in the build.
+                let _ = Command::new("sccache")
+                    .arg("--start-server")
+                    .envs(build_env.iter())
+                    .output();
+            }
+        }
+        Ok(())
+    }
+
+    /// Current hit/miss counters, if the tool can report them.
+    pub fn read_stats(self, build_env: &HashMap<String, String>) -> Option<CacheStats> {
+        match self {
+            CompilerCache::Ccache => {
+                let out = tool_output("ccache", &["--print-stats"], build_env)?;
+                let mut stats = CacheStats::default();
+                for line in out.lines() {
+                    let Some((key, value)) = line.split_once('\t') else {
+                        continue;
+                    };
+                    let value: u64 = value.trim().parse().unwrap_or(0);
+                    match key {
+                        "direct_cache_hit" | "preprocessed_cache_hit" => stats.hits += value,
+                        "cache_miss" => stats.misses += value,
+                        _ => {}
+                    }
+                }
+                Some(stats)
+            }
+            CompilerCache::Sccache => {
+                let out = tool_output(
+                    "sccache",
+                    &["--show-stats", "--stats-format", "json"],
+                    build_env,
+                )?;
+                let json: serde_json::Value = serde_json::from_str(&out).ok()?;
+                let sum = |key: &str| -> u64 {
+                    json["stats"][key]["counts"]
+                        .as_object()
+                        .map(|counts| counts.values().filter_map(|v| v.as_u64()).sum())
+                        .unwrap_or(0)
+                };
+                Some(CacheStats {
+                    hits: sum("cache_hits"),
+                    misses: sum("cache_misses"),
+                })
+            }
+        }
+    }
+
+    /// Emits the counters gained since `before` as `cache_stats` events
+    /// every few seconds until the watch is dropped, and once more then.
+    pub fn watch(self, before: CacheStats, build_env: &HashMap<String, String>) -> StatsWatch {
+        let (stop, stopped) = mpsc::channel();
+        let build_env = build_env.clone();
+        let thread = thread::spawn(move || {
+            loop {
+                // Stopped, or the sender is gone: one last reading.
+                let done = !matches!(
+                    stopped.recv_timeout(Duration::from_secs(5)),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                if let Some(stats) = self.read_stats(&build_env) {
+                    let stats = stats.since(&before);
+                    events::emit(Event::CacheStats {
+                        tool: self.name(),
+                        hits: stats.hits,
+                        misses: stats.misses,
+                    });
+                }
+                if done {
+                    return;
+                }
+            }
+        });
+        StatsWatch {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Prints the tool's own statistics; failures are only warned about.
+    pub fn print_summary(self, label: &str, build_env: &HashMap<String, String>) {
+        info!("--- {} {} ---", self.name(), label);
+        let cmd: &[&str] = match self {
+            CompilerCache::Ccache => &["ccache", "-s"],
+            CompilerCache::Sccache => &["sccache", "--show-stats"],
+        };
+        if let Err(e) = run_cmd_with_env(cmd, None, build_env) {
+            warn!("{} failed: {}", cmd.join(" "), e);
+        }
+    }
+}
+
+/// Sets up distcc/icecc for the build and returns the wrapper to put in
+/// front of the compiler, or `None` when ccache will call it through
+/// CCACHE_PREFIX instead.
+pub fn configure_distributed(
+    cfg: &DistccConfig,
+    cache: Option<CompilerCache>,
+    build_env: &mut HashMap<String, String>,
+) -> Result<Option<&'static str>> {
+    let tool = match cfg.tool.as_deref().unwrap_or("distcc") {
+        "distcc" => "distcc",
+        "icecc" => "icecc",
+        other => {
+            return Err(anyhow!(
+                "Unknown distcc.tool '{}' (expected distcc or icecc)",
+                other
+            ));
+        }
+    };
+    if run_cmd(&["which", tool], None, true).is_err() {
+        return Err(anyhow!("distcc is configured but {} isn't on PATH", tool));
+    }
+    if tool == "distcc" {
+        if cfg.hosts.is_empty() {
+            return Err(anyhow!("distcc.hosts is empty"));
+        }
+        build_env.insert("DISTCC_HOSTS".to_string(), cfg.hosts.join(" "));
+        info!("distcc hosts: {}", cfg.hosts.join(" "));
+    }
+    match cache {
+        Some(CompilerCache::Ccache) => {
+            build_env.insert("CCACHE_PREFIX".to_string(), tool.to_string());
+            Ok(None)
+        }
+        Some(CompilerCache::Sccache) => Err(anyhow!(
+            "distcc can't be combined with sccache, use sccache's own distributed mode"
+        )),
+        None => Ok(Some(tool)),
+    }
+}
+
+fn tool_output(tool: &str, args: &[&str], build_env: &HashMap<String, String>) -> Option<String> {
+    let output = Command::new(tool)
+        .args(args)
+        .envs(build_env)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A running `CompilerCache::watch`.
+pub struct StatsWatch {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for StatsWatch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Hit/miss counters of a compiler cache.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Counters accumulated since `before` was read.
+    pub fn since(&self, before: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits.saturating_sub(before.hits),
+            misses: self.misses.saturating_sub(before.misses),
+        }
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 * 100.0 / total as f64
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hits / {} misses ({:.1}%)",
+            self.hits,
+            self.misses,
+            self.hit_rate()
+        )
+    }
+}
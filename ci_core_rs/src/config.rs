@@ -1,14 +1,16 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ProjectConfig {
+    pub extends: Option<String>,
     pub repo: String,
     pub defconfig: String,
     pub localversion_base: String,
     pub lto: Option<String>,
     pub supported_ksu: Option<Vec<String>>,
     pub toolchain_urls: Option<Vec<String>>,
+    pub toolchain_sha256: Option<Vec<String>>,
     pub toolchain_path_prefix: Option<String>,
     pub toolchain_path_exports: Option<Vec<String>>,
     pub anykernel_repo: Option<String>,
@@ -16,44 +18,705 @@ pub struct ProjectConfig {
     pub zip_name_prefix: Option<String>,
     pub version_method: Option<String>,
     pub extra_host_env: Option<bool>,
+    /// Build with fixed timestamps, user and host, and zip deterministically,
+    /// so two builds of the same tree give identical files.
+    pub reproducible: Option<bool>,
     pub disable_security: Option<Vec<String>>,
     pub readme_placeholders: Option<HashMap<String, String>>,
+    pub branches: Option<HashMap<String, serde_json::Value>>,
+    /// Pins the KernelSU setup script and source to a commit, tag or branch.
+    pub ksu_ref: Option<String>,
+    /// SUSFS branch for variants that use it; detected from the kernel when unset.
+    pub susfs_branch: Option<String>,
+    /// Extra patches applied after KernelSU integration.
+    pub patches: Option<Vec<PatchSpec>>,
+    /// Runs checkpatch.pl over `patches/<project>/<branch>/` before applying.
+    pub checkpatch: Option<CheckpatchConfig>,
+    /// Manual hook patch for variants that use one; the URL wins over the version.
+    pub manual_hook_url: Option<String>,
+    pub manual_hook_version: Option<String>,
+    /// Kconfig fragments merged into out/.config after defconfig.
+    pub config_fragments: Option<Vec<String>>,
+    /// Kconfig options set with scripts/config after defconfig; enables
+    /// accept `NAME=value`.
+    pub enable_configs: Option<Vec<String>>,
+    pub disable_configs: Option<Vec<String>>,
+    /// Fail the build when olddefconfig drops a requested option.
+    pub strict_config: Option<bool>,
+    /// Directory for the build dir, AnyKernel3 and zips (`--out-dir` wins).
+    pub output_dir: Option<String>,
+    /// Target architecture: arm64 (default), arm, x86_64 or riscv64.
+    pub arch: Option<String>,
+    /// `clang` (default, LLVM=1) or `gcc` for vendor kernels that need it.
+    pub compiler: Option<String>,
+    /// make targets to build and package, e.g. `["Image.gz-dtb", "dtbs", "modules"]`.
+    /// Defaults to the arch's image with make's default target.
+    pub make_targets: Option<Vec<String>>,
+    pub modules: Option<ModulesConfig>,
+    pub dtb: Option<DtbConfig>,
+    pub boot_image: Option<BootImageConfig>,
+    /// Signs the repacked boot.img for verified boot.
+    pub avb: Option<AvbConfig>,
+    /// Runs sparse, smatch or the clang analyzer after the build.
+    pub analyze: Option<AnalyzeConfig>,
+    /// Checks exported symbols against the GKI symbol lists after the build.
+    pub abi_check: Option<AbiCheckConfig>,
+    /// Boots the kernel in QEMU after the build and fails on a panic.
+    pub boot_test: Option<BootTestConfig>,
+    /// Runs KUnit suites after the build, in UML or QEMU.
+    pub kunit: Option<KunitConfig>,
+    /// Device the `deploy` command flashes the build onto.
+    pub deploy: Option<DeployConfig>,
+    /// Builds `serve` starts on a timer, e.g. nightly.
+    pub schedules: Option<Vec<ScheduleConfig>>,
+    /// Enables CONFIG_MODULE_SIG and signs modules on install.
+    pub module_signing: Option<ModuleSigningConfig>,
+    /// make -j; defaults to nproc.
+    pub jobs: Option<u32>,
+    /// make -l: don't start new jobs above this load average.
+    pub load_average: Option<f64>,
+    /// Niceness for the build and everything it spawns.
+    pub nice: Option<i32>,
+    pub timeout_minutes: Option<TimeoutConfig>,
+    /// How downloads, clones, setup scripts and uploads are retried.
+    pub retry: Option<RetryConfig>,
+    /// GiB that must be free where the build and toolchains go before it
+    /// starts (default 40, 0 skips the check).
+    pub min_free_space_gib: Option<u64>,
+    /// Run the whole build inside this container image.
+    pub container: Option<ContainerConfig>,
+    /// Appended to every make invocation, e.g. `["W=1", "KBUILD_BUILD_USER=ci"]`.
+    pub extra_make_args: Option<Vec<String>>,
+    /// Extra compiler flags, passed as KCFLAGS (e.g. `-Wno-error=...`).
+    pub kcflags: Option<String>,
+    /// Extra linker flags for modules, passed as LDFLAGS_MODULE. The vmlinux
+    /// link flags are set by the arch Makefile and can't be extended.
+    pub kldflags: Option<String>,
+    /// Fail the build when it has more compiler warnings than this that the
+    /// last successful build of the branch didn't have.
+    pub max_new_warnings: Option<u32>,
+    /// Fail the build when the packaged kernel image (the first of
+    /// `make_targets` under arch/<arch>/boot/, else the arch's image) is
+    /// larger than this many bytes.
+    pub max_image_size: Option<u64>,
+    /// Kernel source to clone into ./kernel_source (URL or `owner/name`);
+    /// defaults to `repo` for build-all. Existing clean checkouts are updated.
+    pub source_repo: Option<String>,
+    pub source_branch: Option<String>,
+    /// Clone depth; 0 fetches full history. Defaults to 1.
+    pub source_depth: Option<u32>,
+    /// Builds from a `repo` manifest checkout instead of a single repo.
+    pub source_manifest: Option<ManifestConfig>,
+    /// Local mirrors (`<name>.git` or `<name>` per repo) that clones of the
+    /// kernel, SUSFS and AnyKernel3 borrow objects from; `repo --reference`
+    /// for manifests.
+    pub git_reference_dir: Option<String>,
+    /// Local compiler cache size (`ccache -M`, SCCACHE_CACHE_SIZE). Defaults to 5G.
+    pub ccache_max_size: Option<String>,
+    /// `ccache` (default, when installed), `sccache` or `none`. sccache uses a
+    /// remote backend when SCCACHE_BUCKET/SCCACHE_GCS_BUCKET/... are set.
+    pub compiler_cache: Option<String>,
+    pub distcc: Option<DistccConfig>,
+    /// Signs the zip and its SHA256SUMS with detached `.asc` signatures.
+    pub gpg: Option<GpgConfig>,
+    pub release: Option<ReleaseConfig>,
+    pub notify: Option<NotifyConfig>,
+    /// Where release artifacts go besides (or instead of) GitHub Releases.
+    #[serde(alias = "upload")]
+    pub distribution: Option<DistributionConfig>,
 }
 
+/// GitHub release options for `--do-release`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ReleaseConfig {
+    pub draft: bool,
+    pub prerelease: bool,
+    /// If the tag already has a release, replace assets with the same name
+    /// instead of failing on them.
+    pub overwrite_existing: bool,
+    /// Branch or commit the tag is created from; the default branch if unset.
+    pub target_commitish: Option<String>,
+    /// Append GitHub's generated notes.
+    pub generate_notes: bool,
+}
+
+/// Where releases are announced, on top of the `_globals` channels.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Report failed `--do-release` and unattended (CI, `serve`, scheduled)
+    /// builds, with the failing stage and the end of the command output, to
+    /// the project's own Telegram chat, Matrix room, mail recipients and
+    /// webhooks (never the `_globals` channels). Defaults to true.
+    pub on_failure: Option<bool>,
+    pub telegram: Option<TelegramConfig>,
+    pub discord: Option<WebhookConfig>,
+    pub slack: Option<WebhookConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub email: Option<EmailConfig>,
+}
+
+/// SMTP delivery, e.g. to a mailing list.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EmailConfig {
+    pub host: String,
+    /// Defaults to 587 for `starttls`, 465 for `tls` and 25 for `none`.
+    pub port: Option<u16>,
+    /// `starttls` (default), `tls` (implicit TLS) or `none`.
+    pub tls: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    /// Env vars with the login; no AUTH when the username var is unset.
+    /// Default to SMTP_USERNAME and SMTP_PASSWORD.
+    pub username_env: Option<String>,
+    pub password_env: Option<String>,
+}
+
+/// A Matrix room the bot account has joined.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MatrixConfig {
+    /// e.g. `https://matrix.org`.
+    pub homeserver: String,
+    /// `!opaque:server` id; aliases (`#room:server`) are resolved first.
+    pub room_id: String,
+    /// Env var with the access token; defaults to MATRIX_ACCESS_TOKEN.
+    pub access_token_env: Option<String>,
+}
+
+/// An incoming webhook; give the URL directly or, since it is a secret,
+/// the env var holding it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: Option<String>,
+    pub url_env: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TelegramConfig {
+    /// Channel or group to post to; TELEGRAM_CHAT_ID if unset.
+    pub chat_id: Option<String>,
+    /// Forum topic within `chat_id`.
+    pub topic_id: Option<i32>,
+    /// Also send the release files (up to 50 MB each); defaults to true.
+    pub upload_files: Option<bool>,
+    /// Env var with the bot token; defaults to TELEGRAM_BOT_TOKEN.
+    pub bot_token_env: Option<String>,
+}
+
+/// Upload targets for `--do-release`; every one that is set gets the
+/// artifacts under a directory named after the release tag.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DistributionConfig {
+    /// Publish a GitHub release (and notify); defaults to true.
+    pub github_release: Option<bool>,
+    pub s3: Option<S3Config>,
+    /// e.g. SourceForge: host `frs.sourceforge.net`, path
+    /// `/home/frs/project/<project>`.
+    pub sftp: Option<SshTargetConfig>,
+    pub rsync: Option<SshTargetConfig>,
+}
+
+/// A server reached over ssh with key auth.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SshTargetConfig {
+    pub host: String,
+    pub user: String,
+    pub port: Option<u16>,
+    /// Remote directory the tag directory is created in.
+    pub path: String,
+    /// Private key file, relative to the CI root.
+    pub key: Option<String>,
+    /// Env var holding the private key itself, for CI secrets.
+    pub key_env: Option<String>,
+    /// Public base URL for printed links.
+    pub public_url: Option<String>,
+}
+
+/// S3-compatible bucket (AWS, R2, MinIO, ...). Credentials come from
+/// AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY and optionally AWS_SESSION_TOKEN.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    /// Key prefix; objects go to `<prefix>/<tag>/<file>`.
+    pub prefix: Option<String>,
+    /// e.g. `https://<account>.r2.cloudflarestorage.com`; AWS if unset.
+    pub endpoint: Option<String>,
+    /// Defaults to AWS_REGION, then `us-east-1` (`auto` for R2).
+    pub region: Option<String>,
+    /// `<endpoint>/<bucket>/<key>` instead of `<bucket>.<endpoint>/<key>`;
+    /// defaults to true with a custom endpoint.
+    pub path_style: Option<bool>,
+    /// Public base URL for printed links, e.g. an R2 custom domain.
+    pub public_url: Option<String>,
+}
+
+/// Distributed compilation over a build farm.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DistccConfig {
+    /// `distcc` (default) or `icecc`, which finds hosts via its scheduler.
+    pub tool: Option<String>,
+    /// DISTCC_HOSTS entries, e.g. `["10.0.0.2/16", "10.0.0.3/16,lzo"]`.
+    pub hosts: Vec<String>,
+    /// The default `-j` (nproc) is multiplied by this. Defaults to 2.
+    pub jobs_multiplier: Option<u32>,
+}
+
+/// `repo init`/`repo sync` settings for multi-repo kernel trees.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ManifestConfig {
+    /// Manifest repo URL.
+    pub url: String,
+    pub branch: String,
+    /// Manifest file within the repo (`-m`); defaults to default.xml.
+    pub file: Option<String>,
+    /// `-g` groups filter.
+    pub groups: Option<String>,
+    /// Clone depth; 0 fetches full history. Defaults to 1.
+    pub depth: Option<u32>,
+    /// Kernel directory inside the synced tree, e.g. `common` or
+    /// `kernel_platform/msm-kernel`.
+    pub kernel_dir: Option<String>,
+}
+
+/// Minutes before a hung stage, or the whole build, is killed.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    pub total: Option<u64>,
+    /// Toolchain download and extraction.
+    pub toolchain: Option<u64>,
+    /// KernelSU setup and patches.
+    pub integrate: Option<u64>,
+    /// defconfig, fragments and olddefconfig.
+    pub configure: Option<u64>,
+    /// make, dtbs and modules.
+    pub build: Option<u64>,
+    /// Static analysis.
+    pub analyze: Option<u64>,
+    /// Booting the kernel in QEMU.
+    pub boot_test: Option<u64>,
+    /// Building and running the KUnit kernel.
+    pub kunit: Option<u64>,
+    /// AnyKernel3, images and the zip.
+    pub package: Option<u64>,
+    pub release: Option<u64>,
+}
+
+impl TimeoutConfig {
+    pub fn stage(&self, name: &str) -> Option<u64> {
+        match name {
+            "toolchain" => self.toolchain,
+            "integrate" => self.integrate,
+            "configure" => self.configure,
+            "build" => self.build,
+            "analyze" => self.analyze,
+            "boot_test" => self.boot_test,
+            "kunit" => self.kunit,
+            "package" => self.package,
+            "release" => self.release,
+            _ => None,
+        }
+    }
+}
+
+/// An image the build runs in, with the CI root, the cache and the output
+/// dirs bind-mounted at their host paths.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ContainerConfig {
+    /// e.g. `ghcr.io/owner/kernel-builder:22.04`. It needs a glibc at least
+    /// as new as the host's, as this binary is mounted in and run there.
+    pub image: String,
+    /// `docker` or `podman`; whichever is installed when unset.
+    pub engine: Option<String>,
+    /// More host environment variables to pass in, e.g. the ones notifier
+    /// tokens are read from.
+    pub env: Option<Vec<String>>,
+    /// Extra arguments for `run`, e.g. `["--cpus=8"]`.
+    pub run_args: Option<Vec<String>>,
+}
+
+/// Retries of network operations that failed for a reason that may pass:
+/// connection errors, 5xx responses, a failed `git clone`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Tries per operation, the first one included. Defaults to 4.
+    pub attempts: Option<u32>,
+    /// Seconds before the first retry, doubled after each one. Defaults to 2.
+    pub backoff_seconds: Option<u64>,
+    /// Upper bound for the delay. Defaults to 60.
+    pub max_backoff_seconds: Option<u64>,
+    /// Each delay is shortened by a random part of up to this fraction, so
+    /// parallel jobs don't retry in lockstep. Defaults to 0.25.
+    pub jitter: Option<f64>,
+}
+
+/// A flashable boot.img made by swapping the kernel in a stock one.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BootImageConfig {
+    /// Stock boot.img: URL, or a path relative to the CI root or kernel source.
+    pub stock: String,
+    /// Built image to put in, relative to `arch/<arch>/boot`; defaults to the
+    /// arch's image.
+    pub kernel_image: Option<String>,
+    /// magiskboot binary; defaults to `magiskboot` on PATH.
+    pub magiskboot: Option<String>,
+    /// Also write an lz4-compressed copy (Odin/Samsung style).
+    #[serde(default)]
+    pub lz4: bool,
+}
+
+/// `avbtool add_hash_footer` settings.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AvbConfig {
+    /// Private key, relative to the CI root or kernel source (`${VAR}` works).
+    pub key: String,
+    /// Defaults to SHA256_RSA4096.
+    pub algorithm: Option<String>,
+    /// Size of the device's boot partition in bytes.
+    pub partition_size: u64,
+    /// Defaults to `boot`.
+    pub partition_name: Option<String>,
+    /// avbtool binary; defaults to `avbtool` on PATH.
+    pub avbtool: Option<String>,
+}
+
+/// GPG key for release signatures. Without `key` or `private_key_env`,
+/// gpg's default key signs.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GpgConfig {
+    /// Key ID, fingerprint or user ID to sign with.
+    pub key: Option<String>,
+    /// Variable holding an armored private key, imported into a throwaway
+    /// keyring instead of using the user's.
+    pub private_key_env: Option<String>,
+    /// Variable holding the key's passphrase.
+    pub passphrase_env: Option<String>,
+}
+
+/// Static analysis of the files integration and patches changed.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct AnalyzeConfig {
+    /// `sparse` (default), `smatch` or `clang-analyzer`.
+    pub tool: Option<String>,
+    /// Analyze the whole tree instead of the changed files.
+    pub whole_tree: bool,
+    /// Fail on more new findings than this since the last analyzed build.
+    pub max_new_findings: Option<u32>,
+}
+
+/// GKI ABI check of the built kernel's `Module.symvers`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct AbiCheckConfig {
+    /// Symbol lists, relative to the CI root or kernel source; defaults to
+    /// `android/abi_gki_aarch64`. Add the vendor's, e.g.
+    /// `android/abi_gki_aarch64_qcom`.
+    pub symbol_lists: Vec<String>,
+    /// The stock kernel's `Module.symvers` (or `vmlinux.symvers`), to catch
+    /// listed symbols whose CRC changed.
+    pub reference_symvers: Option<String>,
+    /// Report missing and changed symbols without failing the build.
+    pub allow_changes: bool,
+}
+
+/// QEMU boot smoke test, run between the build and packaging.
 #[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BootTestConfig {
+    /// Image under `arch/<arch>/boot/`; defaults to the arch's image.
+    pub kernel_image: Option<String>,
+    /// Defaults to `qemu-system-<arch>` on PATH.
+    pub qemu: Option<String>,
+    /// cpio initramfs, relative to the CI root or kernel source. Defaults to
+    /// a generated one with a placeholder `/init`.
+    pub initramfs: Option<String>,
+    /// Console text that means the kernel booted; defaults to the
+    /// "Freeing unused kernel" line printed just before init runs.
+    pub marker: Option<String>,
+    /// How long to wait for it (default 120).
+    pub timeout_seconds: Option<u64>,
+    /// Guest memory (default 1024).
+    pub memory_mib: Option<u32>,
+    /// Appended to the kernel command line.
+    pub cmdline: Option<String>,
+    /// Extra QEMU arguments, e.g. `["-smp", "2"]`.
+    pub qemu_args: Option<Vec<String>>,
+}
+
+/// KUnit run with `tools/testing/kunit/kunit.py`, in its own build dir.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct KunitConfig {
+    /// Suite filters such as `ext4*` or `kunit-example-test.*`, each run in
+    /// turn; every suite the kunitconfig enables when empty.
+    pub suites: Vec<String>,
+    /// `uml` (default) or `qemu`, which boots the project's arch.
+    pub mode: Option<String>,
+    /// .kunitconfig to build with, relative to the CI root or kernel source.
+    pub kunitconfig: Option<String>,
+    /// kunit.py's `--timeout` for each run.
+    pub timeout_seconds: Option<u64>,
+    /// Report failed tests without failing the build.
+    pub allow_failures: bool,
+}
+
+/// A timed build in `serve`, skipped when the kernel HEAD is the one the
+/// last successful build of the branch was built from.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ScheduleConfig {
+    /// Cron expression in local time (minute hour day month weekday), e.g.
+    /// `0 3 * * *`, or `@nightly`/`@weekly`.
+    pub cron: String,
+    /// Variants to build; main and every supported variant when unset.
+    pub branches: Option<Vec<String>>,
+    /// Publish the release; defaults to `serve --do-release`.
+    pub release: Option<bool>,
+    /// Build even without new commits.
+    #[serde(default)]
+    pub always: bool,
+}
+
+/// A test device `deploy` flashes the last build onto over adb/fastboot.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DeployConfig {
+    /// adb/fastboot serial; ANDROID_SERIAL or the only connected device when
+    /// unset.
+    pub serial: Option<String>,
+    /// `fastboot` flashes the boot.img, `sideload` the AnyKernel3 zip from
+    /// recovery. Defaults to fastboot when the build made a boot.img.
+    pub method: Option<String>,
+    /// Partition the boot.img is flashed to (default `boot`).
+    pub partition: Option<String>,
+    /// How long the device gets to come back up (default 300).
+    pub timeout_seconds: Option<u64>,
+}
+
+/// Kernel module signing. Without `key` or `key_env`, a key is generated
+/// once per project and kept in the cache.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ModuleSigningConfig {
+    /// PEM with the private key and certificate, as `certs/signing_key.pem`,
+    /// relative to the CI root or kernel source.
+    pub key: Option<String>,
+    /// Variable holding that PEM instead.
+    pub key_env: Option<String>,
+    /// Defaults to sha256.
+    pub hash: Option<String>,
+    /// Refuse to load unsigned modules (CONFIG_MODULE_SIG_FORCE).
+    pub force: Option<bool>,
+}
+
+/// dtb.img/dtbo.img generation from the `dtbs` target.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct DtbConfig {
+    /// Files under `arch/<arch>/boot/dts` for dtb.img, in order; `*` globs
+    /// within a path segment.
+    pub dtbs: Vec<String>,
+    /// Overlays for dtbo.img, same syntax.
+    pub dtbos: Vec<String>,
+    /// `mkdtimg` (default, Android DT table) or `concat` for dtb.img.
+    pub dtb_format: Option<String>,
+    /// mkdtimg/mkdtboimg.py binary, looked up on the toolchain PATH.
+    pub tool: Option<String>,
+    pub page_size: Option<u32>,
+    /// `zip` (default) puts `dtb`/`dtbo.img` in AnyKernel3, `separate`
+    /// writes them next to the zip.
+    pub package: Option<String>,
+}
+
+/// Kernel module build and packaging.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct ModulesConfig {
+    /// Out-of-tree module dirs, relative to the kernel source, built with `M=`.
+    pub external: Vec<String>,
+    /// Strip debug info on install (`INSTALL_MOD_STRIP=1`).
+    pub strip: bool,
+    /// `zip` (default) puts them in AnyKernel3 under vendor_dlkm/lib/modules,
+    /// `archive` writes a separate `<zip name>-modules.tar.gz`.
+    pub package: Option<String>,
+}
+
+/// `scripts/checkpatch.pl` over the patch directory's patches.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct CheckpatchConfig {
+    /// Fail the build when a patch has checkpatch errors; they are only
+    /// reported otherwise.
+    pub errors_fatal: bool,
+    /// Message types to skip, e.g. `["LONG_LINE", "FILE_PATH_CHANGES"]`.
+    pub ignore: Vec<String>,
+}
+
+/// A patch applied to the kernel source with `patch -p<strip> --fuzz=<fuzz>`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PatchSpec {
+    /// URL, or a path relative to the CI root or the kernel source.
+    pub src: String,
+    #[serde(default = "default_patch_strip")]
+    pub strip: u32,
+    #[serde(default = "default_patch_fuzz")]
+    pub fuzz: u32,
+    /// Only apply when building one of these branches/variants.
+    pub branches: Option<Vec<String>>,
+}
+
+fn default_patch_strip() -> u32 {
+    1
+}
+
+fn default_patch_fuzz() -> u32 {
+    3
+}
+
+impl PatchSpec {
+    pub fn new(src: &str) -> Self {
+        PatchSpec {
+            src: src.to_string(),
+            strip: default_patch_strip(),
+            fuzz: default_patch_fuzz(),
+            branches: None,
+        }
+    }
+
+    pub fn is_url(&self) -> bool {
+        self.src.starts_with("http://") || self.src.starts_with("https://")
+    }
+
+    pub fn applies_to(&self, names: &[&str]) -> bool {
+        self.branches
+            .as_ref()
+            .is_none_or(|b| b.iter().any(|n| names.contains(&n.as_str())))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct GlobalConfig {
     pub broadcast_channel: Option<String>,
     pub resukisu_chat_id: Option<String>,
     pub resukisu_topic_id: Option<i32>,
+    pub metrics: Option<MetricsConfig>,
+}
+
+/// Prometheus Pushgateway that every build pushes its metrics to.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MetricsConfig {
+    /// Base URL, e.g. `http://pushgateway:9091`; `PUSHGATEWAY_URL` also works
+    /// without any config.
+    pub pushgateway: Option<String>,
+    /// Job label. Defaults to `kokuban_ci`.
+    pub job: Option<String>,
+    /// Env vars with basic auth credentials, when the gateway needs them.
+    pub username_env: Option<String>,
+    pub password_env: Option<String>,
 }
 
 pub type ProjectsMap = HashMap<String, serde_json::Value>;
 
-pub const KSU_CONFIG_JSON: &str = r#"{
+/// A KernelSU flavour. Built-in entries live in `BUILTIN_KSU_VARIANTS`;
+/// the projects file can add or override them under `_ksu_variants`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct KsuVariant {
+    /// Suffix used in localversion, zip names and release tags.
+    pub label: Option<String>,
+    /// Legacy names that resolve to this variant (e.g. `sukisuultra`).
+    pub aliases: Vec<String>,
+    /// Upstream repo/branch tracked by `watch`.
+    pub repo: Option<String>,
+    pub branch: Option<String>,
+    /// setup.sh URL; `{ref}` is replaced by the pinned ref or `branch`.
+    pub setup_url: Option<String>,
+    /// Directory setup.sh clones the KernelSU source into.
+    pub source_dir: Option<String>,
+    /// Arguments for setup.sh when `update` commits it into a kernel repo.
+    pub setup_args: Vec<String>,
+    /// Arguments for setup.sh when run at build time. `None` skips it.
+    pub build_setup_args: Option<Vec<String>>,
+    /// SUSFS branch, or `auto` to derive it from the kernel version.
+    pub susfs_branch: Option<String>,
+    /// Manual hook patch URL; `{version}` is replaced by the hook version.
+    pub manual_hook_url: Option<String>,
+    pub manual_hook_version: Option<String>,
+    /// Extra patches (URLs or paths relative to the kernel source).
+    pub patches: Vec<String>,
+    pub config_enable: Vec<String>,
+    pub config_disable: Vec<String>,
+}
+
+impl KsuVariant {
+    /// The project's `manual_hook_url`/`manual_hook_version` override the
+    /// variant's; `None` when the variant doesn't use a manual hook.
+    pub fn manual_hook_url_for(&self, proj: &ProjectConfig) -> Option<String> {
+        let template = self.manual_hook_url.as_ref()?;
+        if let Some(url) = &proj.manual_hook_url {
+            return Some(url.clone());
+        }
+        let version = proj
+            .manual_hook_version
+            .as_deref()
+            .or(self.manual_hook_version.as_deref())
+            .unwrap_or_default();
+        Some(template.replace("{version}", version))
+    }
+
+    pub fn setup_url_for(&self, ksu_ref: Option<&str>) -> Option<String> {
+        let git_ref = ksu_ref.or(self.branch.as_deref()).unwrap_or("main");
+        self.setup_url
+            .as_ref()
+            .map(|url| url.replace("{ref}", git_ref))
+    }
+}
+
+pub type KsuVariants = BTreeMap<String, KsuVariant>;
+
+pub const BUILTIN_KSU_VARIANTS: &str = r#"{
+    "lkm": {
+        "label": "LKM",
+        "aliases": ["main"]
+    },
     "ksu": {
+        "label": "KSU",
         "repo": "https://github.com/tiann/KernelSU.git",
         "branch": "main",
-        "setup_url": "https://raw.githubusercontent.com/tiann/KernelSU/main/kernel/setup.sh",
-        "setup_args": ["main"]
+        "setup_url": "https://raw.githubusercontent.com/tiann/KernelSU/{ref}/kernel/setup.sh",
+        "setup_args": ["main"],
+        "build_setup_args": ["-"]
     },
     "mksu": {
+        "label": "MKSU",
         "repo": "https://github.com/5ec1cff/KernelSU.git",
         "branch": "main",
-        "setup_url": "https://raw.githubusercontent.com/5ec1cff/KernelSU/main/kernel/setup.sh",
-        "setup_args": ["main"]
+        "setup_url": "https://raw.githubusercontent.com/5ec1cff/KernelSU/{ref}/kernel/setup.sh",
+        "setup_args": ["main"],
+        "build_setup_args": ["-"]
     },
     "resukisu": {
+        "label": "ReSuki",
+        "aliases": ["sukisuultra"],
         "repo": "https://github.com/ReSukiSU/ReSukiSU.git",
         "branch": "main",
-        "setup_url": "https://raw.githubusercontent.com/ReSukiSU/ReSukiSU/main/kernel/setup.sh",
-        "setup_args": ["main"]
+        "setup_url": "https://raw.githubusercontent.com/ReSukiSU/ReSukiSU/{ref}/kernel/setup.sh",
+        "setup_args": ["main"],
+        "build_setup_args": ["builtin"]
+    },
+    "wildksu": {
+        "label": "WildKSU",
+        "branch": "wild",
+        "setup_url": "https://raw.githubusercontent.com/WildKernels/Wild_KSU/{ref}/kernel/setup.sh",
+        "build_setup_args": ["wild"],
+        "susfs_branch": "auto",
+        "manual_hook_url": "https://github.com/SukiSU-Ultra/SukiSU_patch/raw/83aa64b7548890bb1f2eff6c990c03a1802df27b/hooks/scope_min_manual_hooks_{version}.patch",
+        "manual_hook_version": "v1.6",
+        "config_enable": ["KSU_MANUAL_HOOK", "KSU_SUSFS"],
+        "config_disable": ["KSU_KPROBES_HOOK", "KSU_SUSFS_SUS_SU"]
     }
 }"#;
-
-#[derive(Deserialize)]
-pub struct KsuConfigItem {
-    pub repo: String,
-    pub branch: String,
-    pub setup_url: String,
-    pub setup_args: Vec<String>,
-}
@@ -0,0 +1,145 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{self, Path, PathBuf};
+use std::process::{self, Command};
+
+use crate::config::{ContainerConfig, ProjectConfig};
+use crate::interrupt;
+use crate::utils::{get_cache_dir, get_root_dir, run_cmd};
+
+/// Set in the container, so the build there doesn't start another one.
+const INSIDE: &str = "KOKUBAN_IN_CONTAINER";
+/// Where this binary is mounted in the container.
+const EXE: &str = "/usr/local/bin/kokuban_ci_core";
+
+/// Host variables passed in as-is, and prefixes of more of them.
+const ENV: &[&str] = &["CI", "GH_TOKEN", "PUSHGATEWAY_URL", "TELEGRAM_CHAT_ID"];
+const ENV_PREFIXES: &[&str] = &["GITHUB_", "RUNNER_", "KOKUBAN_", "AWS_", "OTEL_"];
+/// Variables holding host file paths, whose directories are mounted too.
+const PATH_ENV: &[&str] = &[
+    "GITHUB_ENV",
+    "GITHUB_OUTPUT",
+    "GITHUB_STEP_SUMMARY",
+    "KOKUBAN_HISTORY_DB",
+];
+
+/// The container a build should run in: `--container` wins over the
+/// project's `container` block. `None` when already inside one.
+pub fn resolve(image: Option<String>, proj: Option<&ProjectConfig>) -> Option<ContainerConfig> {
+    if env::var_os(INSIDE).is_some() {
+        return None;
+    }
+    let configured = proj.and_then(|p| p.container.clone());
+    match (image, configured) {
+        (Some(image), Some(cfg)) => Some(ContainerConfig { image, ..cfg }),
+        (Some(image), None) => Some(ContainerConfig {
+            image,
+            engine: None,
+            env: None,
+            run_args: None,
+        }),
+        (None, cfg) => cfg,
+    }
+}
+
+/// Host dirs a project's build writes to or reads from outside the CI root.
+pub fn project_paths(proj: &ProjectConfig) -> Vec<PathBuf> {
+    proj.output_dir
+        .iter()
+        .chain(&proj.git_reference_dir)
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Runs this same command line in `cfg.image` and exits with its status.
+/// The working dir, CI root, cache dir and `paths` are mounted at their
+/// host paths, so paths in configs, logs and checkpoints mean the same on
+/// both sides.
+pub fn run(cfg: &ContainerConfig, paths: &[PathBuf]) -> Result<()> {
+    let engine = match &cfg.engine {
+        Some(engine) => engine.clone(),
+        None => ["podman", "docker"]
+            .into_iter()
+            .find(|e| run_cmd(&["which", e], None, true).is_ok())
+            .ok_or_else(|| anyhow!("--container needs docker or podman"))?
+            .to_string(),
+    };
+
+    let cwd = env::current_dir()?;
+    // Created up front, or the engine creates them owned by root.
+    fs::create_dir_all(get_cache_dir())?;
+    let mut mounts = vec![cwd.clone(), get_root_dir(), get_cache_dir()];
+    for path in paths {
+        fs::create_dir_all(path)?;
+        mounts.push(path.clone());
+    }
+    for name in PATH_ENV {
+        if let Some(file) = env::var_os(name) {
+            mounts.extend(Path::new(&file).parent().map(Path::to_path_buf));
+        }
+    }
+    let mut mounts: Vec<PathBuf> = mounts
+        .iter()
+        .filter_map(|p| path::absolute(p).ok())
+        .filter(|p| p.exists())
+        .collect();
+    mounts.sort();
+    // A dir under one that's already mounted comes along with it.
+    mounts.dedup_by(|b, a| b.starts_with(a));
+
+    let exe = env::current_exe()?;
+    let mut command = Command::new(&engine);
+    command.args(["run", "--rm", "--init"]);
+    if engine == "podman" {
+        command.arg("--userns=keep-id");
+    } else {
+        // SAFETY: getuid/getgid can't fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        command.args(["--user", &format!("{}:{}", uid, gid)]);
+    }
+    command
+        .arg("-v")
+        .arg(format!("{}:{}:ro", exe.display(), EXE));
+    for dir in &mounts {
+        command
+            .arg("-v")
+            .arg(format!("{}:{}", dir.display(), dir.display()));
+    }
+    command.arg("-w").arg(&cwd);
+    command.args(["-e", &format!("{}=1", INSIDE)]);
+    command.args(["-e", "HOME=/tmp"]);
+    command.arg("-e").arg(format!(
+        "KOKUBAN_CACHE_DIR={}",
+        path::absolute(get_cache_dir())?.display()
+    ));
+    command.arg("-e").arg(format!(
+        "CI_CENTRAL_ROOT={}",
+        path::absolute(get_root_dir())?.display()
+    ));
+    let extra = cfg.env.iter().flatten().map(|s| s.as_str());
+    for (name, _) in env::vars_os() {
+        let Some(name) = name.to_str() else { continue };
+        let passed = ENV.contains(&name)
+            || ENV_PREFIXES.iter().any(|p| name.starts_with(p))
+            || extra.clone().any(|e| e == name);
+        if passed && !matches!(name, "KOKUBAN_CACHE_DIR" | INSIDE) {
+            command.args(["-e", name]);
+        }
+    }
+    command.args(cfg.run_args.iter().flatten());
+    command.arg(&cfg.image).arg(EXE);
+    command.args(env::args_os().skip(1));
+
+    info!("Building in {} ({})", cfg.image, engine);
+    // Its own process group, so Ctrl-C reaches the container through the
+    // interrupt handler's SIGTERM and the build inside cleans up.
+    let mut child = command.process_group(0).spawn()?;
+    let status = {
+        let _tracked = interrupt::track(child.id() as i32);
+        child.wait()?
+    };
+    process::exit(status.code().unwrap_or(interrupt::EXIT_CODE));
+}
@@ -0,0 +1,106 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::arch::resolve_arch;
+use crate::build::{kernel_source_dir, load_branch_config, toolchain_env};
+use crate::toolchain::setup_toolchain;
+use crate::utils::{load_projects, run_cmd_with_env};
+
+/// Build dir for the regenerated config, kept apart from the builds' `out`.
+const SYNC_OUT_DIR: &str = "out-defconfig";
+
+/// Regenerates a project's defconfig with `make savedefconfig` and diffs it
+/// against the one in the kernel source. With `check` any difference is an
+/// error; with `write` the regenerated one replaces it, ready to commit.
+pub fn handle_sync(
+    project_key: &str,
+    branch: &str,
+    check: bool,
+    write: bool,
+    out_dir: Option<PathBuf>,
+) -> Result<()> {
+    let projects = load_projects()?;
+    let proj = load_branch_config(&projects, project_key, branch)?;
+    let arch = resolve_arch(proj.arch.as_deref())?;
+    let use_gcc = match proj.compiler.as_deref().unwrap_or("clang") {
+        "clang" => false,
+        "gcc" => true,
+        other => {
+            return Err(anyhow!(
+                "Unknown compiler '{}' (expected clang or gcc)",
+                other
+            ));
+        }
+    };
+    let kernel_source = kernel_source_dir(&proj)?;
+    let in_tree = kernel_source.join(arch.defconfig_path(&proj.defconfig));
+    if !in_tree.exists() {
+        return Err(anyhow!("{} not found", in_tree.display()));
+    }
+
+    // Kconfig asks the compiler what it supports, so run it with the
+    // project's toolchain or options like CC_IS_CLANG drift for nothing.
+    let toolchain = setup_toolchain(&proj, false)?;
+    let env = toolchain_env(&proj, &toolchain, arch, use_gcc);
+    let out = match out_dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            fs::canonicalize(dir)?.join(SYNC_OUT_DIR)
+        }
+        None => fs::canonicalize(&kernel_source)?.join(SYNC_OUT_DIR),
+    };
+    let o_arg = format!("O={}", out.display());
+    let arch_arg = format!("ARCH={}", arch.kernel_arch);
+    let cross_arg = format!("CROSS_COMPILE={}", arch.cross_compile);
+    let mut make = vec!["make", o_arg.as_str(), arch_arg.as_str()];
+    if use_gcc {
+        make.push(&cross_arg);
+    } else {
+        make.extend(["LLVM=1", "LLVM_IAS=1"]);
+    }
+    make.extend(proj.extra_make_args.iter().flatten().map(|a| a.as_str()));
+
+    for target in [proj.defconfig.as_str(), "savedefconfig"] {
+        let mut cmd = make.clone();
+        cmd.push(target);
+        run_cmd_with_env(&cmd, Some(&kernel_source), &env)?;
+    }
+    let regenerated = out.join("defconfig");
+
+    if fs::read(&regenerated)? == fs::read(&in_tree)? {
+        info!("{} is minimal and current.", proj.defconfig);
+        return Ok(());
+    }
+    let old_label = format!("a/{}", arch.defconfig_path(&proj.defconfig));
+    let new_label = format!("b/{}", arch.defconfig_path(&proj.defconfig));
+    let diff = Command::new("diff")
+        .args(["-u", "--label", &old_label, "--label", &new_label])
+        .arg(&in_tree)
+        .arg(&regenerated)
+        .output()?;
+    print!("{}", String::from_utf8_lossy(&diff.stdout));
+
+    if write {
+        fs::copy(&regenerated, &in_tree)?;
+        info!(
+            "Wrote {}; review it with `git diff` in kernel_source and commit it.",
+            in_tree.display()
+        );
+        return Ok(());
+    }
+    if check {
+        return Err(anyhow!(
+            "{} differs from `make savedefconfig`; run `defconfig sync --write` to update it",
+            proj.defconfig
+        ));
+    }
+    info!(
+        "{} differs from `make savedefconfig` ({}); --write replaces it.",
+        proj.defconfig,
+        regenerated.display()
+    );
+    Ok(())
+}
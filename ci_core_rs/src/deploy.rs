@@ -0,0 +1,217 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::build::{kernel_source_dir, load_branch_config};
+use crate::checkpoint::Checkpoint;
+use crate::interrupt;
+use crate::ksu::{canonical_variant_name, load_ksu_variants, variant_label};
+use crate::utils::{load_projects, run_cmd};
+
+/// Values `deploy.method` accepts.
+pub const METHODS: &[&str] = &["fastboot", "sideload"];
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// `adb`/`fastboot` with `-s <serial>` when one is set. Without it both
+/// fall back to ANDROID_SERIAL, then to the only connected device.
+fn device_cmd<'a>(tool: &'a str, serial: Option<&'a str>, args: &[&'a str]) -> Vec<&'a str> {
+    let mut cmd = vec![tool];
+    if let Some(serial) = serial {
+        cmd.extend(["-s", serial]);
+    }
+    cmd.extend_from_slice(args);
+    cmd
+}
+
+/// Trimmed stdout of a device query, or None if it failed (typically: no
+/// device in that mode yet).
+fn query(tool: &str, serial: Option<&str>, args: &[&str]) -> Option<String> {
+    let cmd = device_cmd(tool, serial, args);
+    let output = Command::new(cmd[0]).args(&cmd[1..]).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// adb's view of the device: `device`, `recovery`, `sideload`, ...
+fn adb_state(serial: Option<&str>) -> Option<String> {
+    query("adb", serial, &["get-state"])
+}
+
+fn in_fastboot(serial: Option<&str>) -> bool {
+    // `fastboot devices` lists "<serial>\tfastboot" and exits 0 either way.
+    query("fastboot", None, &["devices"]).is_some_and(|list| {
+        list.lines()
+            .filter_map(|l| l.split_whitespace().next())
+            .any(|s| serial.is_none_or(|serial| s == serial))
+    })
+}
+
+fn wait_for(what: &str, deadline: Instant, mut ready: impl FnMut() -> bool) -> Result<()> {
+    info!("Waiting for {}...", what);
+    while !ready() {
+        interrupt::check()?;
+        if Instant::now() >= deadline {
+            return Err(anyhow!("Timed out waiting for {}", what));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// The checkpoint of the branch's last build: in `out` after `build`, in
+/// `out-<variant>` after `build-all`.
+fn last_build(base: &Path, project_key: &str, branch: &str, variant: &str) -> Result<Checkpoint> {
+    for dir in [base.join("out"), base.join(format!("out-{}", variant))] {
+        if let Some(checkpoint) = Checkpoint::load(&dir)?
+            && checkpoint.project == project_key
+            && checkpoint.branch == branch
+        {
+            return Ok(checkpoint);
+        }
+    }
+    Err(anyhow!(
+        "No build of {} / {} found under {}; build it first or pass --file",
+        project_key,
+        branch,
+        base.display()
+    ))
+}
+
+/// Flashes the last build of a branch onto a connected device — the
+/// boot.img with fastboot, or the AnyKernel3 zip with `adb sideload` from
+/// recovery — reboots it and waits until `uname -r` shows the build's
+/// localversion.
+pub fn handle_deploy(
+    project_key: &str,
+    branch: &str,
+    serial: Option<String>,
+    method: Option<String>,
+    file: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+) -> Result<()> {
+    let projects = load_projects()?;
+    let proj = load_branch_config(&projects, project_key, branch)?;
+    let ksu_variants = load_ksu_variants(&projects)?;
+    let cfg = proj.deploy.clone().unwrap_or_default();
+    let localversion = format!(
+        "{}-{}",
+        proj.localversion_base,
+        variant_label(&ksu_variants, branch)
+    );
+
+    let (boot_img, zip) = match file {
+        Some(file) if file.extension().is_some_and(|e| e == "zip") => (None, Some(file)),
+        Some(file) => (Some(file), None),
+        None => {
+            let base = match out_dir.or_else(|| proj.output_dir.as_ref().map(PathBuf::from)) {
+                Some(dir) => dir,
+                None => kernel_source_dir(&proj)?,
+            };
+            let variant = canonical_variant_name(&ksu_variants, branch);
+            let checkpoint = last_build(&base, project_key, branch, &variant)?;
+            let boot_img = checkpoint
+                .extra_artifacts
+                .iter()
+                .find(|a| a.to_string_lossy().ends_with("-boot.img"))
+                .cloned();
+            (boot_img, checkpoint.zip)
+        }
+    };
+    let method = method.or(cfg.method.clone()).unwrap_or_else(|| {
+        if boot_img.is_some() {
+            "fastboot"
+        } else {
+            "sideload"
+        }
+        .to_string()
+    });
+    let serial = serial.or(cfg.serial.clone());
+    let serial = serial.as_deref();
+    let timeout = Duration::from_secs(cfg.timeout_seconds.unwrap_or(300));
+    let deadline = Instant::now() + timeout;
+
+    match method.as_str() {
+        "fastboot" => {
+            let image = boot_img.ok_or_else(|| {
+                anyhow!(
+                    "The build has no boot.img to flash; set boot_image or use --method sideload"
+                )
+            })?;
+            if !image.exists() {
+                return Err(anyhow!("{} not found", image.display()));
+            }
+            let partition = cfg.partition.as_deref().unwrap_or("boot");
+            if !in_fastboot(serial) {
+                run_cmd(
+                    &device_cmd("adb", serial, &["reboot", "bootloader"]),
+                    None,
+                    false,
+                )?;
+                wait_for("the bootloader", deadline, || in_fastboot(serial))?;
+            }
+            info!("Flashing {} to {}...", image.display(), partition);
+            let image = image.to_string_lossy();
+            run_cmd(
+                &device_cmd("fastboot", serial, &["flash", partition, &image]),
+                None,
+                false,
+            )?;
+            run_cmd(&device_cmd("fastboot", serial, &["reboot"]), None, false)?;
+        }
+        "sideload" => {
+            let zip = zip.ok_or_else(|| anyhow!("The build has no zip to sideload"))?;
+            if !zip.exists() {
+                return Err(anyhow!("{} not found", zip.display()));
+            }
+            if adb_state(serial).as_deref() != Some("sideload") {
+                run_cmd(
+                    &device_cmd("adb", serial, &["reboot", "sideload-auto-reboot"]),
+                    None,
+                    false,
+                )?;
+                wait_for("recovery's sideload mode", deadline, || {
+                    adb_state(serial).as_deref() == Some("sideload")
+                })?;
+            }
+            info!("Sideloading {}...", zip.display());
+            let zip = zip.to_string_lossy();
+            run_cmd(&device_cmd("adb", serial, &["sideload", &zip]), None, false)?;
+        }
+        other => {
+            return Err(anyhow!(
+                "Unknown deploy method '{}' (expected one of: {})",
+                other,
+                METHODS.join(", ")
+            ));
+        }
+    }
+
+    // Give the device a moment to drop off adb so the old system isn't
+    // mistaken for the new one.
+    thread::sleep(POLL_INTERVAL);
+    wait_for("the device to boot", deadline, || {
+        adb_state(serial).as_deref() == Some("device")
+            && query("adb", serial, &["shell", "getprop", "sys.boot_completed"]).as_deref()
+                == Some("1")
+    })?;
+    let release = query("adb", serial, &["shell", "uname", "-r"]).unwrap_or_default();
+    if !release.contains(&localversion) {
+        return Err(anyhow!(
+            "The device booted {} instead of a kernel with localversion {}",
+            if release.is_empty() {
+                "an unknown kernel"
+            } else {
+                &release
+            },
+            localversion
+        ));
+    }
+    info!("✅ Device is running {}", release);
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{self, Path};
+
+use crate::utils::run_cmd;
+
+/// A kernel build with LTO, its toolchain and ccache need about this much.
+pub const MIN_DISK_GIB: u64 = 40;
+/// Below this full LTO links tend to get OOM-killed; thin LTO still works.
+const MIN_RAM_GIB: u64 = 8;
+
+/// (binary, Debian/Ubuntu package, what the build uses it for).
+const REQUIRED_TOOLS: &[(&str, &str, &str)] = &[
+    ("bash", "bash", "KernelSU setup scripts"),
+    ("git", "git", "sources and snapshots"),
+    ("curl", "curl", "patches and downloads"),
+    ("tar", "tar", "toolchain archives"),
+    ("zip", "zip", "AnyKernel3 packaging"),
+    ("make", "make", "the kernel build"),
+    ("gcc", "build-essential", "host programs (HOSTCC)"),
+    ("flex", "flex", "Kconfig and dtc"),
+    ("bison", "bison", "Kconfig and dtc"),
+    ("bc", "bc", "kernel timeconst"),
+    ("perl", "perl", "kernel headers"),
+    ("sed", "sed", "scripts/config"),
+    ("awk", "gawk", "scripts/config"),
+    ("patch", "patch", "project patches"),
+];
+
+const OPTIONAL_TOOLS: &[(&str, &str, &str)] = &[
+    ("cpio", "cpio", "CONFIG_IKHEADERS"),
+    ("lz4", "lz4", "boot.img.lz4"),
+    ("pahole", "dwarves", "CONFIG_DEBUG_INFO_BTF"),
+    ("ccache", "ccache", "compiler caching"),
+    ("python3", "python3", "mkdtboimg.py"),
+    ("gpg", "gnupg", "release signatures"),
+    ("qemu-system-aarch64", "qemu-system-arm", "boot_test"),
+    ("sparse", "sparse", "analyze"),
+    ("adb", "adb", "deploy"),
+    ("fastboot", "fastboot", "deploy"),
+];
+
+/// Headers the kernel's host tools are built against.
+const HEADERS: &[(&str, &str, &str)] = &[
+    (
+        "openssl/opensslv.h",
+        "libssl-dev",
+        "module signing and sign-file",
+    ),
+    ("libelf.h", "libelf-dev", "objtool and resolve_btfids"),
+];
+
+const INCLUDE_DIRS: &[&str] = &["/usr/include", "/usr/local/include"];
+
+fn has_header(header: &str) -> bool {
+    INCLUDE_DIRS
+        .iter()
+        .any(|dir| Path::new(dir).join(header).exists())
+        || fs::read_dir("/usr/include")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().ends_with("-linux-gnu"))
+            .any(|e| e.path().join(header).exists())
+}
+
+/// Free space on the filesystem holding `path`, in bytes.
+fn free_space(path: &Path) -> Result<u64> {
+    let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out pointer.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(anyhow!(
+            "statvfs {}: {}",
+            path.display(),
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Fails fast unless every filesystem holding one of `paths` (or the
+/// nearest existing parent) has `min_gib` free, rather than letting a build
+/// run out of space half-way through extracting or linking.
+pub fn check_free_space(paths: &[&Path], min_gib: u64) -> Result<()> {
+    let mut checked = Vec::new();
+    for path in paths {
+        let path = path::absolute(path)?;
+        let Some(existing) = path.ancestors().find(|p| p.exists()) else {
+            continue;
+        };
+        let dev = fs::metadata(existing)?.dev();
+        if checked.contains(&dev) {
+            continue;
+        }
+        checked.push(dev);
+        let free = free_space(existing)?;
+        if gib(free) < min_gib as f64 {
+            return Err(anyhow!(
+                "Only {:.1} GiB free on the filesystem holding {}, the build needs {} GiB; \
+                 free up space or lower min_free_space_gib",
+                gib(free),
+                path.display(),
+                min_gib
+            ));
+        }
+        info!("{:.1} GiB free for {}", gib(free), path.display());
+    }
+    Ok(())
+}
+
+/// MemTotal from /proc/meminfo, in bytes.
+fn total_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+fn gib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0 * 1024.0)
+}
+
+/// Checks the host for the tools, headers, disk space and memory a build
+/// needs and prints what to install. Fails if anything required is missing.
+pub fn handle_doctor() -> Result<()> {
+    let mut missing: Vec<&str> = Vec::new();
+    let mut problems = 0;
+
+    println!("Tools:");
+    for (name, package, used_for) in REQUIRED_TOOLS {
+        if run_cmd(&["which", name], None, true).is_ok() {
+            println!("  ✅ {:<8} {}", name, used_for);
+        } else {
+            println!("  ❌ {:<8} {} (missing)", name, used_for);
+            missing.push(package);
+            problems += 1;
+        }
+    }
+    for (name, _, used_for) in OPTIONAL_TOOLS {
+        if run_cmd(&["which", name], None, true).is_ok() {
+            println!("  ✅ {:<8} {}", name, used_for);
+        } else {
+            println!("  ⚠️  {:<8} {} (optional, missing)", name, used_for);
+        }
+    }
+
+    println!("Headers:");
+    for (header, package, used_for) in HEADERS {
+        if has_header(header) {
+            println!("  ✅ {:<18} {}", header, used_for);
+        } else {
+            println!("  ❌ {:<18} {} (missing)", header, used_for);
+            missing.push(package);
+            problems += 1;
+        }
+    }
+
+    println!("Resources:");
+    match free_space(Path::new(".")) {
+        Ok(free) if gib(free) < MIN_DISK_GIB as f64 => {
+            println!(
+                "  ❌ disk     {:.1} GiB free here, a build needs about {} GiB",
+                gib(free),
+                MIN_DISK_GIB
+            );
+            problems += 1;
+        }
+        Ok(free) => println!("  ✅ disk     {:.1} GiB free", gib(free)),
+        Err(e) => println!("  ⚠️  disk     {:#}", e),
+    }
+    match total_memory() {
+        Some(mem) if gib(mem) < MIN_RAM_GIB as f64 => println!(
+            "  ⚠️  memory   {:.1} GiB; full LTO may run out, consider `lto: thin` or fewer --jobs",
+            gib(mem)
+        ),
+        Some(mem) => println!("  ✅ memory   {:.1} GiB", gib(mem)),
+        None => println!("  ⚠️  memory   couldn't read /proc/meminfo"),
+    }
+
+    missing.sort();
+    missing.dedup();
+    if !missing.is_empty() {
+        println!("\nInstall the missing packages with:");
+        println!("  sudo apt-get install -y {}", missing.join(" "));
+    }
+    if problems > 0 {
+        return Err(anyhow!("{} problem(s) found", problems));
+    }
+    println!("\nAll required prerequisites are present.");
+    Ok(())
+}
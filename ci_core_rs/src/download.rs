@@ -0,0 +1,178 @@
+use anyhow::{Context, Result, anyhow};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use reqwest::header::RANGE;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use crate::interrupt;
+use crate::progress;
+use crate::retry;
+use crate::timeout;
+
+#[derive(Debug)]
+struct HttpError(StatusCode);
+
+impl HttpError {
+    /// Client errors won't fix themselves, except timeouts and rate limits.
+    fn is_retryable(&self) -> bool {
+        !self.0.is_client_error()
+            || self.0 == StatusCode::REQUEST_TIMEOUT
+            || self.0 == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+impl std::fmt::Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {}", self.0)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+pub struct DownloadJob {
+    pub url: String,
+    pub dest: PathBuf,
+}
+
+/// Downloads all jobs concurrently. Partially downloaded files are resumed
+/// with an HTTP Range request, and each file is retried with exponential
+/// backoff before the whole batch is reported as failed.
+pub fn download_all(jobs: &[DownloadJob]) -> Result<()> {
+    let client = Client::builder()
+        .connect_timeout(Duration::from_secs(30))
+        .timeout(None)
+        .build()?;
+    let multi = MultiProgress::with_draw_target(progress::draw_target());
+    let style = ProgressStyle::with_template(
+        "{msg:30!} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+    )
+    .unwrap()
+    .progress_chars("=> ");
+
+    let errors: Vec<String> = thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .iter()
+            .map(|job| {
+                let pb = multi.add(ProgressBar::new(0));
+                pb.set_style(style.clone());
+                pb.set_message(file_label(job));
+                let client = &client;
+                scope.spawn(move || download_with_retry(client, job, &pb))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .zip(jobs)
+            .filter_map(|(h, job)| match h.join() {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(format!("{}: {:#}", job.url, e)),
+                Err(_) => Some(format!("{}: download thread panicked", job.url)),
+            })
+            .collect()
+    });
+
+    if !errors.is_empty() {
+        return Err(anyhow!("Download failed:\n  {}", errors.join("\n  ")));
+    }
+    Ok(())
+}
+
+fn file_label(job: &DownloadJob) -> String {
+    job.dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| job.url.clone())
+}
+
+fn download_with_retry(client: &Client, job: &DownloadJob, pb: &ProgressBar) -> Result<()> {
+    let policy = retry::policy();
+    let mut attempt = 1;
+    loop {
+        match download_once(client, job, pb) {
+            Ok(()) => {
+                pb.finish();
+                info!(
+                    "Downloaded {} ({} bytes)",
+                    file_label(job),
+                    fs::metadata(&job.dest)?.len()
+                );
+                return Ok(());
+            }
+            Err(e)
+                if policy.should_retry(attempt, &e)
+                    && e.downcast_ref::<HttpError>()
+                        .is_none_or(|h| h.is_retryable()) =>
+            {
+                let delay = policy.delay(attempt);
+                let msg = format!(
+                    "Attempt {}/{} for {} failed: {:#}. Retrying in {:.1}s...",
+                    attempt,
+                    policy.attempts,
+                    file_label(job),
+                    e,
+                    delay.as_secs_f64()
+                );
+                if pb.is_hidden() {
+                    info!("{}", msg);
+                } else {
+                    pb.println(msg);
+                }
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => {
+                pb.abandon();
+                return Err(e);
+            }
+        }
+    }
+}
+
+fn download_once(client: &Client, job: &DownloadJob, pb: &ProgressBar) -> Result<()> {
+    let existing = fs::metadata(&job.dest).map(|m| m.len()).unwrap_or(0);
+
+    let mut request = client.get(&job.url);
+    if let Some(left) = timeout::remaining()? {
+        request = request.timeout(left);
+    }
+    if existing > 0 {
+        request = request.header(RANGE, format!("bytes={}-", existing));
+    }
+    let response = request.send()?;
+
+    let (offset, append) = match response.status() {
+        StatusCode::PARTIAL_CONTENT => (existing, true),
+        // The server has nothing past what we already hold.
+        StatusCode::RANGE_NOT_SATISFIABLE if existing > 0 => return Ok(()),
+        status if status.is_success() => (0, false),
+        status => return Err(HttpError(status).into()),
+    };
+
+    let total = response.content_length().map(|len| len + offset);
+    pb.set_length(total.unwrap_or(0));
+    pb.set_position(offset);
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(&job.dest)
+        .with_context(|| format!("Failed to open {}", job.dest.display()))?;
+    io::copy(&mut interrupt::Checked(pb.wrap_read(response)), &mut file)?;
+
+    let written = fs::metadata(&job.dest)?.len();
+    if let Some(total) = total
+        && written != total
+    {
+        return Err(anyhow!("incomplete download ({}/{} bytes)", written, total));
+    }
+    Ok(())
+}
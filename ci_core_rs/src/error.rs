@@ -0,0 +1,174 @@
+use std::fmt;
+
+use crate::secrets;
+use crate::timeout::TimedOut;
+
+/// A command exited unsuccessfully, with the tail of what it printed (its
+/// stderr when the output was captured).
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub command: Vec<String>,
+    pub output: Vec<String>,
+    /// Captured stderr, which is part of the message.
+    pub stderr: Option<String>,
+}
+
+impl CommandFailed {
+    /// Masks secrets (e.g. a token in a clone URL) in everything it keeps,
+    /// since the error ends up in history, notifications and traces.
+    pub fn new(cmd: &[&str], output: Vec<String>, stderr: Option<String>) -> Self {
+        let redact = |s: &str| secrets::redact(s).into_owned();
+        CommandFailed {
+            command: cmd.iter().map(|a| redact(a)).collect(),
+            output: output.iter().map(|l| redact(l)).collect(),
+            stderr: stderr.as_deref().map(redact),
+        }
+    }
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Command failed: {:?}", self.command)?;
+        if let Some(stderr) = &self.stderr {
+            write!(f, " Stderr: {}", stderr)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// What part of a build failed. Each kind exits with its own status so CI
+/// can tell a broken patch from a compile error without reading the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The project config, or the checks before the build starts.
+    Config,
+    Toolchain,
+    /// KernelSU integration and project patches.
+    Patch,
+    Compile,
+    /// Static analysis, the ABI check, QEMU boot test or KUnit.
+    Test,
+    Package,
+    Release,
+}
+
+/// Exit status when a command or stage ran past its `timeout_minutes`, as
+/// timeout(1) uses.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+impl ErrorKind {
+    /// The kind of failure for an error in pipeline `stage`; config edits
+    /// happen in `configure`, so its errors are config errors too.
+    pub fn of_stage(stage: Option<&str>) -> Self {
+        match stage {
+            Some("toolchain") => ErrorKind::Toolchain,
+            Some("integrate") => ErrorKind::Patch,
+            Some("build") => ErrorKind::Compile,
+            Some("analyze" | "abi_check" | "boot_test" | "kunit") => ErrorKind::Test,
+            Some("package") => ErrorKind::Package,
+            Some("release") => ErrorKind::Release,
+            _ => ErrorKind::Config,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Config => 10,
+            ErrorKind::Toolchain => 11,
+            ErrorKind::Patch => 12,
+            ErrorKind::Compile => 13,
+            ErrorKind::Package => 14,
+            ErrorKind::Release => 15,
+            ErrorKind::Test => 16,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Config => "config",
+            ErrorKind::Toolchain => "toolchain",
+            ErrorKind::Patch => "patch",
+            ErrorKind::Compile => "compile",
+            ErrorKind::Test => "test",
+            ErrorKind::Package => "package",
+            ErrorKind::Release => "release",
+        }
+    }
+}
+
+/// A failed build: the kind of failure, the stage it happened in, and the
+/// command and output behind it when a command failed.
+#[derive(Debug)]
+pub struct BuildError {
+    pub kind: ErrorKind,
+    pub stage: Option<String>,
+    pub command: Option<String>,
+    pub output: Vec<String>,
+    pub source: anyhow::Error,
+}
+
+impl BuildError {
+    /// Classifies an error the pipeline returned while in `stage`.
+    pub fn in_stage(source: anyhow::Error, stage: Option<String>) -> Self {
+        let failed = source
+            .chain()
+            .find_map(|e| e.downcast_ref::<CommandFailed>());
+        BuildError {
+            kind: ErrorKind::of_stage(stage.as_deref()),
+            stage,
+            command: failed.map(|f| f.command.join(" ")),
+            output: failed.map(|f| f.output.clone()).unwrap_or_default(),
+            source,
+        }
+    }
+
+    /// Sums up several failed builds: as their kind if they all failed the
+    /// same way, else as a plain error.
+    pub fn summary<'a>(
+        errors: impl IntoIterator<Item = &'a anyhow::Error>,
+        message: String,
+    ) -> anyhow::Error {
+        let mut kinds: Vec<Option<ErrorKind>> = errors
+            .into_iter()
+            .map(|e| e.downcast_ref::<BuildError>().map(|e| e.kind))
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        match kinds[..] {
+            [Some(kind)] => BuildError {
+                kind,
+                stage: None,
+                command: None,
+                output: Vec::new(),
+                source: anyhow::anyhow!(message),
+            }
+            .into(),
+            _ => anyhow::anyhow!(message),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self
+            .source
+            .chain()
+            .any(|e| e.downcast_ref::<TimedOut>().is_some())
+        {
+            return TIMEOUT_EXIT_CODE;
+        }
+        self.kind.exit_code()
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error", self.kind.name())?;
+        if let Some(stage) = &self.stage {
+            write!(f, " in {}", stage)?;
+        }
+        write!(f, ": {:#}", self.source)
+    }
+}
+
+impl std::error::Error for BuildError {}
@@ -0,0 +1,127 @@
+use chrono::Local;
+use log::warn;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::FromRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::secrets;
+
+/// Where `--output json` events go: the real stdout, saved before fd 1 was
+/// pointed at stderr. `None` in text mode.
+static SINK: Mutex<Option<File>> = Mutex::new(None);
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable progress on stdout.
+    #[default]
+    Text,
+    /// One JSON event per line on stdout; progress and command output move
+    /// to stderr.
+    Json,
+}
+
+/// A build progress event, written as `{"event": "<kind>", "time": ..., ...}`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    BuildStart {
+        project: &'a str,
+        branch: &'a str,
+    },
+    BuildEnd {
+        project: &'a str,
+        branch: &'a str,
+        ok: bool,
+        duration_ms: u128,
+        stages: Vec<StageTime>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        /// `config`, `toolchain`, `patch`, `compile`, `package` or `release`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error_kind: Option<&'a str>,
+    },
+    StageStart {
+        stage: &'a str,
+    },
+    StageEnd {
+        stage: &'a str,
+        duration_ms: u128,
+    },
+    Command {
+        command: &'a str,
+        ok: bool,
+        /// `None` when the command was killed by a signal or couldn't start.
+        exit_code: Option<i32>,
+        duration_ms: u128,
+    },
+    /// Compiler cache counters of this build so far, sent while make runs.
+    CacheStats {
+        tool: &'a str,
+        hits: u64,
+        misses: u64,
+    },
+    Artifact {
+        path: &'a Path,
+        size: u64,
+        sha256: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+pub struct StageTime {
+    stage: String,
+    duration_ms: u128,
+}
+
+impl StageTime {
+    pub fn list(timings: &[(String, Duration)]) -> Vec<StageTime> {
+        timings
+            .iter()
+            .map(|(stage, took)| StageTime {
+                stage: stage.clone(),
+                duration_ms: took.as_millis(),
+            })
+            .collect()
+    }
+}
+
+/// Switches to JSON output. Everything else this process and its children
+/// print goes to stderr from here on, so stdout carries only events.
+pub fn init(format: OutputFormat) {
+    if format != OutputFormat::Json {
+        return;
+    }
+    let _ = std::io::stdout().flush();
+    // SAFETY: duplicates and redirects this process's own standard fds.
+    let saved = unsafe {
+        let saved = libc::dup(libc::STDOUT_FILENO);
+        if saved < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            warn!("Failed to set up JSON output, staying in text mode.");
+            return;
+        }
+        File::from_raw_fd(saved)
+    };
+    *SINK.lock().unwrap() = Some(saved);
+}
+
+/// Whether events are being written, i.e. `--output json` is on.
+pub fn enabled() -> bool {
+    SINK.lock().unwrap().is_some()
+}
+
+pub fn emit(event: Event) {
+    let mut sink = SINK.lock().unwrap();
+    let Some(out) = sink.as_mut() else {
+        return;
+    };
+    let Ok(mut value) = serde_json::to_value(&event) else {
+        return;
+    };
+    value["time"] = Local::now().to_rfc3339().into();
+    let line = value.to_string();
+    let _ = writeln!(out, "{}", secrets::redact(&line)).and_then(|_| out.flush());
+}
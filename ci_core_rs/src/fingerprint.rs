@@ -0,0 +1,99 @@
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use crate::build::load_branch_config;
+use crate::config::{PatchSpec, ProjectsMap};
+use crate::ksu::{load_ksu_variants, resolve_variant};
+use crate::patch::directory_patches;
+use crate::utils::{apply_branch_override, find_local_file, get_toolchain_lock_path, run_cmd};
+
+/// Hash of what a build of `project_key`/`branch` turns into: the kernel
+/// source HEAD and local changes, the patches and config fragments, the
+/// branch's resolved config and KernelSU variant (with the upstream commit
+/// it would integrate, unless pinned), the project's toolchain.lock entry
+/// and whether it releases. Untracked files count by name only.
+pub fn compute(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+    kernel_source: &Path,
+    do_release: bool,
+) -> Result<String> {
+    let proj = load_branch_config(projects, project_key, branch)?;
+    let ksu_variants = load_ksu_variants(projects)?;
+    let variant = resolve_variant(&ksu_variants, branch);
+    let mut hasher = Sha256::new();
+    let mut part = |name: &str, data: &[u8]| {
+        hasher.update(name.as_bytes());
+        hasher.update((data.len() as u64).to_le_bytes());
+        hasher.update(data);
+    };
+
+    let head =
+        run_cmd(&["git", "rev-parse", "HEAD"], Some(kernel_source), true)?.unwrap_or_default();
+    part("head", head.trim().as_bytes());
+    for cmd in [
+        &["git", "status", "--porcelain"][..],
+        &["git", "diff", "--binary", "HEAD"],
+    ] {
+        let local = run_cmd(cmd, Some(kernel_source), true)?.unwrap_or_default();
+        part("local", local.as_bytes());
+    }
+    // The merged JSON rather than ProjectConfig: its maps are sorted, while
+    // the struct's HashMaps serialize in a different order each run.
+    let config = apply_branch_override(&projects[project_key], branch);
+    part("config", serde_json::to_string(&config)?.as_bytes());
+    part("release", &[do_release as u8]);
+
+    if let Some((name, v)) = variant {
+        part("variant", name.as_bytes());
+        part("variant_config", serde_json::to_string(v)?.as_bytes());
+        if proj.ksu_ref.is_none()
+            && let (Some(repo), Some(upstream_branch)) = (&v.repo, &v.branch)
+        {
+            let listed = run_cmd(&["git", "ls-remote", repo, upstream_branch], None, true)?
+                .unwrap_or_default();
+            let upstream = listed
+                .split_whitespace()
+                .next()
+                .ok_or_else(|| anyhow!("{} has no {}", repo, upstream_branch))?;
+            part("ksu", upstream.as_bytes());
+        }
+    }
+
+    let variant_name = variant.map_or(branch, |(name, _)| name);
+    let names = [branch, variant_name];
+    let mut specs: Vec<PatchSpec> = proj
+        .patches
+        .iter()
+        .flatten()
+        .filter(|p| p.applies_to(&names))
+        .cloned()
+        .collect();
+    specs.extend(directory_patches(project_key, &names)?);
+    for spec in &specs {
+        // Remote patches are named by their URL; local ones by content.
+        if spec.src.contains("://") {
+            part("patch", spec.src.as_bytes());
+        } else {
+            part(
+                "patch",
+                &fs::read(find_local_file(&spec.src, kernel_source)?)?,
+            );
+        }
+    }
+
+    for path in proj.config_fragments.iter().flatten() {
+        part("fragment", &fs::read(find_local_file(path, kernel_source)?)?);
+    }
+
+    let lock: serde_json::Value = match fs::read_to_string(get_toolchain_lock_path()) {
+        Ok(content) => serde_json::from_str(&content)?,
+        Err(_) => serde_json::Value::Null,
+    };
+    part("toolchain", lock[project_key].to_string().as_bytes());
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
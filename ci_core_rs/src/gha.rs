@@ -0,0 +1,191 @@
+use anyhow::Result;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::history::BuildRecord;
+use crate::progress;
+use crate::secrets;
+use crate::utils::format_duration;
+
+/// Annotations of each level written per build. GitHub shows ten per step
+/// anyway; the rest would only clutter the log.
+const MAX_ANNOTATIONS: usize = 20;
+
+/// Running as a GitHub Actions step.
+pub fn active() -> bool {
+    env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+/// Appends to one of the files a step reports through ($GITHUB_OUTPUT,
+/// $GITHUB_STEP_SUMMARY); nothing outside a workflow.
+fn append(var: &str, text: &str) -> Result<()> {
+    if let Ok(path) = env::var(var) {
+        let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+        file.write_all(text.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Sets a step output, `steps.<id>.outputs.<key>` in the workflow.
+pub fn set_output(key: &str, value: &str) -> Result<()> {
+    append("GITHUB_OUTPUT", &format!("{}={}\n", key, value))
+}
+
+/// Adds Markdown to the job summary.
+pub fn add_summary(markdown: &str) -> Result<()> {
+    append("GITHUB_STEP_SUMMARY", markdown)
+}
+
+/// `::error` or `::warning`, shown on the file and line in the run and
+/// the PR diff.
+fn annotate(level: &str, file: Option<&str>, line: Option<u32>, message: &str) {
+    let escape = |s: &str| {
+        secrets::redact(s)
+            .replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let command = match (file, line) {
+        (Some(file), Some(line)) => format!(
+            "::{} file={},line={}::{}",
+            level,
+            escape(file).replace(':', "%3A").replace(',', "%2C"),
+            line,
+            escape(message)
+        ),
+        // Without a line the location is a tool (`ld.lld`), not a file.
+        (Some(tool), None) => format!("::{}::{}: {}", level, escape(tool), escape(message)),
+        (None, _) => format!("::{}::{}", level, escape(message)),
+    };
+    progress::suspend(|| println!("{}", command));
+}
+
+/// `file.c:12:5: error: message`, or `ld.lld: error: message` with the
+/// tool as the file.
+fn parse_error(line: &str) -> Option<(String, Option<u32>, String)> {
+    let (location, message) = line
+        .split_once(": fatal error: ")
+        .or_else(|| line.split_once(": error: "))?;
+    let mut parts = location.splitn(3, ':');
+    let file = parts.next()?.trim_start_matches("./").to_string();
+    let line_no = parts.next().and_then(|l| l.parse().ok());
+    Some((file, line_no, message.trim().to_string()))
+}
+
+fn size(bytes: u64) -> String {
+    if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Reports a finished build to the workflow: its zip, kernel version and
+/// release tag as step outputs, a job summary section with its stage
+/// timings and artifact sizes, and annotations for its compile errors and
+/// warnings. `failed_output` is the tail of the command that failed it.
+pub fn report_build(
+    record: &BuildRecord,
+    id: Option<i64>,
+    release_tag: Option<&str>,
+    failed_output: &[String],
+) -> Result<()> {
+    let mut errors = 0;
+    for (file, line, message) in failed_output.iter().filter_map(|l| parse_error(l)) {
+        if errors == MAX_ANNOTATIONS {
+            break;
+        }
+        annotate("error", Some(&file), line, &message);
+        errors += 1;
+    }
+    if errors == 0
+        && let Some(error) = &record.error
+    {
+        let message = format!(
+            "{} / {} failed: {}",
+            record.project,
+            record.branch,
+            error.lines().next().unwrap_or_default()
+        );
+        annotate("error", None, None, &message);
+    }
+    for warning in record.warnings.iter().take(MAX_ANNOTATIONS) {
+        let mut message = warning.message.clone();
+        if let Some(flag) = &warning.flag {
+            message.push_str(&format!(" [{}]", flag));
+        }
+        annotate("warning", Some(&warning.file), warning.line, &message);
+    }
+
+    let zip = record
+        .artifacts
+        .iter()
+        .find(|a| a.path.extension().is_some_and(|e| e == "zip"));
+    if record.error.is_none() {
+        if let Some(zip) = zip {
+            let name = zip.path.file_name().unwrap_or_default().to_string_lossy();
+            set_output("zip_name", &name)?;
+            set_output("zip_path", &zip.path.to_string_lossy())?;
+        }
+        if let Some(version) = &record.kernel_version {
+            set_output("kernel_version", version)?;
+        }
+        if let Some(tag) = release_tag {
+            set_output("release_tag", tag)?;
+        }
+    }
+
+    let mut summary = match (&record.error, &record.failed_stage) {
+        (None, _) => format!("### ✅ {} / {}\n\n", record.project, record.branch),
+        (Some(_), Some(stage)) => format!(
+            "### ❌ {} / {} failed in {}\n\n",
+            record.project, record.branch, stage
+        ),
+        (Some(_), None) => format!("### ❌ {} / {} failed\n\n", record.project, record.branch),
+    };
+    let mut facts = vec![format_duration(record.duration)];
+    if let Some(version) = &record.kernel_version {
+        facts.insert(0, format!("Kernel {}", version));
+    }
+    if let Some(id) = id {
+        facts.push(format!("build #{}", id));
+    }
+    if !record.warnings.is_empty() {
+        facts.push(format!("{} warning(s)", record.warnings.len()));
+    }
+    summary.push_str(&format!("{}\n\n", facts.join(" · ")));
+    if let Some(error) = &record.error {
+        summary.push_str(&format!("```\n{}\n```\n\n", secrets::redact(error.trim())));
+    }
+    if !record.stages.is_empty() {
+        summary.push_str("| Stage | Time |\n| --- | ---: |\n");
+        for (stage, took) in &record.stages {
+            summary.push_str(&format!("| {} | {} |\n", stage, format_duration(*took)));
+        }
+        summary.push_str(&format!(
+            "| **total** | **{}** |\n\n",
+            format_duration(record.duration)
+        ));
+    }
+    if !record.artifacts.is_empty() {
+        summary.push_str("| Artifact | Size | SHA-256 |\n| --- | ---: | --- |\n");
+        for artifact in &record.artifacts {
+            summary.push_str(&format!(
+                "| {} | {} | `{}` |\n",
+                artifact
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                size(artifact.size),
+                &artifact.sha256[..artifact.sha256.len().min(12)]
+            ));
+        }
+        summary.push('\n');
+    }
+    if let Some(tag) = release_tag {
+        summary.push_str(&format!("Released as `{}`.\n\n", tag));
+    }
+    add_summary(&summary)
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::info;
+use reqwest::StatusCode;
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::interrupt;
+use crate::progress;
+use crate::retry;
+use crate::secrets;
+use crate::utils::url_encode;
+
+const API: &str = "https://api.github.com";
+const UPLOADS: &str = "https://uploads.github.com";
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseInfo {
+    pub id: u64,
+    pub name: Option<String>,
+    pub html_url: String,
+    pub author: Option<Author>,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Author {
+    pub login: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Asset {
+    pub id: u64,
+    pub name: String,
+    pub size: u64,
+    /// API URL; serves the file with `Accept: application/octet-stream`.
+    pub url: String,
+}
+
+/// A new release for `create_release`.
+#[derive(Debug, serde::Serialize)]
+pub struct NewRelease<'a> {
+    pub tag_name: &'a str,
+    pub name: &'a str,
+    pub body: &'a str,
+    pub draft: bool,
+    pub prerelease: bool,
+    pub generate_release_notes: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_commitish: Option<&'a str>,
+}
+
+/// Minimal GitHub REST client for releases, authenticated with GH_TOKEN
+/// or GITHUB_TOKEN.
+pub struct GitHub {
+    client: Client,
+    token: String,
+}
+
+impl GitHub {
+    pub fn from_env() -> Result<Self> {
+        let token = secrets::find("GH_TOKEN")
+            .or_else(|| secrets::find("GITHUB_TOKEN"))
+            .context("Set GH_TOKEN or GITHUB_TOKEN to talk to GitHub")?;
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(None)
+            .build()?;
+        Ok(GitHub { client, token })
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .header(USER_AGENT, "kokuban-ci")
+            .header(ACCEPT, "application/vnd.github+json")
+            .header(AUTHORIZATION, format!("Bearer {}", self.token))
+            .header("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    /// Sends the request `build` makes, retrying network errors and 5xx as
+    /// the retry policy says. Other error statuses are returned as errors.
+    fn send(&self, what: &str, build: impl Fn() -> Result<RequestBuilder>) -> Result<Response> {
+        let policy = retry::policy();
+        let mut attempt = 1;
+        loop {
+            let error = match build()?.send() {
+                Ok(resp) if resp.status().is_server_error() => format!("HTTP {}", resp.status()),
+                Ok(resp)
+                    if resp.status().is_success() || resp.status() == StatusCode::NOT_FOUND =>
+                {
+                    return Ok(resp);
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = resp.text().unwrap_or_default();
+                    return Err(anyhow!("{} failed: HTTP {}: {}", what, status, body.trim()));
+                }
+                Err(e) => e.to_string(),
+            };
+            if attempt >= policy.attempts || interrupt::interrupted() {
+                return Err(anyhow!(
+                    "{} failed after {} attempts: {}",
+                    what,
+                    attempt,
+                    error
+                ));
+            }
+            let delay = policy.delay(attempt);
+            info!(
+                "Attempt {}/{} for {} failed: {}. Retrying in {:.1}s...",
+                attempt,
+                policy.attempts,
+                what,
+                error,
+                delay.as_secs_f64()
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// The release for `tag`, or `None` if there is none.
+    pub fn release_by_tag(&self, repo: &str, tag: &str) -> Result<Option<ReleaseInfo>> {
+        let url = format!("{}/repos/{}/releases/tags/{}", API, repo, tag);
+        let resp = self.send("fetching release", || {
+            Ok(self.request(reqwest::Method::GET, &url))
+        })?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        Ok(Some(resp.json()?))
+    }
+
+    pub fn create_release(&self, repo: &str, release: &NewRelease) -> Result<ReleaseInfo> {
+        let url = format!("{}/repos/{}/releases", API, repo);
+        let resp = self.send("creating release", || {
+            Ok(self.request(reqwest::Method::POST, &url).json(release))
+        })?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(anyhow!(
+                "Repository {} not found or token lacks access",
+                repo
+            ));
+        }
+        Ok(resp.json()?)
+    }
+
+    /// Uploads `path` as an asset. An existing asset with the same name is
+    /// deleted first with `clobber`, otherwise it is an error.
+    pub fn upload_asset(
+        &self,
+        repo: &str,
+        release: &ReleaseInfo,
+        path: &Path,
+        clobber: bool,
+    ) -> Result<()> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("{} has no file name", path.display()))?
+            .to_string_lossy()
+            .to_string();
+        if let Some(existing) = release.assets.iter().find(|a| a.name == name) {
+            if !clobber {
+                return Err(anyhow!(
+                    "Release already has an asset named {} (set release.overwrite_existing)",
+                    name
+                ));
+            }
+            let url = format!("{}/repos/{}/releases/assets/{}", API, repo, existing.id);
+            self.send("deleting old asset", || {
+                Ok(self.request(reqwest::Method::DELETE, &url))
+            })?;
+        }
+
+        let len = fs::metadata(path)?.len();
+        let pb = ProgressBar::with_draw_target(Some(len), progress::draw_target());
+        pb.set_style(
+            ProgressStyle::with_template(
+                "{msg:30!} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        );
+        pb.set_message(name.clone());
+        let url = format!(
+            "{}/repos/{}/releases/{}/assets?name={}",
+            UPLOADS,
+            repo,
+            release.id,
+            url_encode(&name)
+        );
+        self.send(&format!("uploading {}", name), || {
+            pb.set_position(0);
+            let file = File::open(path)?;
+            Ok(self
+                .request(reqwest::Method::POST, &url)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .body(Body::sized(pb.wrap_read(file), len)))
+        })?;
+        pb.finish();
+        info!("Uploaded {} ({} bytes)", name, len);
+        Ok(())
+    }
+
+    pub fn download_asset(&self, asset: &Asset, dest: &Path) -> Result<()> {
+        let mut resp = self.send(&format!("downloading {}", asset.name), || {
+            Ok(self
+                .request(reqwest::Method::GET, &asset.url)
+                .header(ACCEPT, "application/octet-stream"))
+        })?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(anyhow!("Asset {} not found", asset.name));
+        }
+        io::copy(&mut resp, &mut File::create(dest)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,543 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::secrets;
+use crate::utils::{format_duration, get_cache_dir};
+use crate::warnings::Warning;
+
+/// Schema changes, applied in order; `PRAGMA user_version` counts how many
+/// a database already has.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE builds (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        project TEXT NOT NULL,
+        branch TEXT NOT NULL,
+        started_at TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        ok INTEGER NOT NULL,
+        error TEXT,
+        failed_stage TEXT,
+        kernel_version TEXT,
+        commit_sha TEXT,
+        stages TEXT NOT NULL
+    );
+    CREATE TABLE artifacts (
+        build_id INTEGER NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+        path TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        sha256 TEXT NOT NULL
+    );
+    CREATE INDEX builds_project ON builds(project, branch);
+",
+    "
+    ALTER TABLE builds ADD COLUMN config BLOB;
+    ALTER TABLE builds ADD COLUMN image_size INTEGER;
+    ALTER TABLE builds ADD COLUMN sections TEXT;
+    ALTER TABLE builds ADD COLUMN warnings TEXT;
+",
+    "
+    ALTER TABLE builds ADD COLUMN warning_list TEXT;
+",
+    "
+    ALTER TABLE builds ADD COLUMN fingerprint TEXT;
+",
+];
+
+#[derive(Serialize)]
+pub struct Artifact {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What `compare` looks at. Everything is empty for builds that stopped
+/// before the compile finished.
+#[derive(Default)]
+pub struct BuildMetrics {
+    /// The final `.config`.
+    pub config: Option<String>,
+    pub image_size: Option<u64>,
+    /// vmlinux section sizes from `size -A`.
+    pub sections: BTreeMap<String, u64>,
+    /// Compiler warnings by `-W` flag ("other" when there is none).
+    pub warnings: BTreeMap<String, u64>,
+}
+
+/// A recorded build's metrics with a one-line description of the build.
+pub struct Measured {
+    pub label: String,
+    pub metrics: BuildMetrics,
+}
+
+/// One finished or failed build.
+pub struct BuildRecord {
+    pub project: String,
+    pub branch: String,
+    pub started_at: DateTime<Local>,
+    pub duration: Duration,
+    pub error: Option<String>,
+    pub failed_stage: Option<String>,
+    pub kernel_version: Option<String>,
+    pub commit: Option<String>,
+    pub stages: Vec<(String, Duration)>,
+    pub artifacts: Vec<Artifact>,
+    pub metrics: BuildMetrics,
+    /// Each warning, for the next build's `max_new_warnings` check.
+    pub warnings: Vec<Warning>,
+    /// The whole kernel was compiled. Only such builds keep their warning
+    /// list as a baseline; partial ones would make every other warning
+    /// look new.
+    pub full_compile: bool,
+    /// What went into the build, for skipping unchanged rebuilds.
+    pub fingerprint: Option<String>,
+}
+
+/// A recorded build as the dashboard lists it.
+#[derive(Serialize)]
+pub struct BuildSummary {
+    pub id: i64,
+    pub project: String,
+    pub branch: String,
+    pub started_at: String,
+    pub duration_ms: i64,
+    pub ok: bool,
+    pub failed_stage: Option<String>,
+    pub kernel_version: Option<String>,
+    pub artifacts: Vec<Artifact>,
+}
+
+/// `KOKUBAN_HISTORY_DB`, else `history.db` in the cache dir so it outlives
+/// checkouts of the CI repo.
+fn db_path() -> PathBuf {
+    env::var("KOKUBAN_HISTORY_DB")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| get_cache_dir().join("history.db"))
+}
+
+fn open() -> Result<Connection> {
+    let path = db_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut conn = Connection::open(&path)
+        .with_context(|| format!("Failed to open build history at {}", path.display()))?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow!(
+            "Build history at {} is from a newer version of this tool",
+            path.display()
+        ));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+    }
+    Ok(conn)
+}
+
+/// Saves `build` and returns its id.
+pub fn record(build: &BuildRecord) -> Result<i64> {
+    let mut conn = open()?;
+    let stages: Vec<(&str, u128)> = build
+        .stages
+        .iter()
+        .map(|(stage, took)| (stage.as_str(), took.as_millis()))
+        .collect();
+    // .config files are a few hundred KB of text; keep them compressed.
+    let config = build
+        .metrics
+        .config
+        .as_ref()
+        .map(|c| zstd::encode_all(c.as_bytes(), 9))
+        .transpose()?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO builds (project, branch, started_at, duration_ms, ok, error, failed_stage,
+             kernel_version, commit_sha, stages, config, image_size, sections, warnings,
+             warning_list, fingerprint)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+        params![
+            build.project,
+            build.branch,
+            build.started_at.to_rfc3339(),
+            build.duration.as_millis() as i64,
+            build.error.is_none(),
+            build.error.as_deref().map(secrets::redact),
+            build.failed_stage,
+            build.kernel_version,
+            build.commit,
+            serde_json::to_string(&stages)?,
+            config,
+            build.metrics.image_size.map(|n| n as i64),
+            serde_json::to_string(&build.metrics.sections)?,
+            serde_json::to_string(&build.metrics.warnings)?,
+            build
+                .full_compile
+                .then(|| serde_json::to_string(&build.warnings))
+                .transpose()?,
+            build.fingerprint,
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    for artifact in &build.artifacts {
+        tx.execute(
+            "INSERT INTO artifacts (build_id, path, size, sha256) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                id,
+                artifact.path.to_string_lossy(),
+                artifact.size as i64,
+                artifact.sha256
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(id)
+}
+
+/// How many builds of `project`/`branch` succeeded and failed.
+pub fn result_counts(project: &str, branch: &str) -> Result<(u64, u64)> {
+    let conn = open()?;
+    Ok(conn.query_row(
+        "SELECT COALESCE(SUM(ok), 0), COALESCE(SUM(NOT ok), 0) FROM builds
+         WHERE project = ?1 AND branch = ?2",
+        [project, branch],
+        |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+    )?)
+}
+
+/// The warnings of the last successful full build of `project`/`branch`
+/// and its id; incremental and resumed builds have no list recorded.
+pub fn previous_warnings(project: &str, branch: &str) -> Result<Option<(i64, Vec<Warning>)>> {
+    let conn = open()?;
+    let row = conn
+        .query_row(
+            "SELECT id, warning_list FROM builds
+             WHERE project = ?1 AND branch = ?2 AND ok AND warning_list IS NOT NULL
+             ORDER BY id DESC LIMIT 1",
+            [project, branch],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+        )
+        .optional()?;
+    match row {
+        Some((id, list)) => Ok(Some((id, serde_json::from_str(&list)?))),
+        None => Ok(None),
+    }
+}
+
+/// The id of the last successful build of `project`/`branch`.
+pub fn last_successful(project: &str, branch: &str) -> Result<Option<i64>> {
+    let conn = open()?;
+    Ok(conn
+        .query_row(
+            "SELECT id FROM builds WHERE project = ?1 AND branch = ?2 AND ok
+             ORDER BY id DESC LIMIT 1",
+            [project, branch],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// The id and fingerprint of the last successful build of
+/// `project`/`branch`, if it was recorded with one.
+pub fn last_successful_fingerprint(project: &str, branch: &str) -> Result<Option<(i64, String)>> {
+    let conn = open()?;
+    let row = conn
+        .query_row(
+            "SELECT id, fingerprint FROM builds WHERE project = ?1 AND branch = ?2 AND ok
+             ORDER BY id DESC LIMIT 1",
+            [project, branch],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?)),
+        )
+        .optional()?;
+    Ok(row.and_then(|(id, fingerprint)| Some((id, fingerprint?))))
+}
+
+/// The kernel_source HEAD the last successful build of `project`/`branch`
+/// was built from.
+pub fn last_successful_commit(project: &str, branch: &str) -> Result<Option<String>> {
+    let conn = open()?;
+    Ok(conn
+        .query_row(
+            "SELECT commit_sha FROM builds WHERE project = ?1 AND branch = ?2 AND ok
+             ORDER BY id DESC LIMIT 1",
+            [project, branch],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten())
+}
+
+/// The last `limit` builds, newest first, with their artifacts.
+pub fn recent_builds(limit: usize) -> Result<Vec<BuildSummary>> {
+    let conn = open()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, project, branch, started_at, duration_ms, ok, failed_stage, kernel_version
+         FROM builds ORDER BY id DESC LIMIT ?1",
+    )?;
+    let mut builds = stmt
+        .query_map([limit as i64], |row| {
+            Ok(BuildSummary {
+                id: row.get(0)?,
+                project: row.get(1)?,
+                branch: row.get(2)?,
+                started_at: row.get(3)?,
+                duration_ms: row.get(4)?,
+                ok: row.get(5)?,
+                failed_stage: row.get(6)?,
+                kernel_version: row.get(7)?,
+                artifacts: Vec::new(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut stmt = conn
+        .prepare("SELECT path, size, sha256 FROM artifacts WHERE build_id = ?1 ORDER BY rowid")?;
+    for build in &mut builds {
+        build.artifacts = stmt
+            .query_map([build.id], artifact_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+    Ok(builds)
+}
+
+/// The artifacts recorded for build `id`.
+pub fn artifacts(id: i64) -> Result<Vec<Artifact>> {
+    let conn = open()?;
+    let mut stmt = conn
+        .prepare("SELECT path, size, sha256 FROM artifacts WHERE build_id = ?1 ORDER BY rowid")?;
+    let artifacts = stmt
+        .query_map([id], artifact_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(artifacts)
+}
+
+/// An `artifacts` row selected as `path, size, sha256`.
+fn artifact_row(row: &rusqlite::Row) -> rusqlite::Result<Artifact> {
+    Ok(Artifact {
+        path: PathBuf::from(row.get::<_, String>(0)?),
+        size: row.get::<_, i64>(1)? as u64,
+        sha256: row.get(2)?,
+    })
+}
+
+/// The path of the `index`th artifact recorded for build `id`.
+pub fn artifact_path(id: i64, index: usize) -> Result<Option<PathBuf>> {
+    let conn = open()?;
+    Ok(conn
+        .query_row(
+            "SELECT path FROM artifacts WHERE build_id = ?1 ORDER BY rowid LIMIT 1 OFFSET ?2",
+            params![id, index as i64],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?
+        .map(PathBuf::from))
+}
+
+pub fn load_metrics(id: i64) -> Result<Measured> {
+    let conn = open()?;
+    let row = conn
+        .query_row(
+            "SELECT project, branch, started_at, kernel_version, config, image_size, sections,
+                 warnings
+             FROM builds WHERE id = ?1",
+            [id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<Vec<u8>>>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                ))
+            },
+        )
+        .optional()?
+        .ok_or_else(|| anyhow!("No build with id {}", id))?;
+    let (project, branch, started, kernel, config, image_size, sections, warnings) = row;
+    let config = match config {
+        Some(blob) => {
+            Some(String::from_utf8_lossy(&zstd::decode_all(blob.as_slice())?).into_owned())
+        }
+        None => None,
+    };
+    let parse = |json: Option<String>| -> BTreeMap<String, u64> {
+        json.and_then(|j| serde_json::from_str(&j).ok())
+            .unwrap_or_default()
+    };
+    Ok(Measured {
+        label: format!(
+            "#{} {} / {} ({}, {})",
+            id,
+            project,
+            branch,
+            kernel.as_deref().unwrap_or("unknown kernel"),
+            short_time(&started)
+        ),
+        metrics: BuildMetrics {
+            config,
+            image_size: image_size.map(|n| n as u64),
+            sections: parse(sections),
+            warnings: parse(warnings),
+        },
+    })
+}
+
+fn short_time(rfc3339: &str) -> String {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|_| rfc3339.to_string())
+}
+
+pub fn handle_history_list(project: Option<String>, limit: usize) -> Result<()> {
+    let conn = open()?;
+    let mut stmt = conn.prepare(
+        "SELECT id, project, branch, started_at, duration_ms, ok, kernel_version, failed_stage
+         FROM builds WHERE ?1 IS NULL OR project = ?1 ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt
+        .query_map(params![project, limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                format!(
+                    "{} / {}",
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?
+                ),
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, bool>(5)?,
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<String>>(7)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        println!("No builds recorded in {}", db_path().display());
+        return Ok(());
+    }
+
+    let width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+    println!(
+        "{:>5}  {:<16}  {:<width$}  {:<10}  {:>7}  Result",
+        "ID", "Started", "Build", "Kernel", "Time"
+    );
+    for (id, build, started, ms, ok, kernel, stage) in rows {
+        let result = match (ok, stage) {
+            (true, _) => "✅".to_string(),
+            (false, Some(stage)) => format!("❌ {}", stage),
+            (false, None) => "❌".to_string(),
+        };
+        println!(
+            "{:>5}  {:<16}  {:<width$}  {:<10}  {:>7}  {}",
+            id,
+            short_time(&started),
+            build,
+            kernel.as_deref().unwrap_or("-"),
+            format_duration(Duration::from_millis(ms as u64)),
+            result
+        );
+    }
+    Ok(())
+}
+
+pub fn handle_history_show(id: i64) -> Result<()> {
+    let conn = open()?;
+    let build = conn
+        .query_row(
+            "SELECT project, branch, started_at, duration_ms, ok, error, failed_stage,
+                 kernel_version, commit_sha, stages, image_size, warnings
+             FROM builds WHERE id = ?1",
+            [id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, Option<String>>(5)?,
+                    row.get::<_, Option<String>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                    row.get::<_, String>(9)?,
+                    row.get::<_, Option<i64>>(10)?,
+                    row.get::<_, Option<String>>(11)?,
+                ))
+            },
+        )
+        .optional()?
+        .ok_or_else(|| anyhow!("No build with id {}", id))?;
+    let (project, branch, started, ms, ok, error, stage, kernel, commit, stages, image, warnings) =
+        build;
+
+    println!("Build #{}: {} / {}", id, project, branch);
+    println!("  Started:  {}", short_time(&started));
+    println!(
+        "  Duration: {}",
+        format_duration(Duration::from_millis(ms as u64))
+    );
+    match (ok, stage) {
+        (true, _) => println!("  Result:   ✅ success"),
+        (false, Some(stage)) => println!("  Result:   ❌ failed in {}", stage),
+        (false, None) => println!("  Result:   ❌ failed"),
+    }
+    if let Some(error) = error {
+        println!("  Error:    {}", error.lines().next().unwrap_or_default());
+    }
+    println!("  Kernel:   {}", kernel.as_deref().unwrap_or("-"));
+    println!("  Commit:   {}", commit.as_deref().unwrap_or("-"));
+    if let Some(size) = image {
+        println!("  Image:    {} bytes", size);
+    }
+    let warnings: BTreeMap<String, u64> = warnings
+        .and_then(|w| serde_json::from_str(&w).ok())
+        .unwrap_or_default();
+    if !warnings.is_empty() {
+        println!("  Warnings: {}", warnings.values().sum::<u64>());
+    }
+
+    let stages: Vec<(String, u64)> = serde_json::from_str(&stages).unwrap_or_default();
+    if !stages.is_empty() {
+        println!("\nStages:");
+        let width = stages.iter().map(|(s, _)| s.len()).max().unwrap_or(0);
+        for (stage, ms) in stages {
+            println!(
+                "  {:<width$}  {:>7}",
+                stage,
+                format_duration(Duration::from_millis(ms))
+            );
+        }
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT path, size, sha256 FROM artifacts WHERE build_id = ?1 ORDER BY rowid")?;
+    let artifacts = stmt
+        .query_map([id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    if !artifacts.is_empty() {
+        println!("\nArtifacts:");
+        for (path, size, sha256) in artifacts {
+            println!("  {}  ({} bytes)\n    sha256 {}", path, size, sha256);
+        }
+    }
+    Ok(())
+}
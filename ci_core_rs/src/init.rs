@@ -0,0 +1,154 @@
+use anyhow::{Result, anyhow};
+use serde_json::{Map, Value, json};
+use std::io::{self, BufRead, Write};
+
+use crate::ksu::{load_ksu_variants, resolve_variant};
+use crate::utils::{get_config_path, load_projects_raw, save_projects};
+use crate::validate::project_issues;
+
+const DEFAULT_AK3_REPO: &str = "https://github.com/YuzakiKokuban/AnyKernel3.git";
+const DEFAULT_KSU: &str = "resukisu,mksu,ksu";
+
+/// Reads one answer; `default` is used for an empty line. Questions without
+/// a default are asked again until they get one.
+fn ask(input: &mut impl BufRead, question: &str, default: Option<&str>) -> Result<String> {
+    loop {
+        match default {
+            Some(d) if !d.is_empty() => print!("{} [{}]: ", question, d),
+            Some(_) => print!("{} (optional): ", question),
+            None => print!("{}: ", question),
+        }
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(anyhow!("init aborted: no more input"));
+        }
+        let answer = line.trim();
+        if !answer.is_empty() {
+            return Ok(answer.to_string());
+        }
+        if let Some(d) = default {
+            return Ok(d.to_string());
+        }
+    }
+}
+
+/// Asks for list entries one per line until an empty line.
+fn ask_list(input: &mut impl BufRead, question: &str) -> Result<Vec<String>> {
+    println!("{} (one per line, empty line to finish):", question);
+    let mut items = Vec::new();
+    loop {
+        print!("  > ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(items);
+        }
+        items.push(line.trim().to_string());
+    }
+}
+
+fn split_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|p| p.trim().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+/// Walks through the fields a new device needs and appends the entry to
+/// the projects file once it passes the same checks as `validate`.
+pub fn handle_init() -> Result<()> {
+    let mut raw = load_projects_raw()?;
+    let ksu_variants = load_ksu_variants(&raw)?;
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    println!("New project for {}", get_config_path().display());
+    let device_en = ask(&mut input, "Device name", None)?;
+    let device_cn = ask(&mut input, "Device name (Chinese)", Some(&device_en))?;
+    let device = ask(&mut input, "Short device id, e.g. s23", None)?.to_lowercase();
+    // The build passes the second part of the key as TARGET_SOC.
+    let soc = ask(&mut input, "SoC, e.g. sm8550", None)?.to_lowercase();
+    let key = format!("{}_{}", device, soc);
+    if raw.contains_key(&key) {
+        return Err(anyhow!("Project '{}' already exists", key));
+    }
+
+    let repo = ask(&mut input, "Kernel repo (owner/name)", None)?;
+    let defconfig = ask(&mut input, "Defconfig", None)?;
+    let localversion = ask(&mut input, "LOCALVERSION base", Some("-Kokuban"))?;
+    let lto = ask(&mut input, "LTO (thin, full, none)", Some("thin"))?;
+    let toolchain_urls = ask_list(&mut input, "Toolchain archive URLs")?;
+    let mut toolchain_prefix = String::new();
+    let mut toolchain_exports = Vec::new();
+    if !toolchain_urls.is_empty() {
+        toolchain_prefix = ask(&mut input, "Toolchain dir inside the archive", Some(""))?;
+        toolchain_exports = ask_list(&mut input, "Toolchain bin dirs to put on PATH")?;
+    }
+
+    let known: Vec<&str> = ksu_variants.keys().map(|k| k.as_str()).collect();
+    println!("KernelSU variants: {}", known.join(", "));
+    let supported_ksu = loop {
+        let answer = ask(&mut input, "Supported KernelSU variants", Some(DEFAULT_KSU))?;
+        let names = split_list(&answer);
+        match names
+            .iter()
+            .find(|n| resolve_variant(&ksu_variants, n).is_none())
+        {
+            Some(unknown) => println!("Unknown variant '{}'", unknown),
+            None => break names,
+        }
+    };
+    let ak3_repo = ask(&mut input, "AnyKernel3 repo", Some(DEFAULT_AK3_REPO))?;
+    let ak3_branch = ask(&mut input, "AnyKernel3 branch", Some("master"))?;
+    let default_zip = format!("{}_Kernel", device.to_uppercase());
+    let zip_name = ask(&mut input, "Zip name prefix", Some(&default_zip))?;
+
+    // Unlike `add`, unset fields are left out rather than written as null,
+    // like the hand-written entries.
+    let mut entry = Map::new();
+    entry.insert("repo".into(), json!(repo));
+    entry.insert("defconfig".into(), json!(defconfig));
+    entry.insert("localversion_base".into(), json!(localversion));
+    entry.insert("lto".into(), json!(lto));
+    entry.insert("supported_ksu".into(), json!(supported_ksu));
+    entry.insert(
+        "readme_placeholders".into(),
+        json!({ "DEVICE_NAME_CN": device_cn, "DEVICE_NAME_EN": device_en }),
+    );
+    if !toolchain_urls.is_empty() {
+        entry.insert("toolchain_urls".into(), json!(toolchain_urls));
+    }
+    if !toolchain_prefix.is_empty() {
+        entry.insert("toolchain_path_prefix".into(), json!(toolchain_prefix));
+    }
+    if !toolchain_exports.is_empty() {
+        entry.insert("toolchain_path_exports".into(), json!(toolchain_exports));
+    }
+    entry.insert("anykernel_repo".into(), json!(ak3_repo));
+    entry.insert("anykernel_branch".into(), json!(ak3_branch));
+    entry.insert("zip_name_prefix".into(), json!(zip_name));
+    let entry = Value::Object(entry);
+
+    let issues = project_issues(&key, &entry, &ksu_variants);
+    if !issues.is_empty() {
+        for issue in &issues {
+            println!("  {}: {}", key, issue);
+        }
+        return Err(anyhow!(
+            "The new entry has {} problem(s); nothing was written",
+            issues.len()
+        ));
+    }
+
+    println!("\n\"{}\": {}", key, serde_json::to_string_pretty(&entry)?);
+    let confirm = ask(&mut input, "Add this project?", Some("Y"))?;
+    if !confirm.eq_ignore_ascii_case("y") && !confirm.eq_ignore_ascii_case("yes") {
+        println!("Nothing written.");
+        return Ok(());
+    }
+    raw.insert(key.clone(), entry);
+    save_projects(&raw)?;
+    println!("Added {} to {}", key, get_config_path().display());
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use log::warn;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+/// Live `cleanup()` guards.
+static CLEANING: AtomicUsize = AtomicUsize::new(0);
+/// Process groups of the commands running right now.
+static GROUPS: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// The build was stopped by Ctrl-C, SIGTERM or SIGHUP.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Exit status after an interrupt, as shells report for SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+/// On the first signal, terminates the running commands' process groups
+/// and lets the build unwind: commands that haven't started yet fail with
+/// `Interrupted`, and the snapshot, localversion and package guards clean
+/// up as they drop. A second signal kills everything and exits at once.
+pub fn install() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("Interrupted again, exiting without cleaning up.");
+            signal_groups(libc::SIGKILL);
+            process::exit(EXIT_CODE);
+        }
+        warn!("Interrupted, stopping the build and cleaning up (Ctrl-C again to exit now)...");
+        signal_groups(libc::SIGTERM);
+    });
+    match result {
+        Ok(()) => INSTALLED.store(true, Ordering::SeqCst),
+        Err(e) => warn!("Failed to install the Ctrl-C handler: {}", e),
+    }
+}
+
+/// The handler is in place. Only then may commands run in their own
+/// process group: the terminal's Ctrl-C no longer reaches them, so
+/// without the handler they would outlive us.
+pub fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// `Interrupted` once a signal came in, unless a cleanup is running.
+pub fn check() -> Result<(), Interrupted> {
+    if interrupted() && CLEANING.load(Ordering::SeqCst) == 0 {
+        return Err(Interrupted);
+    }
+    Ok(())
+}
+
+/// Fails reads with `Interrupted` once a signal came in, so downloads and
+/// extractions stop between chunks.
+pub struct Checked<R>(pub R);
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        check().map_err(io::Error::other)?;
+        self.0.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for Checked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+/// Lets commands run again after an interrupt while the guard lives, e.g.
+/// to roll back the kernel source.
+pub fn cleanup() -> Cleanup {
+    CLEANING.fetch_add(1, Ordering::SeqCst);
+    Cleanup
+}
+
+pub struct Cleanup;
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        CLEANING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Registers a command's process group until the guard drops.
+pub fn track(pgid: i32) -> Tracked {
+    GROUPS.lock().unwrap().push(pgid);
+    if check().is_err() {
+        // Spawned just as the signal came in.
+        // SAFETY: signals the process group of a command we spawned.
+        unsafe { libc::kill(-pgid, libc::SIGTERM) };
+    }
+    Tracked(pgid)
+}
+
+pub struct Tracked(i32);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        GROUPS.lock().unwrap().retain(|&g| g != self.0);
+    }
+}
+
+fn signal_groups(signal: i32) {
+    for pgid in GROUPS.lock().unwrap().iter() {
+        // SAFETY: signals process groups of commands we spawned.
+        unsafe { libc::kill(-pgid, signal) };
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// A Kconfig assignment; `None` is `# CONFIG_X is not set`.
+pub type ConfigEntry = (String, Option<String>);
+
+/// Parses `CONFIG_X=val` and `# CONFIG_X is not set` lines, ignoring
+/// everything else. `CONFIG_X=n` is normalised to "not set".
+pub fn parse_fragment(content: &str) -> Vec<ConfigEntry> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| {
+            if let Some(name) = line
+                .strip_prefix("# CONFIG_")
+                .and_then(|l| l.strip_suffix(" is not set"))
+            {
+                return Some((name.to_string(), None));
+            }
+            let (name, value) = line.strip_prefix("CONFIG_")?.split_once('=')?;
+            let value = Some(value.to_string()).filter(|v| v != "n");
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+pub fn render_entry((name, value): &ConfigEntry) -> String {
+    match value {
+        Some(v) => format!("CONFIG_{}={}", name, v),
+        None => format!("# CONFIG_{} is not set", name),
+    }
+}
+
+/// Merges entries into a `.config`, later entries winning. Run
+/// `make olddefconfig` afterwards to resolve dependencies.
+pub fn merge_into(config_path: &Path, entries: &[ConfigEntry]) -> Result<()> {
+    let content = fs::read_to_string(config_path)?;
+    let names: Vec<&str> = entries.iter().map(|(n, _)| n.as_str()).collect();
+
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| {
+            parse_fragment(line)
+                .first()
+                .is_none_or(|(name, _)| !names.contains(&name.as_str()))
+        })
+        .map(str::to_string)
+        .collect();
+    for (i, entry) in entries.iter().enumerate() {
+        // Only the last assignment of a symbol is kept.
+        if !names[i + 1..].contains(&entry.0.as_str()) {
+            lines.push(render_entry(entry));
+        }
+    }
+
+    fs::write(config_path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Parses an `enable_configs` entry: `NAME`, `NAME=y|m|n`, `NAME=123` or
+/// `NAME="string"`. A `CONFIG_` prefix is optional.
+pub fn parse_spec(spec: &str) -> ConfigEntry {
+    let spec = spec.trim().trim_start_matches("CONFIG_");
+    let (name, value) = spec.split_once('=').unwrap_or((spec, "y"));
+    (
+        name.to_string(),
+        Some(value.to_string()).filter(|v| v != "n"),
+    )
+}
+
+/// `scripts/config` arguments that apply an entry.
+pub fn config_args((name, value): &ConfigEntry) -> Vec<String> {
+    let args: Vec<&str> = match value.as_deref() {
+        None => vec!["--disable", name],
+        Some("y") => vec!["--enable", name],
+        Some("m") => vec!["--module", name],
+        Some(v) if v.len() >= 2 && v.starts_with('"') && v.ends_with('"') => {
+            vec!["--set-str", name, &v[1..v.len() - 1]]
+        }
+        Some(v) => vec!["--set-val", name, v],
+    };
+    args.into_iter().map(str::to_string).collect()
+}
+
+/// Which of `names` (without `CONFIG_`) some `Kconfig*` file in the tree
+/// declares with `config` or `menuconfig`.
+pub fn declared_symbols(kernel_source: &Path, names: &[&str]) -> Result<HashSet<String>> {
+    let mut found = HashSet::new();
+    let mut dirs = vec![kernel_source.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if name.starts_with("Kconfig") && entry.path().is_file() {
+                for line in fs::read_to_string(entry.path())?.lines() {
+                    let mut words = line.split_whitespace();
+                    if let (Some("config" | "menuconfig"), Some(symbol)) =
+                        (words.next(), words.next())
+                        && names.contains(&symbol)
+                    {
+                        found.insert(symbol.to_string());
+                    }
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Requested entries whose value `make olddefconfig` changed, with the value
+/// it ended up with. Later requests for the same symbol win.
+pub fn dropped_entries(
+    requested: &[ConfigEntry],
+    config_path: &Path,
+) -> Result<Vec<(ConfigEntry, Option<String>)>> {
+    let actual: HashMap<String, Option<String>> = parse_fragment(&fs::read_to_string(config_path)?)
+        .into_iter()
+        .collect();
+    let mut wanted: Vec<&ConfigEntry> = Vec::new();
+    for entry in requested {
+        wanted.retain(|(name, _)| *name != entry.0);
+        wanted.push(entry);
+    }
+    Ok(wanted
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let got = actual.get(name).cloned().flatten();
+            (got != *value).then(|| ((name.clone(), value.clone()), got))
+        })
+        .collect())
+}
+
+/// What changed between two `.config`s, in `scripts/diffconfig` style:
+/// `-FOO y` removed, ` FOO y -> n` changed, `+FOO m` added.
+pub fn diff_configs(old: &str, new: &str) -> Vec<String> {
+    let old: BTreeMap<String, Option<String>> = parse_fragment(old).into_iter().collect();
+    let new: BTreeMap<String, Option<String>> = parse_fragment(new).into_iter().collect();
+    let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "n".to_string());
+    let mut lines: Vec<String> = old
+        .iter()
+        .filter(|(name, _)| !new.contains_key(*name))
+        .map(|(name, v)| format!("-{} {}", name, value(v)))
+        .collect();
+    lines.extend(new.iter().filter_map(|(name, v)| match old.get(name) {
+        Some(o) if o != v => Some(format!(" {} {} -> {}", name, value(o), value(v))),
+        _ => None,
+    }));
+    lines.extend(
+        new.iter()
+            .filter(|(name, _)| !old.contains_key(*name))
+            .map(|(name, v)| format!("+{} {}", name, value(v))),
+    );
+    lines
+}
@@ -0,0 +1,523 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+
+use crate::config::{
+    BUILTIN_KSU_VARIANTS, KsuVariant, KsuVariants, PatchSpec, ProjectConfig, ProjectsMap,
+};
+use crate::kconfig::{ConfigEntry, declared_symbols};
+use crate::patch;
+use crate::retry;
+use crate::utils::{git_reference_args, merge_values, run_cmd};
+
+/// Built-in variants with the projects file's `_ksu_variants` merged on top.
+pub fn load_ksu_variants(projects: &ProjectsMap) -> Result<KsuVariants> {
+    let mut registry: serde_json::Value = serde_json::from_str(BUILTIN_KSU_VARIANTS)?;
+    if let Some(custom) = projects.get("_ksu_variants") {
+        merge_values(&mut registry, custom);
+    }
+    Ok(serde_json::from_value(registry)?)
+}
+
+/// Looks a variant up by name or alias and returns its canonical name.
+pub fn resolve_variant<'a>(
+    variants: &'a KsuVariants,
+    name: &str,
+) -> Option<(&'a str, &'a KsuVariant)> {
+    variants
+        .iter()
+        .find(|(key, v)| *key == name || v.aliases.iter().any(|a| a == name))
+        .map(|(key, v)| (key.as_str(), v))
+}
+
+pub fn canonical_variant_name(variants: &KsuVariants, name: &str) -> String {
+    resolve_variant(variants, name)
+        .map(|(key, _)| key.to_string())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// The branches a project is built as: main plus each supported variant,
+/// as in the CI matrix.
+pub fn project_branches(variants: &KsuVariants, proj: &ProjectConfig) -> Vec<String> {
+    let mut branches = vec!["main".to_string()];
+    for variant in proj.supported_ksu.iter().flatten() {
+        branches.push(canonical_variant_name(variants, variant));
+    }
+    branches
+}
+
+pub fn variant_label(variants: &KsuVariants, name: &str) -> String {
+    resolve_variant(variants, name)
+        .and_then(|(_, v)| v.label.clone())
+        .unwrap_or_else(|| name.to_uppercase())
+}
+
+/// Applies a variant to the kernel tree before `make defconfig`: runs its
+/// setup script, SUSFS, manual hooks and extra patches. Its config switches
+/// come from `config_fragment` and are merged after defconfig.
+///
+/// Returns the KernelSU commit that ended up in the tree, if known.
+pub fn integrate(
+    name: &str,
+    variant: &KsuVariant,
+    proj: &ProjectConfig,
+    kernel_source: &Path,
+) -> Result<Option<String>> {
+    let ksu_ref = proj.ksu_ref.as_deref();
+    let mut ksu_commit = None;
+    if let (Some(url), Some(args)) = (variant.setup_url_for(ksu_ref), &variant.build_setup_args) {
+        info!("Installing KernelSU for {}", name);
+        // pipefail, so a failed download fails the step and is retried.
+        let cmd = format!(
+            "set -o pipefail; curl -fLSs '{}' | bash -s {}",
+            url,
+            args.join(" ")
+        );
+        retry::run("KernelSU setup", || {
+            run_cmd(&["bash", "-c", &cmd], Some(kernel_source), false)
+        })?;
+        ksu_commit = pin_source(variant, ksu_ref, kernel_source)?;
+    }
+
+    if let Some(susfs_branch) = susfs_branch_for(variant, proj, kernel_source)? {
+        apply_susfs(
+            &susfs_branch,
+            proj.git_reference_dir.as_deref(),
+            kernel_source,
+        )?;
+    }
+
+    if let Some(hook_url) = variant.manual_hook_url_for(proj) {
+        apply_manual_hook(&hook_url, kernel_source)?;
+    }
+
+    for patch in &variant.patches {
+        patch::apply(&PatchSpec::new(patch), kernel_source)?;
+    }
+
+    Ok(ksu_commit)
+}
+
+/// The variant's config switches as a fragment for `kconfig::merge_into`.
+pub fn config_fragment(variant: &KsuVariant) -> Vec<ConfigEntry> {
+    let enable = variant
+        .config_enable
+        .iter()
+        .map(|c| (c.clone(), Some("y".to_string())));
+    let disable = variant.config_disable.iter().map(|c| (c.clone(), None));
+    enable.chain(disable).collect()
+}
+
+/// Checks that integration left the expected artifacts in the tree, naming
+/// everything that is missing so a rootless kernel is never packaged.
+pub fn verify_sources(variant: &KsuVariant, kernel_source: &Path) -> Result<()> {
+    let mut expected = Vec::new();
+    if variant.build_setup_args.is_some() {
+        // setup.sh symlinks the KernelSU source here; exists() follows it.
+        expected.push("drivers/kernelsu");
+    }
+    if variant.susfs_branch.is_some() {
+        expected.push("include/linux/susfs.h");
+    }
+
+    let missing: Vec<&str> = expected
+        .into_iter()
+        .filter(|p| !kernel_source.join(p).exists())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "KernelSU integration incomplete, missing: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Checks the final `.config` has KernelSU and the variant's options on.
+/// Options the kernel's Kconfig doesn't declare are skipped with a warning;
+/// olddefconfig drops them and there is nothing to check.
+pub fn verify_config(variant: &KsuVariant, kernel_source: &Path, config_path: &Path) -> Result<()> {
+    let mut expected: Vec<&str> = variant.config_enable.iter().map(|c| c.as_str()).collect();
+    if variant.build_setup_args.is_some() {
+        expected.insert(0, "KSU");
+    }
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let declared = declared_symbols(kernel_source, &expected)?;
+    expected.retain(|c| {
+        let known = declared.contains(*c);
+        if !known {
+            warn!(
+                "CONFIG_{} isn't declared in any Kconfig, not checking it",
+                c
+            );
+        }
+        known
+    });
+
+    let config = fs::read_to_string(config_path)?;
+    let missing: Vec<String> = expected
+        .into_iter()
+        .filter(|c| {
+            !config
+                .lines()
+                .any(|l| l == format!("CONFIG_{}=y", c) || l == format!("CONFIG_{}=m", c))
+        })
+        .map(|c| format!("CONFIG_{}", c))
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "not enabled in {}: {} (check the defconfig and Kconfig dependencies)",
+            config_path.display(),
+            missing.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// The patches `integrate` applies for a variant, in order, for a dry run.
+/// SUSFS is cloned into `work_dir` so the kernel tree stays untouched.
+pub fn integration_patches(
+    variant: &KsuVariant,
+    proj: &ProjectConfig,
+    kernel_source: &Path,
+    work_dir: &Path,
+) -> Result<Vec<PatchSpec>> {
+    let mut patches = Vec::new();
+    if let Some(susfs_branch) = susfs_branch_for(variant, proj, kernel_source)? {
+        let susfs_dir = work_dir.join("susfs4ksu");
+        clone_susfs(&susfs_branch, proj.git_reference_dir.as_deref(), &susfs_dir)?;
+        let susfs_patch = susfs_dir.join(format!(
+            "kernel_patches/50_add_susfs_in_{}.patch",
+            susfs_branch
+        ));
+        patches.push(PatchSpec::new(&susfs_patch.to_string_lossy()));
+    }
+    if let Some(hook_url) = variant.manual_hook_url_for(proj) {
+        patches.push(PatchSpec::new(&hook_url));
+    }
+    patches.extend(variant.patches.iter().map(|p| PatchSpec::new(p)));
+    Ok(patches)
+}
+
+/// The project's `susfs_branch` wins; `auto` in the variant means detect.
+fn susfs_branch_for(
+    variant: &KsuVariant,
+    proj: &ProjectConfig,
+    kernel_source: &Path,
+) -> Result<Option<String>> {
+    Ok(
+        match (&proj.susfs_branch, variant.susfs_branch.as_deref()) {
+            (_, None) => None,
+            (Some(branch), _) => Some(branch.clone()),
+            (None, Some("auto")) => Some(detect_susfs_branch(kernel_source)?),
+            (None, Some(branch)) => Some(branch.to_string()),
+        },
+    )
+}
+
+/// Checks the cloned KernelSU source out at `ksu_ref` (if pinned) and
+/// returns the commit it is at.
+fn pin_source(
+    variant: &KsuVariant,
+    ksu_ref: Option<&str>,
+    kernel_source: &Path,
+) -> Result<Option<String>> {
+    let source_dir = kernel_source.join(variant.source_dir.as_deref().unwrap_or("KernelSU"));
+    if !source_dir.join(".git").exists() {
+        if let Some(r) = ksu_ref {
+            return Err(anyhow!(
+                "ksu_ref '{}' is set but {} is not a git checkout",
+                r,
+                source_dir.display()
+            ));
+        }
+        warn!(
+            "{} is not a git checkout, KernelSU commit unknown.",
+            source_dir.display()
+        );
+        return Ok(None);
+    }
+
+    if let Some(r) = ksu_ref {
+        info!("Pinning KernelSU to {}", r);
+        // Shallow clones may not have the ref yet.
+        let _ = run_cmd(
+            &["git", "fetch", "--tags", "origin", r],
+            Some(&source_dir),
+            false,
+        );
+        run_cmd(
+            &["git", "checkout", "--detach", r],
+            Some(&source_dir),
+            false,
+        )
+        .or_else(|_| {
+            run_cmd(
+                &["git", "checkout", "--detach", "FETCH_HEAD"],
+                Some(&source_dir),
+                false,
+            )
+        })?;
+    }
+
+    let commit =
+        run_cmd(&["git", "rev-parse", "HEAD"], Some(&source_dir), true)?.unwrap_or_default();
+    info!("KernelSU commit: {}", commit);
+    Ok(Some(commit))
+}
+
+/// Maps the kernel tree to its `gki-androidXX-Y.ZZ` SUSFS branch. The
+/// Android release comes from the GKI `BRANCH=` in build.config.constants
+/// (or build.config.common); without one the oldest GKI release for the
+/// kernel version is assumed.
+fn detect_susfs_branch(kernel_source: &Path) -> Result<String> {
+    let makefile = fs::read_to_string(kernel_source.join("Makefile"))?;
+    let make_var = |name: &str| {
+        makefile
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(k, _)| k.trim() == name)
+            .map(|(_, v)| v.trim().to_string())
+    };
+    let (Some(version), Some(patchlevel)) = (make_var("VERSION"), make_var("PATCHLEVEL")) else {
+        return Err(anyhow!(
+            "Could not read VERSION/PATCHLEVEL from the kernel Makefile"
+        ));
+    };
+    let kernel = format!("{}.{}", version, patchlevel);
+
+    let branch_re = regex::Regex::new(r"(?m)^BRANCH=android(\d+)-(\d+\.\d+)").unwrap();
+    let android = ["build.config.constants", "build.config.common"]
+        .iter()
+        .filter_map(|f| fs::read_to_string(kernel_source.join(f)).ok())
+        .find_map(|content| {
+            branch_re
+                .captures(&content)
+                .filter(|caps| caps[2] == kernel)
+                .map(|caps| caps[1].to_string())
+        })
+        .or_else(|| {
+            let release = match kernel.as_str() {
+                "5.10" => "12",
+                "5.15" => "13",
+                "6.1" => "14",
+                "6.6" => "15",
+                "6.12" => "16",
+                _ => return None,
+            };
+            Some(release.to_string())
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "Kernel {} is not a GKI kernel; set susfs_branch in the project config",
+                kernel
+            )
+        })?;
+
+    let branch = format!("gki-android{}-{}", android, kernel);
+    info!("   - Detected SUSFS branch: {}", branch);
+    Ok(branch)
+}
+
+fn clone_susfs(susfs_branch: &str, reference_dir: Option<&str>, dest: &Path) -> Result<()> {
+    info!("   - Cloning SUSFS...");
+    let susfs_url = "https://gitlab.com/simonpunk/susfs4ksu.git";
+    let dest_str = dest.to_string_lossy();
+    let reference = git_reference_args(reference_dir, susfs_url);
+    let mut cmd = vec!["git", "clone", "-b", susfs_branch, "--depth=1"];
+    cmd.extend(reference.iter().map(|a| a.as_str()));
+    cmd.extend([susfs_url, &dest_str]);
+    let existed = dest.exists();
+    retry::run("SUSFS clone", || {
+        if !existed && dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        run_cmd(&cmd, None, false)
+    })?;
+    Ok(())
+}
+
+fn apply_susfs(
+    susfs_branch: &str,
+    reference_dir: Option<&str>,
+    kernel_source: &Path,
+) -> Result<()> {
+    clone_susfs(
+        susfs_branch,
+        reference_dir,
+        &kernel_source.join("susfs4ksu"),
+    )?;
+
+    info!("   - Applying SUSFS patches...");
+    let cp_patch_cmd = format!(
+        "cp susfs4ksu/kernel_patches/50_add_susfs_in_{}.patch .",
+        susfs_branch
+    );
+    run_cmd(&["bash", "-c", &cp_patch_cmd], Some(kernel_source), false)?;
+    run_cmd(
+        &["bash", "-c", "cp -rv susfs4ksu/kernel_patches/fs/* fs/"],
+        Some(kernel_source),
+        false,
+    )?;
+    run_cmd(
+        &[
+            "bash",
+            "-c",
+            "cp -rv susfs4ksu/kernel_patches/include/linux/* include/linux/",
+        ],
+        Some(kernel_source),
+        false,
+    )?;
+
+    let patch_cmd = format!(
+        "patch -p1 --fuzz=3 < 50_add_susfs_in_{}.patch",
+        susfs_branch
+    );
+    run_cmd(&["bash", "-c", &patch_cmd], Some(kernel_source), false)?;
+    Ok(())
+}
+
+fn apply_manual_hook(hook_url: &str, kernel_source: &Path) -> Result<()> {
+    info!("   - Applying manual hook patch {}...", hook_url);
+    retry::run("manual hook download", || {
+        run_cmd(
+            &["curl", "-fL", "-o", "manual-hook.patch", hook_url],
+            Some(kernel_source),
+            false,
+        )
+    })?;
+    run_cmd(
+        &["bash", "-c", "patch -p1 --fuzz=3 < manual-hook.patch"],
+        Some(kernel_source),
+        false,
+    )?;
+
+    relocate_clone_newns_hook(kernel_source)
+}
+
+const CLONE_NEWNS_IF: &str = "if (flags & CLONE_NEWNS)";
+const CLONE_NEWNS_SET: &str = "copy_flags |= CL_COPY_MNT_NS;";
+const COPY_FLAGS_ANCHOR: &str = "copy_flags = CL_COPY_UNBINDABLE | CL_EXPIRE;";
+
+/// The hook's CLONE_NEWNS hunk lands in a function (around line 3808) that
+/// has no `copy_flags`. Moves it into copy_mnt_ns right after `copy_flags`
+/// is initialised, checking every step instead of trusting the patch layout.
+fn relocate_clone_newns_hook(kernel_source: &Path) -> Result<()> {
+    info!("   - Relocating Manual Hook to correct function...");
+    let path = kernel_source.join("fs/namespace.c");
+    let original = fs::read_to_string(&path)?;
+    let lines: Vec<&str> = original.lines().collect();
+
+    let body = function_span(&lines, "copy_mnt_ns")
+        .ok_or_else(|| anyhow!("copy_mnt_ns() not found in fs/namespace.c"))?;
+    let anchor = body
+        .clone()
+        .find(|&i| lines[i].contains(COPY_FLAGS_ANCHOR))
+        .ok_or_else(|| {
+            anyhow!(
+                "'{}' not found in copy_mnt_ns():\n{}",
+                COPY_FLAGS_ANCHOR,
+                excerpt(&lines, body.clone())
+            )
+        })?;
+
+    let hook_lines: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].contains(CLONE_NEWNS_IF) || lines[i].contains(CLONE_NEWNS_SET))
+        .collect();
+    let (placed, misplaced): (Vec<usize>, Vec<usize>) =
+        hook_lines.iter().partition(|i| body.contains(i));
+
+    if misplaced.is_empty() {
+        if placed.is_empty() {
+            return Err(anyhow!(
+                "manual hook's CLONE_NEWNS lines not found in fs/namespace.c; did the hook patch change?"
+            ));
+        }
+        info!("   - CLONE_NEWNS hook is already in copy_mnt_ns.");
+        return Ok(());
+    }
+    let pair_ok = misplaced.len() == 2
+        && misplaced[1] == misplaced[0] + 1
+        && lines[misplaced[0]].contains(CLONE_NEWNS_IF)
+        && lines[misplaced[1]].contains(CLONE_NEWNS_SET);
+    if !pair_ok {
+        let found: Vec<String> = misplaced
+            .iter()
+            .map(|&i| format!("{:>6}: {}", i + 1, lines[i]))
+            .collect();
+        return Err(anyhow!(
+            "unexpected CLONE_NEWNS hook layout in fs/namespace.c, expected '{}' followed by '{}':\n{}",
+            CLONE_NEWNS_IF,
+            CLONE_NEWNS_SET,
+            found.join("\n")
+        ));
+    }
+
+    let indent: String = lines[anchor]
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect();
+    let inserted = [
+        format!("{}{}", indent, CLONE_NEWNS_IF),
+        format!("{}\t{}", indent, CLONE_NEWNS_SET),
+    ];
+
+    let mut out = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        if misplaced.contains(&i) {
+            info!("     -{:>6}: {}", i + 1, line);
+            continue;
+        }
+        out.push(line.to_string());
+        if i == anchor && placed.is_empty() {
+            for new_line in &inserted {
+                out.push(new_line.clone());
+                info!("     +{:>6}: {}", out.len(), new_line);
+            }
+        }
+    }
+
+    let mut content = out.join("\n");
+    if original.ends_with('\n') {
+        content.push('\n');
+    }
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+/// Line range of a C function body, from its opening to its closing brace.
+fn function_span(lines: &[&str], name: &str) -> Option<std::ops::Range<usize>> {
+    let signature = regex::Regex::new(&format!(r"^[A-Za-z_].*\b{}\s*\(", name)).unwrap();
+    let mut i = 0;
+    while i < lines.len() {
+        if signature.is_match(lines[i]) {
+            // Skip prototypes: the definition reaches `{` before any `;`.
+            let open =
+                (i..lines.len()).find(|&j| lines[j].contains('{') || lines[j].contains(';'))?;
+            if lines[open].contains('{') {
+                let mut depth = 0i32;
+                for (j, line) in lines.iter().enumerate().skip(open) {
+                    depth += line.matches('{').count() as i32;
+                    depth -= line.matches('}').count() as i32;
+                    if depth == 0 {
+                        return Some(open..j + 1);
+                    }
+                }
+                return None;
+            }
+            i = open;
+        }
+        i += 1;
+    }
+    None
+}
+
+fn excerpt(lines: &[&str], range: std::ops::Range<usize>) -> String {
+    range
+        .map(|i| format!("{:>6}: {}", i + 1, lines[i]))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
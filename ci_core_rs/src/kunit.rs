@@ -0,0 +1,139 @@
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::arch::Arch;
+use crate::config::KunitConfig;
+use crate::utils::{find_local_file, run_cmd_with_env};
+
+const KUNIT_PY: &str = "tools/testing/kunit/kunit.py";
+
+/// Test case results across the suites that ran.
+#[derive(Clone, Copy, Default)]
+pub struct KunitResults {
+    pub passed: u32,
+    pub failed: u32,
+    pub skipped: u32,
+}
+
+impl fmt::Display for KunitResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.passed, self.failed, self.skipped
+        )
+    }
+}
+
+impl KunitResults {
+    /// Adds up the test cases of a kunit.py `--json` report, whose suites
+    /// nest as `sub_groups`.
+    fn count(&mut self, group: &Value) {
+        for case in group["test_cases"].as_array().into_iter().flatten() {
+            match case["status"].as_str() {
+                Some("PASS") => self.passed += 1,
+                Some("SKIP") => self.skipped += 1,
+                _ => self.failed += 1,
+            }
+        }
+        for sub in group["sub_groups"].as_array().into_iter().flatten() {
+            self.count(sub);
+        }
+    }
+}
+
+/// Builds the KUnit kernel in `<out>/kunit` and runs the configured suites,
+/// under UML or QEMU for the project's arch. Fails when a test fails unless
+/// `allow_failures` is set.
+pub fn run(
+    cfg: &KunitConfig,
+    arch: &Arch,
+    use_gcc: bool,
+    kernel_source: &Path,
+    out_path: &Path,
+    build_env: &HashMap<String, String>,
+) -> Result<KunitResults> {
+    if !kernel_source.join(KUNIT_PY).exists() {
+        return Err(anyhow!("{} not found; this kernel has no KUnit", KUNIT_PY));
+    }
+    let build_dir = fs::canonicalize(out_path)?.join("kunit");
+    let mut common = vec![format!("--build_dir={}", build_dir.display())];
+    match cfg.mode.as_deref().unwrap_or("uml") {
+        "uml" => {}
+        "qemu" => {
+            common.push(format!("--arch={}", arch.kernel_arch));
+            common.push(format!("--cross_compile={}", arch.cross_compile));
+        }
+        other => {
+            return Err(anyhow!(
+                "Unknown kunit.mode '{}' (expected uml or qemu)",
+                other
+            ));
+        }
+    }
+
+    let mut build = vec![
+        "python3".to_string(),
+        KUNIT_PY.to_string(),
+        "build".to_string(),
+    ];
+    build.extend(common.iter().cloned());
+    if !use_gcc {
+        build.push("--make_options=LLVM=1".to_string());
+    }
+    if let Some(path) = &cfg.kunitconfig {
+        let file = find_local_file(path, kernel_source)?;
+        build.push(format!("--kunitconfig={}", file.display()));
+    }
+    info!("Building the KUnit kernel...");
+    let build: Vec<&str> = build.iter().map(|a| a.as_str()).collect();
+    run_cmd_with_env(&build, Some(kernel_source), build_env)?;
+
+    let mut results = KunitResults::default();
+    let filters: Vec<Option<&str>> = if cfg.suites.is_empty() {
+        vec![None]
+    } else {
+        cfg.suites.iter().map(|s| Some(s.as_str())).collect()
+    };
+    for filter in filters {
+        let json = build_dir.join("kokuban-results.json");
+        let _ = fs::remove_file(&json);
+        let mut exec = vec![
+            "python3".to_string(),
+            KUNIT_PY.to_string(),
+            "exec".to_string(),
+        ];
+        exec.extend(common.iter().cloned());
+        exec.push(format!("--json={}", json.display()));
+        if let Some(seconds) = cfg.timeout_seconds {
+            exec.push(format!("--timeout={}", seconds));
+        }
+        exec.extend(filter.map(String::from));
+        info!(
+            "Running KUnit tests{}...",
+            filter.map(|f| format!(" ({})", f)).unwrap_or_default()
+        );
+        let exec: Vec<&str> = exec.iter().map(|a| a.as_str()).collect();
+        // kunit.py exits non-zero when a test fails; the report says which.
+        let status = run_cmd_with_env(&exec, Some(kernel_source), build_env);
+        let report = fs::read_to_string(&json)
+            .ok()
+            .and_then(|content| serde_json::from_str::<Value>(&content).ok());
+        match (report, status) {
+            (Some(report), _) => results.count(&report),
+            (None, Err(e)) => return Err(e).context("kunit.py wrote no results"),
+            (None, Ok(())) => warn!("kunit.py wrote no results for {:?}", filter),
+        }
+    }
+
+    info!("KUnit: {}", results);
+    if results.failed > 0 && !cfg.allow_failures {
+        return Err(anyhow!("{} KUnit test(s) failed", results.failed));
+    }
+    Ok(results)
+}
@@ -0,0 +1,49 @@
+use env_logger::fmt::WriteStyle;
+use env_logger::fmt::style::{AnsiColor, Style};
+use log::{Level, LevelFilter};
+use std::io::Write;
+
+use crate::secrets;
+
+/// Target of the stage headers, which get their own look. Kept under the
+/// crate so the crate's level applies to it.
+pub const STAGE: &str = concat!(env!("CARGO_CRATE_NAME"), "::stage");
+
+/// Sets up the logger behind the progress output: `-q` keeps only
+/// warnings and errors, `-v` adds the commands being run and `-vv` their
+/// working directory and environment. `RUST_LOG` still overrides this.
+/// Messages go to stdout like the command output they are interleaved with.
+pub fn init(verbose: u8, quiet: bool, color: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Warn,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        // Dependencies (reqwest, rustls) only get to warn.
+        .filter_level(LevelFilter::Warn)
+        .filter_module(env!("CARGO_CRATE_NAME"), level)
+        .parse_default_env()
+        .target(env_logger::Target::Stdout)
+        .write_style(if color {
+            WriteStyle::Auto
+        } else {
+            WriteStyle::Never
+        })
+        .format(|buf, record| {
+            let (style, prefix) = if record.target() == STAGE {
+                (AnsiColor::Cyan.on_default().bold(), "\n==> ")
+            } else {
+                match record.level() {
+                    Level::Error => (AnsiColor::Red.on_default().bold(), "❌ Error: "),
+                    Level::Warn => (AnsiColor::Yellow.on_default(), "⚠️ Warning: "),
+                    Level::Info => (Style::new(), ""),
+                    Level::Debug | Level::Trace => (Style::new().dimmed(), ""),
+                }
+            };
+            let message = record.args().to_string();
+            writeln!(buf, "{style}{prefix}{}{style:#}", secrets::redact(&message))
+        })
+        .init();
+}
@@ -1,11 +1,67 @@
+mod abi;
+mod analyze;
+mod arch;
+mod archive;
+mod artifacts;
+mod bloat;
+mod bootimg;
+mod boottest;
 mod build;
+mod cache;
+mod changelog;
+mod checkpoint;
+mod clean;
+mod compare;
+mod compiler_cache;
 mod config;
+mod container;
+mod defconfig;
+mod deploy;
+mod doctor;
+mod download;
+mod error;
+mod events;
+mod fingerprint;
+mod gha;
+mod github;
+mod history;
+mod init;
+mod interrupt;
+mod kconfig;
+mod ksu;
+mod kunit;
+mod logging;
+mod manifest;
+mod metrics;
+mod modsign;
+mod notify;
+mod output;
+mod patch;
+mod plan;
+mod progress;
+mod projects;
+mod provenance;
+mod release;
+mod retry;
+mod schedule;
+mod secrets;
+mod serve;
+mod sign;
+mod snapshot;
+mod timeout;
+mod toolchain;
+mod trace;
+mod tui;
+mod upload;
 mod utils;
+mod validate;
+mod warnings;
 
 use anyhow::{Result, anyhow};
 use chrono::Local;
-use clap::{Parser, Subcommand};
-use config::{KSU_CONFIG_JSON, KsuConfigItem, ProjectConfig};
+use clap::{CommandFactory, Parser, Subcommand};
+use config::ProjectConfig;
+use log::{info, warn};
 use std::collections::HashMap;
 use std::env;
 use std::fs::{self, OpenOptions};
@@ -13,33 +69,52 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+use crate::ksu::{canonical_variant_name, load_ksu_variants, project_branches, variant_label};
 use crate::utils::*;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// `json` prints one JSON event per line on stdout (stages, commands,
+    /// artifacts) and moves all other output to stderr.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    output: events::OutputFormat,
+    /// Show the commands being run (-v) and their environment (-vv).
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only print warnings and errors; command output still goes to logs/.
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Plain output without ANSI colors, e.g. for CI logs.
+    #[arg(long, global = true)]
+    no_color: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Export a project's repo, defconfig and LOCALVERSION to $GITHUB_ENV.
     Parse {
         #[arg(long)]
         project: String,
     },
+    /// Export a build's release tag, title, zip name and LOCALVERSION to
+    /// $GITHUB_ENV.
     Meta {
         #[arg(long)]
         project: String,
         #[arg(long)]
         branch: String,
     },
+    /// Print the GitHub Actions matrix of branches to build for a project.
     Matrix {
         #[arg(long)]
         project: String,
         #[arg(long)]
         token: Option<String>,
     },
+    /// Add a project entry from command-line flags.
     Add {
         #[arg(long)]
         key: String,
@@ -65,6 +140,7 @@ enum Commands {
         #[arg(long, default_value = "")]
         toolchain_prefix: String,
     },
+    /// Push the trigger workflow and README to every project's kernel repo.
     Setup {
         #[arg(long)]
         token: Option<String>,
@@ -73,7 +149,48 @@ enum Commands {
         #[arg(long, default_value = "both")]
         readme_language: String,
     },
+    /// Check the projects file for missing fields and unknown values.
+    Validate,
+    /// Ask for a new device's settings and add it to the projects file.
+    Init,
+    /// Print every project with its device, SoC and defconfig.
+    List,
+    /// Print a project's config after `extends` and `${VAR}` expansion.
+    Show {
+        key: String,
+        /// Also apply this branch's overrides.
+        #[arg(long)]
+        branch: Option<String>,
+    },
+    /// Check KernelSU variant repos for new commits and output the update
+    /// matrix.
     Watch,
+    /// Run as a small CI server: queue builds from GitHub push webhooks
+    /// (`POST /webhook`) and serve the builds API.
+    ///
+    /// `POST /builds` with `{"project", "branch", "release"}` starts a build,
+    /// `GET /builds` lists them, `GET /builds/<id>/log` streams one's output
+    /// and `POST /builds/<id>/cancel` stops it. Set KOKUBAN_WEBHOOK_SECRET to
+    /// require the webhook's signature, or the secret as a bearer token for
+    /// the API.
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8787")]
+        listen: String,
+        /// Builds run at once; builds of one project always run in turn.
+        /// More than one needs --out-dir.
+        #[arg(long, default_value_t = 1)]
+        jobs: usize,
+        #[arg(long, action = clap::ArgAction::Set, default_value_t = false)]
+        do_release: bool,
+        /// Per-project output dirs are created under this one.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Serve a non-loopback address without KOKUBAN_WEBHOOK_SECRET,
+        /// e.g. behind a proxy that authenticates.
+        #[arg(long)]
+        insecure: bool,
+    },
+    /// Re-run a KernelSU variant's setup on a project's branch and push it.
     Update {
         #[arg(long)]
         token: String,
@@ -84,24 +201,255 @@ enum Commands {
         #[arg(long)]
         commit_id: String,
     },
+    /// Announce a published release on the configured channels.
     Notify {
         #[arg(long)]
         tag: String,
     },
+    /// Build one project's kernel and package it.
+    ///
+    /// A failed build exits with 10 (config), 11 (toolchain), 12 (patch or
+    /// KernelSU integration), 13 (compile), 14 (package), 15 (release), 16
+    /// (analyze, abi_check, boot_test or kunit), 124 (timeout) or 130
+    /// (interrupted).
     Build {
         #[arg(long)]
         project: String,
+        #[arg(long, required_unless_present = "branches")]
+        branch: Option<String>,
+        /// Build several variants in one run, e.g. `ksu,mksu,wildksu`.
+        #[arg(long, value_delimiter = ',', conflicts_with = "branch")]
+        branches: Vec<String>,
+        #[arg(long, action = clap::ArgAction::Set)]
+        do_release: bool,
+        #[arg(long)]
+        refresh_toolchain: bool,
+        #[arg(long)]
+        locked: bool,
+        /// Dry-run every patch against the kernel source and report conflicts.
+        #[arg(long)]
+        check_patches: bool,
+        /// Leave kernel_source as-is when the build fails instead of rolling back.
+        #[arg(long)]
+        keep_source: bool,
+        /// Put the build dir, AnyKernel3 and zips here instead of kernel_source/out and ./
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Reuse the previous build's out dir, KernelSU integration and toolchain
+        /// and go straight to make.
+        #[arg(long, conflicts_with_all = ["refresh_toolchain", "branches"])]
+        incremental: bool,
+        /// Print the commands the build would run, stage by stage, without
+        /// running anything.
+        #[arg(long, conflicts_with = "check_patches")]
+        dry_run: bool,
+        /// Pick up a build that failed in `package` or `release` from that
+        /// stage, reusing the kernel (and zips) it left in the out dir.
+        #[arg(long, value_name = "STAGE", conflicts_with_all = ["incremental", "check_patches", "dry_run"])]
+        resume_from: Option<checkpoint::ResumeStage>,
+        /// Build even when the source, patches, config and toolchain are
+        /// unchanged since the last successful build.
+        #[arg(long)]
+        force: bool,
+        /// Run the build inside this docker/podman image, overriding the
+        /// project's `container` block.
+        #[arg(long, value_name = "IMAGE")]
+        container: Option<String>,
+        #[command(flatten)]
+        resources: build::ResourceArgs,
+        #[command(flatten)]
+        release: release::ReleaseArgs,
+    },
+    /// Build several projects, or all of them, one after another.
+    ///
+    /// Exits with `build`'s status for its kind of failure when every failed
+    /// project failed the same way, else 1.
+    BuildAll {
+        /// Project keys to build; all projects when omitted.
+        #[arg(long, value_delimiter = ',')]
+        projects: Vec<String>,
         #[arg(long)]
         branch: String,
+        /// Kernel repo ref to build for projects without a source_branch.
+        /// Existing checkouts are updated to it; without it they are built
+        /// as they are, and missing ones are cloned at main.
+        #[arg(long)]
+        source_ref: Option<String>,
         #[arg(long, action = clap::ArgAction::Set)]
         do_release: bool,
+        #[arg(long)]
+        refresh_toolchain: bool,
+        #[arg(long)]
+        locked: bool,
+        /// Build projects that are unchanged since their last successful
+        /// build too.
+        #[arg(long)]
+        force: bool,
+        /// Per-project output dirs are created under this one.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Run all the builds inside this docker/podman image.
+        #[arg(long, value_name = "IMAGE")]
+        container: Option<String>,
+        #[command(flatten)]
+        resources: build::ResourceArgs,
+        #[command(flatten)]
+        release: release::ReleaseArgs,
+    },
+    /// Run `build` or `build-all` under a live dashboard of stages, output,
+    /// compiler cache hit rate and elapsed time, e.g.
+    /// `tui build --project s23_sm8550 --branches ksu,mksu`.
+    Tui {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Remove build dirs, AnyKernel3, zips and integration leftovers.
+    Clean {
+        /// Project whose kernel to clean, for `source_manifest` projects
+        /// that build in a dir of the repo checkout.
+        #[arg(long)]
+        project: Option<String>,
+        /// Output dir used with `build --out-dir`, instead of the current dir.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Also run `make mrproper` in kernel_source.
+        #[arg(long)]
+        mrproper: bool,
+        /// Only list what would be removed.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Save/restore the build dir and .ccache between CI runs.
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Keep a project's in-tree defconfig in sync with `make savedefconfig`.
+    Defconfig {
+        #[command(subcommand)]
+        action: DefconfigAction,
+    },
+    /// Flash the last build onto a connected device and wait for it to boot
+    /// the new kernel.
+    Deploy {
+        #[arg(long)]
+        project: String,
+        #[arg(long, default_value = "main")]
+        branch: String,
+        /// adb/fastboot serial of the device (overrides deploy.serial).
+        #[arg(long)]
+        serial: Option<String>,
+        /// `fastboot` (the boot.img) or `sideload` (the AnyKernel3 zip).
+        #[arg(long, value_parser = deploy::METHODS.to_vec())]
+        method: Option<String>,
+        /// Flash this boot.img or zip instead of the last build's.
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// Output dir the build used, when not the project's.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+    /// Inspect past builds recorded in the local build history.
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Check the host for the tools, headers, disk space and memory a build
+    /// needs.
+    Doctor,
+    /// Diff the .config, image and section sizes and warning counts of two
+    /// recorded builds (ids from `history list`).
+    Compare { a: i64, b: i64 },
+    /// Print a shell completion script, e.g.
+    /// `completions bash > /etc/bash_completion.d/kokuban_ci_core`.
+    Completions { shell: clap_complete::Shell },
+    /// Write man pages for the command and each subcommand.
+    Man {
+        #[arg(long, default_value = "man")]
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum DefconfigAction {
+    /// Regenerate the defconfig with `make savedefconfig` and print how it
+    /// differs from the one in kernel_source.
+    Sync {
+        #[arg(long)]
+        project: String,
+        #[arg(long, default_value = "main")]
+        branch: String,
+        /// Fail when the defconfig isn't what savedefconfig produces.
+        #[arg(long)]
+        check: bool,
+        /// Replace the in-tree defconfig with the regenerated one.
+        #[arg(long, conflicts_with = "check")]
+        write: bool,
+        /// Put the scratch build dir here instead of kernel_source.
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryAction {
+    /// Most recent builds first.
+    List {
+        #[arg(long)]
+        project: Option<String>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Details of one build: stage timings, commit and artifact checksums.
+    Show { id: i64 },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Print the cache key for kernel_source HEAD and the project config.
+    Key {
+        #[arg(long)]
+        project: String,
+        #[arg(long)]
+        branch: String,
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+    Save {
+        #[arg(long)]
+        project: String,
+        #[arg(long)]
+        branch: String,
+        /// Directory the archive is written to (e.g. the actions/cache path).
+        #[arg(long)]
+        dir: PathBuf,
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+    Restore {
+        #[arg(long)]
+        project: String,
+        #[arg(long)]
+        branch: String,
+        #[arg(long)]
+        dir: PathBuf,
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
     },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    logging::init(cli.verbose, cli.quiet, !cli.no_color);
+    events::init(cli.output);
+    if matches!(
+        cli.command,
+        Commands::Build { .. } | Commands::BuildAll { .. }
+    ) {
+        interrupt::install();
+    }
 
-    match cli.command {
+    let result = match cli.command {
         Commands::Parse { project } => handle_parse(&project),
         Commands::Meta { project, branch } => handle_meta(&project, &branch),
         Commands::Matrix { project, token } => handle_matrix(&project, token),
@@ -133,20 +481,201 @@ fn main() -> Result<()> {
             commit_message,
             readme_language,
         } => handle_setup(token, commit_message, readme_language),
+        Commands::Validate => validate::handle_validate(),
+        Commands::Init => init::handle_init(),
+        Commands::List => projects::handle_list(),
+        Commands::Show { key, branch } => projects::handle_show(&key, branch.as_deref()),
+        Commands::Doctor => doctor::handle_doctor(),
         Commands::Watch => handle_watch(),
+        Commands::Serve {
+            listen,
+            jobs,
+            do_release,
+            out_dir,
+            insecure,
+        } => serve::handle_serve(serve::ServeOptions {
+            listen,
+            jobs,
+            do_release,
+            out_dir,
+            insecure,
+        }),
         Commands::Update {
             token,
             project,
             variant,
             commit_id,
         } => handle_update(token, project, variant, commit_id),
-        Commands::Notify { tag } => utils::handle_notify(tag),
+        Commands::Notify { tag } => notify::handle_notify(tag),
         Commands::Build {
             project,
             branch,
+            branches,
+            do_release,
+            refresh_toolchain,
+            locked,
+            check_patches,
+            keep_source,
+            out_dir,
+            incremental,
+            dry_run,
+            resume_from,
+            force,
+            container,
+            resources,
+            release,
+        } => {
+            let first = branch.as_ref().or(branches.first()).cloned();
+            let proj = load_projects().ok().and_then(|projects| {
+                build::load_branch_config(&projects, &project, &first.unwrap_or_default()).ok()
+            });
+            if !dry_run && let Some(cfg) = container::resolve(container, proj.as_ref()) {
+                let mut paths: Vec<PathBuf> =
+                    proj.iter().flat_map(container::project_paths).collect();
+                paths.extend(out_dir);
+                return container::run(&cfg, &paths);
+            }
+            let opts = build::BuildOptions {
+                do_release,
+                refresh_toolchain,
+                locked,
+                check_patches,
+                keep_source,
+                out_dir,
+                incremental,
+                dry_run,
+                resume_from,
+                force,
+                resources,
+                release,
+            };
+            match branch {
+                Some(branch) => build::handle_build(project, branch, opts),
+                None => build::handle_build_matrix(project, branches, opts),
+            }
+        }
+        Commands::BuildAll {
+            projects,
+            branch,
+            source_ref,
             do_release,
-        } => build::handle_build(project, branch, do_release),
+            refresh_toolchain,
+            locked,
+            force,
+            out_dir,
+            container,
+            resources,
+            release,
+        } => {
+            if let Some(cfg) = container::resolve(container, None) {
+                let all = load_projects()?;
+                let mut paths: Vec<PathBuf> = all
+                    .keys()
+                    .filter_map(|key| build::load_branch_config(&all, key, &branch).ok())
+                    .flat_map(|proj| container::project_paths(&proj))
+                    .collect();
+                paths.extend(out_dir);
+                return container::run(&cfg, &paths);
+            }
+            build::handle_build_all(
+                projects,
+                branch,
+                source_ref,
+                build::BuildOptions {
+                    do_release,
+                    refresh_toolchain,
+                    locked,
+                    check_patches: false,
+                    keep_source: false,
+                    out_dir,
+                    incremental: false,
+                    dry_run: false,
+                    resume_from: None,
+                    force,
+                    resources,
+                    release,
+                },
+            )
+        }
+        Commands::Tui { args } => tui::handle_tui(&args),
+        Commands::Completions { shell } => {
+            let mut cmd = Cli::command();
+            let name = cmd.get_name().to_string();
+            clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+            Ok(())
+        }
+        Commands::Man { dir } => handle_man(&dir),
+        Commands::Clean {
+            project,
+            out_dir,
+            mrproper,
+            dry_run,
+        } => clean::handle_clean(project.as_deref(), out_dir, mrproper, dry_run),
+        Commands::Cache { action } => match action {
+            CacheAction::Key {
+                project,
+                branch,
+                out_dir,
+            } => cache::handle_cache_key(&project, &branch, out_dir),
+            CacheAction::Save {
+                project,
+                branch,
+                dir,
+                out_dir,
+            } => cache::handle_cache_save(&project, &branch, &dir, out_dir),
+            CacheAction::Restore {
+                project,
+                branch,
+                dir,
+                out_dir,
+            } => cache::handle_cache_restore(&project, &branch, &dir, out_dir),
+        },
+        Commands::Defconfig { action } => match action {
+            DefconfigAction::Sync {
+                project,
+                branch,
+                check,
+                write,
+                out_dir,
+            } => defconfig::handle_sync(&project, &branch, check, write, out_dir),
+        },
+        Commands::Deploy {
+            project,
+            branch,
+            serial,
+            method,
+            file,
+            out_dir,
+        } => deploy::handle_deploy(&project, &branch, serial, method, file, out_dir),
+        Commands::History { action } => match action {
+            HistoryAction::List { project, limit } => history::handle_history_list(project, limit),
+            HistoryAction::Show { id } => history::handle_history_show(id),
+        },
+        Commands::Compare { a, b } => compare::handle_compare(a, b),
+    };
+    if interrupt::interrupted() {
+        if let Err(e) = result {
+            eprintln!("Error: {}", secrets::redact(&format!("{:#}", e)));
+        }
+        std::process::exit(interrupt::EXIT_CODE);
+    }
+    // Printed here rather than by returning it, so a token in a failed
+    // command line or a response is masked.
+    if let Err(e) = &result {
+        eprintln!("Error: {}", secrets::redact(&format!("{:#}", e)));
+        let code = e
+            .downcast_ref::<error::BuildError>()
+            .map_or(1, |failure| failure.exit_code());
+        std::process::exit(code);
     }
+    Ok(())
+}
+
+fn handle_man(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    clap_mangen::generate_to(Cli::command(), dir)?;
+    println!("Man pages written to {}", dir.display());
+    Ok(())
 }
 
 fn handle_parse(project_key: &str) -> Result<()> {
@@ -154,7 +683,7 @@ fn handle_parse(project_key: &str) -> Result<()> {
     let proj_val = projects
         .get(project_key)
         .ok_or_else(|| anyhow!("Project not found"))?;
-    let proj: ProjectConfig = serde_json::from_value(proj_val.clone())?;
+    let proj: ProjectConfig = serde_json::from_value(expand_entry(project_key, proj_val)?)?;
 
     set_github_env("PROJECT_REPO", &proj.repo)?;
     set_github_env("PROJECT_DEFCONFIG", &proj.defconfig)?;
@@ -165,21 +694,12 @@ fn handle_parse(project_key: &str) -> Result<()> {
 
 fn handle_meta(project_key: &str, branch: &str) -> Result<()> {
     let projects = load_projects()?;
-    let proj_val = projects
-        .get(project_key)
-        .ok_or_else(|| anyhow!("Project not found"))?;
-    let proj: ProjectConfig = serde_json::from_value(proj_val.clone())?;
+    let proj = build::load_branch_config(&projects, project_key, branch)?;
 
     let zip_prefix = proj.zip_name_prefix.as_deref().unwrap_or("Kernel");
     let localversion_base = &proj.localversion_base;
 
-    let variant_suffix = match branch {
-        "main" | "lkm" => "LKM".to_string(),
-        "ksu" => "KSU".to_string(),
-        "mksu" => "MKSU".to_string(),
-        "resukisu" | "sukisuultra" => "ReSuki".to_string(),
-        _ => branch.to_uppercase(),
-    };
+    let variant_suffix = variant_label(&load_ksu_variants(&projects)?, branch);
 
     let date_str = Local::now().format("%Y%m%d-%H%M").to_string();
 
@@ -206,16 +726,8 @@ fn handle_matrix(project_key: &str, _token: Option<String>) -> Result<()> {
         .ok_or_else(|| anyhow!("Project not found"))?;
     let proj: ProjectConfig = serde_json::from_value(proj_val.clone())?;
 
-    let raw_supported = proj.supported_ksu.unwrap_or_default();
-    let mut branches = vec!["main".to_string()];
-
-    for x in raw_supported {
-        if x != "sukisuultra" {
-            branches.push(x);
-        } else {
-            branches.push("resukisu".to_string());
-        }
-    }
+    let ksu_variants = load_ksu_variants(&projects)?;
+    let branches = project_branches(&ksu_variants, &proj);
 
     let include: Vec<HashMap<String, String>> = branches
         .into_iter()
@@ -235,6 +747,7 @@ fn handle_matrix(project_key: &str, _token: Option<String>) -> Result<()> {
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn handle_add(
     key: String,
     repo: String,
@@ -247,13 +760,14 @@ fn handle_add(
     zip_name: String,
     toolchain_prefix: String,
 ) -> Result<()> {
-    let mut projects = load_projects()?;
+    let mut projects = load_projects_raw()?;
 
     let mut placeholders = HashMap::new();
     placeholders.insert("DEVICE_NAME_CN".to_string(), device_cn);
     placeholders.insert("DEVICE_NAME_EN".to_string(), device_en);
 
     let new_proj = ProjectConfig {
+        extends: None,
         repo,
         defconfig,
         localversion_base: localversion,
@@ -264,6 +778,7 @@ fn handle_add(
             "ksu".to_string(),
         ]),
         toolchain_urls: None,
+        toolchain_sha256: None,
         toolchain_path_prefix: if toolchain_prefix.is_empty() {
             None
         } else {
@@ -275,12 +790,63 @@ fn handle_add(
         zip_name_prefix: Some(zip_name),
         version_method: None,
         extra_host_env: None,
+        reproducible: None,
         disable_security: None,
         readme_placeholders: Some(placeholders),
+        branches: None,
+        ksu_ref: None,
+        susfs_branch: None,
+        patches: None,
+        checkpatch: None,
+        manual_hook_url: None,
+        manual_hook_version: None,
+        config_fragments: None,
+        enable_configs: None,
+        disable_configs: None,
+        strict_config: None,
+        output_dir: None,
+        arch: None,
+        compiler: None,
+        make_targets: None,
+        modules: None,
+        dtb: None,
+        boot_image: None,
+        avb: None,
+        analyze: None,
+        abi_check: None,
+        boot_test: None,
+        kunit: None,
+        deploy: None,
+        schedules: None,
+        module_signing: None,
+        jobs: None,
+        load_average: None,
+        nice: None,
+        timeout_minutes: None,
+        retry: None,
+        min_free_space_gib: None,
+        container: None,
+        extra_make_args: None,
+        kcflags: None,
+        kldflags: None,
+        max_new_warnings: None,
+        max_image_size: None,
+        source_repo: None,
+        source_branch: None,
+        source_depth: None,
+        source_manifest: None,
+        git_reference_dir: None,
+        ccache_max_size: None,
+        compiler_cache: None,
+        distcc: None,
+        gpg: None,
+        release: None,
+        notify: None,
+        distribution: None,
     };
 
     projects.insert(key, serde_json::to_value(new_proj)?);
-    save_json(&get_config_path(), &projects)?;
+    save_projects(&projects)?;
     Ok(())
 }
 
@@ -315,10 +881,16 @@ fn handle_setup(
             continue;
         }
 
-        let proj: ProjectConfig = serde_json::from_value(val)?;
+        let proj: ProjectConfig = match expand_entry(&key, &val) {
+            Ok(val) => serde_json::from_value(val)?,
+            Err(e) => {
+                warn!("Skipping {}: {:#}", key, e);
+                continue;
+            }
+        };
         let repo_url = proj.repo.clone();
 
-        println!("Processing project: {} -> {}", key, repo_url);
+        info!("Processing project: {} -> {}", key, repo_url);
 
         let target_dir = workspace.join(&key);
         let auth_url = if let Some(t) = &token {
@@ -331,11 +903,16 @@ fn handle_setup(
             fs::remove_dir_all(&target_dir)?;
         }
 
-        run_cmd(
-            &["git", "clone", &auth_url, target_dir.to_str().unwrap()],
-            None,
-            false,
-        )?;
+        retry::run("git clone", || {
+            if target_dir.exists() {
+                fs::remove_dir_all(&target_dir)?;
+            }
+            run_cmd(
+                &["git", "clone", &auth_url, target_dir.to_str().unwrap()],
+                None,
+                false,
+            )
+        })?;
 
         let readme_content = process_readme(&readme_tpl, &proj, &repo_url, &readme_language);
         let target_branches = vec!["main", "ksu", "mksu", "resukisu"];
@@ -434,7 +1011,7 @@ fn handle_setup(
 
         if let Some(t) = &token {
             let child = Command::new("gh")
-                .args(&["secret", "set", "CI_TOKEN"])
+                .args(["secret", "set", "CI_TOKEN"])
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
                 .current_dir(&target_dir)
@@ -485,24 +1062,25 @@ fn process_readme(template: &str, proj: &ProjectConfig, repo_url: &str, lang: &s
         .replace("__LOCALVERSION_BASE__", &proj.localversion_base);
 
     if lang == "zh-CN" {
-        let re = regex::Regex::new(r"(?s).*?").unwrap();
+        let re = regex::Regex::new(r"(?s)<!-- BEGIN-EN -->.*?<!-- END-EN -->").unwrap();
         content = re.replace_all(&content, "").to_string();
     } else if lang == "en-US" {
-        let re = regex::Regex::new(r"(?s).*?").unwrap();
+        let re = regex::Regex::new(r"(?s)<!-- BEGIN-ZH -->.*?<!-- END-ZH -->").unwrap();
         content = re.replace_all(&content, "").to_string();
     }
 
     content
-        .replace("", "")
-        .replace("", "")
-        .replace("", "")
-        .replace("", "")
+        .replace("<!-- BEGIN-ZH -->", "")
+        .replace("<!-- END-ZH -->", "")
+        .replace("<!-- BEGIN-EN -->", "")
+        .replace("<!-- END-EN -->", "")
         .trim()
         .to_string()
 }
 
 fn handle_watch() -> Result<()> {
-    let ksu_configs: HashMap<String, KsuConfigItem> = serde_json::from_str(KSU_CONFIG_JSON)?;
+    let projects_map = load_projects()?;
+    let ksu_variants = load_ksu_variants(&projects_map)?;
     let upstream_path = get_upstream_path();
     let mut track_data: HashMap<String, String> = if upstream_path.exists() {
         serde_json::from_str(&fs::read_to_string(&upstream_path)?)?
@@ -510,22 +1088,26 @@ fn handle_watch() -> Result<()> {
         HashMap::new()
     };
 
-    track_data.remove("sukisuultra");
-    let projects_map = load_projects()?;
+    for config in ksu_variants.values() {
+        for alias in &config.aliases {
+            track_data.remove(alias);
+        }
+    }
     let mut update_matrix = Vec::new();
 
-    for (variant, config) in ksu_configs {
-        let output = run_cmd(
-            &["git", "ls-remote", &config.repo, &config.branch],
-            None,
-            true,
-        )?;
+    for (variant, config) in &ksu_variants {
+        let (Some(repo), Some(branch)) = (&config.repo, &config.branch) else {
+            continue;
+        };
+        let output = retry::run("git ls-remote", || {
+            run_cmd(&["git", "ls-remote", repo, branch], None, true)
+        })?;
         let latest_hash = match output {
             Some(s) => s.split_whitespace().next().unwrap_or("").to_string(),
             None => continue,
         };
 
-        let stored_hash = track_data.get(&variant).cloned().unwrap_or_default();
+        let stored_hash = track_data.get(variant).cloned().unwrap_or_default();
 
         if latest_hash != stored_hash {
             track_data.insert(variant.clone(), latest_hash.clone());
@@ -537,11 +1119,11 @@ fn handle_watch() -> Result<()> {
                 let p: ProjectConfig = serde_json::from_value(p_val.clone())?;
                 let supported = p.supported_ksu.unwrap_or_default();
                 let normalized_supported: Vec<String> = supported
-                    .into_iter()
-                    .map(|x| x.replace("sukisuultra", "resukisu"))
+                    .iter()
+                    .map(|x| canonical_variant_name(&ksu_variants, x))
                     .collect();
 
-                if normalized_supported.contains(&variant) {
+                if normalized_supported.contains(variant) {
                     let mut map = HashMap::new();
                     map.insert("project".to_string(), p_key.clone());
                     map.insert("variant".to_string(), variant.clone());
@@ -584,9 +1166,10 @@ fn handle_update(
     let proj_val = projects
         .get(&project_key)
         .ok_or_else(|| anyhow!("Project not found"))?;
-    let proj: ProjectConfig = serde_json::from_value(proj_val.clone())?;
+    let proj: ProjectConfig = serde_json::from_value(expand_entry(&project_key, proj_val)?)?;
 
-    let normalized_variant = variant.replace("sukisuultra", "resukisu");
+    let ksu_variants = load_ksu_variants(&projects)?;
+    let normalized_variant = canonical_variant_name(&ksu_variants, &variant);
     let repo_url = proj.repo;
     let target_dir = PathBuf::from("temp_kernel");
 
@@ -595,19 +1178,24 @@ fn handle_update(
     }
 
     let auth_url = format!("https://{}@github.com/{}.git", token, repo_url);
-    run_cmd(
-        &[
-            "git",
-            "clone",
-            "--depth=1",
-            "--branch",
-            &normalized_variant,
-            &auth_url,
-            target_dir.to_str().unwrap(),
-        ],
-        None,
-        false,
-    )?;
+    retry::run("git clone", || {
+        if target_dir.exists() {
+            fs::remove_dir_all(&target_dir)?;
+        }
+        run_cmd(
+            &[
+                "git",
+                "clone",
+                "--depth=1",
+                "--branch",
+                &normalized_variant,
+                &auth_url,
+                target_dir.to_str().unwrap(),
+            ],
+            None,
+            false,
+        )
+    })?;
 
     fs::write(target_dir.join("KERNELSU_VERSION.txt"), &commit_id)?;
 
@@ -616,10 +1204,16 @@ fn handle_update(
         fs::copy(univ_ignore, target_dir.join(".gitignore"))?;
     }
 
-    let ksu_configs: HashMap<String, KsuConfigItem> = serde_json::from_str(KSU_CONFIG_JSON)?;
-    if let Some(cfg) = ksu_configs.get(&normalized_variant) {
+    if let Some(cfg) = ksu_variants.get(&normalized_variant)
+        && let Some(setup_url) = cfg.setup_url_for(proj.ksu_ref.as_deref())
+        && !cfg.setup_args.is_empty()
+    {
         let setup_script = target_dir.join("setup.sh");
-        let script_content = reqwest::blocking::get(&cfg.setup_url)?.text()?;
+        let script_content = retry::run("setup script download", || {
+            Ok(reqwest::blocking::get(&setup_url)?
+                .error_for_status()?
+                .text()?)
+        })?;
         fs::write(&setup_script, script_content)?;
 
         let mut args = vec!["bash", "setup.sh"];
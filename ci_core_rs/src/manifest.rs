@@ -0,0 +1,70 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::ManifestConfig;
+use crate::retry;
+use crate::utils::run_cmd;
+
+/// Assembles a multi-repo kernel tree in `dest` with `repo init`/`repo sync`
+/// and returns the directory the kernel itself is built in.
+pub fn sync_manifest(
+    cfg: &ManifestConfig,
+    reference_dir: Option<&str>,
+    dest: &Path,
+) -> Result<PathBuf> {
+    if run_cmd(&["which", "repo"], None, true).is_err() {
+        return Err(anyhow!(
+            "source_manifest needs the `repo` tool on PATH (https://gerrit.googlesource.com/git-repo)"
+        ));
+    }
+    fs::create_dir_all(dest)?;
+
+    // Re-running init on an existing checkout switches it to the configured
+    // manifest/branch, so it is cheap to always do it.
+    info!("repo init {} ({})", cfg.url, cfg.branch);
+    let depth_arg = format!("--depth={}", cfg.depth.unwrap_or(1));
+    let mut init = vec!["repo", "init", "-u", &cfg.url, "-b", &cfg.branch];
+    if let Some(file) = &cfg.file {
+        init.extend(["-m", file]);
+    }
+    if let Some(groups) = &cfg.groups {
+        init.extend(["-g", groups]);
+    }
+    if cfg.depth != Some(0) {
+        init.push(&depth_arg);
+    }
+    let reference_arg = reference_dir.map(|dir| format!("--reference={}", dir));
+    init.extend(reference_arg.as_deref());
+    run_cmd(&init, Some(dest), false)?;
+
+    let threads = run_cmd(&["nproc"], None, true)?.unwrap_or_else(|| "4".to_string());
+    let jobs = format!("-j{}", threads.trim());
+    info!("repo sync...");
+    // repo sync picks up where a failed attempt left off.
+    retry::run("repo sync", || {
+        run_cmd(
+            &[
+                "repo",
+                "sync",
+                "-c",
+                "--no-tags",
+                "--no-clone-bundle",
+                "--force-sync",
+                &jobs,
+            ],
+            Some(dest),
+            false,
+        )
+    })?;
+
+    let kernel_dir = dest.join(cfg.kernel_dir.as_deref().unwrap_or("."));
+    if !kernel_dir.join("Makefile").exists() {
+        return Err(anyhow!(
+            "No kernel Makefile in {:?} after repo sync, check source_manifest.kernel_dir",
+            kernel_dir
+        ));
+    }
+    Ok(kernel_dir)
+}
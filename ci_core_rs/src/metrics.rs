@@ -0,0 +1,175 @@
+use log::{info, warn};
+use reqwest::blocking::Client;
+use std::env;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::config::MetricsConfig;
+use crate::history::Artifact;
+use crate::secrets;
+use crate::utils::url_encode;
+
+/// What one build reports to the Pushgateway.
+pub struct BuildSample<'a> {
+    pub project: &'a str,
+    pub branch: &'a str,
+    pub ok: bool,
+    pub duration: Duration,
+    pub stages: &'a [(String, Duration)],
+    /// Compiler cache hit rate in percent.
+    pub cache_hit_rate: Option<f64>,
+    pub artifacts: &'a [Artifact],
+    pub image_size: Option<u64>,
+    pub warnings: usize,
+    /// Recorded (successful, failed) builds of the branch, this one included.
+    pub totals: Option<(u64, u64)>,
+}
+
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The sample in the Prometheus text format. Everything is a gauge except
+/// the build totals, which come from the build history because each push
+/// replaces the group's previous values.
+fn render(sample: &BuildSample) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, rows: &[(String, f64)]| {
+        if rows.is_empty() {
+            return;
+        }
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for (labels, value) in rows {
+            let _ = writeln!(out, "{}{} {}", name, labels, value);
+        }
+    };
+    let one = |value: f64| vec![(String::new(), value)];
+
+    metric(
+        "kokuban_build_success",
+        "gauge",
+        "1 if the last build succeeded, 0 if it failed.",
+        &one(if sample.ok { 1.0 } else { 0.0 }),
+    );
+    metric(
+        "kokuban_build_duration_seconds",
+        "gauge",
+        "Wall time of the last build.",
+        &one(sample.duration.as_secs_f64()),
+    );
+    let stages: Vec<(String, f64)> = sample
+        .stages
+        .iter()
+        .map(|(stage, took)| {
+            (
+                format!("{{stage=\"{}\"}}", label(stage)),
+                took.as_secs_f64(),
+            )
+        })
+        .collect();
+    metric(
+        "kokuban_build_stage_duration_seconds",
+        "gauge",
+        "Wall time of each stage of the last build.",
+        &stages,
+    );
+    metric(
+        "kokuban_build_last_run_timestamp_seconds",
+        "gauge",
+        "When the last build finished.",
+        &one(chrono::Utc::now().timestamp() as f64),
+    );
+    if let Some(rate) = sample.cache_hit_rate {
+        metric(
+            "kokuban_compiler_cache_hit_ratio",
+            "gauge",
+            "Compiler cache hits per lookup during the last build.",
+            &one(rate / 100.0),
+        );
+    }
+    metric(
+        "kokuban_compiler_warnings",
+        "gauge",
+        "Distinct compiler warnings in the last build.",
+        &one(sample.warnings as f64),
+    );
+    if let Some(size) = sample.image_size {
+        metric(
+            "kokuban_kernel_image_size_bytes",
+            "gauge",
+            "Size of the kernel image of the last successful build.",
+            &one(size as f64),
+        );
+    }
+    let artifacts: Vec<(String, f64)> = sample
+        .artifacts
+        .iter()
+        .map(|a| {
+            let name = a.path.file_name().unwrap_or_default().to_string_lossy();
+            (format!("{{artifact=\"{}\"}}", label(&name)), a.size as f64)
+        })
+        .collect();
+    metric(
+        "kokuban_artifact_size_bytes",
+        "gauge",
+        "Size of each release artifact of the last build.",
+        &artifacts,
+    );
+    if let Some((ok, failed)) = sample.totals {
+        metric(
+            "kokuban_builds_total",
+            "counter",
+            "Builds recorded in the build history, by result.",
+            &[
+                ("{result=\"success\"}".to_string(), ok as f64),
+                ("{result=\"failure\"}".to_string(), failed as f64),
+            ],
+        );
+    }
+    out
+}
+
+/// Replaces the project/branch group on the Pushgateway with `sample`.
+/// Failures are only warned about.
+pub fn push(cfg: Option<&MetricsConfig>, sample: &BuildSample) {
+    let Some(base) = cfg
+        .and_then(|c| c.pushgateway.clone())
+        .or_else(|| env::var("PUSHGATEWAY_URL").ok())
+    else {
+        return;
+    };
+    let job = cfg.and_then(|c| c.job.as_deref()).unwrap_or("kokuban_ci");
+    let url = format!(
+        "{}/metrics/job/{}/project/{}/branch/{}",
+        base.trim_end_matches('/'),
+        url_encode(job),
+        url_encode(sample.project),
+        url_encode(sample.branch)
+    );
+    let mut req = Client::new()
+        .put(&url)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(render(sample));
+    if let Some(user_var) = cfg.and_then(|c| c.username_env.as_deref()) {
+        let pass_var = cfg
+            .and_then(|c| c.password_env.as_deref())
+            .unwrap_or("PUSHGATEWAY_PASSWORD");
+        req = req.basic_auth(
+            secrets::find(user_var).unwrap_or_default(),
+            secrets::find(pass_var),
+        );
+    }
+    match req.send() {
+        Ok(resp) if resp.status().is_success() => info!("Pushed build metrics to {}", base),
+        Ok(resp) => warn!(
+            "Pushgateway rejected metrics: HTTP {}: {}",
+            resp.status(),
+            resp.text().unwrap_or_default().trim()
+        ),
+        Err(e) => warn!("Failed to push metrics: {}", e),
+    }
+}
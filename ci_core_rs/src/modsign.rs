@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow};
+use log::info;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::ModuleSigningConfig;
+use crate::kconfig::ConfigEntry;
+use crate::secrets;
+use crate::utils::{find_local_file, get_cache_dir, run_cmd};
+
+/// Where the kernel looks for the key by default, relative to the build dir.
+const KEY_PATH: &str = "certs/signing_key.pem";
+
+/// Hashes `CONFIG_MODULE_SIG_<HASH>` exists for.
+pub const HASHES: &[&str] = &["sha1", "sha224", "sha256", "sha384", "sha512"];
+
+/// The `CONFIG_MODULE_SIG*` options for `cfg`. MODULE_SIG_ALL has
+/// modules_install sign every module it installs, external ones included.
+pub fn config_entries(cfg: &ModuleSigningConfig) -> Vec<ConfigEntry> {
+    let hash = cfg.hash.as_deref().unwrap_or("sha256");
+    let mut entries: Vec<ConfigEntry> = ["MODULE_SIG", "MODULE_SIG_ALL"]
+        .iter()
+        .map(|c| (c.to_string(), Some("y".to_string())))
+        .collect();
+    entries.push((
+        "MODULE_SIG_FORCE".to_string(),
+        cfg.force.unwrap_or(false).then(|| "y".to_string()),
+    ));
+    entries.push((
+        format!("MODULE_SIG_{}", hash.to_uppercase()),
+        Some("y".to_string()),
+    ));
+    entries.push((
+        "MODULE_SIG_KEY".to_string(),
+        Some(format!("\"{}\"", KEY_PATH)),
+    ));
+    entries
+}
+
+/// Puts the signing key at `certs/signing_key.pem` in the build dir: the
+/// configured one, or one generated for the project and kept in the cache
+/// so every build of it signs with the same key.
+pub fn install_key(
+    cfg: &ModuleSigningConfig,
+    project_key: &str,
+    kernel_source: &Path,
+    out_path: &Path,
+) -> Result<()> {
+    let dest = out_path.join(KEY_PATH);
+    fs::create_dir_all(dest.parent().unwrap())?;
+    if let Some(name) = &cfg.key_env {
+        let pem = secrets::find(name)
+            .filter(|v| v.contains("PRIVATE KEY"))
+            .ok_or_else(|| anyhow!("module_signing: ${} holds no PEM private key", name))?;
+        fs::write(&dest, pem)?;
+        info!("Module signing key from ${}", name);
+    } else {
+        let key = match &cfg.key {
+            Some(key) => find_local_file(key, kernel_source)?,
+            None => generated_key(project_key)?,
+        };
+        fs::copy(&key, &dest)?;
+        info!("Module signing key: {}", key.display());
+    }
+    fs::set_permissions(&dest, fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+/// The project's generated key, made the way the kernel's certs/Makefile
+/// makes one when the key is missing.
+fn generated_key(project_key: &str) -> Result<PathBuf> {
+    let dir = get_cache_dir().join("module-signing");
+    let pem = dir.join(format!("{}.pem", project_key));
+    if pem.exists() {
+        return Ok(pem);
+    }
+    fs::create_dir_all(&dir)?;
+    info!("Generating a module signing key for {}...", project_key);
+    let path = pem.to_string_lossy();
+    let subject = format!("/CN={} module signing key", project_key);
+    run_cmd(
+        &[
+            "openssl",
+            "req",
+            "-new",
+            "-nodes",
+            "-utf8",
+            "-sha256",
+            "-days",
+            "36500",
+            "-batch",
+            "-x509",
+            "-newkey",
+            "rsa:4096",
+            "-subj",
+            &subject,
+            "-addext",
+            "basicConstraints=critical,CA:FALSE",
+            "-addext",
+            "keyUsage=digitalSignature",
+            "-outform",
+            "PEM",
+            "-out",
+            &path,
+            "-keyout",
+            &path,
+        ],
+        None,
+        true,
+    )?;
+    fs::set_permissions(&pem, fs::Permissions::from_mode(0o600))?;
+    Ok(pem)
+}
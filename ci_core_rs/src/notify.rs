@@ -0,0 +1,266 @@
+mod email;
+mod matrix;
+mod telegram;
+mod webhook;
+
+use anyhow::{Result, anyhow};
+use log::info;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{ProjectConfig, ProjectsMap};
+use crate::github::GitHub;
+use crate::toolchain::sha256_file;
+use crate::utils::{expand_entry, load_globals, load_projects};
+use webhook::Service;
+
+/// Assets larger than this aren't fetched for `notify`; no backend takes them.
+const MAX_DOWNLOAD: u64 = 50 * 1024 * 1024;
+
+/// A published release as the notification backends see it.
+pub struct ReleaseEvent<'a> {
+    pub repo: &'a str,
+    pub tag: &'a str,
+    pub title: &'a str,
+    pub author: &'a str,
+    pub url: &'a str,
+    pub kernel_version: Option<&'a str>,
+    pub variant: Option<&'a str>,
+    /// Extra line such as the build time and compiler cache hit rate.
+    pub build_stats: Option<&'a str>,
+    /// SHA-256 of the kernel zip.
+    pub zip_sha256: Option<&'a str>,
+    /// Local copies of the release files.
+    pub files: &'a [PathBuf],
+}
+
+/// A build that stopped with an error.
+pub struct FailureEvent<'a> {
+    pub repo: &'a str,
+    pub project: &'a str,
+    pub branch: &'a str,
+    pub stage: Option<&'a str>,
+    pub error: &'a str,
+    /// The last command that failed and the end of its output.
+    pub command: Option<&'a str>,
+    pub tail: &'a [String],
+    /// The CI run, when running under GitHub Actions.
+    pub run_url: Option<&'a str>,
+    /// Full output of the stage that failed.
+    pub log: Option<&'a Path>,
+}
+
+impl FailureEvent<'_> {
+    /// The last lines of `tail` that fit in `max_chars`.
+    pub fn tail_within(&self, max_chars: usize) -> String {
+        let mut used = 0;
+        let mut lines: Vec<&str> = Vec::new();
+        for line in self.tail.iter().rev() {
+            used += line.len() + 1;
+            if used > max_chars {
+                break;
+            }
+            lines.push(line);
+        }
+        lines.reverse();
+        lines.join("\n")
+    }
+}
+
+/// Size and checksum of a release file, for the webhook embeds.
+pub struct Artifact {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Artifact {
+    fn from_path(path: &Path) -> Result<Self> {
+        Ok(Artifact {
+            name: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            size: fs::metadata(path)?.len(),
+            sha256: sha256_file(path)?,
+        })
+    }
+
+    pub fn size_label(&self) -> String {
+        format!("{:.1} MiB", self.size as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Escapes text for the HTML flavours Telegram and Matrix accept.
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Sends `event` to every backend configured for `proj`.
+pub fn notify_release(
+    projects: &ProjectsMap,
+    proj: &ProjectConfig,
+    event: &ReleaseEvent,
+) -> Result<()> {
+    let globals = load_globals(projects);
+    let cfg = proj.notify.clone().unwrap_or_default();
+    telegram::send_release(&globals, cfg.telegram.as_ref(), event)?;
+    if let Some(room) = &cfg.matrix {
+        matrix::send_release(room, event)?;
+    }
+    if let Some(mail) = &cfg.email {
+        email::send_release(mail, event)?;
+    }
+
+    let webhooks = [
+        (Service::Discord, cfg.discord.as_ref()),
+        (Service::Slack, cfg.slack.as_ref()),
+    ];
+    if webhooks.iter().all(|(_, c)| c.is_none()) {
+        return Ok(());
+    }
+    let artifacts = event
+        .files
+        .iter()
+        .map(|f| Artifact::from_path(f))
+        .collect::<Result<Vec<_>>>()?;
+    for (service, hook) in webhooks {
+        if let Some(hook) = hook {
+            webhook::send_release(service, hook, event, &artifacts)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reports a failed build to the project's own Telegram chat, Matrix room,
+/// mail recipients and webhooks, unless `notify.on_failure` is false.
+pub fn notify_failure(proj: &ProjectConfig, event: &FailureEvent) -> Result<()> {
+    let cfg = proj.notify.clone().unwrap_or_default();
+    if !cfg.on_failure.unwrap_or(true) {
+        return Ok(());
+    }
+    telegram::send_failure(cfg.telegram.as_ref(), event)?;
+    if let Some(room) = &cfg.matrix {
+        matrix::send_failure(room, event)?;
+    }
+    if let Some(mail) = &cfg.email {
+        email::send_failure(mail, event)?;
+    }
+    if let Some(hook) = &cfg.discord {
+        webhook::send_failure(Service::Discord, hook, event)?;
+    }
+    if let Some(hook) = &cfg.slack {
+        webhook::send_failure(Service::Slack, hook, event)?;
+    }
+    Ok(())
+}
+
+/// `notify --tag`: looks up the project by tag prefix, fetches the release
+/// and its small assets from GitHub and announces it.
+pub fn handle_notify(tag_name: String) -> Result<()> {
+    let projects = load_projects()?;
+
+    let mut target_project: Option<ProjectConfig> = None;
+    for (key, val) in &projects {
+        if key.starts_with("_") {
+            continue;
+        }
+        let p: ProjectConfig = serde_json::from_value(val.clone())?;
+        let mut prefixes = vec![p.zip_name_prefix.as_deref().unwrap_or("Kernel")];
+        if let Some(branches) = &p.branches {
+            prefixes.extend(
+                branches
+                    .values()
+                    .filter_map(|b| b.get("zip_name_prefix").and_then(|v| v.as_str())),
+            );
+        }
+
+        if prefixes.iter().any(|prefix| tag_name.starts_with(prefix)) {
+            target_project = Some(serde_json::from_value(expand_entry(key, val)?)?);
+            break;
+        }
+    }
+
+    let Some(proj) = target_project else {
+        info!("No project found for tag {}", tag_name);
+        return Ok(());
+    };
+
+    let github = GitHub::from_env()?;
+    let mut attempt = 0;
+    let max_attempts = 5;
+    let mut release_info = None;
+
+    while attempt < max_attempts {
+        match github.release_by_tag(&proj.repo, &tag_name) {
+            Ok(Some(info)) => {
+                release_info = Some(info);
+                break;
+            }
+            result => {
+                let reason = match result {
+                    Err(e) => e.to_string(),
+                    _ => "not found yet".to_string(),
+                };
+                info!(
+                    "Attempt {}/{} failed to verify release: {}. Retrying in 5s...",
+                    attempt + 1,
+                    max_attempts,
+                    reason
+                );
+                attempt += 1;
+                thread::sleep(Duration::from_secs(5));
+            }
+        }
+    }
+
+    let release_info = release_info
+        .ok_or_else(|| anyhow!("Failed to retrieve release info after multiple attempts."))?;
+
+    let download_dir = env::temp_dir().join(format!("kokuban-notify-{}", std::process::id()));
+    fs::create_dir_all(&download_dir)?;
+    let mut files = Vec::new();
+    for asset in &release_info.assets {
+        if asset.size > MAX_DOWNLOAD {
+            info!("Skipping {} (too large)", asset.name);
+            continue;
+        }
+        let dest = download_dir.join(&asset.name);
+        github.download_asset(asset, &dest)?;
+        files.push(dest);
+    }
+
+    let zip_sha256 = files
+        .iter()
+        .find(|f| f.extension().is_some_and(|e| e == "zip"))
+        .map(|f| sha256_file(f))
+        .transpose()?;
+    let result = notify_release(
+        &projects,
+        &proj,
+        &ReleaseEvent {
+            repo: &proj.repo,
+            tag: &tag_name,
+            title: release_info.name.as_deref().unwrap_or("Update"),
+            author: release_info
+                .author
+                .as_ref()
+                .map(|a| a.login.as_str())
+                .unwrap_or("YuzakiKokuban"),
+            url: &release_info.html_url,
+            kernel_version: None,
+            variant: None,
+            build_stats: None,
+            zip_sha256: zip_sha256.as_deref(),
+            files: &files,
+        },
+    );
+    let _ = fs::remove_dir_all(&download_dir);
+    result
+}
@@ -0,0 +1,263 @@
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Local;
+use log::{info, warn};
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{FailureEvent, ReleaseEvent};
+use crate::config::EmailConfig;
+use crate::secrets;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Security {
+    StartTls,
+    Tls,
+    None,
+}
+
+impl Security {
+    fn parse(setting: Option<&str>) -> Result<Self> {
+        match setting.unwrap_or("starttls") {
+            "starttls" => Ok(Security::StartTls),
+            "tls" => Ok(Security::Tls),
+            "none" => Ok(Security::None),
+            other => Err(anyhow!(
+                "Unknown email.tls '{}' (expected starttls, tls or none)",
+                other
+            )),
+        }
+    }
+
+    fn default_port(self) -> u16 {
+        match self {
+            Security::StartTls => 587,
+            Security::Tls => 465,
+            Security::None => 25,
+        }
+    }
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    fn inner(&mut self) -> &mut dyn ReadWrite {
+        match self {
+            Stream::Plain(s) => s,
+            Stream::Tls(s) => s.as_mut(),
+        }
+    }
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// One SMTP session. Replies are read byte by byte, which keeps the stream
+/// unbuffered so it can be handed to TLS after STARTTLS.
+struct Session {
+    stream: Option<Stream>,
+    host: String,
+}
+
+impl Session {
+    fn connect(host: &str, port: u16, security: Security) -> Result<Self> {
+        let tcp = TcpStream::connect((host, port))
+            .with_context(|| format!("Failed to connect to {}:{}", host, port))?;
+        tcp.set_read_timeout(Some(TIMEOUT))?;
+        tcp.set_write_timeout(Some(TIMEOUT))?;
+        let mut session = Session {
+            stream: Some(Stream::Plain(tcp)),
+            host: host.to_string(),
+        };
+        if security == Security::Tls {
+            session.start_tls()?;
+        }
+        session.expect(220)?;
+        Ok(session)
+    }
+
+    fn start_tls(&mut self) -> Result<()> {
+        let Some(Stream::Plain(tcp)) = self.stream.take() else {
+            return Err(anyhow!("SMTP connection is already encrypted"));
+        };
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let name = ServerName::try_from(self.host.clone())?;
+        let conn = ClientConnection::new(Arc::new(config), name)?;
+        self.stream = Some(Stream::Tls(Box::new(StreamOwned::new(conn, tcp))));
+        Ok(())
+    }
+
+    fn stream(&mut self) -> &mut dyn ReadWrite {
+        self.stream.as_mut().expect("SMTP stream").inner()
+    }
+
+    /// Reads a (possibly multi-line) reply and checks its code.
+    fn expect(&mut self, code: u16) -> Result<String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = Vec::new();
+            let mut byte = [0u8; 1];
+            while !line.ends_with(b"\r\n") {
+                if self.stream().read(&mut byte)? == 0 {
+                    return Err(anyhow!("SMTP server closed the connection"));
+                }
+                line.push(byte[0]);
+            }
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            reply.push_str(&line);
+            reply.push('\n');
+            // "250-..." continues, "250 ..." ends the reply.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                let got: u16 = line.get(..3).and_then(|c| c.parse().ok()).unwrap_or(0);
+                if got != code {
+                    return Err(anyhow!("SMTP: expected {}, got: {}", code, reply.trim()));
+                }
+                return Ok(reply);
+            }
+        }
+    }
+
+    fn command(&mut self, line: &str, code: u16) -> Result<String> {
+        self.stream()
+            .write_all(format!("{}\r\n", line).as_bytes())?;
+        self.stream().flush()?;
+        self.expect(code)
+    }
+}
+
+/// "=?UTF-8?B?...?=" for non-ASCII header values.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64.encode(value))
+    }
+}
+
+fn send_mail(cfg: &EmailConfig, subject: &str, body: &str) -> Result<()> {
+    if cfg.to.is_empty() {
+        return Err(anyhow!("email.to is empty"));
+    }
+    let security = Security::parse(cfg.tls.as_deref())?;
+    let user_var = cfg.username_env.as_deref().unwrap_or("SMTP_USERNAME");
+    let pass_var = cfg.password_env.as_deref().unwrap_or("SMTP_PASSWORD");
+    let login = match secrets::find(user_var) {
+        Some(user) => Some((user, secrets::get(pass_var, "SMTP password")?)),
+        None => None,
+    };
+
+    let port = cfg.port.unwrap_or(security.default_port());
+    let mut session = Session::connect(&cfg.host, port, security)?;
+    session.command("EHLO kokuban-ci", 250)?;
+    if security == Security::StartTls {
+        session.command("STARTTLS", 220)?;
+        session.start_tls()?;
+        session.command("EHLO kokuban-ci", 250)?;
+    }
+    if let Some((user, pass)) = login {
+        let token = BASE64.encode(format!("\0{}\0{}", user, pass));
+        session.command(&format!("AUTH PLAIN {}", token), 235)?;
+    }
+    session.command(&format!("MAIL FROM:<{}>", cfg.from), 250)?;
+    for rcpt in &cfg.to {
+        session.command(&format!("RCPT TO:<{}>", rcpt), 250)?;
+    }
+    session.command("DATA", 354)?;
+
+    // A base64 body needs no dot-stuffing and has no long lines.
+    let encoded = BASE64.encode(body);
+    let wrapped: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|c| std::str::from_utf8(c).unwrap_or_default())
+        .collect();
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}\r\n.",
+        cfg.from,
+        cfg.to.join(", "),
+        encode_header(subject),
+        Local::now().to_rfc2822(),
+        wrapped.join("\r\n")
+    );
+    session.command(&message, 250)?;
+    let _ = session.command("QUIT", 221);
+    info!("Mailed {} recipient(s) via {}", cfg.to.len(), cfg.host);
+    Ok(())
+}
+
+/// Delivery problems are only warned about, like the other backends.
+fn deliver(cfg: &EmailConfig, subject: &str, body: &str) {
+    if let Err(e) = send_mail(cfg, subject, body) {
+        warn!("Email notification failed: {:#}", e);
+    }
+}
+
+pub fn send_release(cfg: &EmailConfig, event: &ReleaseEvent) -> Result<()> {
+    Security::parse(cfg.tls.as_deref())?;
+    let mut body = format!(
+        "New release of {}: {}\n\nTag: {}\n",
+        event.repo, event.title, event.tag
+    );
+    if let Some(version) = event.kernel_version {
+        body.push_str(&format!("Kernel: {}\n", version));
+    }
+    if let Some(variant) = event.variant {
+        body.push_str(&format!("Variant: {}\n", variant));
+    }
+    if let Some(stats) = event.build_stats {
+        body.push_str(&format!("Build: {}\n", stats));
+    }
+    if let Some(sha256) = event.zip_sha256 {
+        body.push_str(&format!("SHA-256: {}\n", sha256));
+    }
+    body.push_str(&format!("\n{}\n", event.url));
+    deliver(cfg, &format!("[kokuban] {} released", event.tag), &body);
+    Ok(())
+}
+
+pub fn send_failure(cfg: &EmailConfig, event: &FailureEvent) -> Result<()> {
+    Security::parse(cfg.tls.as_deref())?;
+    let stage = event.stage.unwrap_or("setup");
+    let mut body = format!(
+        "Build of {} / {} ({}) failed in stage {}.\n\nError: {}\n",
+        event.project, event.branch, event.repo, stage, event.error
+    );
+    if let Some(url) = event.run_url {
+        body.push_str(&format!("CI log: {}\n", url));
+    }
+    if let Some(log) = event.log {
+        body.push_str(&format!("Full log: {}\n", log.display()));
+    }
+    if let Some(command) = event.command {
+        body.push_str(&format!("\nOutput of {}:\n\n", command));
+    }
+    // Mail has no practical size limit; send the whole tail.
+    body.push_str(&event.tail.join("\n"));
+    body.push('\n');
+    deliver(
+        cfg,
+        &format!(
+            "[kokuban] build failed: {} / {} ({})",
+            event.project, event.branch, stage
+        ),
+        &body,
+    );
+    Ok(())
+}
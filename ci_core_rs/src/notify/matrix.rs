@@ -0,0 +1,165 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde_json::json;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{FailureEvent, ReleaseEvent, escape_html};
+use crate::config::MatrixConfig;
+use crate::secrets;
+use crate::utils::url_encode;
+
+const MAX_TAIL_CHARS: usize = 3000;
+
+struct Room<'a> {
+    cfg: &'a MatrixConfig,
+    client: Client,
+    token: String,
+}
+
+impl<'a> Room<'a> {
+    fn new(cfg: &'a MatrixConfig) -> Result<Self> {
+        let var = cfg
+            .access_token_env
+            .as_deref()
+            .unwrap_or("MATRIX_ACCESS_TOKEN");
+        Ok(Room {
+            cfg,
+            client: Client::new(),
+            token: secrets::get(var, "Matrix access token")?,
+        })
+    }
+
+    fn api(&self, path: &str) -> String {
+        format!(
+            "{}/_matrix/client/v3/{}",
+            self.cfg.homeserver.trim_end_matches('/'),
+            path
+        )
+    }
+
+    fn room_id(&self) -> Result<String> {
+        if !self.cfg.room_id.starts_with('#') {
+            return Ok(self.cfg.room_id.clone());
+        }
+        let resp = self
+            .client
+            .get(self.api(&format!("directory/room/{}", url_encode(&self.cfg.room_id))))
+            .bearer_auth(&self.token)
+            .send()?;
+        if !resp.status().is_success() {
+            return Err(anyhow!(
+                "Matrix room alias {} not found: HTTP {}",
+                self.cfg.room_id,
+                resp.status()
+            ));
+        }
+        let body: serde_json::Value = resp.json()?;
+        body["room_id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Matrix returned no room_id for {}", self.cfg.room_id))
+    }
+
+    /// Sends an HTML message with a plain-text fallback. Rejections are only
+    /// warned about.
+    fn send(&self, plain: &str, html: &str) -> Result<()> {
+        let room = self.room_id()?;
+        let txn = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let url = self.api(&format!(
+            "rooms/{}/send/m.room.message/kokuban-{}",
+            url_encode(&room),
+            txn
+        ));
+        let body = json!({
+            "msgtype": "m.text",
+            "body": plain,
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        });
+        match self
+            .client
+            .put(url)
+            .bearer_auth(&self.token)
+            .json(&body)
+            .send()
+        {
+            Ok(resp) if resp.status().is_success() => info!("Posted to Matrix room {}", room),
+            Ok(resp) => warn!(
+                "Matrix send failed: HTTP {}: {}",
+                resp.status(),
+                resp.text().unwrap_or_default().trim()
+            ),
+            Err(e) => warn!("Matrix send failed: {}", e),
+        }
+        Ok(())
+    }
+}
+
+pub fn send_release(cfg: &MatrixConfig, event: &ReleaseEvent) -> Result<()> {
+    let mut facts = vec![("Tag", event.tag.to_string())];
+    if let Some(version) = event.kernel_version {
+        facts.push(("Kernel", version.to_string()));
+    }
+    if let Some(variant) = event.variant {
+        facts.push(("Variant", variant.to_string()));
+    }
+    if let Some(stats) = event.build_stats {
+        facts.push(("Build", stats.to_string()));
+    }
+    if let Some(sha256) = event.zip_sha256 {
+        facts.push(("SHA-256", sha256.to_string()));
+    }
+
+    let mut plain = format!("New release of {}: {}\n", event.repo, event.title);
+    let mut html = format!(
+        "<p>New release of <code>{}</code>: <a href=\"{}\">{}</a></p><ul>",
+        escape_html(event.repo),
+        event.url,
+        escape_html(event.title)
+    );
+    for (name, value) in &facts {
+        plain.push_str(&format!("{}: {}\n", name, value));
+        html.push_str(&format!("<li><b>{}:</b> {}</li>", name, escape_html(value)));
+    }
+    html.push_str("</ul>");
+    plain.push_str(event.url);
+    Room::new(cfg)?.send(&plain, &html)
+}
+
+pub fn send_failure(cfg: &MatrixConfig, event: &FailureEvent) -> Result<()> {
+    let stage = event.stage.unwrap_or("setup");
+    let tail = event.tail_within(MAX_TAIL_CHARS);
+    let mut plain = format!(
+        "Build failed: {} {} / {}\nStage: {}\nError: {}\n",
+        event.repo, event.project, event.branch, stage, event.error
+    );
+    let mut html = format!(
+        "<p>❌ <b>Build failed:</b> <code>{}</code> {} / {}</p><ul><li><b>Stage:</b> {}</li><li><b>Error:</b> <code>{}</code></li>",
+        escape_html(event.repo),
+        escape_html(event.project),
+        escape_html(event.branch),
+        escape_html(stage),
+        escape_html(event.error)
+    );
+    if let Some(command) = event.command {
+        plain.push_str(&format!("Command: {}\n", command));
+        html.push_str(&format!(
+            "<li><b>Command:</b> <code>{}</code></li>",
+            escape_html(command)
+        ));
+    }
+    html.push_str("</ul>");
+    if let Some(url) = event.run_url {
+        plain.push_str(&format!("{}\n", url));
+        html.push_str(&format!("<p><a href=\"{}\">CI log</a></p>", url));
+    }
+    if !tail.is_empty() {
+        plain.push_str(&format!("\n{}", tail));
+        html.push_str(&format!("<pre><code>{}</code></pre>", escape_html(&tail)));
+    }
+    Room::new(cfg)?.send(&plain, &html)
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use log::{info, warn};
+use reqwest::blocking::{Client, multipart};
+use std::env;
+use std::fs;
+
+use super::{FailureEvent, ReleaseEvent, escape_html};
+use crate::config::{GlobalConfig, TelegramConfig};
+use crate::secrets;
+
+/// Bot API limit for documents sent by bots.
+const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+/// Room left for the output excerpt within the 4096-character message limit.
+const MAX_TAIL_CHARS: usize = 3000;
+
+struct Destination {
+    chat_id: String,
+    topic_id: Option<i32>,
+}
+
+struct Bot {
+    client: Client,
+    token: String,
+}
+
+impl Bot {
+    fn post(&self, method: &str, req: reqwest::blocking::RequestBuilder) {
+        match req.send() {
+            Ok(resp) if resp.status().is_success() => {}
+            Ok(resp) => warn!(
+                "Telegram {} failed: HTTP {}: {}",
+                method,
+                resp.status(),
+                resp.text().unwrap_or_default().trim()
+            ),
+            Err(e) => warn!("Telegram {} failed: {}", method, e),
+        }
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("https://api.telegram.org/bot{}/{}", self.token, method)
+    }
+
+    fn send_message(&self, dest: &Destination, text: &str) {
+        let mut body = serde_json::json!({
+            "chat_id": dest.chat_id,
+            "text": text,
+            "parse_mode": "HTML",
+            "disable_web_page_preview": true,
+        });
+        if let Some(tid) = dest.topic_id {
+            body["message_thread_id"] = tid.into();
+        }
+        self.post(
+            "sendMessage",
+            self.client.post(self.url("sendMessage")).json(&body),
+        );
+    }
+
+    fn send_document(&self, dest: &Destination, name: &str, data: Vec<u8>, caption: &str) {
+        let form = multipart::Form::new()
+            .text("chat_id", dest.chat_id.clone())
+            .text("caption", caption.to_string())
+            .text("parse_mode", "HTML");
+        let form = match dest.topic_id {
+            Some(tid) => form.text("message_thread_id", tid.to_string()),
+            None => form,
+        };
+        let form = form.part(
+            "document",
+            multipart::Part::bytes(data).file_name(name.to_owned()),
+        );
+        self.post(
+            "sendDocument",
+            self.client.post(self.url("sendDocument")).multipart(form),
+        );
+    }
+}
+
+fn bot(cfg: Option<&TelegramConfig>) -> Result<Bot> {
+    let token_var = cfg
+        .and_then(|c| c.bot_token_env.as_deref())
+        .unwrap_or("TELEGRAM_BOT_TOKEN");
+    Ok(Bot {
+        client: Client::new(),
+        token: secrets::get(token_var, "Telegram bot token")?,
+    })
+}
+
+/// The project's own chat (`chat_id` or TELEGRAM_CHAT_ID), if any.
+fn own_destination(cfg: Option<&TelegramConfig>) -> Option<Destination> {
+    cfg.and_then(|c| c.chat_id.clone())
+        .or_else(|| env::var("TELEGRAM_CHAT_ID").ok())
+        .map(|chat_id| Destination {
+            chat_id,
+            topic_id: cfg.and_then(|c| c.topic_id),
+        })
+}
+
+/// The `_globals` channels plus the project's own chat (or TELEGRAM_CHAT_ID).
+fn destinations(
+    globals: &GlobalConfig,
+    cfg: Option<&TelegramConfig>,
+    tag: &str,
+) -> Vec<Destination> {
+    let mut destinations = Vec::new();
+    if let Some(chan) = &globals.broadcast_channel {
+        destinations.push(Destination {
+            chat_id: chan.clone(),
+            topic_id: None,
+        });
+    }
+    if tag.contains("ReSuki")
+        && let Some(chat) = &globals.resukisu_chat_id
+    {
+        destinations.push(Destination {
+            chat_id: chat.clone(),
+            topic_id: globals.resukisu_topic_id,
+        });
+    }
+    if let Some(own) = own_destination(cfg)
+        && !destinations.iter().any(|d| d.chat_id == own.chat_id)
+    {
+        destinations.push(own);
+    }
+    destinations
+}
+
+/// Posts the release message and, unless `upload_files` is off, the files.
+pub fn send_release(
+    globals: &GlobalConfig,
+    cfg: Option<&TelegramConfig>,
+    event: &ReleaseEvent,
+) -> Result<()> {
+    let destinations = destinations(globals, cfg, event.tag);
+    if destinations.is_empty() {
+        info!("No Telegram destinations.");
+        return Ok(());
+    }
+    let bot = bot(cfg)?;
+
+    let stats = event
+        .build_stats
+        .map(|s| format!("\n<b>构建 (Build):</b> {}", s))
+        .unwrap_or_default();
+    let sha256 = event
+        .zip_sha256
+        .map(|s| format!("\n<b>SHA-256:</b> <code>{}</code>", s))
+        .unwrap_or_default();
+    let msg = format!(
+        "兄长大人，快看！<code>{}</code> 有新的 Release 了哦。\n\n<b>版本 (Version):</b> <code>{}</code>\n<b>标题 (Title):</b> {}\n<b>作者 (Author):</b> {}{}{}\n\n总之，快去看看吧！ <a href='{}'>点击这里跳转</a>",
+        event.repo, event.tag, event.title, event.author, stats, sha256, event.url
+    );
+    for dest in &destinations {
+        bot.send_message(dest, &msg);
+    }
+
+    if !cfg.and_then(|c| c.upload_files).unwrap_or(true) {
+        return Ok(());
+    }
+    for file in event.files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        if fs::metadata(file)?.len() > MAX_FILE_SIZE {
+            info!("Skipping {} (too large)", name);
+            continue;
+        }
+        let data = fs::read(file)?;
+        let caption = format!(
+            "兄长大人，附件来了。\n<b>仓库 (Repo):</b> <code>{}</code>\n<b>版本 (Version):</b> <code>{}</code>\n\n📄 <b>文件 (File):</b> <code>{}</code>",
+            event.repo, event.tag, name
+        );
+        for dest in &destinations {
+            bot.send_document(dest, &name, data.clone(), &caption);
+        }
+    }
+    Ok(())
+}
+
+/// Posts a failure report to the project's own chat only; the public
+/// broadcast channels just get releases.
+pub fn send_failure(cfg: Option<&TelegramConfig>, event: &FailureEvent) -> Result<()> {
+    let Some(dest) = own_destination(cfg) else {
+        return Ok(());
+    };
+    let mut msg = format!(
+        "❌ <b>构建失败 (Build failed)</b>\n\n<b>仓库 (Repo):</b> <code>{}</code>\n<b>项目 (Project):</b> <code>{}</code> / <code>{}</code>\n<b>阶段 (Stage):</b> {}\n<b>错误 (Error):</b> <code>{}</code>",
+        event.repo,
+        event.project,
+        event.branch,
+        event.stage.unwrap_or("setup"),
+        escape_html(event.error)
+    );
+    if let Some(command) = event.command {
+        msg.push_str(&format!(
+            "\n<b>命令 (Command):</b> <code>{}</code>",
+            escape_html(command)
+        ));
+    }
+    if let Some(url) = event.run_url {
+        msg.push_str(&format!("\n<a href='{}'>CI log</a>", url));
+    }
+    let tail = event.tail_within(MAX_TAIL_CHARS);
+    if !tail.is_empty() {
+        msg.push_str(&format!("\n\n<pre>{}</pre>", escape_html(&tail)));
+    }
+    let bot = bot(cfg)?;
+    bot.send_message(&dest, &msg);
+    if let Some(log) = event.log
+        && fs::metadata(log).is_ok_and(|m| m.len() <= MAX_FILE_SIZE)
+    {
+        let name = format!(
+            "{}-{}-{}",
+            event.project,
+            event.branch,
+            log.file_name().unwrap_or_default().to_string_lossy()
+        );
+        bot.send_document(&dest, &name, fs::read(log)?, "Full log of the failed stage");
+    }
+    Ok(())
+}
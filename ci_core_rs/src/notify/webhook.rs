@@ -0,0 +1,240 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde_json::{Value, json};
+
+use super::{Artifact, FailureEvent, ReleaseEvent};
+use crate::config::WebhookConfig;
+use crate::secrets;
+
+/// Discord allows 25 fields per embed; keep room for the fixed ones.
+const MAX_FILE_FIELDS: usize = 20;
+const DISCORD_COLOR: u32 = 0x5865F2;
+const DISCORD_FAILURE_COLOR: u32 = 0xED4245;
+/// Output excerpt size; Discord descriptions take 4096 characters and Slack
+/// text blocks 3000.
+const MAX_TAIL_CHARS: usize = 2500;
+
+#[derive(Clone, Copy)]
+pub enum Service {
+    Discord,
+    Slack,
+}
+
+impl Service {
+    fn name(self) -> &'static str {
+        match self {
+            Service::Discord => "Discord",
+            Service::Slack => "Slack",
+        }
+    }
+}
+
+fn webhook_url(cfg: &WebhookConfig, service: Service) -> Result<String> {
+    match (&cfg.url, &cfg.url_env) {
+        (Some(url), _) => Ok(url.clone()),
+        (None, Some(var)) => secrets::get(var, &format!("{} webhook", service.name())),
+        (None, None) => Err(anyhow!(
+            "{} notifications need a webhook url or url_env",
+            service.name()
+        )),
+    }
+}
+
+/// Facts shown by both services, as (label, value) pairs.
+fn summary(event: &ReleaseEvent) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("Tag", format!("`{}`", event.tag))];
+    if let Some(version) = event.kernel_version {
+        fields.push(("Kernel", version.to_string()));
+    }
+    if let Some(variant) = event.variant {
+        fields.push(("Variant", variant.to_string()));
+    }
+    if let Some(stats) = event.build_stats {
+        fields.push(("Build", stats.to_string()));
+    }
+    fields
+}
+
+fn discord_payload(event: &ReleaseEvent, artifacts: &[Artifact]) -> Value {
+    let mut fields: Vec<Value> = summary(event)
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+    for artifact in artifacts.iter().take(MAX_FILE_FIELDS) {
+        fields.push(json!({
+            "name": artifact.name,
+            "value": format!("{}\nSHA-256 `{}`", artifact.size_label(), artifact.sha256),
+        }));
+    }
+    json!({
+        "embeds": [{
+            "title": event.title,
+            "url": event.url,
+            "description": format!("New release of `{}`", event.repo),
+            "color": DISCORD_COLOR,
+            "fields": fields,
+            "footer": { "text": format!("by {}", event.author) },
+        }]
+    })
+}
+
+fn slack_payload(event: &ReleaseEvent, artifacts: &[Artifact]) -> Value {
+    let facts: Vec<Value> = summary(event)
+        .into_iter()
+        .map(|(name, value)| json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", name, value) }))
+        .collect();
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": event.title },
+        }),
+        json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!("New release of `{}` by {}", event.repo, event.author),
+            },
+            "fields": facts,
+        }),
+    ];
+    for artifact in artifacts {
+        blocks.push(json!({
+            "type": "section",
+            "text": {
+                "type": "mrkdwn",
+                "text": format!(
+                    "*{}* ({})\nSHA-256 `{}`",
+                    artifact.name,
+                    artifact.size_label(),
+                    artifact.sha256
+                ),
+            },
+        }));
+    }
+    blocks.push(json!({
+        "type": "actions",
+        "elements": [{
+            "type": "button",
+            "text": { "type": "plain_text", "text": "Open release" },
+            "url": event.url,
+        }],
+    }));
+    json!({
+        "text": format!("{} released: {}", event.tag, event.url),
+        "blocks": blocks,
+    })
+}
+
+fn failure_facts(event: &FailureEvent) -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "Project",
+            format!("`{}` / `{}`", event.project, event.branch),
+        ),
+        ("Stage", event.stage.unwrap_or("setup").to_string()),
+    ]
+}
+
+/// The error and, if there is one, the failed command's output tail.
+fn failure_text(event: &FailureEvent) -> String {
+    let mut text = format!("**Error:** `{}`", event.error);
+    let tail = event.tail_within(MAX_TAIL_CHARS);
+    if !tail.is_empty() {
+        if let Some(command) = event.command {
+            text.push_str(&format!("\n**Output of** `{}`", command));
+        }
+        text.push_str(&format!("\n```\n{}\n```", tail.replace("```", "'''")));
+    }
+    text
+}
+
+fn discord_failure_payload(event: &FailureEvent) -> Value {
+    let fields: Vec<Value> = failure_facts(event)
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "inline": true }))
+        .collect();
+    let mut embed = json!({
+        "title": format!("Build failed: {}", event.repo),
+        "description": failure_text(event),
+        "color": DISCORD_FAILURE_COLOR,
+        "fields": fields,
+    });
+    if let Some(url) = event.run_url {
+        embed["url"] = url.into();
+    }
+    json!({ "embeds": [embed] })
+}
+
+fn slack_failure_payload(event: &FailureEvent) -> Value {
+    let facts: Vec<Value> = failure_facts(event)
+        .into_iter()
+        .map(|(name, value)| json!({ "type": "mrkdwn", "text": format!("*{}*\n{}", name, value) }))
+        .collect();
+    // Slack's mrkdwn uses single asterisks for bold.
+    let text = failure_text(event).replace("**", "*");
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": format!("Build failed: {}", event.repo) },
+        }),
+        json!({ "type": "section", "fields": facts }),
+        json!({ "type": "section", "text": { "type": "mrkdwn", "text": text } }),
+    ];
+    if let Some(url) = event.run_url {
+        blocks.push(json!({
+            "type": "actions",
+            "elements": [{
+                "type": "button",
+                "text": { "type": "plain_text", "text": "Open CI log" },
+                "url": url,
+            }],
+        }));
+    }
+    json!({
+        "text": format!("Build of {} / {} failed", event.project, event.branch),
+        "blocks": blocks,
+    })
+}
+
+fn post(service: Service, url: &str, payload: &Value, what: &str) {
+    match Client::new().post(url).json(payload).send() {
+        Ok(resp) if resp.status().is_success() => {
+            info!("Posted {} to {}", what, service.name());
+        }
+        Ok(resp) => warn!(
+            "{} webhook failed: HTTP {}: {}",
+            service.name(),
+            resp.status(),
+            resp.text().unwrap_or_default().trim()
+        ),
+        Err(e) => warn!("{} webhook failed: {}", service.name(), e),
+    }
+}
+
+pub fn send_failure(service: Service, cfg: &WebhookConfig, event: &FailureEvent) -> Result<()> {
+    let url = webhook_url(cfg, service)?;
+    let payload = match service {
+        Service::Discord => discord_failure_payload(event),
+        Service::Slack => slack_failure_payload(event),
+    };
+    post(service, &url, &payload, "failure report");
+    Ok(())
+}
+
+/// Posts the release to a Discord or Slack webhook. A rejected post is
+/// only warned about; a missing URL is a config error.
+pub fn send_release(
+    service: Service,
+    cfg: &WebhookConfig,
+    event: &ReleaseEvent,
+    artifacts: &[Artifact],
+) -> Result<()> {
+    let url = webhook_url(cfg, service)?;
+    let payload = match service {
+        Service::Discord => discord_payload(event, artifacts),
+        Service::Slack => slack_payload(event, artifacts),
+    };
+    post(service, &url, &payload, "release");
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use chrono::Local;
+use log::{Level, log_enabled, warn};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::progress;
+use crate::secrets;
+use crate::utils::get_root_dir;
+use crate::warnings::Warning;
+
+/// Lines of a failed command kept for failure reports.
+const TAIL_LINES: usize = 50;
+
+/// Output of the command that is running, then of the last one that failed.
+static CURRENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+static FAILED: Mutex<Option<Vec<String>>> = Mutex::new(None);
+/// This build's log dir, and the log file of the stage that is running.
+static LOG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static LOG: Mutex<Option<(PathBuf, File)>> = Mutex::new(None);
+/// Compiler and linker warnings seen since the last reset, without repeats
+/// (a warning in a header shows up once per file including it).
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+/// Forgets earlier failures, e.g. when a new build starts.
+pub fn reset() {
+    CURRENT.lock().unwrap().clear();
+    *FAILED.lock().unwrap() = None;
+    WARNINGS.lock().unwrap().clear();
+    *LOG_DIR.lock().unwrap() = None;
+    *LOG.lock().unwrap() = None;
+}
+
+/// Starts `logs/<project>/<timestamp>-<branch>/`; each stage's command
+/// output is written to `<stage>.log` in it.
+pub fn start_logs(project: &str, branch: &str) {
+    let dir = get_root_dir().join("logs").join(project).join(format!(
+        "{}-{}",
+        Local::now().format("%Y%m%d-%H%M%S"),
+        branch
+    ));
+    match fs::create_dir_all(&dir) {
+        Ok(()) => *LOG_DIR.lock().unwrap() = Some(dir),
+        Err(e) => warn!("Failed to create log dir {}: {}", dir.display(), e),
+    }
+}
+
+pub fn log_dir() -> Option<PathBuf> {
+    LOG_DIR.lock().unwrap().clone()
+}
+
+/// Sends command output to `<stage>.log` from now on.
+pub fn log_stage(stage: &str) {
+    let Some(dir) = log_dir() else {
+        return;
+    };
+    let path = dir.join(format!("{}.log", stage));
+    let file = OpenOptions::new().create(true).append(true).open(&path);
+    *LOG.lock().unwrap() = match file {
+        Ok(file) => Some((path, file)),
+        Err(e) => {
+            warn!("Failed to open {}: {}", path.display(), e);
+            None
+        }
+    };
+}
+
+/// Stops logging and returns the log file of the stage that ran last.
+pub fn close_log() -> Option<PathBuf> {
+    LOG.lock().unwrap().take().map(|(path, _)| path)
+}
+
+pub fn start_command(command: &str) {
+    CURRENT.lock().unwrap().clear();
+    if let Some((_, file)) = LOG.lock().unwrap().as_mut() {
+        let _ = writeln!(file, "$ {}", secrets::redact(command));
+    }
+}
+
+/// Copies `reader` line by line to stdout or stderr, remembering the tail.
+pub fn tee(reader: impl Read, to_stderr: bool) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    while let Ok(n) = reader.read_until(b'\n', &mut line) {
+        if n == 0 {
+            break;
+        }
+        let shown = secrets::redact_bytes(&line);
+        // With -q the output only goes to the log and the failure tail.
+        if log_enabled!(Level::Info) {
+            progress::suspend(|| {
+                if to_stderr {
+                    let _ = io::stderr().write_all(&shown);
+                } else {
+                    let _ = io::stdout().write_all(&shown);
+                }
+            });
+        }
+        if let Some((_, file)) = LOG.lock().unwrap().as_mut() {
+            let _ = file.write_all(&shown);
+        }
+        let text = String::from_utf8_lossy(&shown).trim_end().to_string();
+        progress::make_line(&text);
+        if let Some(warning) = Warning::parse(&text) {
+            let mut warnings = WARNINGS.lock().unwrap();
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        let mut tail = CURRENT.lock().unwrap();
+        if tail.len() == TAIL_LINES {
+            tail.pop_front();
+        }
+        tail.push_back(text);
+        line.clear();
+    }
+}
+
+pub fn warnings() -> Vec<Warning> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+/// Removes and returns the warnings seen after the first `from`.
+pub fn take_warnings_from(from: usize) -> Vec<Warning> {
+    let mut warnings = WARNINGS.lock().unwrap();
+    let from = from.min(warnings.len());
+    warnings.split_off(from)
+}
+
+/// Keeps the tail of the command that just exited unsuccessfully.
+pub fn command_failed() {
+    let tail = CURRENT.lock().unwrap().iter().cloned().collect();
+    *FAILED.lock().unwrap() = Some(tail);
+}
+
+/// The tail of the last command that failed.
+pub fn failed_output() -> Vec<String> {
+    FAILED.lock().unwrap().clone().unwrap_or_default()
+}
@@ -0,0 +1,273 @@
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{CheckpatchConfig, PatchSpec};
+use crate::retry;
+use crate::toolchain::sha256_file;
+use crate::utils::{find_local_file, get_root_dir, run_cmd};
+
+const DOWNLOAD_NAME: &str = "kokuban-download.patch";
+
+/// A patch a build applied, as recorded in its provenance.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AppliedPatch {
+    pub src: String,
+    pub sha256: String,
+}
+
+/// Applies the patches whose `branches` condition matches any of `names`
+/// (the branch as given and its canonical variant name), in order.
+pub fn apply_patches(
+    patches: &[PatchSpec],
+    names: &[&str],
+    kernel_source: &Path,
+) -> Result<Vec<AppliedPatch>> {
+    patches
+        .iter()
+        .filter(|p| p.applies_to(names))
+        .map(|spec| apply(spec, kernel_source))
+        .collect()
+}
+
+/// The `*.patch` files in `patches/<project>/<name>/` under the CI root for
+/// each of `names`, in lexical order within each directory.
+pub fn directory_patches(project_key: &str, names: &[&str]) -> Result<Vec<PatchSpec>> {
+    let mut specs = Vec::new();
+    let mut seen = Vec::new();
+    for name in names {
+        if seen.contains(name) {
+            continue;
+        }
+        seen.push(*name);
+        let rel = Path::new("patches").join(project_key).join(name);
+        let dir = get_root_dir().join(&rel);
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut files: Vec<String> = fs::read_dir(&dir)?
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|f| f.ends_with(".patch"))
+            .collect();
+        files.sort();
+        specs.extend(
+            files
+                .iter()
+                .map(|f| PatchSpec::new(&rel.join(f).to_string_lossy())),
+        );
+    }
+    Ok(specs)
+}
+
+pub fn apply(spec: &PatchSpec, kernel_source: &Path) -> Result<AppliedPatch> {
+    info!("   - Applying patch {}...", spec.src);
+    let (patch_file, downloaded) = fetch(spec, kernel_source)?;
+    let sha256 = sha256_file(&patch_file)?;
+
+    let strip = format!("-p{}", spec.strip);
+    let fuzz = format!("--fuzz={}", spec.fuzz);
+    let result = run_cmd(
+        &[
+            "patch",
+            &strip,
+            &fuzz,
+            "--forward",
+            "--batch",
+            "-i",
+            &patch_file.to_string_lossy(),
+        ],
+        Some(kernel_source),
+        false,
+    );
+    if downloaded {
+        let _ = fs::remove_file(&patch_file);
+    }
+    result.with_context(|| format!("Patch {} failed to apply", spec.src))?;
+    Ok(AppliedPatch {
+        src: spec.src.clone(),
+        sha256,
+    })
+}
+
+/// Runs each patch with `--dry-run` against the untouched tree and prints a
+/// single report of the hunks that would fail. Patches are checked on their
+/// own, so one that depends on an earlier patch may show false conflicts.
+pub fn check_patches(patches: &[PatchSpec], kernel_source: &Path) -> Result<()> {
+    let mut conflicts: Vec<(&str, Vec<String>)> = Vec::new();
+    for spec in patches {
+        let (patch_file, downloaded) = fetch(spec, kernel_source)?;
+        let output = Command::new("patch")
+            .args([
+                &format!("-p{}", spec.strip),
+                &format!("--fuzz={}", spec.fuzz),
+                "--dry-run",
+                "--forward",
+                "--batch",
+                "-i",
+                &patch_file.to_string_lossy(),
+            ])
+            .current_dir(kernel_source)
+            .stdin(Stdio::null())
+            .output()?;
+        if downloaded {
+            let _ = fs::remove_file(&patch_file);
+        }
+
+        if output.status.success() {
+            info!("   ✅ {}", spec.src);
+            continue;
+        }
+        info!("   ❌ {}", spec.src);
+
+        let mut current_file = String::new();
+        let mut failures = Vec::new();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(file) = line
+                .strip_prefix("checking file ")
+                .or_else(|| line.strip_prefix("patching file "))
+            {
+                current_file = file.to_string();
+            } else if line.starts_with("Hunk #") && line.contains("FAILED")
+                || line.contains("Reversed (or previously applied)")
+                || line.starts_with("can't find file")
+                || line.contains("malformed patch")
+            {
+                failures.push(format!("{}: {}", current_file, line.trim()));
+            }
+        }
+        if failures.is_empty() {
+            failures.push(String::from_utf8_lossy(&output.stderr).trim().to_string());
+        }
+        conflicts.push((&spec.src, failures));
+    }
+
+    if conflicts.is_empty() {
+        info!("All {} patch(es) apply cleanly.", patches.len());
+        return Ok(());
+    }
+
+    info!("\n=== Patch conflict report ===");
+    for (src, failures) in &conflicts {
+        info!("{}", src);
+        for failure in failures {
+            info!("  - {}", failure);
+        }
+    }
+    Err(anyhow!(
+        "{} of {} patch(es) would not apply cleanly",
+        conflicts.len(),
+        patches.len()
+    ))
+}
+
+const CHECKPATCH: &str = "scripts/checkpatch.pl";
+
+/// Messages listed per patch; the rest are only counted.
+const MAX_LISTED_MESSAGES: usize = 20;
+
+/// Runs the kernel's checkpatch.pl over each patch and reports its errors
+/// and warnings. With `errors_fatal`, any error fails.
+pub fn checkpatch(
+    cfg: &CheckpatchConfig,
+    patches: &[PatchSpec],
+    kernel_source: &Path,
+) -> Result<()> {
+    if patches.is_empty() {
+        return Ok(());
+    }
+    if !kernel_source.join(CHECKPATCH).exists() {
+        warn!("{} not found, skipping checkpatch.", CHECKPATCH);
+        return Ok(());
+    }
+    let mut args = vec![
+        CHECKPATCH.to_string(),
+        "--no-tree".to_string(),
+        "--terse".to_string(),
+        "--no-summary".to_string(),
+        "--show-types".to_string(),
+        "--color=never".to_string(),
+    ];
+    if !cfg.ignore.is_empty() {
+        args.push(format!("--ignore={}", cfg.ignore.join(",")));
+    }
+
+    let mut total_errors = 0;
+    for spec in patches {
+        let (patch_file, downloaded) = fetch(spec, kernel_source)?;
+        // checkpatch exits 1 whenever it has something to say; the counts
+        // below are what matters.
+        let output = Command::new("perl")
+            .args(&args)
+            .arg(&patch_file)
+            .current_dir(kernel_source)
+            .stdin(Stdio::null())
+            .output()?;
+        if downloaded {
+            let _ = fs::remove_file(&patch_file);
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let messages: Vec<&str> = stdout
+            .lines()
+            .filter(|l| l.contains(": ERROR:") || l.contains(": WARNING:"))
+            .collect();
+        let errors = messages.iter().filter(|l| l.contains(": ERROR:")).count();
+        let warnings = messages.len() - errors;
+        total_errors += errors;
+        if messages.is_empty() {
+            info!("   ✅ {}", spec.src);
+            continue;
+        }
+        info!(
+            "   {} {}: {} error(s), {} warning(s)",
+            if errors > 0 { "❌" } else { "⚠️" },
+            spec.src,
+            errors,
+            warnings
+        );
+        let prefix = format!("{}:", patch_file.display());
+        for message in messages.iter().take(MAX_LISTED_MESSAGES) {
+            // --terse prefixes the patch file; keep the line number.
+            let message = message.strip_prefix(prefix.as_str()).unwrap_or(message);
+            info!("      line {}", message);
+        }
+        if messages.len() > MAX_LISTED_MESSAGES {
+            info!(
+                "      ... and {} more",
+                messages.len() - MAX_LISTED_MESSAGES
+            );
+        }
+    }
+
+    if total_errors > 0 && cfg.errors_fatal {
+        return Err(anyhow!(
+            "checkpatch found {} error(s) in the project's patches",
+            total_errors
+        ));
+    }
+    Ok(())
+}
+
+/// Returns an absolute path to the patch file, downloading URLs into the
+/// kernel source. Local paths are looked up under the CI root first, then
+/// the kernel source.
+fn fetch(spec: &PatchSpec, kernel_source: &Path) -> Result<(PathBuf, bool)> {
+    if spec.is_url() {
+        let dest = fs::canonicalize(kernel_source)?.join(DOWNLOAD_NAME);
+        retry::run("patch download", || {
+            run_cmd(
+                &["curl", "-fL", "-o", &dest.to_string_lossy(), &spec.src],
+                None,
+                false,
+            )
+        })?;
+        return Ok((dest, true));
+    }
+
+    let path = find_local_file(&spec.src, kernel_source)
+        .with_context(|| format!("Patch {} not found", spec.src))?;
+    Ok((path, false))
+}
@@ -0,0 +1,489 @@
+use anyhow::{Result, anyhow};
+use std::path::{Path, PathBuf};
+
+use crate::arch::resolve_arch;
+use crate::build::{BuildOptions, config_edits};
+use crate::compiler_cache::CompilerCache;
+use crate::config::{PatchSpec, ProjectConfig, ProjectsMap};
+use crate::kconfig::config_args;
+use crate::ksu::{config_fragment, load_ksu_variants, resolve_variant, variant_label};
+use crate::patch::directory_patches;
+use crate::toolchain::cached_toolchain;
+use crate::utils::git_reference_args;
+
+/// Quotes an argument the way it would have to be typed in a shell.
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn command<S: AsRef<str>>(cmd: &[S]) {
+    let line: Vec<String> = cmd.iter().map(|a| quote(a.as_ref())).collect();
+    println!("  $ {}", line.join(" "));
+}
+
+/// `make [extra] <make_args> [tail]`, in the order the build passes them.
+fn make(extra: &[&str], make_args: &[String], tail: &[&str]) {
+    let mut cmd: Vec<&str> = vec!["make"];
+    cmd.extend_from_slice(extra);
+    cmd.extend(make_args.iter().map(|a| a.as_str()));
+    cmd.extend_from_slice(tail);
+    command(&cmd);
+}
+
+fn stage(name: &str) {
+    println!("\n[{}]", name);
+}
+
+fn patch(spec: &PatchSpec) {
+    command(&[
+        "patch".to_string(),
+        format!("-p{}", spec.strip),
+        format!("--fuzz={}", spec.fuzz),
+        "-i".to_string(),
+        spec.src.clone(),
+    ]);
+}
+
+/// `build --dry-run`: resolves the config the way `build` does and prints
+/// each stage's commands in order without running them or touching any
+/// files. Values only known after a step has run are shown as `<...>`.
+pub fn print_plan(
+    projects: &ProjectsMap,
+    project_key: &str,
+    branch: &str,
+    proj: &ProjectConfig,
+    kernel_source_path: &Path,
+    opts: &BuildOptions,
+    out_dir: &str,
+) -> Result<()> {
+    let arch = resolve_arch(proj.arch.as_deref())?;
+    let use_gcc = match proj.compiler.as_deref().unwrap_or("clang") {
+        "clang" => false,
+        "gcc" => true,
+        other => {
+            return Err(anyhow!(
+                "Unknown compiler '{}' (expected clang or gcc)",
+                other
+            ));
+        }
+    };
+    let output_dir = opts
+        .out_dir
+        .clone()
+        .or_else(|| proj.output_dir.as_ref().map(PathBuf::from));
+    let artifacts_dir = output_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let out_arg = match &output_dir {
+        Some(dir) => dir.join(out_dir).display().to_string(),
+        None => out_dir.to_string(),
+    };
+    let ksu_variants = load_ksu_variants(projects)?;
+    let variant = resolve_variant(&ksu_variants, branch);
+
+    println!("Dry run: {} / {}", project_key, branch);
+    println!("  kernel source: {}", kernel_source_path.display());
+    println!("  arch:          {}", arch.kernel_arch);
+    println!("  compiler:      {}", if use_gcc { "gcc" } else { "clang" });
+    println!("  build dir:     {}", out_arg);
+    println!("  artifacts:     {}", artifacts_dir.display());
+    if opts.incremental {
+        println!(
+            "  --incremental: integration and defconfig are skipped if the last build can be reused"
+        );
+    }
+
+    stage("toolchain");
+    match (&proj.toolchain_urls, cached_toolchain(proj)) {
+        (None, _) => println!("  use the host toolchain on PATH"),
+        (Some(_), Some(cached)) if !opts.refresh_toolchain => {
+            println!("  use cached toolchain {}", cached.root.display())
+        }
+        (Some(urls), _) => {
+            let sums = proj.toolchain_sha256.as_deref().unwrap_or_default();
+            for (i, url) in urls.iter().enumerate() {
+                match sums.get(i) {
+                    Some(sum) => println!("  download {} (sha256 {})", url, sum),
+                    None => println!("  download {}", url),
+                }
+            }
+            println!("  extract into the toolchain cache");
+        }
+    }
+    let prefix = proj.toolchain_path_prefix.as_deref().unwrap_or("");
+    for export in proj.toolchain_path_exports.iter().flatten() {
+        println!(
+            "  PATH += <toolchain>/{}",
+            Path::new(prefix).join(export).display()
+        );
+    }
+    if opts.locked {
+        println!("  check the toolchain against toolchain.lock");
+    }
+
+    stage("integrate");
+    if !opts.keep_source {
+        println!("  snapshot kernel_source so it can be rolled back");
+    }
+    match variant {
+        Some((name, v)) => {
+            println!("  KernelSU variant: {}", name);
+            if let (Some(url), Some(args)) = (
+                v.setup_url_for(proj.ksu_ref.as_deref()),
+                &v.build_setup_args,
+            ) {
+                // Run through `bash -c`, so shown as the shell line it is.
+                println!("  $ curl -fLSs '{}' | bash -s {}", url, args.join(" "));
+            }
+            match (&proj.susfs_branch, v.susfs_branch.as_deref()) {
+                (_, None) => {}
+                (Some(b), _) => println!("  apply SUSFS from {}", b),
+                (None, Some("auto")) => {
+                    println!("  apply SUSFS from the branch matching the kernel version")
+                }
+                (None, Some(b)) => println!("  apply SUSFS from {}", b),
+            }
+            if let Some(url) = v.manual_hook_url_for(proj) {
+                println!("  apply manual hook {}", url);
+            }
+            for p in &v.patches {
+                patch(&PatchSpec::new(p));
+            }
+        }
+        None => println!("  no KernelSU variant named '{}'", branch),
+    }
+    let variant_name = variant.map_or(branch, |(name, _)| name);
+    let names = [branch, variant_name];
+    for spec in proj
+        .patches
+        .iter()
+        .flatten()
+        .filter(|p| p.applies_to(&names))
+    {
+        patch(spec);
+    }
+    let specs = directory_patches(project_key, &names)?;
+    if let Some(cfg) = &proj.checkpatch
+        && !specs.is_empty()
+    {
+        println!(
+            "  checkpatch.pl on {} patch(es){}",
+            specs.len(),
+            if cfg.errors_fatal {
+                ", errors are fatal"
+            } else {
+                ""
+            }
+        );
+    }
+    for spec in &specs {
+        patch(spec);
+    }
+    command(&["make", "kernelversion"]);
+
+    stage("configure");
+    let mut make_args = vec![
+        format!("O={}", out_arg),
+        format!("ARCH={}", arch.kernel_arch),
+    ];
+    if use_gcc {
+        make_args.push(format!("CROSS_COMPILE={}", arch.cross_compile));
+    } else {
+        make_args.extend(["LLVM=1".to_string(), "LLVM_IAS=1".to_string()]);
+    }
+    make_args.push(format!(
+        "TARGET_SOC={}",
+        project_key.split('_').nth(1).unwrap_or("unknown")
+    ));
+    let cc = if use_gcc {
+        format!("{}gcc", arch.cross_compile)
+    } else {
+        "clang".to_string()
+    };
+    let distributed = proj
+        .distcc
+        .as_ref()
+        .map(|cfg| cfg.tool.as_deref().unwrap_or("distcc"));
+    make_args.push(
+        match (
+            CompilerCache::resolve(proj.compiler_cache.as_deref())?,
+            distributed,
+        ) {
+            (Some(cache), _) => format!("CC={} {}", cache.name(), cc),
+            (None, Some(wrapper)) => format!("CC={} {}", wrapper, cc),
+            (None, None) => format!("CC={}", cc),
+        },
+    );
+    if let Some(kcflags) = &proj.kcflags {
+        make_args.push(format!("KCFLAGS={}", kcflags));
+    }
+    if let Some(kldflags) = &proj.kldflags {
+        make_args.push(format!("LDFLAGS_MODULE={}", kldflags));
+    }
+    make_args.extend(proj.extra_make_args.iter().flatten().cloned());
+
+    make(&[], &make_args, &[&proj.defconfig]);
+    let variant_options = variant.map_or(0, |(_, v)| config_fragment(v).len());
+    if variant_options > 0 {
+        println!(
+            "  merge {} option(s) of the KernelSU variant",
+            variant_options
+        );
+    }
+    for path in proj.config_fragments.iter().flatten() {
+        println!("  merge config fragment {}", path);
+    }
+    let config_file = format!("{}/.config", out_arg);
+    for edit in &config_edits(proj, use_gcc) {
+        let mut cmd = vec![
+            "scripts/config".to_string(),
+            "--file".to_string(),
+            config_file.clone(),
+        ];
+        cmd.extend(config_args(edit));
+        command(&cmd);
+    }
+    make(&[], &make_args, &["olddefconfig"]);
+    println!(
+        "  write {}/config.diff: what changed from {}",
+        out_arg, proj.defconfig
+    );
+    if let Some(signing) = &proj.module_signing {
+        let source = match (&signing.key_env, &signing.key) {
+            (Some(name), _) => format!("${}", name),
+            (None, Some(key)) => key.clone(),
+            (None, None) => "the project's generated key".to_string(),
+        };
+        println!("  install {} as {}/certs/signing_key.pem", source, out_arg);
+    }
+
+    let variant_suffix = variant_label(&ksu_variants, branch);
+    let localversion = format!("{}-{}", proj.localversion_base, variant_suffix);
+    if proj.version_method.as_deref().unwrap_or("param") == "file" {
+        println!(
+            "  write {}-g<sha> to kernel_source/localversion",
+            localversion
+        );
+    } else {
+        make_args.push("LOCALVERSION=".to_string());
+        println!("  LOCALVERSION={}", localversion);
+    }
+
+    stage("build");
+    let jobs = match opts.resources.jobs.or(proj.jobs) {
+        Some(n) => n,
+        None => {
+            let nproc = std::thread::available_parallelism().map_or(1, |n| n.get() as u32);
+            nproc
+                * proj
+                    .distcc
+                    .as_ref()
+                    .map_or(1, |cfg| cfg.jobs_multiplier.unwrap_or(2))
+        }
+    };
+    let mut parallel = vec![format!("-j{}", jobs)];
+    if let Some(load) = opts.resources.load_average.or(proj.load_average) {
+        parallel.push(format!("-l{}", load));
+    }
+    let parallel: Vec<&str> = parallel.iter().map(|a| a.as_str()).collect();
+    let targets: Vec<&str> = proj
+        .make_targets
+        .iter()
+        .flatten()
+        .map(|t| t.as_str())
+        .collect();
+    make(&parallel, &make_args, &targets);
+    if proj.dtb.is_some() {
+        make(&parallel, &make_args, &["dtbs"]);
+    }
+    if let Some(modules) = &proj.modules {
+        let install = format!("INSTALL_MOD_PATH={}/modules_install", out_arg);
+        let mut dirs = vec![None];
+        dirs.extend(modules.external.iter().map(|d| Some(format!("M={}", d))));
+        for dir in &dirs {
+            let dir: Vec<&str> = dir.as_deref().into_iter().collect();
+            make(
+                &parallel,
+                &make_args,
+                &[dir.as_slice(), &["modules"]].concat(),
+            );
+            let mut tail = dir.clone();
+            tail.push(&install);
+            if modules.strip {
+                tail.push("INSTALL_MOD_STRIP=1");
+            }
+            tail.push("modules_install");
+            make(&[], &make_args, &tail);
+        }
+    }
+    if let Some(max) = proj.max_new_warnings {
+        println!("  fail on more than {} new compiler warning(s)", max);
+    }
+    println!("  report image and section sizes, bloat-o-meter against the last build");
+    if let Some(max) = proj.max_image_size {
+        println!("  fail if the kernel image is over {} bytes", max);
+    }
+
+    if let Some(analyze) = &proj.analyze {
+        stage("analyze");
+        println!(
+            "  {} on {}",
+            analyze.tool.as_deref().unwrap_or("sparse"),
+            if analyze.whole_tree {
+                "the whole tree"
+            } else {
+                "the files integration changed"
+            }
+        );
+        if let Some(max) = analyze.max_new_findings {
+            println!("  fail on more than {} new finding(s)", max);
+        }
+    }
+
+    if let Some(abi_check) = &proj.abi_check {
+        stage("abi_check");
+        let lists = if abi_check.symbol_lists.is_empty() {
+            "android/abi_gki_aarch64".to_string()
+        } else {
+            abi_check.symbol_lists.join(", ")
+        };
+        println!("  check Module.symvers against {}", lists);
+        if let Some(reference) = &abi_check.reference_symvers {
+            println!("  compare listed symbol CRCs with {}", reference);
+        }
+    }
+
+    if let Some(boot_test) = &proj.boot_test {
+        stage("boot_test");
+        println!(
+            "  boot {} in {} and wait up to {}s for \"{}\"",
+            boot_test.kernel_image.as_deref().unwrap_or(arch.image),
+            boot_test.qemu.as_deref().unwrap_or("QEMU"),
+            boot_test.timeout_seconds.unwrap_or(120),
+            boot_test
+                .marker
+                .as_deref()
+                .unwrap_or("Freeing unused kernel")
+        );
+    }
+
+    if let Some(kunit) = &proj.kunit {
+        stage("kunit");
+        let suites = if kunit.suites.is_empty() {
+            "all suites".to_string()
+        } else {
+            kunit.suites.join(", ")
+        };
+        println!(
+            "  kunit.py build and exec ({}, {})",
+            kunit.mode.as_deref().unwrap_or("uml"),
+            suites
+        );
+    }
+
+    stage("package");
+    let ak3_repo = proj
+        .anykernel_repo
+        .as_deref()
+        .unwrap_or("https://github.com/YuzakiKokuban/AnyKernel3.git");
+    let ak3_branch = proj.anykernel_branch.as_deref().unwrap_or("master");
+    let ak3_dir = artifacts_dir.join("AnyKernel3");
+    let mut clone = vec!["git".to_string(), "clone".to_string(), ak3_repo.to_string()];
+    clone.extend(["-b".to_string(), ak3_branch.to_string()]);
+    clone.extend(git_reference_args(
+        proj.git_reference_dir.as_deref(),
+        ak3_repo,
+    ));
+    clone.push(ak3_dir.display().to_string());
+    command(&clone);
+    let default_targets = vec![arch.image.to_string()];
+    for target in proj.make_targets.as_ref().unwrap_or(&default_targets) {
+        println!("  collect {} into AnyKernel3", target);
+    }
+    if let Some(dtb) = &proj.dtb {
+        println!(
+            "  build dtb/dtbo images ({})",
+            dtb.package.as_deref().unwrap_or("zip")
+        );
+    }
+    if let Some(boot_image) = &proj.boot_image {
+        println!("  repack boot.img from {}", boot_image.stock);
+        if proj.avb.is_some() {
+            println!("  sign boot.img with avbtool");
+        }
+    }
+    if let Some(modules) = &proj.modules {
+        println!(
+            "  package modules ({})",
+            modules.package.as_deref().unwrap_or("zip")
+        );
+    }
+    let zip_prefix = proj.zip_name_prefix.as_deref().unwrap_or("Kernel");
+    let zip_name = format!(
+        "{}-<kernel version>-{}-<date>.zip",
+        zip_prefix,
+        localversion.trim_start_matches('-')
+    );
+    println!("  (in {})", ak3_dir.display());
+    if proj.reproducible == Some(true) {
+        println!(
+            "  zip ../{} with sorted entries and SOURCE_DATE_EPOCH timestamps",
+            zip_name
+        );
+    } else {
+        command(&["zip", "-r9", &format!("../{}", zip_name), "."]);
+    }
+    println!("  write {}-SHA256SUMS", zip_name.trim_end_matches(".zip"));
+    println!(
+        "  write {}.intoto.jsonl (SLSA provenance)",
+        zip_name.trim_end_matches(".zip")
+    );
+    if let Some(gpg) = &proj.gpg {
+        println!(
+            "  sign the zip and SHA256SUMS with gpg ({})",
+            gpg.key.as_deref().unwrap_or("default key")
+        );
+    }
+
+    stage("release");
+    if !opts.do_release {
+        println!("  skipped (--do-release false)");
+        return Ok(());
+    }
+    println!(
+        "  tag {}-{}-<date>, title \"{} {} Build (<date>)\"",
+        zip_prefix, variant_suffix, zip_prefix, variant_suffix
+    );
+    let distribution = proj.distribution.clone().unwrap_or_default();
+    if let Some(s3) = &distribution.s3 {
+        println!("  upload to s3://{}", s3.bucket);
+    }
+    if let Some(sftp) = &distribution.sftp {
+        println!("  upload over sftp to {}@{}", sftp.user, sftp.host);
+    }
+    if let Some(rsync) = &distribution.rsync {
+        println!("  upload with rsync to {}@{}", rsync.user, rsync.host);
+    }
+    if distribution.github_release.unwrap_or(true) {
+        println!("  publish a GitHub release on {}", proj.repo);
+        let notify = proj.notify.clone().unwrap_or_default();
+        let backends: Vec<&str> = [
+            ("Telegram chats from _globals and notify.telegram", true),
+            ("matrix", notify.matrix.is_some()),
+            ("email", notify.email.is_some()),
+            ("discord", notify.discord.is_some()),
+            ("slack", notify.slack.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, on)| on.then_some(name))
+        .collect();
+        println!("  notify {}", backends.join(", "));
+    }
+    Ok(())
+}
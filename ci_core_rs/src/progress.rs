@@ -0,0 +1,123 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::{Level, log_enabled, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::artifacts::find_files;
+use crate::utils::get_cache_dir;
+
+/// The bar of the make that is running; `output::tee` prints around it.
+static MAKE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Bars draw on stderr, and only when it's a terminal; `-q` hides them too.
+pub fn draw_target() -> ProgressDrawTarget {
+    if log_enabled!(Level::Info) {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    }
+}
+
+/// Bar over an archive's compressed bytes, with the files unpacked so far
+/// as its prefix.
+pub fn extract_bar(name: &str, len: u64) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(Some(len), draw_target());
+    pb.set_style(
+        ProgressStyle::with_template(
+            "{msg:30!} [{bar:30.cyan/blue}] {percent:>3}% {prefix} ({elapsed})",
+        )
+        .unwrap()
+        .progress_chars("=> "),
+    );
+    pb.set_message(name.to_string());
+    pb
+}
+
+/// Runs `f` with the make bar, if any, cleared off the screen.
+pub fn suspend<T>(f: impl FnOnce() -> T) -> T {
+    let pb = MAKE.lock().unwrap().clone();
+    match pb {
+        Some(pb) => pb.suspend(f),
+        None => f(),
+    }
+}
+
+/// Advances the make bar on kbuild's `  CC      path/file.o` lines.
+pub fn make_line(line: &str) {
+    let mut words = line.split_whitespace();
+    let compiles = line.starts_with("  ")
+        && matches!(words.next(), Some("CC" | "AS"))
+        && words.last().is_some_and(|w| w.ends_with(".o"));
+    if compiles && let Some(pb) = MAKE.lock().unwrap().as_ref() {
+        pb.inc(1);
+    }
+}
+
+/// Progress of the main kernel make. A clean build is measured against the
+/// object count of this project's last build; an incremental one only
+/// counts, as there's no telling how much make will redo.
+pub struct MakeProgress {
+    project: String,
+    out: PathBuf,
+}
+
+impl MakeProgress {
+    pub fn start(project: &str, out: &Path) -> Self {
+        let incremental = !find_files(out, "o").unwrap_or_default().is_empty();
+        let estimate = fs::read_to_string(estimate_path(project))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .filter(|_| !incremental);
+        let pb = ProgressBar::with_draw_target(estimate, draw_target());
+        let template = match estimate {
+            Some(_) => {
+                "{spinner} make [{bar:30.cyan/blue}] {pos}/~{len} objects ({elapsed}, {eta})"
+            }
+            None => "{spinner} make: {pos} objects compiled ({elapsed})",
+        };
+        pb.set_style(
+            ProgressStyle::with_template(template)
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        pb.enable_steady_tick(Duration::from_millis(200));
+        *MAKE.lock().unwrap() = Some(pb);
+        MakeProgress {
+            project: project.to_string(),
+            out: out.to_path_buf(),
+        }
+    }
+
+    /// Remembers how many objects the finished build has for the next
+    /// clean build's estimate.
+    pub fn finish(self) {
+        let count = find_files(&self.out, "o").map(|f| f.len()).unwrap_or(0);
+        if count == 0 {
+            return;
+        }
+        let path = estimate_path(&self.project);
+        let saved = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, count.to_string()));
+        if let Err(e) = saved {
+            warn!("Failed to save {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl Drop for MakeProgress {
+    fn drop(&mut self) {
+        if let Some(pb) = MAKE.lock().unwrap().take() {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+fn estimate_path(project: &str) -> PathBuf {
+    get_cache_dir()
+        .join("make-objects")
+        .join(format!("{}.txt", project))
+}
@@ -0,0 +1,80 @@
+use anyhow::{Result, anyhow};
+
+use crate::config::ProjectConfig;
+use crate::utils::{apply_branch_override, expand_entry, load_projects};
+
+/// Project keys are `<device>_<soc>`; the build passes the SoC part as
+/// TARGET_SOC.
+fn soc(key: &str) -> &str {
+    key.split('_').nth(1).unwrap_or("-")
+}
+
+/// One line per project: key, device, SoC and defconfig.
+pub fn handle_list() -> Result<()> {
+    let projects = load_projects()?;
+    let mut rows = Vec::new();
+    for (key, val) in &projects {
+        if key.starts_with('_') {
+            continue;
+        }
+        let proj: ProjectConfig = serde_json::from_value(val.clone())
+            .map_err(|e| anyhow!("Project '{}' is invalid: {}", key, e))?;
+        let device = proj
+            .readme_placeholders
+            .as_ref()
+            .and_then(|p| p.get("DEVICE_NAME_EN"))
+            .cloned()
+            .unwrap_or_else(|| "-".to_string());
+        rows.push((key.clone(), device, proj.defconfig));
+    }
+    if rows.is_empty() {
+        println!("No projects configured.");
+        return Ok(());
+    }
+    rows.sort();
+    let key_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(3);
+    let device_width = rows
+        .iter()
+        .map(|r| r.1.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+    println!(
+        "{:<key_width$}  {:<device_width$}  {:<8}  DEFCONFIG",
+        "KEY", "DEVICE", "SOC"
+    );
+    for (key, device, defconfig) in &rows {
+        println!(
+            "{:<key_width$}  {:<device_width$}  {:<8}  {}",
+            key,
+            device,
+            soc(key),
+            defconfig
+        );
+    }
+    Ok(())
+}
+
+/// Prints a project's config as the build sees it: `extends` merged,
+/// `${VAR}` expanded and, with `branch`, that branch's overrides applied.
+pub fn handle_show(key: &str, branch: Option<&str>) -> Result<()> {
+    let projects = load_projects()?;
+    let val = projects
+        .get(key)
+        .ok_or_else(|| anyhow!("Project '{}' not found", key))?;
+    let mut resolved = match branch {
+        Some(branch) => expand_entry(key, &apply_branch_override(val, branch))?,
+        None => expand_entry(key, val)?,
+    };
+    // Catch what the build would reject before showing it.
+    serde_json::from_value::<ProjectConfig>(resolved.clone())
+        .map_err(|e| anyhow!("Project '{}' is invalid: {}", key, e))?;
+    if let Some(obj) = resolved.as_object_mut() {
+        obj.remove("extends");
+        if branch.is_some() {
+            obj.remove("branches");
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&resolved)?);
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde_json::{Value, json};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::history::Artifact;
+use crate::patch::AppliedPatch;
+use crate::toolchain::{ResolvedToolchain, sha256_file};
+use crate::utils::run_cmd;
+
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v1";
+const BUILD_TYPE: &str = "https://github.com/YuzakiKokuban/Kokuban_Kernel_CI_Center/build/v1";
+
+/// What went into a build, for its provenance.
+pub struct Inputs<'a> {
+    pub project: &'a str,
+    pub branch: &'a str,
+    pub kernel_source: &'a Path,
+    pub commit: Option<&'a str>,
+    /// KernelSU variant name, its upstream repo and the commit integrated.
+    pub ksu: Option<(&'a str, Option<&'a str>, &'a str)>,
+    pub toolchain: &'a ResolvedToolchain,
+    pub patches: &'a [AppliedPatch],
+    pub config: &'a Path,
+    pub started: DateTime<Utc>,
+}
+
+/// Who ran the build: the GitHub Actions workflow and run, or this binary
+/// on the local host.
+fn builder() -> (String, Option<String>) {
+    let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+    let server = var("GITHUB_SERVER_URL").unwrap_or_else(|| "https://github.com".to_string());
+    if let Some(workflow) = var("GITHUB_WORKFLOW_REF") {
+        let run = var("GITHUB_REPOSITORY")
+            .zip(var("GITHUB_RUN_ID"))
+            .map(|(repo, id)| {
+                let attempt = var("GITHUB_RUN_ATTEMPT").unwrap_or_else(|| "1".to_string());
+                format!(
+                    "{}/{}/actions/runs/{}/attempts/{}",
+                    server, repo, id, attempt
+                )
+            });
+        return (format!("{}/{}", server, workflow), run);
+    }
+    let user = var("USER").unwrap_or_else(|| "unknown".to_string());
+    let host = run_cmd(&["hostname"], None, true)
+        .ok()
+        .flatten()
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|| "localhost".to_string());
+    (format!("local://{}@{}/kokuban_ci_core", user, host), None)
+}
+
+/// A `git+` URI for a clone URL or `owner/name`, without the token clone
+/// URLs may carry.
+fn git_uri(url: &str) -> String {
+    match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let host = host.rsplit('@').next().unwrap_or(host);
+            format!("git+{}://{}/{}", scheme, host, path)
+        }
+        None if url.contains(':') => format!("git+{}", url),
+        None => format!("git+https://github.com/{}", url),
+    }
+}
+
+/// An in-toto resource descriptor; `digest` is (algorithm, value).
+fn descriptor(name: &str, uri: Option<&str>, digest: (&str, &str)) -> Value {
+    let mut value = json!({ "name": name, "digest": { digest.0: digest.1 } });
+    if let Some(uri) = uri {
+        value["uri"] = uri.into();
+    }
+    value
+}
+
+/// Writes `<zip stem>.intoto.jsonl` next to the zip: an in-toto statement
+/// with SLSA v1 provenance for `artifacts`. Returns its path.
+pub fn write(inputs: &Inputs, artifacts: &[Artifact], zip: &Path) -> Result<PathBuf> {
+    let mut dependencies = Vec::new();
+    if let Some(commit) = inputs.commit {
+        let origin = run_cmd(
+            &["git", "remote", "get-url", "origin"],
+            Some(inputs.kernel_source),
+            true,
+        )
+        .ok()
+        .flatten()
+        .map(|url| git_uri(url.trim()));
+        dependencies.push(descriptor(
+            "kernel_source",
+            origin.as_deref(),
+            ("gitCommit", commit),
+        ));
+    }
+    if let Some((name, repo, commit)) = inputs.ksu {
+        let uri = repo.map(git_uri);
+        dependencies.push(descriptor(name, uri.as_deref(), ("gitCommit", commit)));
+    }
+    for (url, sha256) in inputs.toolchain.urls.iter().zip(&inputs.toolchain.sha256) {
+        dependencies.push(descriptor("toolchain", Some(url), ("sha256", sha256)));
+    }
+    for patch in inputs.patches {
+        dependencies.push(descriptor(
+            "patch",
+            Some(&patch.src),
+            ("sha256", &patch.sha256),
+        ));
+    }
+
+    let mut byproducts = Vec::new();
+    if inputs.config.exists() {
+        let sha256 = sha256_file(inputs.config)?;
+        byproducts.push(descriptor(".config", None, ("sha256", &sha256)));
+    }
+
+    let (builder_id, invocation) = builder();
+    let statement = json!({
+        "_type": STATEMENT_TYPE,
+        "subject": artifacts
+            .iter()
+            .map(|a| {
+                let name = a.path.file_name().unwrap_or_default().to_string_lossy();
+                descriptor(&name, None, ("sha256", &a.sha256))
+            })
+            .collect::<Vec<_>>(),
+        "predicateType": PREDICATE_TYPE,
+        "predicate": {
+            "buildDefinition": {
+                "buildType": BUILD_TYPE,
+                "externalParameters": {
+                    "project": inputs.project,
+                    "branch": inputs.branch,
+                },
+                "resolvedDependencies": dependencies,
+            },
+            "runDetails": {
+                "builder": {
+                    "id": builder_id,
+                    "version": { "kokuban_ci_core": env!("CARGO_PKG_VERSION") },
+                },
+                "metadata": {
+                    "invocationId": invocation,
+                    "startedOn": inputs.started.to_rfc3339(),
+                    "finishedOn": Utc::now().to_rfc3339(),
+                },
+                "byproducts": byproducts,
+            },
+        },
+    });
+
+    let stem = zip.file_stem().unwrap_or_default().to_string_lossy();
+    let path = zip.with_file_name(format!("{}.intoto.jsonl", stem));
+    fs::write(&path, format!("{}\n", statement))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!("Wrote {}", path.display());
+    Ok(path)
+}
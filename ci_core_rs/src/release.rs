@@ -0,0 +1,86 @@
+use anyhow::Result;
+use log::info;
+use std::path::PathBuf;
+
+use crate::config::ReleaseConfig;
+use crate::github::{GitHub, NewRelease, ReleaseInfo};
+
+/// `--draft`/`--prerelease`/... for `build`; each one set wins over the
+/// project's `release` section.
+#[derive(clap::Args, Clone, Default)]
+pub struct ReleaseArgs {
+    #[arg(long)]
+    pub draft: bool,
+    #[arg(long)]
+    pub prerelease: bool,
+    /// Replace assets with the same name when the release already exists.
+    #[arg(long)]
+    pub overwrite_existing: bool,
+    /// Branch or commit the release tag is created from.
+    #[arg(long)]
+    pub target_commitish: Option<String>,
+    /// Append GitHub's generated notes to ours.
+    #[arg(long)]
+    pub generate_notes: bool,
+}
+
+impl ReleaseArgs {
+    pub fn apply_to(&self, cfg: Option<&ReleaseConfig>) -> ReleaseConfig {
+        let mut cfg = cfg.cloned().unwrap_or_default();
+        cfg.draft |= self.draft;
+        cfg.prerelease |= self.prerelease;
+        cfg.overwrite_existing |= self.overwrite_existing;
+        cfg.generate_notes |= self.generate_notes;
+        if self.target_commitish.is_some() {
+            cfg.target_commitish = self.target_commitish.clone();
+        }
+        cfg
+    }
+}
+
+pub struct Release<'a> {
+    pub repo: &'a str,
+    pub tag: &'a str,
+    pub title: &'a str,
+    pub notes: &'a str,
+    pub files: &'a [PathBuf],
+}
+
+/// Creates the release, or uploads to it if the tag already has one.
+pub fn publish(release: &Release, cfg: &ReleaseConfig) -> Result<ReleaseInfo> {
+    let github = GitHub::from_env()?;
+    let info = match github.release_by_tag(release.repo, release.tag)? {
+        Some(info) => {
+            info!(
+                "Release {} already exists, uploading{}",
+                release.tag,
+                if cfg.overwrite_existing {
+                    " (replacing assets)"
+                } else {
+                    ""
+                }
+            );
+            info
+        }
+        None => {
+            let info = github.create_release(
+                release.repo,
+                &NewRelease {
+                    tag_name: release.tag,
+                    name: release.title,
+                    body: release.notes,
+                    draft: cfg.draft,
+                    prerelease: cfg.prerelease,
+                    generate_release_notes: cfg.generate_notes,
+                    target_commitish: cfg.target_commitish.as_deref(),
+                },
+            )?;
+            info!("Created release {}", info.html_url);
+            info
+        }
+    };
+    for file in release.files {
+        github.upload_asset(release.repo, &info, file, cfg.overwrite_existing)?;
+    }
+    Ok(info)
+}
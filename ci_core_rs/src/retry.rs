@@ -0,0 +1,84 @@
+use anyhow::Result;
+use log::info;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::RetryConfig;
+use crate::interrupt::{self, Interrupted};
+use crate::timeout::TimedOut;
+
+/// The `retry` settings of the project being built.
+static CONFIG: Mutex<Option<RetryConfig>> = Mutex::new(None);
+
+/// Uses a project's `retry` settings for the rest of the run.
+pub fn configure(cfg: Option<&RetryConfig>) {
+    *CONFIG.lock().unwrap() = cfg.cloned();
+}
+
+/// Attempts and delays, with the defaults filled in.
+pub struct Policy {
+    pub attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    jitter: f64,
+}
+
+pub fn policy() -> Policy {
+    let cfg = CONFIG.lock().unwrap().clone().unwrap_or_default();
+    Policy {
+        attempts: cfg.attempts.unwrap_or(4).max(1),
+        backoff: Duration::from_secs(cfg.backoff_seconds.unwrap_or(2)),
+        max_backoff: Duration::from_secs(cfg.max_backoff_seconds.unwrap_or(60)),
+        jitter: cfg.jitter.unwrap_or(0.25).clamp(0.0, 1.0),
+    }
+}
+
+impl Policy {
+    /// How long to wait after failed attempt number `attempt` (from 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        exp.mul_f64(1.0 - self.jitter * random)
+    }
+
+    /// Whether `error` after attempt number `attempt` is worth another try.
+    /// Deadlines and Ctrl-C never are.
+    pub fn should_retry(&self, attempt: u32, error: &anyhow::Error) -> bool {
+        attempt < self.attempts
+            && !interrupt::interrupted()
+            && error.downcast_ref::<TimedOut>().is_none()
+            && error.downcast_ref::<Interrupted>().is_none()
+    }
+}
+
+/// Runs `op` until it succeeds or the project's retry policy gives up.
+/// `what` names the operation in the retry messages.
+pub fn run<T>(what: &str, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+    let policy = policy();
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) if policy.should_retry(attempt, &e) => {
+                let delay = policy.delay(attempt);
+                info!(
+                    "Attempt {}/{} for {} failed: {:#}. Retrying in {:.1}s...",
+                    attempt,
+                    policy.attempts,
+                    what,
+                    e,
+                    delay.as_secs_f64()
+                );
+                thread::sleep(delay);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
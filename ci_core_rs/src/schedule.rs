@@ -0,0 +1,180 @@
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use log::{info, warn};
+
+use crate::build::source_url;
+use crate::config::ProjectConfig;
+use crate::history;
+use crate::utils::{get_workspace_dir, run_cmd};
+
+const MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A five-field cron expression (minute hour day-of-month month weekday) in
+/// local time, or one of `@hourly`, `@daily`/`@nightly`, `@weekly` and
+/// `@monthly`.
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Day of month and weekday were both restricted, so either matching is
+    /// enough, as in crontab(5).
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@nightly" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!(
+                "'{}' is not a cron expression (minute hour day month weekday)",
+                expr
+            ));
+        };
+        let mut weekdays = field(weekday, 0, 7, WEEKDAYS)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: field(minute, 0, 59, &[])?,
+            hours: field(hour, 0, 23, &[])?,
+            days: field(day, 1, 31, &[])?,
+            months: field(month, 1, 12, MONTHS)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+            && day_matches
+    }
+}
+
+/// Bit mask of the values a field allows: `*`, `5`, `1-5`, `*/15`, `1-30/2`
+/// or a comma-separated list of those. `names` are the values from `min`
+/// on, e.g. `mon` for weekdays.
+fn field(spec: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let lower = s.to_ascii_lowercase();
+        let n = match names.iter().position(|n| *n == lower) {
+            Some(i) => i as u32 + min,
+            None => s
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number in '{}'", s, spec))?,
+        };
+        if n < min || n > max {
+            return Err(anyhow!(
+                "{} is out of range {}-{} in '{}'",
+                n,
+                min,
+                max,
+                spec
+            ));
+        }
+        Ok(n)
+    };
+    let mut mask = 0;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .ok()
+                    .filter(|s| *s > 0)
+                    .ok_or_else(|| anyhow!("bad step '{}' in '{}'", step, spec))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                // `5/10` runs from 5 to the end.
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if start > end {
+            return Err(anyhow!("{}-{} is backwards in '{}'", start, end, spec));
+        }
+        for n in (start..=end).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok(mask)
+}
+
+/// The kernel HEAD the project's next `build-all` would build: upstream's
+/// for projects whose checkout is updated before each build (`source_repo`)
+/// or not cloned yet, else the checkout's.
+fn source_head(project_key: &str, proj: &ProjectConfig) -> Result<String> {
+    let checkout = get_workspace_dir().join(project_key);
+    if proj.source_repo.is_none() && checkout.join(".git").exists() {
+        return Ok(
+            run_cmd(&["git", "rev-parse", "HEAD"], Some(&checkout), true)?.unwrap_or_default(),
+        );
+    }
+    let repo = proj.source_repo.as_deref().unwrap_or(&proj.repo);
+    // Scheduled builds clone at build-all's default source ref.
+    let git_ref = proj.source_branch.as_deref().unwrap_or("main");
+    let url = source_url(repo);
+    let listed = run_cmd(&["git", "ls-remote", &url, git_ref], None, true)?.unwrap_or_default();
+    listed
+        .split_whitespace()
+        .next()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("{} has no {}", repo, git_ref))
+}
+
+/// Whether a scheduled build of `project_key`/`branch` has anything new to
+/// build: false when the last successful one was of the same kernel HEAD.
+/// Errors finding out count as new.
+pub fn has_new_commits(project_key: &str, branch: &str, proj: &ProjectConfig) -> bool {
+    let head = match source_head(project_key, proj) {
+        Ok(head) => head,
+        Err(e) => {
+            warn!("Can't tell if {} has new commits: {:#}", project_key, e);
+            return true;
+        }
+    };
+    match history::last_successful_commit(project_key, branch) {
+        Ok(Some(built)) if built == head => {
+            info!(
+                "Skipping the scheduled {} / {} build: {} was already built.",
+                project_key,
+                branch,
+                &head[..head.len().min(12)]
+            );
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            warn!("Can't read the build history: {:#}", e);
+            true
+        }
+    }
+}
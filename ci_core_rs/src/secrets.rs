@@ -0,0 +1,104 @@
+use anyhow::{Result, anyhow};
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Values handed out so far, masked wherever output leaves the process.
+static KNOWN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Shorter values are too likely to show up by chance to be masked.
+const MIN_REDACTED_LEN: usize = 4;
+
+/// Service the OS keyring entries are stored under.
+const KEYRING_SERVICE: &str = "kokuban-ci";
+
+const MASK: &str = "***";
+
+/// Looks up secret `name`, in order: the `name` variable, the file named by
+/// `name_FILE`, `$KOKUBAN_SECRETS_DIR/name` (default `/run/secrets`), and
+/// the OS keyring (`secret-tool`, service `kokuban-ci`, account `name`).
+/// The value is masked in logs and JSON output from then on.
+pub fn find(name: &str) -> Option<String> {
+    let value = from_env(name)
+        .or_else(|| from_file(name))
+        .or_else(|| from_keyring(name))?;
+    remember(&value);
+    Some(value)
+}
+
+/// Like `find`, but a missing secret is an error. `what` says what it's for.
+pub fn get(name: &str, what: &str) -> Result<String> {
+    find(name).ok_or_else(|| {
+        anyhow!(
+            "{} ({}) is not set: export it, point {}_FILE at a file holding it, or store it in the keyring",
+            name,
+            what,
+            name
+        )
+    })
+}
+
+fn from_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+fn from_file(name: &str) -> Option<String> {
+    let path = match env::var_os(format!("{}_FILE", name)) {
+        Some(path) => PathBuf::from(path),
+        None => env::var_os("KOKUBAN_SECRETS_DIR")
+            .map_or_else(|| PathBuf::from("/run/secrets"), PathBuf::from)
+            .join(name),
+    };
+    let content = fs::read_to_string(path).ok()?;
+    // Files written by `echo` end in a newline that isn't part of the secret.
+    let value = content.strip_suffix('\n').unwrap_or(&content);
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+fn from_keyring(name: &str) -> Option<String> {
+    let output = Command::new("secret-tool")
+        .args(["lookup", "service", KEYRING_SERVICE, "account", name])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let value = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !value.is_empty()).then_some(value)
+}
+
+/// Masks `value` from now on, along with each line of a multi-line one
+/// (keys show up line by line in command output).
+pub fn remember(value: &str) {
+    let mut known = KNOWN.lock().unwrap();
+    for part in std::iter::once(value).chain(value.lines().map(str::trim)) {
+        if part.len() >= MIN_REDACTED_LEN && !known.iter().any(|k| k == part) {
+            known.push(part.to_string());
+        }
+    }
+    // Longest first, so a line of a key isn't masked before the whole key.
+    known.sort_by_key(|k| std::cmp::Reverse(k.len()));
+}
+
+/// `text` with every secret handed out so far replaced by `***`.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let known = KNOWN.lock().unwrap();
+    let mut text = Cow::Borrowed(text);
+    for secret in known.iter() {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), MASK));
+        }
+    }
+    text
+}
+
+/// `redact` for command output, which needn't be UTF-8; untouched unless
+/// it holds a secret.
+pub fn redact_bytes(bytes: &[u8]) -> Cow<'_, [u8]> {
+    match redact(&String::from_utf8_lossy(bytes)) {
+        Cow::Borrowed(_) => Cow::Borrowed(bytes),
+        Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+    }
+}
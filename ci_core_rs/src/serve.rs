@@ -0,0 +1,896 @@
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, Timelike};
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::ProjectConfig;
+use crate::history;
+use crate::ksu::{load_ksu_variants, project_branches};
+use crate::schedule::{self, Cron};
+use crate::secrets;
+use crate::utils::{get_root_dir, load_projects};
+
+/// Secret GitHub signs webhook deliveries with (`X-Hub-Signature-256`);
+/// other clients may send it as `Authorization: Bearer <secret>`.
+const SECRET_NAME: &str = "KOKUBAN_WEBHOOK_SECRET";
+
+/// GitHub's push payloads are capped at 25 MB.
+const MAX_BODY: usize = 25 * 1024 * 1024;
+
+/// The request line and headers are read before the request is
+/// authorized, so they get a much smaller cap than the body.
+const MAX_HEAD: u64 = 64 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// Connections handled at once; more are turned away until one closes.
+/// Each followed log holds one for as long as its build runs.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Finished jobs kept for the status endpoint; older ones are forgotten.
+const MAX_FINISHED_JOBS: usize = 200;
+
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a followed log is checked for new output.
+const LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Builds `GET /history` lists unless asked for more.
+const DEFAULT_HISTORY: usize = 50;
+
+/// The page served at `/`. It holds no data itself; it calls the API with
+/// the token it's given.
+const DASHBOARD: &str = include_str!("serve/dashboard.html");
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    fn finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+/// One `build-all --projects <project> --branch <branch>` run, a build in
+/// the API.
+#[derive(Serialize, Clone)]
+struct Job {
+    id: u64,
+    project: String,
+    branch: String,
+    /// Kernel ref to clone when the project has no checkout yet.
+    source_ref: Option<String>,
+    /// Publish the release once built.
+    release: bool,
+    /// What queued it, e.g. `push owner/kernel@main` or `api`.
+    trigger: String,
+    state: JobState,
+    queued_at: String,
+    started_at: Option<String>,
+    finished_at: Option<String>,
+    /// The build's exit status; see `build --help` for what each means.
+    exit_code: Option<i32>,
+    log: PathBuf,
+    /// The build process while it runs.
+    #[serde(skip)]
+    pid: Option<u32>,
+    /// A cancel came in while it was running.
+    #[serde(skip)]
+    cancelling: bool,
+}
+
+struct Queue {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+/// Options every queued build runs with.
+pub struct ServeOptions {
+    pub listen: String,
+    pub jobs: usize,
+    pub do_release: bool,
+    pub out_dir: Option<PathBuf>,
+    /// Allow no secret on a non-loopback address.
+    pub insecure: bool,
+}
+
+struct Server {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+    secret: Option<String>,
+    opts: ServeOptions,
+    log_dir: PathBuf,
+    connections: AtomicUsize,
+}
+
+/// A request that gets a JSON error instead of a build.
+struct Rejection(u16, String);
+
+fn reject(status: u16, message: impl Into<String>) -> Rejection {
+    Rejection(status, message.into())
+}
+
+/// Listens for GitHub push webhooks (`POST /webhook`) and the builds API
+/// (`POST /builds` with `{"project", "branch", "release"}`, `GET /builds`,
+/// `GET /builds/<id>`, `GET /builds/<id>/log` and `POST
+/// /builds/<id>/cancel`), queues builds of the matching projects and runs
+/// up to `jobs` of them at once, never two of the same project. `/` is a
+/// dashboard over the same API, plus `GET /projects`, `GET /history` and
+/// `GET /history/<id>/artifacts/<n>` to download what a build made.
+pub fn handle_serve(opts: ServeOptions) -> Result<()> {
+    // Builds of different projects would otherwise share ./AnyKernel3 and
+    // the zip dir.
+    if opts.jobs > 1 && opts.out_dir.is_none() {
+        return Err(anyhow!(
+            "--jobs {} needs --out-dir, so parallel builds get their own output dirs",
+            opts.jobs
+        ));
+    }
+    let listener = TcpListener::bind(&opts.listen)
+        .with_context(|| format!("Failed to bind {}", opts.listen))?;
+    let secret = secrets::find(SECRET_NAME);
+    if secret.is_none() {
+        let loopback = listener.local_addr()?.ip().is_loopback();
+        if !loopback && !opts.insecure {
+            return Err(anyhow!(
+                "{} is not set, so anyone who can reach {} could start builds; set it, listen on a loopback address or pass --insecure",
+                SECRET_NAME,
+                opts.listen
+            ));
+        }
+        warn!(
+            "{} is not set; anyone who can reach {} can queue builds.",
+            SECRET_NAME, opts.listen
+        );
+    }
+    let log_dir = get_root_dir().join("logs").join("serve");
+    fs::create_dir_all(&log_dir)?;
+    let workers = opts.jobs.max(1);
+    let server = Arc::new(Server {
+        queue: Mutex::new(Queue {
+            next_id: 1,
+            jobs: Vec::new(),
+        }),
+        wake: Condvar::new(),
+        secret,
+        opts,
+        log_dir,
+        connections: AtomicUsize::new(0),
+    });
+    for _ in 0..workers {
+        let server = server.clone();
+        thread::spawn(move || server.work());
+    }
+    {
+        let server = server.clone();
+        thread::spawn(move || server.schedule());
+    }
+    info!(
+        "Listening on {} ({} build(s) at a time)",
+        server.opts.listen, workers
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        if server.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            server.connections.fetch_sub(1, Ordering::SeqCst);
+            let mut stream = stream;
+            let _ = respond_json(&mut stream, 503, &json!({ "error": "too many connections" }));
+            continue;
+        }
+        let server = server.clone();
+        thread::spawn(move || {
+            if let Err(e) = server.handle_connection(stream) {
+                warn!("Request failed: {:#}", e);
+            }
+            server.connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+impl Server {
+    /// Runs queued jobs until the process exits.
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    let busy: Vec<String> = queue
+                        .jobs
+                        .iter()
+                        .filter(|j| j.state == JobState::Running)
+                        .map(|j| j.project.clone())
+                        .collect();
+                    // Builds of one project share its checkout in the workspace.
+                    if let Some(job) = queue
+                        .jobs
+                        .iter_mut()
+                        .find(|j| j.state == JobState::Queued && !busy.contains(&j.project))
+                    {
+                        job.state = JobState::Running;
+                        job.started_at = Some(Local::now().to_rfc3339());
+                        break job.clone();
+                    }
+                    queue = self.wake.wait(queue).unwrap();
+                }
+            };
+
+            info!("Job {}: building {} / {}", job.id, job.project, job.branch);
+            let exit_code = match self.run(&job) {
+                Ok(code) => code,
+                Err(e) => {
+                    warn!("Job {}: {:#}", job.id, e);
+                    None
+                }
+            };
+
+            let mut queue = self.queue.lock().unwrap();
+            if let Some(done) = queue.jobs.iter_mut().find(|j| j.id == job.id) {
+                done.state = if done.cancelling {
+                    JobState::Cancelled
+                } else if exit_code == Some(0) {
+                    JobState::Succeeded
+                } else {
+                    JobState::Failed
+                };
+                done.exit_code = exit_code;
+                done.finished_at = Some(Local::now().to_rfc3339());
+                done.pid = None;
+                info!(
+                    "Job {}: {} / {} {}",
+                    job.id,
+                    job.project,
+                    job.branch,
+                    match done.state {
+                        JobState::Succeeded => "succeeded",
+                        JobState::Cancelled => "cancelled",
+                        _ => "failed",
+                    }
+                );
+            }
+            let finished = queue.jobs.iter().filter(|j| j.state.finished()).count();
+            if finished > MAX_FINISHED_JOBS {
+                let mut excess = finished - MAX_FINISHED_JOBS;
+                queue.jobs.retain(|j| {
+                    let drop = excess > 0 && j.state.finished();
+                    if drop {
+                        excess -= 1;
+                    }
+                    !drop
+                });
+            }
+            self.wake.notify_all();
+        }
+    }
+
+    /// Queues the projects' `schedules` whose time has come, checking at the
+    /// start of every minute.
+    fn schedule(&self) {
+        let mut last = String::new();
+        loop {
+            let now = Local::now();
+            thread::sleep(Duration::from_secs(60 - now.second() as u64));
+            let now = Local::now();
+            // Sleeping can end a little early; don't run a minute twice.
+            let minute = now.format("%Y%m%d%H%M").to_string();
+            if minute == last {
+                continue;
+            }
+            last = minute;
+            if let Err(e) = self.run_schedules(&now) {
+                warn!("Schedules: {:#}", e);
+            }
+        }
+    }
+
+    fn run_schedules(&self, now: &DateTime<Local>) -> Result<()> {
+        let projects = load_projects()?;
+        let ksu_variants = load_ksu_variants(&projects)?;
+        let mut keys: Vec<&String> = projects.keys().filter(|k| !k.starts_with('_')).collect();
+        keys.sort();
+        for key in keys {
+            let Ok(proj) = serde_json::from_value::<ProjectConfig>(projects[key].clone()) else {
+                continue;
+            };
+            for entry in proj.schedules.iter().flatten() {
+                let cron = match Cron::parse(&entry.cron) {
+                    Ok(cron) => cron,
+                    Err(e) => {
+                        warn!("{}: {:#}", key, e);
+                        continue;
+                    }
+                };
+                if !cron.matches(now) {
+                    continue;
+                }
+                let branches = entry
+                    .branches
+                    .clone()
+                    .unwrap_or_else(|| project_branches(&ksu_variants, &proj));
+                let trigger = format!("schedule {}", entry.cron);
+                for branch in &branches {
+                    if entry.always || schedule::has_new_commits(key, branch, &proj) {
+                        self.enqueue(key, branch, None, entry.release, &trigger);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the job's build as a child process with its output in the
+    /// job's log, and returns its exit code.
+    fn run(&self, job: &Job) -> Result<Option<i32>> {
+        let log = File::create(&job.log)?;
+        let mut cmd = Command::new(env::current_exe()?);
+        cmd.args(["--no-color", "build-all", "--projects", &job.project])
+            .args(["--branch", &job.branch])
+            .args(["--do-release", &job.release.to_string()]);
+        if let Some(source_ref) = &job.source_ref {
+            cmd.args(["--source-ref", source_ref]);
+        }
+        if let Some(dir) = &self.opts.out_dir {
+            cmd.arg("--out-dir").arg(dir);
+        }
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()?;
+        let cancelling = {
+            let mut queue = self.queue.lock().unwrap();
+            let running = queue.jobs.iter_mut().find(|j| j.id == job.id);
+            running.is_some_and(|j| {
+                j.pid = Some(child.id());
+                j.cancelling
+            })
+        };
+        // Cancelled between being picked up and starting.
+        if cancelling {
+            stop(child.id());
+        }
+        Ok(child.wait()?.code())
+    }
+
+    /// Queues a build unless the same one is already waiting, and returns
+    /// its id. `release` defaults to the server's `--do-release`.
+    fn enqueue(
+        &self,
+        project: &str,
+        branch: &str,
+        source_ref: Option<&str>,
+        release: Option<bool>,
+        trigger: &str,
+    ) -> u64 {
+        let release = release.unwrap_or(self.opts.do_release);
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(waiting) = queue.jobs.iter().find(|j| {
+            j.state == JobState::Queued
+                && j.project == project
+                && j.branch == branch
+                && j.source_ref.as_deref() == source_ref
+                && j.release == release
+        }) {
+            return waiting.id;
+        }
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.jobs.push(Job {
+            id,
+            project: project.to_string(),
+            branch: branch.to_string(),
+            source_ref: source_ref.map(str::to_string),
+            release,
+            trigger: trigger.to_string(),
+            state: JobState::Queued,
+            queued_at: Local::now().to_rfc3339(),
+            started_at: None,
+            finished_at: None,
+            exit_code: None,
+            log: self.log_dir.join(format!(
+                "{}-{}.log",
+                Local::now().format("%Y%m%d-%H%M%S"),
+                id
+            )),
+            pid: None,
+            cancelling: false,
+        });
+        info!("Job {}: queued {} / {} ({})", id, project, branch, trigger);
+        self.wake.notify_all();
+        id
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let request = match Request::read(&stream)? {
+            Ok(request) => request,
+            Err(Rejection(status, message)) => {
+                return respond_json(&mut stream, status, &json!({ "error": message }));
+            }
+        };
+
+        let path = request.path.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", [""]) => {
+                return respond(
+                    &mut stream,
+                    200,
+                    "text/html; charset=utf-8",
+                    DASHBOARD.as_bytes(),
+                );
+            }
+            ("POST", ["webhook"]) => self
+                .authorize(&request)
+                .and_then(|_| self.webhook(request.header("x-github-event"), &request.body)),
+            (_, ["builds" | "history" | "projects", ..]) => match self.authorize(&request) {
+                Err(rejection) => Err(rejection),
+                Ok(()) => match (request.method.as_str(), segments.as_slice()) {
+                    ("POST", ["builds"]) => self.build_request(&request.body),
+                    ("GET", ["builds"]) => {
+                        let queue = self.queue.lock().unwrap();
+                        let builds: Vec<&Job> = queue.jobs.iter().rev().collect();
+                        Ok(json!({ "builds": builds }))
+                    }
+                    ("GET", ["builds", id]) => self.job(id).map(|job| json!(job)),
+                    ("GET", ["builds", id, "log"]) => match self.job(id) {
+                        Ok(job) => return self.stream_log(&mut stream, job.id),
+                        Err(rejection) => Err(rejection),
+                    },
+                    ("POST", ["builds", id, "cancel"]) => self.cancel(id),
+                    ("GET", ["history"]) => {
+                        let limit = request
+                            .query("limit")
+                            .and_then(|n| n.parse().ok())
+                            .unwrap_or(DEFAULT_HISTORY);
+                        history::recent_builds(limit)
+                            .map(|builds| json!({ "builds": builds }))
+                            .map_err(|e| reject(500, format!("{:#}", e)))
+                    }
+                    ("GET", ["history", id, "artifacts", index]) => match artifact(id, index) {
+                        Ok(path) => return respond_file(&mut stream, &path),
+                        Err(rejection) => Err(rejection),
+                    },
+                    ("GET", ["projects"]) => projects(),
+                    _ => Err(reject(
+                        404,
+                        format!("no route for {} {}", request.method, path),
+                    )),
+                },
+            },
+            _ => Err(reject(
+                404,
+                format!("no route for {} {}", request.method, path),
+            )),
+        };
+        match result {
+            Ok(value) => respond_json(&mut stream, 200, &value),
+            Err(Rejection(status, message)) => {
+                respond_json(&mut stream, status, &json!({ "error": message }))
+            }
+        }
+    }
+
+    fn job(&self, id: &str) -> Result<Job, Rejection> {
+        let queue = self.queue.lock().unwrap();
+        id.parse::<u64>()
+            .ok()
+            .and_then(|id| queue.jobs.iter().find(|j| j.id == id).cloned())
+            .ok_or_else(|| reject(404, format!("no build {}", id)))
+    }
+
+    /// Drops a queued build, or stops a running one the way Ctrl-C would,
+    /// letting it roll kernel_source back.
+    fn cancel(&self, id: &str) -> Result<Value, Rejection> {
+        let id = self.job(id)?.id;
+        let mut queue = self.queue.lock().unwrap();
+        let Some(job) = queue.jobs.iter_mut().find(|j| j.id == id) else {
+            return Err(reject(404, format!("no build {}", id)));
+        };
+        match job.state {
+            JobState::Queued => {
+                job.state = JobState::Cancelled;
+                job.finished_at = Some(Local::now().to_rfc3339());
+            }
+            JobState::Running => {
+                job.cancelling = true;
+                if let Some(pid) = job.pid {
+                    stop(pid);
+                }
+            }
+            _ => return Err(reject(409, format!("build {} has already finished", id))),
+        }
+        info!("Job {}: cancelled", id);
+        Ok(json!(job))
+    }
+
+    /// Sends the build's log as it is written, until the build finishes.
+    fn stream_log(&self, stream: &mut TcpStream, id: u64) -> Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n"
+        )?;
+        let mut log: Option<File> = None;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let (finished, path) = {
+                let queue = self.queue.lock().unwrap();
+                let job = queue.jobs.iter().find(|j| j.id == id);
+                (
+                    job.is_none_or(|j| j.state.finished()),
+                    job.map(|j| j.log.clone()),
+                )
+            };
+            // A queued build has no log yet.
+            if log.is_none() {
+                log = path.and_then(|p| File::open(p).ok());
+            }
+            if let Some(file) = log.as_mut() {
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    write!(stream, "{:x}\r\n", n)?;
+                    stream.write_all(&buf[..n])?;
+                    stream.write_all(b"\r\n")?;
+                }
+                stream.flush()?;
+            }
+            // Read once more after the build finished, then stop.
+            if finished {
+                break;
+            }
+            thread::sleep(LOG_POLL_INTERVAL);
+        }
+        stream.write_all(b"0\r\n\r\n")?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    /// With a secret set, a request needs GitHub's HMAC signature of the
+    /// body or the secret as a bearer token.
+    fn authorize(&self, request: &Request) -> Result<(), Rejection> {
+        let Some(secret) = &self.secret else {
+            return Ok(());
+        };
+        if let Some(signature) = request.header("x-hub-signature-256") {
+            let expected = signature
+                .strip_prefix("sha256=")
+                .and_then(|hex| {
+                    (0..hex.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .unwrap_or_default();
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .expect("HMAC takes any key length");
+            mac.update(&request.body);
+            return mac
+                .verify_slice(&expected)
+                .map_err(|_| reject(401, "bad signature"));
+        }
+        match request
+            .header("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+        {
+            Some(token) if same_secret(token, secret) => Ok(()),
+            _ => Err(reject(401, "missing signature or token")),
+        }
+    }
+
+    /// Queues builds for a GitHub push: every variant of the projects built
+    /// from the pushed kernel repo and branch, and the matching variant of
+    /// the projects that support a pushed KernelSU upstream.
+    fn webhook(&self, event: Option<&str>, body: &[u8]) -> Result<Value, Rejection> {
+        match event {
+            Some("ping") => return Ok(json!({ "ok": true })),
+            Some("push") => {}
+            other => {
+                return Ok(json!({ "ignored": other.unwrap_or("unknown event") }));
+            }
+        }
+        let payload: Value =
+            serde_json::from_slice(body).map_err(|e| reject(400, format!("bad JSON: {}", e)))?;
+        if payload["deleted"].as_bool() == Some(true) {
+            return Ok(json!({ "ignored": "branch deleted" }));
+        }
+        let repo = payload["repository"]["full_name"]
+            .as_str()
+            .ok_or_else(|| reject(400, "push without repository.full_name"))?
+            .to_ascii_lowercase();
+        let Some(branch) = payload["ref"]
+            .as_str()
+            .and_then(|r| r.strip_prefix("refs/heads/"))
+        else {
+            return Ok(json!({ "ignored": "not a branch push" }));
+        };
+        let trigger = format!("push {}@{}", repo, branch);
+
+        let projects = load_projects().map_err(|e| reject(500, format!("{:#}", e)))?;
+        let ksu_variants =
+            load_ksu_variants(&projects).map_err(|e| reject(500, format!("{:#}", e)))?;
+        let mut ids = Vec::new();
+        let mut keys: Vec<&String> = projects.keys().filter(|k| !k.starts_with('_')).collect();
+        keys.sort();
+        for key in keys {
+            let Ok(proj) = serde_json::from_value::<ProjectConfig>(projects[key].clone()) else {
+                continue;
+            };
+            let variants = project_branches(&ksu_variants, &proj);
+            let source = proj.source_repo.as_deref().unwrap_or(&proj.repo);
+            if repo_name(source) == repo
+                && proj.source_branch.as_deref().is_none_or(|b| b == branch)
+            {
+                for variant in &variants {
+                    ids.push(self.enqueue(key, variant, Some(branch), None, &trigger));
+                }
+                continue;
+            }
+            for variant in &variants {
+                let upstream = ksu_variants.get(variant);
+                if upstream
+                    .and_then(|v| v.repo.as_deref())
+                    .map(repo_name)
+                    .as_ref()
+                    == Some(&repo)
+                    && upstream.and_then(|v| v.branch.as_deref()) == Some(branch)
+                {
+                    ids.push(self.enqueue(key, variant, None, None, &trigger));
+                }
+            }
+        }
+        if ids.is_empty() {
+            return Ok(json!({ "ignored": format!("no project builds {}", trigger) }));
+        }
+        Ok(json!({ "builds": ids }))
+    }
+
+    /// `{"project": "<key>", "branch": "ksu", "release": true, "source_ref":
+    /// "main"}`; branch defaults to main and release to `--do-release`.
+    fn build_request(&self, body: &[u8]) -> Result<Value, Rejection> {
+        let request: Value =
+            serde_json::from_slice(body).map_err(|e| reject(400, format!("bad JSON: {}", e)))?;
+        let project = request["project"]
+            .as_str()
+            .ok_or_else(|| reject(400, "missing \"project\""))?;
+        let branch = request["branch"].as_str().unwrap_or("main");
+        let projects = load_projects().map_err(|e| reject(500, format!("{:#}", e)))?;
+        if project.starts_with('_') || !projects.contains_key(project) {
+            return Err(reject(404, format!("no project {}", project)));
+        }
+        let id = self.enqueue(
+            project,
+            branch,
+            request["source_ref"].as_str(),
+            request["release"].as_bool(),
+            "api",
+        );
+        Ok(json!({ "builds": [id] }))
+    }
+}
+
+/// Just enough HTTP/1.1 for webhooks and curl: one request per connection.
+struct Request {
+    method: String,
+    path: String,
+    /// Names lowercased.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> Result<Result<Self, Rejection>> {
+        let mut reader = BufReader::new(stream);
+        let mut head = (&mut reader).take(MAX_HEAD);
+        let mut request_line = String::new();
+        head.read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let path = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if head.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Ok(Err(reject(431, "too many request headers")));
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+        if head.limit() == 0 {
+            return Ok(Err(reject(431, "request headers too large")));
+        }
+        let mut request = Request {
+            method,
+            path,
+            headers,
+            body: Vec::new(),
+        };
+        let length: usize = request
+            .header("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        if length > MAX_BODY {
+            return Ok(Err(reject(413, "request body too large")));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(Ok(request))
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// A query string parameter, as sent; the API's values never need
+    /// decoding.
+    fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.path.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v)
+    }
+}
+
+/// Each project with the branches it can be built as, for the dashboard's
+/// build form.
+fn projects() -> Result<Value, Rejection> {
+    let projects = load_projects().map_err(|e| reject(500, format!("{:#}", e)))?;
+    let ksu_variants = load_ksu_variants(&projects).map_err(|e| reject(500, format!("{:#}", e)))?;
+    let mut keys: Vec<&String> = projects.keys().filter(|k| !k.starts_with('_')).collect();
+    keys.sort();
+    let list: Vec<Value> = keys
+        .into_iter()
+        .filter_map(|key| {
+            let proj = serde_json::from_value::<ProjectConfig>(projects[key].clone()).ok()?;
+            Some(json!({ "key": key, "branches": project_branches(&ksu_variants, &proj) }))
+        })
+        .collect();
+    Ok(json!({ "projects": list }))
+}
+
+/// A file recorded as an artifact of a build. Only recorded paths are
+/// served, so requests can't reach anything else on disk.
+fn artifact(id: &str, index: &str) -> Result<PathBuf, Rejection> {
+    let (Ok(id), Ok(index)) = (id.parse(), index.parse()) else {
+        return Err(reject(
+            404,
+            format!("no artifact {} of build {}", index, id),
+        ));
+    };
+    let path = history::artifact_path(id, index)
+        .map_err(|e| reject(500, format!("{:#}", e)))?
+        .ok_or_else(|| reject(404, format!("no artifact {} of build {}", index, id)))?;
+    if !path.is_file() {
+        return Err(reject(
+            404,
+            format!("{} is no longer on disk", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+/// Compares a bearer token with the secret in constant time, through
+/// their MACs, so response times don't leak how much of it matched.
+fn same_secret(token: &str, secret: &str) -> bool {
+    let mac = |data: &str| {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key length");
+        mac.update(data.as_bytes());
+        mac
+    };
+    mac(token)
+        .verify_slice(&mac(secret).finalize().into_bytes())
+        .is_ok()
+}
+
+/// `owner/name` of a GitHub URL, SSH remote or `owner/name`, lowercased.
+fn repo_name(repo: &str) -> String {
+    let repo = repo.trim_end_matches('/');
+    let repo = repo.strip_suffix(".git").unwrap_or(repo);
+    let repo = repo
+        .split_once("github.com")
+        .map(|(_, rest)| rest.trim_start_matches([':', '/']))
+        .unwrap_or(repo);
+    repo.to_ascii_lowercase()
+}
+
+fn respond_json(stream: &mut TcpStream, status: u16, value: &Value) -> Result<()> {
+    let mut body = serde_json::to_vec_pretty(value)?;
+    body.push(b'\n');
+    respond(stream, status, "application/json", &body)
+}
+
+/// Sends a file as a download without reading it into memory.
+fn respond_file(stream: &mut TcpStream, path: &Path) -> Result<()> {
+    let mut file = File::open(path)?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().replace('"', ""))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Disposition: attachment; filename=\"{}\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        name,
+        file.metadata()?.len()
+    )?;
+    io::copy(&mut file, stream)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn respond(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// SIGTERM, as `tui` stops its build: the build stops its commands and
+/// cleans up; a second one makes it exit at once.
+fn stop(pid: u32) {
+    // SAFETY: signals a build this server spawned.
+    unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+}
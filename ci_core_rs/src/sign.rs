@@ -0,0 +1,109 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::env;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::config::GpgConfig;
+use crate::secrets;
+use crate::utils::run_cmd;
+
+/// A private GnuPG home for the imported key and the passphrase file,
+/// removed (with its gpg-agent) when dropped.
+struct GnupgHome(PathBuf);
+
+impl GnupgHome {
+    fn create() -> Result<Self> {
+        let dir = env::temp_dir().join(format!("kokuban-gnupg-{}", std::process::id()));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+        Ok(GnupgHome(dir))
+    }
+
+    /// Writes `contents` to a file only the owner can read.
+    fn secret_file(&self, name: &str, contents: &str) -> Result<PathBuf> {
+        let path = self.0.join(name);
+        fs::write(&path, contents)?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        Ok(path)
+    }
+}
+
+impl Drop for GnupgHome {
+    fn drop(&mut self) {
+        let home = self.0.to_string_lossy();
+        let _ = run_cmd(
+            &["gpgconf", "--homedir", &home, "--kill", "all"],
+            None,
+            true,
+        );
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            warn!("Failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Writes a detached, armored `<file>.asc` next to each of `files`, with
+/// the configured key from the keyring or the one in `private_key_env`.
+/// Returns the signatures.
+pub fn sign_files(cfg: &GpgConfig, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let home = GnupgHome::create()?;
+    let mut gpg = vec![
+        "gpg".to_string(),
+        "--batch".to_string(),
+        "--yes".to_string(),
+    ];
+
+    if let Some(name) = &cfg.private_key_env {
+        let key = home.secret_file("private.asc", &secrets::get(name, "GPG private key")?)?;
+        let homedir = home.0.to_string_lossy().to_string();
+        run_cmd(
+            &[
+                "gpg",
+                "--batch",
+                "--homedir",
+                &homedir,
+                "--import",
+                &key.to_string_lossy(),
+            ],
+            None,
+            true,
+        )
+        .with_context(|| format!("Failed to import the private key in ${}", name))?;
+        fs::remove_file(&key)?;
+        gpg.extend(["--homedir".to_string(), homedir]);
+    }
+    if let Some(key) = &cfg.key {
+        gpg.extend(["--local-user".to_string(), key.clone()]);
+    }
+    if let Some(name) = &cfg.passphrase_env {
+        let passphrase = home.secret_file("passphrase", &secrets::get(name, "GPG passphrase")?)?;
+        gpg.extend([
+            "--pinentry-mode".to_string(),
+            "loopback".to_string(),
+            "--passphrase-file".to_string(),
+            passphrase.to_string_lossy().to_string(),
+        ]);
+    }
+
+    let mut signatures = Vec::new();
+    for file in files {
+        let asc = signature_path(file);
+        info!("Signing {}", file.display());
+        let mut cmd: Vec<&str> = gpg.iter().map(|a| a.as_str()).collect();
+        let (asc_arg, file_arg) = (asc.to_string_lossy(), file.to_string_lossy());
+        cmd.extend(["--armor", "--detach-sign", "--output", &asc_arg, &file_arg]);
+        run_cmd(&cmd, None, false)?;
+        signatures.push(asc);
+    }
+    Ok(signatures)
+}
+
+fn signature_path(file: &Path) -> PathBuf {
+    let mut name = file.file_name().unwrap_or_default().to_os_string();
+    name.push(".asc");
+    file.with_file_name(name)
+}
@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::interrupt;
+use crate::utils::run_cmd;
+
+/// Records the state of the kernel source git tree before integration and
+/// restores it when dropped, unless `release`d after a successful build.
+pub struct Snapshot {
+    dir: PathBuf,
+    head: String,
+    stash: Option<String>,
+    untracked: HashSet<String>,
+    armed: bool,
+}
+
+impl Snapshot {
+    pub fn take(dir: &Path) -> Result<Self> {
+        let head = git(dir, &["rev-parse", "HEAD"])?;
+        // `stash create` exits 1 when racily-clean files (rewritten within
+        // the index timestamp's second) turn out unchanged; `status`
+        // refreshes the index first.
+        git(dir, &["status", "--porcelain"])?;
+        // Captures uncommitted tracked changes without touching the tree.
+        let stash = Some(git(dir, &["stash", "create"])?).filter(|s| !s.is_empty());
+        let untracked = untracked_files(dir)?;
+        info!(
+            "Snapshot of {:?} at {}{}",
+            dir,
+            &head[..head.len().min(12)],
+            if stash.is_some() {
+                " (with local changes)"
+            } else {
+                ""
+            }
+        );
+        Ok(Snapshot {
+            dir: dir.to_path_buf(),
+            head,
+            stash,
+            untracked,
+            armed: true,
+        })
+    }
+
+    /// Keeps the current tree; nothing is rolled back on drop.
+    pub fn release(mut self) {
+        self.armed = false;
+    }
+
+    pub fn restore(&self) -> Result<()> {
+        git(&self.dir, &["reset", "--hard", "--quiet", &self.head])?;
+        if let Some(stash) = &self.stash {
+            git(&self.dir, &["stash", "apply", "--quiet", stash])?;
+        }
+        for path in untracked_files(&self.dir)?.difference(&self.untracked) {
+            // Build output (out/, out-<variant>/) is kept for incremental builds.
+            let top = path.split('/').next().unwrap_or_default();
+            if top == "out" || top.starts_with("out-") {
+                continue;
+            }
+            let full = self.dir.join(path);
+            if path.ends_with('/') {
+                fs::remove_dir_all(&full)?;
+            } else {
+                fs::remove_file(&full)?;
+            }
+            let mut parent = full.parent();
+            while let Some(p) = parent.filter(|p| *p != self.dir) {
+                if fs::remove_dir(p).is_err() {
+                    break;
+                }
+                parent = p.parent();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let _cleanup = interrupt::cleanup();
+        info!("Restoring {:?} to its snapshot...", self.dir);
+        match self.restore() {
+            Ok(()) => info!("Kernel source restored."),
+            Err(e) => warn!("failed to restore kernel source: {}", e),
+        }
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let mut cmd = vec!["git"];
+    cmd.extend_from_slice(args);
+    run_cmd(&cmd, Some(dir), true)?.ok_or_else(|| anyhow!("git produced no output"))
+}
+
+/// Untracked paths, ignored ones included: the kernel ignores `*.orig`
+/// and `*.rej`, which a failed patch leaves behind. Nested repos (e.g.
+/// KernelSU) are listed as a single `dir/` entry.
+fn untracked_files(dir: &Path) -> Result<HashSet<String>> {
+    let mut files = HashSet::new();
+    for ignored in [&[][..], &["--ignored"]] {
+        let mut args = vec!["ls-files", "--others", "--exclude-standard", "--directory"];
+        args.extend_from_slice(ignored);
+        files.extend(git(dir, &args)?.lines().map(str::to_string));
+    }
+    Ok(files)
+}
@@ -0,0 +1,266 @@
+use anyhow::{Result, anyhow};
+use log::{info, warn};
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::TimeoutConfig;
+use crate::events::{self, Event};
+use crate::interrupt;
+use crate::logging;
+use crate::output;
+use crate::trace;
+
+/// The deadline of the stage that is running, shared with download threads.
+static DEADLINE: Mutex<Option<Deadline>> = Mutex::new(None);
+/// The last stage entered; kept after the build ends for failure reports.
+static STAGE: Mutex<Option<String>> = Mutex::new(None);
+/// How long each finished stage of the current build took, in order.
+static TIMINGS: Mutex<Vec<(String, Duration)>> = Mutex::new(Vec::new());
+
+#[derive(Clone)]
+struct Deadline {
+    at: Instant,
+    stage: String,
+    minutes: u64,
+}
+
+/// A stage, or the whole build, ran past its `timeout_minutes`.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub stage: String,
+    pub minutes: u64,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out after {} min in {}", self.minutes, self.stage)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Tracks one build's `timeout_minutes` and marks its stages. Clears the
+/// deadline and ends the last stage when dropped.
+pub struct Timeouts {
+    cfg: TimeoutConfig,
+    started: Instant,
+    current: RefCell<Option<(String, Instant)>>,
+}
+
+impl Timeouts {
+    pub fn start(cfg: Option<&TimeoutConfig>) -> Self {
+        reset();
+        Timeouts {
+            cfg: cfg.cloned().unwrap_or_default(),
+            started: Instant::now(),
+            current: RefCell::new(None),
+        }
+    }
+
+    fn end_stage(&self) {
+        if let Some((stage, since)) = self.current.borrow_mut().take() {
+            let took = since.elapsed();
+            events::emit(Event::StageEnd {
+                stage: &stage,
+                duration_ms: took.as_millis(),
+            });
+            TIMINGS.lock().unwrap().push((stage, took));
+            trace::end_stage();
+        }
+    }
+
+    /// Enters a stage: commands started from now on are killed at the earlier
+    /// of the stage's limit and the overall one.
+    pub fn stage(&self, name: &str) {
+        self.end_stage();
+        info!(target: logging::STAGE, "{}", name);
+        events::emit(Event::StageStart { stage: name });
+        trace::begin_stage(name);
+        *self.current.borrow_mut() = Some((name.to_string(), Instant::now()));
+        *STAGE.lock().unwrap() = Some(name.to_string());
+        output::log_stage(name);
+        let stage_limit = self.cfg.stage(name).map(|minutes| Deadline {
+            at: Instant::now() + Duration::from_secs(minutes * 60),
+            stage: format!("stage '{}'", name),
+            minutes,
+        });
+        let total_limit = self.cfg.total.map(|minutes| Deadline {
+            at: self.started + Duration::from_secs(minutes * 60),
+            stage: "the build".to_string(),
+            minutes,
+        });
+        *DEADLINE.lock().unwrap() = match (stage_limit, total_limit) {
+            (Some(s), Some(t)) => Some(if s.at <= t.at { s } else { t }),
+            (s, t) => s.or(t),
+        };
+    }
+}
+
+impl Drop for Timeouts {
+    fn drop(&mut self) {
+        self.end_stage();
+        *DEADLINE.lock().unwrap() = None;
+    }
+}
+
+/// Forgets the last build's stages, so a build that fails before its first
+/// stage isn't reported as failing in the last build's.
+pub fn reset() {
+    *DEADLINE.lock().unwrap() = None;
+    *STAGE.lock().unwrap() = None;
+    TIMINGS.lock().unwrap().clear();
+}
+
+/// The stage the last build was in when it stopped.
+pub fn current_stage() -> Option<String> {
+    STAGE.lock().unwrap().clone()
+}
+
+/// The stages the last build finished (or stopped in) and how long each took.
+pub fn stage_timings() -> Vec<(String, Duration)> {
+    TIMINGS.lock().unwrap().clone()
+}
+
+/// Time left before the current deadline, or `TimedOut` if it has passed.
+/// After Ctrl-C this fails with `Interrupted` so nothing new starts.
+pub fn remaining() -> Result<Option<Duration>> {
+    interrupt::check()?;
+    let Some(deadline) = DEADLINE.lock().unwrap().clone() else {
+        return Ok(None);
+    };
+    match deadline.at.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Ok(Some(left)),
+        _ => Err(TimedOut {
+            stage: deadline.stage,
+            minutes: deadline.minutes,
+        }
+        .into()),
+    }
+}
+
+/// Runs `command` with the stdio it was configured with and collects its
+/// output. It gets its own process group so make and all of its children
+/// can be stopped together at a deadline or on Ctrl-C.
+pub fn run(command: &mut Command) -> Result<Output> {
+    let line = command_line(command);
+    let started = SystemTime::now();
+    let result = supervise(command, |child| child.wait_with_output());
+    let status = result.as_ref().ok().map(|o| o.status);
+    trace::command(
+        &line,
+        started,
+        status.and_then(|s| s.code()),
+        status.is_some_and(|s| s.success()),
+    );
+    result
+}
+
+fn command_line(command: &Command) -> String {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|a| a.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs `command` for its exit status, passing stdout/stderr through
+/// `output::tee` so a failure report can quote the end of the output.
+pub fn status_teed(command: &mut Command) -> Result<ExitStatus> {
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let line = command_line(command);
+    output::start_command(&line);
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let result = supervise(command, |mut child| {
+        let (tx, rx) = mpsc::channel();
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|r| Box::new(r) as Box<dyn Read + Send>),
+            child
+                .stderr
+                .take()
+                .map(|r| Box::new(r) as Box<dyn Read + Send>),
+        ];
+        for (i, reader) in readers.into_iter().enumerate() {
+            let Some(reader) = reader else { continue };
+            let tx = tx.clone();
+            thread::spawn(move || {
+                output::tee(reader, i == 1);
+                let _ = tx.send(());
+            });
+        }
+        drop(tx);
+        let status = child.wait();
+        // A daemon the command left behind (e.g. an sccache server) can hold
+        // the pipes open; don't wait for it.
+        let grace = Instant::now() + Duration::from_secs(2);
+        for _ in 0..2 {
+            let left = grace.saturating_duration_since(Instant::now());
+            if rx.recv_timeout(left).is_err() {
+                break;
+            }
+        }
+        status
+    });
+    let ok = result.as_ref().is_ok_and(|s| s.success());
+    let exit_code = result.as_ref().ok().and_then(|s| s.code());
+    events::emit(Event::Command {
+        command: &line,
+        ok,
+        exit_code,
+        duration_ms: started.elapsed().as_millis(),
+    });
+    trace::command(&line, started_at, exit_code, ok);
+    if !ok {
+        output::command_failed();
+    }
+    result
+}
+
+fn supervise<T: Send + 'static>(
+    command: &mut Command,
+    wait: impl FnOnce(Child) -> io::Result<T> + Send + 'static,
+) -> Result<T> {
+    let left = remaining()?;
+    let grouped = interrupt::installed();
+    if grouped {
+        command.process_group(0);
+    }
+    let child = command.spawn()?;
+    let pid = child.id() as i32;
+    let _tracked = grouped.then(|| interrupt::track(pid));
+    let Some(left) = left else {
+        let result = wait(child);
+        interrupt::check()?;
+        return Ok(result?);
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        let _ = tx.send(wait(child));
+    });
+
+    match rx.recv_timeout(left) {
+        Ok(result) => {
+            interrupt::check()?;
+            Ok(result?)
+        }
+        Err(_) => {
+            // SAFETY: signals the process (group) we just spawned.
+            unsafe { libc::kill(if grouped { -pid } else { pid }, libc::SIGKILL) };
+            let _ = waiter.join();
+            warn!("Killed {:?} at its deadline", command.get_program());
+            Err(remaining()
+                .err()
+                .unwrap_or_else(|| anyhow!("{:?} killed at its deadline", command.get_program())))
+        }
+    }
+}
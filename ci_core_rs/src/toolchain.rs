@@ -0,0 +1,281 @@
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::archive::extract_all;
+use crate::config::ProjectConfig;
+use crate::download::{DownloadJob, download_all};
+use crate::utils::{get_cache_dir, get_toolchain_lock_path, lock_file, save_json};
+
+const COMPLETE_MARKER: &str = ".kokuban_complete";
+
+/// A toolchain that is ready on disk. `root` is the directory it was
+/// extracted into; `toolchain_path_prefix` is resolved against it.
+pub struct ResolvedToolchain {
+    pub root: PathBuf,
+    pub urls: Vec<String>,
+    pub sha256: Vec<String>,
+}
+
+/// Contents of the completion marker, so a cached toolchain still knows the
+/// hashes of the archives it came from after they have been deleted.
+#[derive(Serialize, Deserialize)]
+struct CacheMarker {
+    urls: Vec<String>,
+    sha256: Vec<String>,
+}
+
+/// One `toolchain.lock` entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolchainLock {
+    pub urls: Vec<String>,
+    pub sha256: Vec<String>,
+    pub clang_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gcc_version: Option<String>,
+    pub linker_version: Option<String>,
+}
+
+pub fn setup_toolchain(proj: &ProjectConfig, refresh: bool) -> Result<ResolvedToolchain> {
+    let Some(urls) = &proj.toolchain_urls else {
+        return Ok(ResolvedToolchain {
+            root: env::current_dir()?,
+            urls: Vec::new(),
+            sha256: Vec::new(),
+        });
+    };
+
+    if !refresh && let Some(cached) = cached_toolchain(proj) {
+        info!("Using cached toolchain: {}", cached.root.display());
+        return Ok(cached);
+    }
+
+    let toolchain_root = get_cache_dir().join("toolchains").join(cache_key(urls));
+    let marker = toolchain_root.join(COMPLETE_MARKER);
+    // Another build setting up the same toolchain would look half-done
+    // below; wait for it, then use what it extracted.
+    let _lock = lock_file(&toolchain_root.with_extension("lock"))?;
+    if !refresh && let Some(cached) = cached_toolchain(proj) {
+        info!("Using cached toolchain: {}", cached.root.display());
+        return Ok(cached);
+    }
+
+    let tc_download_dir = toolchain_root.join("toolchain_download");
+    if refresh && toolchain_root.exists() {
+        fs::remove_dir_all(&toolchain_root)?;
+    } else if toolchain_root.exists() {
+        // A previous run died half-way: drop whatever was extracted but keep
+        // the downloads so they can be resumed.
+        for entry in fs::read_dir(&toolchain_root)? {
+            let path = entry?.path();
+            if path == tc_download_dir {
+                continue;
+            }
+            if path.is_dir() {
+                fs::remove_dir_all(&path)?;
+            } else {
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+    fs::create_dir_all(&tc_download_dir)?;
+
+    let checksums = proj.toolchain_sha256.as_deref().unwrap_or_default();
+    if !checksums.is_empty() && checksums.len() != urls.len() {
+        return Err(anyhow!(
+            "toolchain_sha256 has {} entries but toolchain_urls has {}",
+            checksums.len(),
+            urls.len()
+        ));
+    }
+
+    let jobs: Vec<DownloadJob> = urls
+        .iter()
+        .map(|url| DownloadJob {
+            url: url.clone(),
+            dest: tc_download_dir.join(archive_name(url)),
+        })
+        .collect();
+    info!("Downloading {} toolchain archive(s)...", jobs.len());
+    download_all(&jobs)?;
+
+    for (job, expected) in jobs.iter().zip(checksums) {
+        if let Err(e) = verify_sha256(&job.dest, expected) {
+            // Don't let the next run resume on top of a bad file.
+            fs::remove_file(&job.dest)?;
+            return Err(e);
+        }
+    }
+    let sha256 = jobs
+        .iter()
+        .map(|job| sha256_file(&job.dest))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!("Extracting toolchain...");
+    extract_all(&tc_download_dir, &toolchain_root)?;
+
+    fs::remove_dir_all(tc_download_dir)?;
+    let resolved = ResolvedToolchain {
+        root: toolchain_root,
+        urls: urls.clone(),
+        sha256,
+    };
+    save_json(
+        &marker,
+        &CacheMarker {
+            urls: resolved.urls.clone(),
+            sha256: resolved.sha256.clone(),
+        },
+    )?;
+
+    Ok(resolved)
+}
+
+/// The project's toolchain if it is fully extracted in the cache.
+pub fn cached_toolchain(proj: &ProjectConfig) -> Option<ResolvedToolchain> {
+    let urls = proj.toolchain_urls.as_ref()?;
+    let toolchain_root = get_cache_dir().join("toolchains").join(cache_key(urls));
+    let content = fs::read_to_string(toolchain_root.join(COMPLETE_MARKER)).ok()?;
+    let cached: CacheMarker = serde_json::from_str(&content).ok()?;
+    Some(ResolvedToolchain {
+        root: toolchain_root,
+        urls: cached.urls,
+        sha256: cached.sha256,
+    })
+}
+
+/// Records the resolved toolchain in `toolchain.lock`, or with `locked`
+/// refuses to continue if it differs from what is recorded there.
+/// `gcc_prefix` is the CROSS_COMPILE prefix of a GCC build; `None` means clang.
+pub fn check_lock(
+    project_key: &str,
+    toolchain: &ResolvedToolchain,
+    build_env: &HashMap<String, String>,
+    gcc_prefix: Option<&str>,
+    locked: bool,
+) -> Result<()> {
+    let current = match gcc_prefix {
+        None => ToolchainLock {
+            urls: toolchain.urls.clone(),
+            sha256: toolchain.sha256.clone(),
+            clang_version: tool_version("clang", build_env),
+            gcc_version: None,
+            linker_version: tool_version("ld.lld", build_env),
+        },
+        Some(prefix) => ToolchainLock {
+            urls: toolchain.urls.clone(),
+            sha256: toolchain.sha256.clone(),
+            clang_version: None,
+            gcc_version: tool_version(&format!("{}gcc", prefix), build_env),
+            linker_version: tool_version(&format!("{}ld", prefix), build_env),
+        },
+    };
+
+    let lock_path = get_toolchain_lock_path();
+    // Held across the read and the write, so builds running side by side
+    // don't drop each other's entries. --locked only reads.
+    let _guard = if locked {
+        None
+    } else {
+        Some(lock_file(&lock_path)?)
+    };
+    let content = if lock_path.exists() {
+        fs::read_to_string(&lock_path)?
+    } else {
+        String::new()
+    };
+    let mut lock: BTreeMap<String, ToolchainLock> = if content.trim().is_empty() {
+        BTreeMap::new()
+    } else {
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {:?}", lock_path))?
+    };
+
+    if locked {
+        let recorded = lock.get(project_key).ok_or_else(|| {
+            anyhow!(
+                "--locked: no entry for '{}' in {:?}",
+                project_key,
+                lock_path
+            )
+        })?;
+        if *recorded != current {
+            return Err(anyhow!(
+                "--locked: toolchain for '{}' differs from {:?}\n  locked:   {:?}\n  resolved: {:?}",
+                project_key,
+                lock_path,
+                recorded,
+                current
+            ));
+        }
+        info!("Toolchain matches {:?}", lock_path);
+        return Ok(());
+    }
+
+    if lock.get(project_key) != Some(&current) {
+        lock.insert(project_key.to_string(), current);
+        save_json(&lock_path, &lock)?;
+        info!("Updated {:?} for {}", lock_path, project_key);
+    }
+    Ok(())
+}
+
+/// First line of `<tool> --version` using the build PATH, if the tool exists.
+fn tool_version(tool: &str, build_env: &HashMap<String, String>) -> Option<String> {
+    let output = Command::new(tool)
+        .arg("--version")
+        .envs(build_env)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|l| l.trim().to_string())
+}
+
+/// Cache directory name for a URL set.
+fn cache_key(urls: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for url in urls {
+        hasher.update(url.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// File name a URL is saved under, i.e. its last path segment without query.
+fn archive_name(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn verify_sha256(path: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {} (corrupted or partial download?)",
+            path.display(),
+            expected.trim(),
+            actual
+        ));
+    }
+    info!("Checksum OK: {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde_json::{Value, json};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::secrets;
+
+/// The trace of the build that is running; `None` when no OTLP endpoint is
+/// configured, which turns every function here into a no-op.
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+struct Trace {
+    id: String,
+    root: OpenSpan,
+    stage: Option<OpenSpan>,
+    /// Index in `finished` of the last stage span, marked failed if the
+    /// build was.
+    last_stage: Option<usize>,
+    finished: Vec<Value>,
+}
+
+struct OpenSpan {
+    id: String,
+    name: String,
+    start: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl OpenSpan {
+    fn new(name: &str, attributes: Vec<(&'static str, String)>) -> Self {
+        OpenSpan {
+            id: random_hex(8),
+            name: name.to_string(),
+            start: SystemTime::now(),
+            attributes,
+        }
+    }
+
+    fn finish(self, trace_id: &str, parent: Option<&str>, error: Option<&str>) -> Value {
+        span_json(
+            trace_id,
+            &self.id,
+            parent,
+            &self.name,
+            self.start,
+            SystemTime::now(),
+            &self.attributes,
+            error,
+        )
+    }
+}
+
+fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    if File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut buf))
+        .is_err()
+    {
+        let nanos = unix_nanos(SystemTime::now()) ^ std::process::id() as u128;
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = (nanos >> ((i % 16) * 8)) as u8;
+        }
+    }
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unix_nanos(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default()
+}
+
+/// One span in the OTLP/JSON encoding.
+#[allow(clippy::too_many_arguments)]
+fn span_json(
+    trace_id: &str,
+    span_id: &str,
+    parent: Option<&str>,
+    name: &str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: &[(&'static str, String)],
+    error: Option<&str>,
+) -> Value {
+    let attributes: Vec<Value> = attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect();
+    let mut span = json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "name": name,
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(start).to_string(),
+        "endTimeUnixNano": unix_nanos(end).to_string(),
+        "attributes": attributes,
+        "status": match error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        },
+    });
+    if let Some(parent) = parent {
+        span["parentSpanId"] = parent.into();
+    }
+    span
+}
+
+/// `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, else `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// plus `/v1/traces`. Only OTLP over HTTP with JSON is supported.
+fn endpoint() -> Option<String> {
+    if let Ok(url) = env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+        return Some(url);
+    }
+    env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .ok()
+        .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+}
+
+/// Starts the root span of a build if tracing is configured.
+pub fn begin_build(project: &str, branch: &str) {
+    if endpoint().is_none() {
+        return;
+    }
+    let root = OpenSpan::new(
+        &format!("build {} / {}", project, branch),
+        vec![
+            ("kokuban.project", project.to_string()),
+            ("kokuban.branch", branch.to_string()),
+        ],
+    );
+    *TRACE.lock().unwrap() = Some(Trace {
+        id: random_hex(16),
+        root,
+        stage: None,
+        last_stage: None,
+        finished: Vec::new(),
+    });
+}
+
+pub fn begin_stage(name: &str) {
+    let mut trace = TRACE.lock().unwrap();
+    let Some(trace) = trace.as_mut() else {
+        return;
+    };
+    end_stage_locked(trace);
+    trace.stage = Some(OpenSpan::new(
+        name,
+        vec![("kokuban.stage", name.to_string())],
+    ));
+}
+
+pub fn end_stage() {
+    if let Some(trace) = TRACE.lock().unwrap().as_mut() {
+        end_stage_locked(trace);
+    }
+}
+
+fn end_stage_locked(trace: &mut Trace) {
+    if let Some(stage) = trace.stage.take() {
+        let span = stage.finish(&trace.id, Some(&trace.root.id), None);
+        trace.last_stage = Some(trace.finished.len());
+        trace.finished.push(span);
+    }
+}
+
+/// Records a finished command under the current stage.
+pub fn command(line: &str, start: SystemTime, exit_code: Option<i32>, ok: bool) {
+    let mut trace = TRACE.lock().unwrap();
+    let Some(trace) = trace.as_mut() else {
+        return;
+    };
+    let program = line.split_whitespace().next().unwrap_or_default();
+    let mut attributes = vec![("process.command_line", secrets::redact(line).into_owned())];
+    if let Some(code) = exit_code {
+        attributes.push(("process.exit_code", code.to_string()));
+    }
+    let parent = trace.stage.as_ref().unwrap_or(&trace.root).id.clone();
+    let span = span_json(
+        &trace.id,
+        &random_hex(8),
+        Some(&parent),
+        program,
+        start,
+        SystemTime::now(),
+        &attributes,
+        (!ok).then_some("command failed"),
+    );
+    trace.finished.push(span);
+}
+
+/// Ends the build's spans and sends them to the collector. The stage that
+/// was running when the build failed is marked as failed too.
+pub fn end_build(error: Option<&str>) {
+    let Some(mut trace) = TRACE.lock().unwrap().take() else {
+        return;
+    };
+    let Some(url) = endpoint() else {
+        return;
+    };
+    end_stage_locked(&mut trace);
+    let error = error.map(secrets::redact);
+    let error = error.as_deref();
+    if let (Some(message), Some(i)) = (error, trace.last_stage) {
+        trace.finished[i]["status"] = json!({ "code": 2, "message": message });
+    }
+    let root = trace.root.finish(&trace.id, None, error);
+    trace.finished.push(root);
+
+    let service = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "kokuban-ci".to_string());
+    let body = json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{ "key": "service.name", "value": { "stringValue": service } }],
+            },
+            "scopeSpans": [{
+                "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                "spans": trace.finished,
+            }],
+        }],
+    });
+    let mut req = Client::new().post(&url).json(&body);
+    // "key1=value1,key2=value2", as the OTel SDKs read it.
+    if let Some(headers) = secrets::find("OTEL_EXPORTER_OTLP_HEADERS") {
+        for (key, value) in headers.split(',').filter_map(|h| h.split_once('=')) {
+            secrets::remember(value.trim());
+            req = req.header(key.trim(), value.trim());
+        }
+    }
+    match req.send() {
+        Ok(resp) if resp.status().is_success() => {
+            info!("Exported trace {} to {}", trace.id, url)
+        }
+        Ok(resp) => warn!(
+            "OTLP export failed: HTTP {}: {}",
+            resp.status(),
+            resp.text().unwrap_or_default().trim()
+        ),
+        Err(e) => warn!("OTLP export failed: {}", e),
+    }
+}
@@ -0,0 +1,348 @@
+use anyhow::{Result, anyhow};
+use ratatui::crossterm::event::{self, Event as TermEvent, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::env;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::compiler_cache::CacheStats;
+use crate::utils::format_duration;
+
+/// Output lines kept for the log pane.
+const LOG_LINES: usize = 1000;
+const STAGE_PANE_WIDTH: u16 = 36;
+
+enum Msg {
+    Event(Value),
+    Log(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    Done,
+    Failed,
+}
+
+impl State {
+    fn symbol(self) -> Span<'static> {
+        match self {
+            State::Running => Span::styled("▶", Style::new().fg(Color::Cyan)),
+            State::Done => Span::styled("✔", Style::new().fg(Color::Green)),
+            State::Failed => Span::styled("✘", Style::new().fg(Color::Red)),
+        }
+    }
+}
+
+struct Stage {
+    name: String,
+    state: State,
+    started: Instant,
+    took: Option<Duration>,
+}
+
+/// One `build_start` .. `build_end`; a matrix run has one per variant.
+struct BuildView {
+    label: String,
+    state: State,
+    started: Instant,
+    took: Option<Duration>,
+    stages: Vec<Stage>,
+    /// Tool and counters from the last `cache_stats` event.
+    cache: Option<(String, CacheStats)>,
+}
+
+struct Dashboard {
+    command: String,
+    started: Instant,
+    builds: Vec<BuildView>,
+    log: VecDeque<String>,
+    exit: Option<ExitStatus>,
+    /// How long the child ran, once it has exited.
+    ended: Option<Duration>,
+    /// `q` was pressed while the build was running.
+    stopping: bool,
+}
+
+/// Runs `build ...` or `build-all ...` as a child with `--output json` and
+/// shows its stages, output and compiler cache hit rate until `q`.
+pub fn handle_tui(args: &[String]) -> Result<()> {
+    if !matches!(args[0].as_str(), "build" | "build-all") {
+        return Err(anyhow!(
+            "tui runs `build` or `build-all`, e.g. `tui build --project <key> --branches ksu,mksu`"
+        ));
+    }
+    if !io::stdout().is_terminal() {
+        return Err(anyhow!("tui needs a terminal; run the build directly"));
+    }
+
+    let mut child = Command::new(env::current_exe()?)
+        .args(["--output", "json", "--no-color"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Ctrl-C is a key press here; the build only stops on `q`.
+        .process_group(0)
+        .spawn()?;
+    let (tx, rx) = mpsc::channel();
+    forward(child.stdout.take().unwrap(), tx.clone(), |line| {
+        serde_json::from_str(&line)
+            .map(Msg::Event)
+            .unwrap_or(Msg::Log(line))
+    });
+    forward(child.stderr.take().unwrap(), tx, Msg::Log);
+
+    let mut dashboard = Dashboard {
+        command: args.join(" "),
+        started: Instant::now(),
+        builds: Vec::new(),
+        log: VecDeque::new(),
+        exit: None,
+        ended: None,
+        stopping: false,
+    };
+    let mut terminal = ratatui::init();
+    let result = dashboard.run(&mut terminal, &mut child, &rx);
+    ratatui::restore();
+
+    let status = result?;
+    for build in &dashboard.builds {
+        let took = build.took.map(format_duration).unwrap_or_default();
+        let result = match build.state {
+            State::Done => "ok",
+            State::Failed => "failed",
+            State::Running => "stopped",
+        };
+        println!("{}: {} {}", build.label, result, took);
+    }
+    if !status.success() {
+        return Err(anyhow!("{} failed ({})", dashboard.command, status));
+    }
+    Ok(())
+}
+
+fn forward(
+    reader: impl Read + Send + 'static,
+    tx: Sender<Msg>,
+    wrap: impl Fn(String) -> Msg + Send + 'static,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim_end().replace('\t', "    ");
+            if tx.send(wrap(line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+impl Dashboard {
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        child: &mut Child,
+        rx: &Receiver<Msg>,
+    ) -> Result<ExitStatus> {
+        loop {
+            while let Ok(msg) = rx.try_recv() {
+                match msg {
+                    Msg::Event(event) => self.apply(&event),
+                    Msg::Log(line) => self.push_log(line),
+                }
+            }
+            if self.exit.is_none() {
+                self.exit = child.try_wait()?;
+                if self.exit.is_some() {
+                    self.ended = Some(self.started.elapsed());
+                }
+            }
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if !event::poll(Duration::from_millis(250))? {
+                continue;
+            }
+            let TermEvent::Key(key) = event::read()? else {
+                continue;
+            };
+            let quit = key.kind == KeyEventKind::Press
+                && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+                    || (key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)));
+            if !quit {
+                continue;
+            }
+            if let Some(status) = self.exit {
+                return Ok(status);
+            }
+            // The build stops its commands and cleans up; a second signal
+            // makes it exit at once.
+            // SAFETY: signals the child we spawned.
+            unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+            self.stopping = true;
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    fn apply(&mut self, event: &Value) {
+        let text = |key: &str| event[key].as_str().unwrap_or_default().to_string();
+        let millis = |key: &str| Duration::from_millis(event[key].as_u64().unwrap_or(0));
+        let kind = text("event");
+        if kind == "build_start" {
+            self.builds.push(BuildView {
+                label: format!("{} / {}", text("project"), text("branch")),
+                state: State::Running,
+                started: Instant::now(),
+                took: None,
+                stages: Vec::new(),
+                cache: None,
+            });
+            return;
+        }
+        let Some(build) = self.builds.last_mut() else {
+            return;
+        };
+        match kind.as_str() {
+            "stage_start" => build.stages.push(Stage {
+                name: text("stage"),
+                state: State::Running,
+                started: Instant::now(),
+                took: None,
+            }),
+            "stage_end" => {
+                let name = text("stage");
+                if let Some(stage) = build
+                    .stages
+                    .iter_mut()
+                    .rev()
+                    .find(|s| s.name == name && s.state == State::Running)
+                {
+                    stage.state = State::Done;
+                    stage.took = Some(millis("duration_ms"));
+                }
+            }
+            "cache_stats" => {
+                let stats = CacheStats {
+                    hits: event["hits"].as_u64().unwrap_or(0),
+                    misses: event["misses"].as_u64().unwrap_or(0),
+                };
+                build.cache = Some((text("tool"), stats));
+            }
+            "build_end" => {
+                let ok = event["ok"].as_bool().unwrap_or(false);
+                build.state = if ok { State::Done } else { State::Failed };
+                build.took = Some(millis("duration_ms"));
+                // The stage the build stopped in was ended as if it had
+                // finished.
+                if !ok && let Some(stage) = build.stages.last_mut() {
+                    stage.state = State::Failed;
+                }
+                if let Some(error) = event["error"].as_str() {
+                    let line = format!("✘ {}: {}", build.label, error);
+                    self.push_log(line);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [stages, log] =
+            Layout::horizontal([Constraint::Length(STAGE_PANE_WIDTH), Constraint::Min(0)])
+                .areas(body);
+
+        let status = match self.exit {
+            None => Span::styled("running", Style::new().fg(Color::Cyan)),
+            Some(s) if s.success() => Span::styled("finished", Style::new().fg(Color::Green)),
+            Some(s) => Span::styled(format!("failed ({})", s), Style::new().fg(Color::Red)),
+        };
+        let elapsed = self.ended.unwrap_or_else(|| self.started.elapsed());
+        let mut summary = vec![
+            status,
+            Span::raw(format!("  elapsed {}", format_duration(elapsed))),
+        ];
+        if let Some((tool, stats)) = self.builds.last().and_then(|b| b.cache.as_ref()) {
+            summary.push(Span::raw(format!("  {}: {}", tool, stats)));
+        }
+        frame.render_widget(
+            Paragraph::new(Line::from(summary))
+                .block(Block::bordered().title(format!(" kokuban_ci_core {} ", self.command))),
+            header,
+        );
+
+        let mut lines = Vec::new();
+        for build in &self.builds {
+            let took = build.took.unwrap_or_else(|| build.started.elapsed());
+            lines.push(Line::from(vec![
+                build.state.symbol(),
+                Span::styled(
+                    format!(" {} ", build.label),
+                    Style::new().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(format_duration(took)),
+            ]));
+            for stage in &build.stages {
+                let took = stage.took.unwrap_or_else(|| stage.started.elapsed());
+                lines.push(Line::from(vec![
+                    Span::raw("  "),
+                    stage.state.symbol(),
+                    Span::raw(format!(" {:<14} {}", stage.name, format_duration(took))),
+                ]));
+            }
+        }
+        // Keep the build that is running in view.
+        let height = stages.height.saturating_sub(2) as usize;
+        let scroll = lines.len().saturating_sub(height) as u16;
+        frame.render_widget(
+            Paragraph::new(lines)
+                .scroll((scroll, 0))
+                .block(Block::bordered().title(" Stages ")),
+            stages,
+        );
+
+        let height = log.height.saturating_sub(2) as usize;
+        let tail: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(height))
+            .map(|l| Line::raw(l.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(tail).block(Block::bordered().title(" Output ")),
+            log,
+        );
+
+        let hint = match (self.exit, self.stopping) {
+            (None, false) => " q: stop the build",
+            (None, true) => " stopping, cleaning up... q: stop right away",
+            (Some(_), _) => " q: quit",
+        };
+        frame.render_widget(
+            Paragraph::new(hint).style(Style::new().add_modifier(Modifier::DIM)),
+            footer,
+        );
+    }
+}
@@ -0,0 +1,48 @@
+mod s3;
+mod ssh;
+
+use anyhow::{Result, anyhow};
+use log::info;
+use std::path::PathBuf;
+
+use crate::config::DistributionConfig;
+use s3::S3Bucket;
+use ssh::SshTarget;
+
+/// Pushes release artifacts to every non-GitHub target in `cfg`, under a
+/// directory named after the release tag.
+pub fn upload_artifacts(cfg: &DistributionConfig, tag: &str, files: &[PathBuf]) -> Result<()> {
+    if let Some(s3_cfg) = &cfg.s3 {
+        let bucket = S3Bucket::new(s3_cfg)?;
+        for file in files {
+            let name = file
+                .file_name()
+                .ok_or_else(|| anyhow!("{} has no file name", file.display()))?
+                .to_string_lossy();
+            let key = bucket.key(tag, &name);
+            info!("Uploading {} to s3://{}/{}", name, s3_cfg.bucket, key);
+            bucket.put_file(file, &key)?;
+            info!("  -> {}", bucket.public_link(&key)?);
+        }
+    }
+    if let Some(sftp_cfg) = &cfg.sftp {
+        let target = SshTarget::new(sftp_cfg)?;
+        target.sftp(tag, files)?;
+        print_links(&target, tag, files);
+    }
+    if let Some(rsync_cfg) = &cfg.rsync {
+        let target = SshTarget::new(rsync_cfg)?;
+        target.rsync(tag, files)?;
+        print_links(&target, tag, files);
+    }
+    Ok(())
+}
+
+fn print_links(target: &SshTarget, tag: &str, files: &[PathBuf]) {
+    for file in files {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        if let Some(link) = target.public_link(tag, &name) {
+            info!("  -> {}", link);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::info;
+use reqwest::Url;
+use reqwest::blocking::{Body, Client};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::S3Config;
+use crate::interrupt;
+use crate::retry;
+use crate::secrets;
+use crate::utils::url_encode;
+
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    fn from_env() -> Result<Self> {
+        Ok(Credentials {
+            access_key: secrets::get("AWS_ACCESS_KEY_ID", "S3 uploads")?,
+            secret_key: secrets::get("AWS_SECRET_ACCESS_KEY", "S3 uploads")?,
+            session_token: secrets::find("AWS_SESSION_TOKEN"),
+        })
+    }
+}
+
+pub struct S3Bucket<'a> {
+    cfg: &'a S3Config,
+    region: String,
+    credentials: Credentials,
+    client: Client,
+}
+
+impl<'a> S3Bucket<'a> {
+    pub fn new(cfg: &'a S3Config) -> Result<Self> {
+        let region = cfg
+            .region
+            .clone()
+            .or_else(|| env::var("AWS_REGION").ok())
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(30))
+            .timeout(None)
+            .build()?;
+        Ok(S3Bucket {
+            cfg,
+            region,
+            credentials: Credentials::from_env()?,
+            client,
+        })
+    }
+
+    /// `<prefix>/<dir>/<file>` without leading or doubled slashes.
+    pub fn key(&self, dir: &str, file: &str) -> String {
+        self.cfg
+            .prefix
+            .iter()
+            .map(|p| p.trim_matches('/'))
+            .chain([dir, file])
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn object_url(&self, key: &str) -> Result<Url> {
+        let path = key.split('/').map(url_encode).collect::<Vec<_>>().join("/");
+        let url = match &self.cfg.endpoint {
+            Some(endpoint) if self.cfg.path_style.unwrap_or(true) => format!(
+                "{}/{}/{}",
+                endpoint.trim_end_matches('/'),
+                self.cfg.bucket,
+                path
+            ),
+            Some(endpoint) => {
+                let mut url = Url::parse(endpoint)?;
+                let host = format!("{}.{}", self.cfg.bucket, url.host_str().unwrap_or_default());
+                url.set_host(Some(&host))?;
+                url.set_path(&path);
+                return Ok(url);
+            }
+            None if self.cfg.path_style.unwrap_or(false) => format!(
+                "https://s3.{}.amazonaws.com/{}/{}",
+                self.region, self.cfg.bucket, path
+            ),
+            None => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.cfg.bucket, self.region, path
+            ),
+        };
+        Ok(Url::parse(&url)?)
+    }
+
+    /// Where the object can be fetched: `public_url` if set, else the API URL.
+    pub fn public_link(&self, key: &str) -> Result<String> {
+        match &self.cfg.public_url {
+            Some(base) => Ok(format!("{}/{}", base.trim_end_matches('/'), key)),
+            None => Ok(self.object_url(key)?.to_string()),
+        }
+    }
+
+    /// PUTs `path` to `key`, retrying network errors and 5xx.
+    pub fn put_file(&self, path: &Path, key: &str) -> Result<()> {
+        let url = self.object_url(key)?;
+        let len = fs::metadata(path)?.len();
+        let payload_hash = {
+            let mut hasher = Sha256::new();
+            io::copy(&mut File::open(path)?, &mut hasher)?;
+            format!("{:x}", hasher.finalize())
+        };
+
+        let policy = retry::policy();
+        let mut attempt = 1;
+        loop {
+            let now = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+            let mut headers = vec![
+                ("host".to_string(), host),
+                ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+                ("x-amz-date".to_string(), now.clone()),
+            ];
+            if let Some(token) = &self.credentials.session_token {
+                headers.push(("x-amz-security-token".to_string(), token.clone()));
+            }
+            let authorization = sign(
+                &self.credentials,
+                &self.region,
+                "PUT",
+                url.path(),
+                &headers,
+                &payload_hash,
+                &now,
+            );
+
+            let mut req = self
+                .client
+                .put(url.clone())
+                .header("authorization", authorization)
+                .header("content-type", "application/octet-stream")
+                .body(Body::sized(File::open(path)?, len));
+            for (name, value) in headers.iter().filter(|(n, _)| n != "host") {
+                req = req.header(name.as_str(), value.as_str());
+            }
+
+            let error = match req.send() {
+                Ok(resp) if resp.status().is_success() => return Ok(()),
+                Ok(resp) if resp.status().is_server_error() => format!("HTTP {}", resp.status()),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let body = resp.text().unwrap_or_default();
+                    return Err(anyhow!(
+                        "S3 PUT {} failed: HTTP {}: {}",
+                        key,
+                        status,
+                        body.trim()
+                    ));
+                }
+                Err(e) => e.to_string(),
+            };
+            if attempt >= policy.attempts || interrupt::interrupted() {
+                return Err(anyhow!(
+                    "S3 PUT {} failed after {} attempts: {}",
+                    key,
+                    attempt,
+                    error
+                ));
+            }
+            let delay = policy.delay(attempt);
+            info!(
+                "Attempt {}/{} to upload {} failed: {}. Retrying in {:.1}s...",
+                attempt,
+                policy.attempts,
+                key,
+                error,
+                delay.as_secs_f64()
+            );
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// AWS Signature Version 4 `Authorization` header for a request without a
+/// query string. `headers` are the signed ones, with lowercase names.
+fn sign(
+    credentials: &Credentials,
+    region: &str,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(n, v)| format!("{}:{}\n", n, v.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(n, _)| n.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+        amz_date,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+
+    let key = [date, region, "s3", "aws4_request"].iter().fold(
+        format!("AWS4{}", credentials.secret_key).into_bytes(),
+        |key, part| hmac(&key, part),
+    );
+    let signature: String = hmac(&key, &string_to_sign)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key, scope, signed_headers, signature
+    )
+}
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result, anyhow};
+use log::info;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::config::SshTargetConfig;
+use crate::secrets;
+use crate::utils::{get_root_dir, run_cmd};
+
+/// A file only this process uses, removed on drop.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn create(name: &str, contents: &str) -> Result<Self> {
+        let path = env::temp_dir().join(format!("kokuban-{}-{}", name, process::id()));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(TempFile(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+pub struct SshTarget<'a> {
+    cfg: &'a SshTargetConfig,
+    key: Option<PathBuf>,
+    _key_file: Option<TempFile>,
+}
+
+impl<'a> SshTarget<'a> {
+    pub fn new(cfg: &'a SshTargetConfig) -> Result<Self> {
+        let mut key_file = None;
+        let key = match (&cfg.key, &cfg.key_env) {
+            (Some(key), _) => {
+                let path = get_root_dir().join(key);
+                if !path.is_file() {
+                    return Err(anyhow!("ssh key {} not found", path.display()));
+                }
+                Some(path)
+            }
+            (None, Some(var)) => {
+                let mut material = secrets::get(var, &format!("ssh key for {}", cfg.host))?;
+                if !material.ends_with('\n') {
+                    material.push('\n');
+                }
+                let file = TempFile::create("ssh-key", &material)?;
+                let path = file.0.clone();
+                key_file = Some(file);
+                Some(path)
+            }
+            // Whatever the ssh agent or ~/.ssh offers.
+            (None, None) => None,
+        };
+        Ok(SshTarget {
+            cfg,
+            key,
+            _key_file: key_file,
+        })
+    }
+
+    fn destination(&self) -> String {
+        format!("{}@{}", self.cfg.user, self.cfg.host)
+    }
+
+    fn remote_dir(&self, tag: &str) -> String {
+        format!("{}/{}", self.cfg.path.trim_end_matches('/'), tag)
+    }
+
+    /// Options shared by ssh and sftp; only the port flag differs.
+    fn ssh_options(&self, port_flag: &str) -> Vec<String> {
+        let mut opts = vec![
+            "-o".to_string(),
+            "BatchMode=yes".to_string(),
+            "-o".to_string(),
+            "StrictHostKeyChecking=accept-new".to_string(),
+        ];
+        if let Some(key) = &self.key {
+            opts.extend([
+                "-i".to_string(),
+                key.display().to_string(),
+                "-o".to_string(),
+                "IdentitiesOnly=yes".to_string(),
+            ]);
+        }
+        if let Some(port) = self.cfg.port {
+            opts.extend([port_flag.to_string(), port.to_string()]);
+        }
+        opts
+    }
+
+    pub fn public_link(&self, tag: &str, name: &str) -> Option<String> {
+        self.cfg
+            .public_url
+            .as_ref()
+            .map(|base| format!("{}/{}/{}", base.trim_end_matches('/'), tag, name))
+    }
+
+    /// Uploads with an sftp batch; `-mkdir` tolerates existing directories.
+    pub fn sftp(&self, tag: &str, files: &[PathBuf]) -> Result<()> {
+        let dir = self.remote_dir(tag);
+        let mut batch = format!("-mkdir \"{}\"\n-mkdir \"{}\"\n", self.cfg.path, dir);
+        for file in files {
+            batch.push_str(&format!("put \"{}\" \"{}/\"\n", absolute(file)?, dir));
+        }
+        let batch = TempFile::create("sftp-batch", &batch)?;
+
+        let batch_path = batch.0.display().to_string();
+        let opts = self.ssh_options("-P");
+        let destination = self.destination();
+        let mut cmd = vec!["sftp", "-b", batch_path.as_str()];
+        cmd.extend(opts.iter().map(|s| s.as_str()));
+        cmd.push(&destination);
+        info!(
+            "Uploading {} file(s) to sftp://{}{}",
+            files.len(),
+            destination,
+            dir
+        );
+        run_cmd(&cmd, None, false)?;
+        Ok(())
+    }
+
+    /// rsync over ssh; `--partial` lets a retried upload resume.
+    pub fn rsync(&self, tag: &str, files: &[PathBuf]) -> Result<()> {
+        let shell = format!("ssh {}", self.ssh_options("-p").join(" "));
+        let target = format!("{}:{}/", self.destination(), self.remote_dir(tag));
+        let sources = files
+            .iter()
+            .map(|f| absolute(f))
+            .collect::<Result<Vec<_>>>()?;
+        let mut cmd = vec![
+            "rsync",
+            "-av",
+            "--partial",
+            "--mkpath",
+            "-e",
+            shell.as_str(),
+        ];
+        cmd.extend(sources.iter().map(|s| s.as_str()));
+        cmd.push(&target);
+        info!("Uploading {} file(s) to {}", files.len(), target);
+        run_cmd(&cmd, None, false)?;
+        Ok(())
+    }
+}
+
+fn absolute(path: &Path) -> Result<String> {
+    Ok(fs::canonicalize(path)
+        .with_context(|| format!("{} not found", path.display()))?
+        .display()
+        .to_string())
+}
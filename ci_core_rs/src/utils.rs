@@ -1,14 +1,19 @@
 use anyhow::{Context, Result, anyhow};
+use log::{debug, info, trace, warn};
 use std::collections::HashMap;
 use std::env;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::thread; // 新增
-use std::time::Duration; // 新增
+use std::time::Duration;
 
-use crate::config::{GlobalConfig, ProjectConfig, ProjectsMap};
+use crate::config::{GlobalConfig, ProjectsMap};
+use crate::error::CommandFailed;
+use crate::output;
+use crate::secrets;
+use crate::timeout;
 
 pub fn get_root_dir() -> PathBuf {
     env::var("CI_CENTRAL_ROOT")
@@ -17,26 +22,301 @@ pub fn get_root_dir() -> PathBuf {
 }
 
 pub fn get_config_path() -> PathBuf {
-    get_root_dir().join("configs/projects.json")
+    let configs_dir = get_root_dir().join("configs");
+    for name in [
+        "projects.json",
+        "projects.toml",
+        "projects.yaml",
+        "projects.yml",
+    ] {
+        let path = configs_dir.join(name);
+        if path.exists() {
+            return path;
+        }
+    }
+    configs_dir.join("projects.json")
 }
 
 pub fn get_upstream_path() -> PathBuf {
     get_root_dir().join("configs/upstream_commits.json")
 }
 
+pub fn get_toolchain_lock_path() -> PathBuf {
+    get_root_dir().join("configs/toolchain.lock")
+}
+
 pub fn get_workspace_dir() -> PathBuf {
     get_root_dir().join("kernel_workspace")
 }
 
+pub fn get_cache_dir() -> PathBuf {
+    if let Ok(dir) = env::var("KOKUBAN_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    let base = env::var("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|_| env::var("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .unwrap_or_else(|_| get_root_dir().join(".cache"));
+    base.join("kokuban")
+}
+
+/// Resolves a config-relative file (patch, config fragment) against the CI
+/// root first, then the kernel source.
+pub fn find_local_file(rel: &str, kernel_source: &Path) -> Result<PathBuf> {
+    let path = [get_root_dir().join(rel), kernel_source.join(rel)]
+        .into_iter()
+        .find(|p| p.is_file())
+        .ok_or_else(|| anyhow!("File not found: {}", rel))?;
+    Ok(fs::canonicalize(path)?)
+}
+
 pub fn get_template_path(name: &str) -> PathBuf {
     get_root_dir().join("templates").join(name)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+/// Loads the projects file with `extends` resolved. `${VAR}` references
+/// are left as written; `expand_entry` expands the one entry being used,
+/// so a variable only one project needs can't break the others.
 pub fn load_projects() -> Result<ProjectsMap> {
+    let raw = load_projects_raw()?;
+    resolve_extends(&raw)
+}
+
+/// The `_globals` entry of the projects file, `${VAR}` expanded.
+pub fn load_globals(projects: &ProjectsMap) -> GlobalConfig {
+    projects
+        .get("_globals")
+        .and_then(|v| match expand_entry("_globals", v) {
+            Ok(v) => serde_json::from_value(v).ok(),
+            Err(e) => {
+                warn!("{:#}", e);
+                None
+            }
+        })
+        .unwrap_or_default()
+}
+
+/// Loads the projects file as written, without resolving `extends`.
+/// Use this when the map is going to be saved back to disk.
+pub fn load_projects_raw() -> Result<ProjectsMap> {
     let path = get_config_path();
     let content = fs::read_to_string(&path)
-        .with_context(|| format!("Failed to read projects.json at {:?}", path))?;
-    serde_json::from_str(&content).context("Failed to parse projects.json")
+        .with_context(|| format!("Failed to read project config at {:?}", path))?;
+    parse_projects(&content, ConfigFormat::from_path(&path))
+        .with_context(|| format!("Failed to parse project config at {:?}", path))
+}
+
+pub fn parse_projects(content: &str, format: ConfigFormat) -> Result<ProjectsMap> {
+    let projects = match format {
+        ConfigFormat::Json => serde_json::from_str(content)?,
+        ConfigFormat::Toml => toml::from_str(content)?,
+        ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+    };
+    Ok(projects)
+}
+
+pub fn resolve_extends(raw: &ProjectsMap) -> Result<ProjectsMap> {
+    let mut resolved = ProjectsMap::new();
+    for key in raw.keys() {
+        let mut chain = Vec::new();
+        let merged = resolve_entry(raw, key, &mut chain)?;
+        resolved.insert(key.clone(), merged);
+    }
+    Ok(resolved)
+}
+
+fn resolve_entry(
+    raw: &ProjectsMap,
+    key: &str,
+    chain: &mut Vec<String>,
+) -> Result<serde_json::Value> {
+    if chain.iter().any(|k| k == key) {
+        chain.push(key.to_string());
+        return Err(anyhow!("Cyclic extends: {}", chain.join(" -> ")));
+    }
+    let entry = raw.get(key).ok_or_else(|| {
+        anyhow!(
+            "Base profile '{}' not found (extends chain: {})",
+            key,
+            chain.join(" -> ")
+        )
+    })?;
+
+    let Some(base_key) = entry.get("extends").and_then(|v| v.as_str()) else {
+        return Ok(entry.clone());
+    };
+
+    chain.push(key.to_string());
+    let mut merged = resolve_entry(raw, base_key, chain)?;
+    chain.pop();
+
+    // The base's own `extends` is an implementation detail of the chain.
+    if let Some(obj) = merged.as_object_mut() {
+        obj.remove("extends");
+    }
+    merge_values(&mut merged, entry);
+    Ok(merged)
+}
+
+/// `val`, the entry `key` of the projects file, with `${VAR}` expanded.
+pub fn expand_entry(key: &str, val: &serde_json::Value) -> Result<serde_json::Value> {
+    let mut val = val.clone();
+    interpolate_env(&mut val).with_context(|| format!("Failed to expand variables in '{}'", key))?;
+    Ok(val)
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` in every string of the entry.
+/// An unset variable without a default is an error so a missing CI secret
+/// fails loudly instead of producing a half-formed URL.
+pub fn interpolate_env(value: &mut serde_json::Value) -> Result<()> {
+    match value {
+        serde_json::Value::String(s) => *s = expand_env(s)?,
+        serde_json::Value::Array(items) => {
+            for item in items {
+                interpolate_env(item)?;
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for v in map.values_mut() {
+                interpolate_env(v)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Values taken from the environment are masked in output from then on,
+/// since they are usually tokens.
+pub fn expand_env(input: &str) -> Result<String> {
+    if !input.contains("${") {
+        return Ok(input.to_string());
+    }
+    let re = regex::Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap();
+    let mut missing = Vec::new();
+    let expanded = re.replace_all(input, |caps: &regex::Captures| {
+        match (env::var(&caps[1]), caps.get(2)) {
+            (Ok(v), _) => {
+                secrets::remember(&v);
+                v
+            }
+            (Err(_), Some(default)) => default.as_str().to_string(),
+            (Err(_), None) => {
+                missing.push(caps[1].to_string());
+                String::new()
+            }
+        }
+    });
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Environment variable(s) not set: {}",
+            missing.join(", ")
+        ));
+    }
+    Ok(expanded.into_owned())
+}
+
+/// Returns the project entry with its `branches.<branch>` override block
+/// merged on top. Entries without a matching override are returned as-is.
+pub fn apply_branch_override(proj_val: &serde_json::Value, branch: &str) -> serde_json::Value {
+    let mut merged = proj_val.clone();
+    let Some(overlay) = proj_val
+        .get("branches")
+        .and_then(|b| b.get(branch))
+        .cloned()
+    else {
+        return merged;
+    };
+    merge_values(&mut merged, &overlay);
+    merged
+}
+
+/// Deep-merges `overlay` into `base`: objects are merged key by key, any
+/// other value (including arrays) replaces the base value outright.
+pub fn merge_values(base: &mut serde_json::Value, overlay: &serde_json::Value) {
+    match (base, overlay) {
+        (serde_json::Value::Object(base_map), serde_json::Value::Object(overlay_map)) => {
+            for (k, v) in overlay_map {
+                match base_map.get_mut(k) {
+                    Some(existing) if existing.is_object() && v.is_object() => {
+                        merge_values(existing, v)
+                    }
+                    _ => {
+                        base_map.insert(k.clone(), v.clone());
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+pub fn save_projects(projects: &ProjectsMap) -> Result<()> {
+    let path = get_config_path();
+    let content = match ConfigFormat::from_path(&path) {
+        ConfigFormat::Json => serde_json::to_string_pretty(projects)? + "\n",
+        ConfigFormat::Toml => {
+            // TOML has no null, so unset optional fields are dropped instead.
+            let mut value = serde_json::to_value(projects)?;
+            strip_nulls(&mut value);
+            toml::to_string_pretty(&value)?
+        }
+        ConfigFormat::Yaml => serde_yaml::to_string(projects)?,
+    };
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+fn strip_nulls(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Takes an exclusive lock on `path`, creating it if needed, waiting while
+/// another process holds it. The lock lasts until the file is dropped.
+pub fn lock_file(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    // SAFETY: flock on a descriptor this function owns.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(anyhow!(
+            "Failed to lock {}: {}",
+            path.display(),
+            io::Error::last_os_error()
+        ));
+    }
+    Ok(file)
 }
 
 pub fn save_json<T: serde::Serialize>(path: &Path, data: &T) -> Result<()> {
@@ -53,7 +333,51 @@ pub fn set_github_env(key: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// `git clone` args that borrow objects from a local mirror of `url` in
+/// `reference_dir`: `<dir>/<name>.git`, `<dir>/<name>`, or the dir itself.
+pub fn git_reference_args(reference_dir: Option<&str>, url: &str) -> Vec<String> {
+    let Some(dir) = reference_dir else {
+        return Vec::new();
+    };
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(url)
+        .trim_end_matches(".git");
+    let dir = Path::new(dir);
+    let Some(mirror) = [
+        dir.join(format!("{}.git", name)),
+        dir.join(name),
+        dir.to_path_buf(),
+    ]
+    .into_iter()
+    .find(|p| p.join("objects").is_dir() || p.join(".git").is_dir()) else {
+        info!("No git mirror for {} in {}", name, dir.display());
+        return Vec::new();
+    };
+    info!("Using git mirror {}", mirror.display());
+    vec![
+        "--reference-if-able".to_string(),
+        mirror.display().to_string(),
+    ]
+}
+
+/// `-v` echoes every command, `-vv` also where it runs and its extra env.
+fn echo_command(cmd: &[&str], cwd: Option<&Path>, envs: Option<&HashMap<String, String>>) {
+    debug!("$ {}", cmd.join(" "));
+    if let Some(dir) = cwd {
+        trace!("  in {}", dir.display());
+    }
+    let mut envs: Vec<_> = envs.into_iter().flatten().collect();
+    envs.sort();
+    for (key, value) in envs {
+        trace!("  {}={}", key, value);
+    }
+}
+
 pub fn run_cmd(cmd: &[&str], cwd: Option<&Path>, capture: bool) -> Result<Option<String>> {
+    echo_command(cmd, cwd, None);
     let mut command = Command::new(cmd[0]);
     command.args(&cmd[1..]);
 
@@ -62,21 +386,20 @@ pub fn run_cmd(cmd: &[&str], cwd: Option<&Path>, capture: bool) -> Result<Option
     }
 
     if capture {
-        let output = command.output()?;
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let output = timeout::run(&mut command)?;
         if !output.status.success() {
-            return Err(anyhow!(
-                "Command failed: {:?} Stderr: {}",
-                cmd,
-                String::from_utf8_lossy(&output.stderr)
-            ));
+            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+            let lines = stderr.lines().map(|l| l.to_string()).collect();
+            return Err(CommandFailed::new(cmd, lines, Some(stderr)).into());
         }
         Ok(Some(
             String::from_utf8_lossy(&output.stdout).trim().to_string(),
         ))
     } else {
-        let status = command.status()?;
+        let status = timeout::status_teed(&mut command)?;
         if !status.success() {
-            return Err(anyhow!("Command failed: {:?}", cmd));
+            return Err(command_failed(cmd));
         }
         Ok(None)
     }
@@ -87,6 +410,7 @@ pub fn run_cmd_with_env(
     cwd: Option<&Path>,
     envs: &HashMap<String, String>,
 ) -> Result<()> {
+    echo_command(cmd, cwd, Some(envs));
     let mut command = Command::new(cmd[0]);
     command.args(&cmd[1..]);
 
@@ -96,204 +420,36 @@ pub fn run_cmd_with_env(
 
     command.envs(envs);
 
-    command.stdout(Stdio::inherit());
-    command.stderr(Stdio::inherit());
-
-    let status = command.status()?;
+    let status = timeout::status_teed(&mut command)?;
     if !status.success() {
-        return Err(anyhow!("Command failed: {:?}", cmd));
+        return Err(command_failed(cmd));
     }
     Ok(())
 }
 
-pub fn handle_notify(tag_name: String) -> Result<()> {
-    let token = env::var("TELEGRAM_BOT_TOKEN").context("Missing TELEGRAM_BOT_TOKEN")?;
-    let projects = load_projects()?;
-
-    let globals_val = projects
-        .get("_globals")
-        .cloned()
-        .unwrap_or(serde_json::Value::Null);
-    let globals: GlobalConfig = serde_json::from_value(globals_val).unwrap_or(GlobalConfig {
-        broadcast_channel: None,
-        resukisu_chat_id: None,
-        resukisu_topic_id: None,
-    });
-
-    let mut target_project: Option<ProjectConfig> = None;
-    let mut repo_url = "Unknown/Repo".to_string();
-
-    for (key, val) in &projects {
-        if key.starts_with("_") {
-            continue;
-        }
-        let p: ProjectConfig = serde_json::from_value(val.clone())?;
-        let prefix = p.zip_name_prefix.as_deref().unwrap_or("Kernel");
-
-        if tag_name.starts_with(prefix) {
-            target_project = Some(p.clone());
-            repo_url = p.repo;
-            break;
-        }
-    }
-
-    if target_project.is_none() {
-        println!("No project found for tag {}", tag_name);
-        return Ok(());
-    }
-
-    let mut destinations = Vec::new();
-    if let Some(chan) = globals.broadcast_channel {
-        destinations.push((chan, None));
-    }
-    if tag_name.contains("ReSuki") {
-        if let Some(chat) = globals.resukisu_chat_id {
-            destinations.push((chat, globals.resukisu_topic_id));
-        }
-    }
-
-    if destinations.is_empty() {
-        println!("No destinations.");
-        return Ok(());
-    }
-
-    let mut attempt = 0;
-    let max_attempts = 5;
-    let mut release_info_str = String::new();
-
-    while attempt < max_attempts {
-        match run_cmd(
-            &[
-                "gh",
-                "release",
-                "view",
-                &tag_name,
-                "--repo",
-                &repo_url,
-                "--json",
-                "assets,body,name,url,author",
-            ],
-            None,
-            true,
-        ) {
-            Ok(Some(output)) => {
-                release_info_str = output;
-                break;
-            }
-            Ok(None) => break, // Should not happen with capture=true
-            Err(e) => {
-                println!(
-                    "Attempt {}/{} failed to verify release: {}. Retrying in 5s...",
-                    attempt + 1,
-                    max_attempts,
-                    e
-                );
-                attempt += 1;
-                thread::sleep(Duration::from_secs(5));
-            }
-        }
-    }
-
-    if release_info_str.is_empty() {
-        return Err(anyhow!(
-            "Failed to retrieve release info after multiple attempts."
-        ));
-    }
-
-    let release_info: serde_json::Value = serde_json::from_str(&release_info_str)?;
-
-    let author = release_info["author"]["login"]
-        .as_str()
-        .unwrap_or("YuzakiKokuban");
-    let name = release_info["name"].as_str().unwrap_or("Update");
-    let url = release_info["url"].as_str().unwrap_or("");
-
-    let msg = format!(
-        "兄长大人，快看！<code>{}</code> 有新的 Release 了哦。\n\n<b>版本 (Version):</b> <code>{}</code>\n<b>标题 (Title):</b> {}\n<b>作者 (Author):</b> {}\n\n总之，快去看看吧！ <a href='{}'>点击这里跳转</a>",
-        repo_url, tag_name, name, author, url
-    );
-
-    let client = reqwest::blocking::Client::new();
-
-    for (chat_id, topic_id) in &destinations {
-        let mut json_body = HashMap::new();
-        json_body.insert("chat_id", serde_json::to_value(chat_id)?);
-        json_body.insert("text", serde_json::to_value(&msg)?);
-        json_body.insert("parse_mode", serde_json::to_value("HTML")?);
-        json_body.insert("disable_web_page_preview", serde_json::to_value(true)?);
-
-        if let Some(tid) = topic_id {
-            json_body.insert("message_thread_id", serde_json::to_value(tid)?);
-        }
-
-        let _ = client
-            .post(format!("https://api.telegram.org/bot{}/sendMessage", token))
-            .json(&json_body)
-            .send();
-    }
-
-    let assets = release_info["assets"].as_array();
-    if let Some(asset_list) = assets {
-        for asset in asset_list {
-            let name = asset["name"].as_str().unwrap();
-            let size = asset["size"].as_i64().unwrap_or(0);
+/// `CommandFailed` for a teed command, with the tail of its output.
+fn command_failed(cmd: &[&str]) -> anyhow::Error {
+    CommandFailed::new(cmd, output::failed_output(), None).into()
+}
 
-            if size > 50 * 1024 * 1024 {
-                println!("Skipping {} (too large)", name);
-                continue;
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+pub fn url_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
             }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
 
-            run_cmd(
-                &[
-                    "gh",
-                    "release",
-                    "download",
-                    &tag_name,
-                    "--repo",
-                    &repo_url,
-                    "-p",
-                    name,
-                    "--clobber",
-                ],
-                None,
-                false,
-            )?;
-
-            for (chat_id, topic_id) in &destinations {
-                let caption = format!(
-                    "兄长大人，附件来了。\n<b>仓库 (Repo):</b> <code>{}</code>\n<b>版本 (Version):</b> <code>{}</code>\n\n📄 <b>文件 (File):</b> <code>{}</code>",
-                    repo_url, tag_name, name
-                );
-
-                let form = reqwest::blocking::multipart::Form::new()
-                    .text("chat_id", chat_id.clone())
-                    .text("caption", caption)
-                    .text("parse_mode", "HTML");
-
-                let form = if let Some(tid) = topic_id {
-                    form.text("message_thread_id", tid.to_string())
-                } else {
-                    form
-                };
-
-                let file_content = fs::read(name)?;
-                let part = reqwest::blocking::multipart::Part::bytes(file_content)
-                    .file_name(name.to_owned());
-                let form = form.part("document", part);
-
-                let _ = client
-                    .post(format!(
-                        "https://api.telegram.org/bot{}/sendDocument",
-                        token
-                    ))
-                    .multipart(form)
-                    .send();
-            }
-            if Path::new(name).exists() {
-                fs::remove_file(name)?;
-            }
-        }
+/// "42s" or "12m04s".
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
     }
-
-    Ok(())
 }
@@ -0,0 +1,354 @@
+use anyhow::{Result, anyhow};
+use std::fs;
+
+use crate::analyze::TOOLS as ANALYZE_TOOLS;
+use crate::arch::ARCHES;
+use crate::compiler_cache::COMPILER_CACHE_VALUES;
+use crate::config::{GlobalConfig, KsuVariant, KsuVariants, ProjectConfig};
+use crate::deploy::METHODS as DEPLOY_METHODS;
+use crate::ksu::{load_ksu_variants, resolve_variant};
+use crate::modsign::HASHES as MODULE_SIG_HASHES;
+use crate::schedule::Cron;
+use crate::utils::{apply_branch_override, get_config_path, interpolate_env, load_projects};
+
+const REQUIRED_FIELDS: &[&str] = &["repo", "defconfig", "localversion_base"];
+const LTO_VALUES: &[&str] = &["thin", "full", "none"];
+const VERSION_METHOD_VALUES: &[&str] = &["param", "file"];
+const COMPILER_VALUES: &[&str] = &["clang", "gcc"];
+
+struct Issue {
+    project: String,
+    field: String,
+    message: String,
+}
+
+pub fn handle_validate() -> Result<()> {
+    let path = get_config_path();
+    let content = fs::read_to_string(&path)?;
+    let projects = load_projects()?;
+    let ksu_variants = load_ksu_variants(&projects)?;
+
+    let mut keys: Vec<&String> = projects.keys().collect();
+    keys.sort();
+
+    let mut issues = Vec::new();
+    for key in keys {
+        let val = &projects[key];
+        if let Err(e) = interpolate_env(&mut val.clone()) {
+            issues.push(Issue {
+                project: key.clone(),
+                field: String::new(),
+                message: format!("{:#}", e),
+            });
+        }
+        if key == "_globals" {
+            check_unknown_keys::<GlobalConfig>(key, val, &mut issues);
+            continue;
+        }
+        if key == "_ksu_variants" {
+            for (name, variant) in val.as_object().into_iter().flatten() {
+                check_unknown_keys::<KsuVariant>(
+                    &format!("{}.{}", key, name),
+                    variant,
+                    &mut issues,
+                );
+            }
+            continue;
+        }
+        if key.starts_with('_') {
+            continue;
+        }
+        issues.extend(validate_project(key, val, &ksu_variants));
+    }
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    for issue in &issues {
+        let location = match find_line(&content, &issue.project, &issue.field) {
+            Some(line) => format!("{}:{}", file_name, line),
+            None => file_name.to_string(),
+        };
+        if issue.field.is_empty() {
+            println!("{}: {}: {}", location, issue.project, issue.message);
+        } else {
+            println!(
+                "{}: {}.{}: {}",
+                location, issue.project, issue.field, issue.message
+            );
+        }
+    }
+
+    if issues.is_empty() {
+        println!("{}: {} entries OK", file_name, projects.len());
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} problem(s) found in {}",
+            issues.len(),
+            file_name
+        ))
+    }
+}
+
+/// What `validate` would report for one project entry, as `field: message`.
+pub fn project_issues(
+    key: &str,
+    val: &serde_json::Value,
+    ksu_variants: &KsuVariants,
+) -> Vec<String> {
+    validate_project(key, val, ksu_variants)
+        .into_iter()
+        .map(|i| match i.field.as_str() {
+            "" => i.message,
+            field => format!("{}: {}", field, i.message),
+        })
+        .collect()
+}
+
+fn validate_project(key: &str, val: &serde_json::Value, ksu_variants: &KsuVariants) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let issue = |field: &str, message: String| Issue {
+        project: key.to_string(),
+        field: field.to_string(),
+        message,
+    };
+
+    let Some(obj) = val.as_object() else {
+        issues.push(issue("", "entry must be a table/object".to_string()));
+        return issues;
+    };
+
+    for field in REQUIRED_FIELDS {
+        match obj.get(*field) {
+            None | Some(serde_json::Value::Null) => {
+                issues.push(issue(field, "missing required field".to_string()))
+            }
+            Some(serde_json::Value::String(s))
+                if s.trim().is_empty() && *field != "localversion_base" =>
+            {
+                issues.push(issue(field, "must not be empty".to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    let arch_values: Vec<&str> = ARCHES.iter().map(|(key, _)| *key).collect();
+    for (field, allowed) in [
+        ("lto", LTO_VALUES),
+        ("version_method", VERSION_METHOD_VALUES),
+        ("compiler", COMPILER_VALUES),
+        ("compiler_cache", COMPILER_CACHE_VALUES),
+        ("arch", arch_values.as_slice()),
+    ] {
+        if let Some(v) = obj.get(field).and_then(|v| v.as_str())
+            && !allowed.contains(&v)
+        {
+            issues.push(issue(
+                field,
+                format!(
+                    "invalid value '{}' (expected one of: {})",
+                    v,
+                    allowed.join(", ")
+                ),
+            ));
+        }
+    }
+
+    for name in obj
+        .get("supported_ksu")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str())
+    {
+        if resolve_variant(ksu_variants, name).is_none() {
+            issues.push(issue(
+                "supported_ksu",
+                format!(
+                    "unknown KernelSU variant '{}' (known: {})",
+                    name,
+                    ksu_variants.keys().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            ));
+        }
+    }
+
+    if let Some(tool) = obj
+        .get("analyze")
+        .and_then(|a| a.get("tool"))
+        .and_then(|v| v.as_str())
+        && !ANALYZE_TOOLS.contains(&tool)
+    {
+        issues.push(issue(
+            "analyze.tool",
+            format!(
+                "invalid value '{}' (expected one of: {})",
+                tool,
+                ANALYZE_TOOLS.join(", ")
+            ),
+        ));
+    }
+
+    if let Some(method) = obj
+        .get("deploy")
+        .and_then(|d| d.get("method"))
+        .and_then(|v| v.as_str())
+        && !DEPLOY_METHODS.contains(&method)
+    {
+        issues.push(issue(
+            "deploy.method",
+            format!(
+                "invalid value '{}' (expected one of: {})",
+                method,
+                DEPLOY_METHODS.join(", ")
+            ),
+        ));
+    }
+
+    for (i, entry) in obj
+        .get("schedules")
+        .and_then(|v| v.as_array())
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        if let Some(cron) = entry.get("cron").and_then(|v| v.as_str())
+            && let Err(e) = Cron::parse(cron)
+        {
+            issues.push(issue(&format!("schedules[{}].cron", i), format!("{:#}", e)));
+        }
+    }
+
+    if let Some(hash) = obj
+        .get("module_signing")
+        .and_then(|m| m.get("hash"))
+        .and_then(|v| v.as_str())
+        && !MODULE_SIG_HASHES.contains(&hash)
+    {
+        issues.push(issue(
+            "module_signing.hash",
+            format!(
+                "invalid value '{}' (expected one of: {})",
+                hash,
+                MODULE_SIG_HASHES.join(", ")
+            ),
+        ));
+    }
+
+    if let Some(sums) = obj.get("toolchain_sha256").and_then(|v| v.as_array()) {
+        let url_count = obj
+            .get("toolchain_urls")
+            .and_then(|v| v.as_array())
+            .map_or(0, |a| a.len());
+        if sums.len() != url_count {
+            issues.push(issue(
+                "toolchain_sha256",
+                format!(
+                    "has {} entries but toolchain_urls has {}",
+                    sums.len(),
+                    url_count
+                ),
+            ));
+        }
+        for sum in sums.iter().filter_map(|v| v.as_str()) {
+            if sum.len() != 64 || !sum.chars().all(|c| c.is_ascii_hexdigit()) {
+                issues.push(issue(
+                    "toolchain_sha256",
+                    format!("'{}' is not a SHA-256 hex digest", sum),
+                ));
+            }
+        }
+    }
+
+    check_unknown_keys::<ProjectConfig>(key, val, &mut issues);
+
+    if let Some(branches) = obj.get("branches").and_then(|b| b.as_object()) {
+        for (branch, overlay) in branches {
+            let Some(overlay_obj) = overlay.as_object() else {
+                issues.push(issue(
+                    &format!("branches.{}", branch),
+                    "override must be a table/object".to_string(),
+                ));
+                continue;
+            };
+            // Only report problems for keys the override itself sets.
+            let mut branch_issues = Vec::new();
+            check_unknown_keys::<ProjectConfig>(
+                key,
+                &apply_branch_override(val, branch),
+                &mut branch_issues,
+            );
+            for mut i in branch_issues {
+                let top = i.field.split('.').next().unwrap_or_default();
+                if overlay_obj.contains_key(top) {
+                    i.field = format!("branches.{}.{}", branch, i.field);
+                    issues.push(i);
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+fn check_unknown_keys<T: serde::de::DeserializeOwned>(
+    key: &str,
+    val: &serde_json::Value,
+    issues: &mut Vec<Issue>,
+) {
+    let mut unknown = Vec::new();
+    let mut on_ignored = |path: serde_ignored::Path| unknown.push(path.to_string());
+    let result: Result<T, _> = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+        val.clone(),
+        &mut on_ignored,
+    ));
+
+    for field in unknown {
+        issues.push(Issue {
+            project: key.to_string(),
+            field,
+            message: "unknown key".to_string(),
+        });
+    }
+
+    if let Err(e) = result {
+        let message = e.inner().to_string();
+        // Missing fields are already reported above with a clearer message.
+        if !message.starts_with("missing field") {
+            let field = e.path().to_string();
+            issues.push(Issue {
+                project: key.to_string(),
+                field: if field == "." { String::new() } else { field },
+                message,
+            });
+        }
+    }
+}
+
+/// Best-effort line lookup: finds the project header, then the first
+/// mention of the field after it. Works for JSON, TOML and YAML layouts.
+fn find_line(content: &str, project: &str, field: &str) -> Option<usize> {
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.iter().position(|l| {
+        let t = l.trim();
+        t.starts_with(&format!("\"{}\"", project))
+            || t.starts_with(&format!("[{}]", project))
+            || t.starts_with(&format!("{}:", project))
+    })?;
+
+    let leaf = field.rsplit('.').next().unwrap_or(field);
+    if leaf.is_empty() {
+        return Some(start + 1);
+    }
+
+    lines[start..]
+        .iter()
+        .position(|l| {
+            let t = l.trim().trim_start_matches('"');
+            t.starts_with(&format!("{}\"", leaf))
+                || t.starts_with(&format!("{} ", leaf))
+                || t.starts_with(&format!("{}=", leaf))
+                || t.starts_with(&format!("{}:", leaf))
+        })
+        .map(|i| start + i + 1)
+        .or(Some(start + 1))
+}
@@ -0,0 +1,91 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+/// One compiler or linker warning from the make output.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Warning {
+    pub file: String,
+    pub line: Option<u32>,
+    /// `-Wfoo`, when the compiler named one.
+    pub flag: Option<String>,
+    pub message: String,
+}
+
+impl Warning {
+    /// Parses `file.c:12:5: warning: message [-Wflag]`. Warnings without a
+    /// location (e.g. `ld.lld: warning: ...`) keep the tool name as `file`.
+    pub fn parse(line: &str) -> Option<Warning> {
+        let (location, message) = line.split_once(": warning: ")?;
+        let mut parts = location.splitn(3, ':');
+        let file = parts.next()?.trim_start_matches("./").to_string();
+        let line_no = parts.next().and_then(|l| l.parse().ok());
+        let (message, flag) = match message
+            .strip_suffix(']')
+            .and_then(|m| m.rsplit_once(" ["))
+            .filter(|(_, flag)| flag.starts_with("-W"))
+        {
+            Some((message, flag)) => (message, Some(flag.to_string())),
+            None => (message, None),
+        };
+        Some(Warning {
+            file,
+            line: line_no,
+            flag,
+            message: message.trim().to_string(),
+        })
+    }
+
+    pub fn category(&self) -> &str {
+        self.flag.as_deref().unwrap_or("other")
+    }
+
+    /// What identifies a warning across builds; line numbers move when
+    /// patches are applied above them.
+    fn key(&self) -> (&str, Option<&str>, &str) {
+        (&self.file, self.flag.as_deref(), &self.message)
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message)?,
+            None => write!(f, "{}: {}", self.file, self.message)?,
+        }
+        match &self.flag {
+            Some(flag) => write!(f, " [{}]", flag),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn counts(warnings: &[Warning]) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+    for warning in warnings {
+        *counts.entry(warning.category().to_string()).or_default() += 1;
+    }
+    counts
+}
+
+/// Warnings in `current` that `previous` doesn't have.
+pub fn new_since<'a>(current: &'a [Warning], previous: &[Warning]) -> Vec<&'a Warning> {
+    let known: HashSet<_> = previous.iter().map(Warning::key).collect();
+    current
+        .iter()
+        .filter(|w| !known.contains(&w.key()))
+        .collect()
+}
+
+pub fn print_summary(warnings: &[Warning]) {
+    if warnings.is_empty() {
+        info!("No compiler warnings.");
+        return;
+    }
+    info!("Compiler warnings: {}", warnings.len());
+    let mut by_count: Vec<(String, u64)> = counts(warnings).into_iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    for (category, count) in by_count {
+        info!("  {:>5}  {}", count, category);
+    }
+}